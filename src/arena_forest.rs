@@ -0,0 +1,463 @@
+//! A forest of Red-Black trees with values stored out-of-line
+//!
+//! [`RBForest`] stores each value in a fixed-size `VSIZE` buffer inside of the node, so if most of
+//! the values are small, but some of them may be big, a lot of space is wasted. [`ArenaForest`]
+//! keeps only a small value reference ([`VALUE_REF_SIZE`] bytes) in the node and stores serialized
+//! values in a separate arena of fixed-size slots. A value occupies as many slots as needed, the
+//! slots are chained in a singly-linked list and returned to a free-list on removal. Node traversal
+//! does not touch the arena, so the node pool stays as dense as for small values.
+//!
+//! The forest and the arena share a single buffer: the forest comes first and takes
+//! `forest_size(ForestParams { k_size: KSIZE, v_size: VALUE_REF_SIZE, max_roots }, max_nodes)`
+//! bytes, the rest of the buffer is the arena. The forest header stores `max_nodes` and
+//! `max_roots`, so the split point is recovered on [`ArenaForest::from_slice`]. Use
+//! [`arena_forest_size`] to compute the size of the buffer.
+//!
+//! ```
+//! use slice_rbtree::arena_forest::{arena_forest_size, ArenaForest};
+//!
+//! let mut buffer = vec![0; arena_forest_size(1, 1, 10, 8, 20)];
+//!
+//! let mut forest: ArenaForest<u8, String, 1, 8> =
+//!     ArenaForest::init_slice(&mut buffer, 1, 10).unwrap();
+//!
+//! forest.insert(0, 1, "short".to_string()).unwrap();
+//! forest.insert(0, 2, "a value, which takes several slots".to_string()).unwrap();
+//!
+//! assert_eq!(forest.get(0, &1).as_deref(), Some("short"));
+//! assert_eq!(
+//!     forest.remove(0, &2).as_deref(),
+//!     Some("a value, which takes several slots")
+//! );
+//! ```
+use borsh::maybestd::vec::Vec;
+use borsh::{BorshDeserialize, BorshSerialize};
+use bytemuck::{cast_slice_mut, from_bytes, Pod, Zeroable};
+use core::borrow::Borrow;
+use core::cmp::Ord;
+use core::fmt;
+use core::mem;
+
+use super::forest::{forest_size, ForestParams, Header, RBForest};
+use super::Error;
+
+/// Size of the value reference, stored in the nodes of [`ArenaForest`]
+pub const VALUE_REF_SIZE: usize = 8;
+
+/// Index of a missing slot
+const NO_SLOT: u32 = u32::MAX;
+
+/// Returns the required size of the arena part of the [`ArenaForest`] buffer
+#[must_use]
+#[inline]
+pub const fn arena_size(slot_size: usize, num_slots: usize) -> usize {
+    mem::size_of::<[u8; 4]>() + (mem::size_of::<[u8; 4]>() + slot_size) * num_slots
+}
+
+/// Returns the required size of the [`ArenaForest`] buffer
+#[must_use]
+#[inline]
+pub const fn arena_forest_size(
+    k_size: usize,
+    max_roots: usize,
+    max_nodes: usize,
+    slot_size: usize,
+    num_slots: usize,
+) -> usize {
+    nodes_size(k_size, max_roots, max_nodes) + arena_size(slot_size, num_slots)
+}
+
+/// Size of the forest part of the buffer
+const fn nodes_size(k_size: usize, max_roots: usize, max_nodes: usize) -> usize {
+    forest_size(
+        ForestParams {
+            k_size,
+            v_size: VALUE_REF_SIZE,
+            max_roots,
+        },
+        max_nodes,
+    )
+}
+
+/// Location of a value in the arena
+#[derive(Clone, Copy, BorshDeserialize, BorshSerialize)]
+struct ValueRef {
+    /// index of the first slot or [`NO_SLOT`] for empty values
+    first: u32,
+    /// length of the serialized value in bytes
+    len: u32,
+}
+
+/// A single slot of the arena
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable)]
+struct Slot<const SSIZE: usize> {
+    /// `u32` encoded as big-endian, index of the next slot of the value or of the free-list
+    next: [u8; 4],
+    /// bytes of the serialized value
+    data: [u8; SSIZE],
+}
+
+unsafe impl<const SSIZE: usize> Pod for Slot<SSIZE> {}
+
+impl<const SSIZE: usize> Slot<SSIZE> {
+    fn next(&self) -> u32 {
+        u32::from_be_bytes(self.next)
+    }
+
+    fn set_next(&mut self, next: u32) {
+        self.next = u32::to_be_bytes(next);
+    }
+}
+
+/// Pool of value slots: the head of the free-list followed by the slots
+struct Arena<'a, const SSIZE: usize> {
+    head: &'a mut [u8; 4],
+    slots: &'a mut [Slot<SSIZE>],
+}
+
+impl<'a, const SSIZE: usize> Arena<'a, SSIZE> {
+    fn from_slice(slice: &'a mut [u8]) -> Result<Self, Error> {
+        if SSIZE == 0 {
+            return Err(Error::WrongValueSize);
+        }
+        if slice.len() < mem::size_of::<[u8; 4]>() {
+            return Err(Error::TooSmall);
+        }
+        let (head, slots) = slice.split_at_mut(mem::size_of::<[u8; 4]>());
+        if slots.len() % mem::size_of::<Slot<SSIZE>>() != 0 {
+            return Err(Error::WrongSliceSize);
+        }
+        if slots.len() / mem::size_of::<Slot<SSIZE>>() >= NO_SLOT as usize {
+            return Err(Error::TooBig);
+        }
+
+        Ok(Self {
+            head: head.try_into().expect("head has exactly 4 bytes"),
+            slots: cast_slice_mut(slots),
+        })
+    }
+
+    /// Links all the slots in the free-list
+    fn init(&mut self) {
+        let len = self.slots.len() as u32;
+        for (i, slot) in self.slots.iter_mut().enumerate() {
+            let next = i as u32 + 1;
+            slot.set_next(if next < len { next } else { NO_SLOT });
+        }
+        self.set_head(if len > 0 { 0 } else { NO_SLOT });
+    }
+
+    fn head(&self) -> u32 {
+        u32::from_be_bytes(*self.head)
+    }
+
+    /// Checks, that the head of the free-list points into the arena
+    fn check_head(&self) -> Result<(), Error> {
+        let head = self.head();
+        if head != NO_SLOT && head as usize >= self.slots.len() {
+            return Err(Error::IndexOutOfRange);
+        }
+        Ok(())
+    }
+
+    fn set_head(&mut self, head: u32) {
+        *self.head = u32::to_be_bytes(head);
+    }
+
+    fn slots_for(len: usize) -> usize {
+        len.div_ceil(SSIZE)
+    }
+
+    fn free_slots(&self) -> usize {
+        let mut count = 0;
+        let mut id = self.head();
+        while id != NO_SLOT {
+            count += 1;
+            id = self.slots[id as usize].next();
+        }
+        count
+    }
+
+    /// Checks, that the free-list has at least `needed` slots
+    fn has_free_slots(&self, needed: usize) -> bool {
+        let mut id = self.head();
+        for _ in 0..needed {
+            if id == NO_SLOT {
+                return false;
+            }
+            id = self.slots[id as usize].next();
+        }
+        true
+    }
+
+    fn alloc(&mut self, bytes: &[u8]) -> Result<ValueRef, Error> {
+        let len = u32::try_from(bytes.len()).map_err(|_| Error::ValueSerializationError)?;
+        if !self.has_free_slots(Self::slots_for(bytes.len())) {
+            return Err(Error::NoNodesLeft);
+        }
+        if bytes.is_empty() {
+            return Ok(ValueRef {
+                first: NO_SLOT,
+                len: 0,
+            });
+        }
+
+        // Allocated slots are a prefix of the free-list, so they are already chained
+        let first = self.head();
+        let mut id = first;
+        let mut chunks = bytes.chunks(SSIZE).peekable();
+        while let Some(chunk) = chunks.next() {
+            let slot = &mut self.slots[id as usize];
+            slot.data[..chunk.len()].copy_from_slice(chunk);
+            let next = slot.next();
+            if chunks.peek().is_none() {
+                slot.set_next(NO_SLOT);
+            }
+            id = next;
+        }
+        self.set_head(id);
+
+        Ok(ValueRef { first, len })
+    }
+
+    fn read(&self, value_ref: ValueRef) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(value_ref.len as usize);
+        let mut remaining = value_ref.len as usize;
+        let mut id = value_ref.first;
+        while remaining > 0 {
+            let slot = &self.slots[id as usize];
+            let chunk_len = remaining.min(SSIZE);
+            bytes.extend_from_slice(&slot.data[..chunk_len]);
+            remaining -= chunk_len;
+            id = slot.next();
+        }
+        bytes
+    }
+
+    fn free(&mut self, value_ref: ValueRef) {
+        if value_ref.first == NO_SLOT {
+            return;
+        }
+        let mut last = value_ref.first;
+        for _ in 1..Self::slots_for(value_ref.len as usize) {
+            last = self.slots[last as usize].next();
+        }
+        let head = self.head();
+        self.slots[last as usize].set_next(head);
+        self.set_head(value_ref.first);
+    }
+}
+
+/// A forest of Red-Black trees, which stores values in a separate arena
+///
+/// `SSIZE` is the size of a single arena slot. See [module](super::arena_forest) level
+/// documentation for more info.
+pub struct ArenaForest<'a, K, V, const KSIZE: usize, const SSIZE: usize>
+where
+    K: Ord + BorshDeserialize + BorshSerialize,
+    V: BorshDeserialize + BorshSerialize,
+{
+    forest: RBForest<'a, K, ValueRef, KSIZE, VALUE_REF_SIZE>,
+    arena: Arena<'a, SSIZE>,
+    _phantom: core::marker::PhantomData<V>,
+}
+
+impl<'a, K, V, const KSIZE: usize, const SSIZE: usize> ArenaForest<'a, K, V, KSIZE, SSIZE>
+where
+    K: Ord + BorshDeserialize + BorshSerialize,
+    V: BorshDeserialize + BorshSerialize,
+{
+    /// Initializes [`ArenaForest`] in the given slice
+    ///
+    /// The first `max_nodes` nodes worth of the slice hold the trees, the rest of the slice holds
+    /// the values, see [`arena_forest_size`].
+    pub fn init_slice(
+        slice: &'a mut [u8],
+        max_roots: usize,
+        max_nodes: usize,
+    ) -> Result<Self, Error> {
+        let forest_len = nodes_size(KSIZE, max_roots, max_nodes);
+        if slice.len() < forest_len {
+            return Err(Error::TooSmall);
+        }
+        let (nodes, arena) = slice.split_at_mut(forest_len);
+
+        let mut arena = Arena::from_slice(arena)?;
+        let forest = RBForest::init_slice(nodes, max_roots)?;
+        arena.init();
+
+        Ok(Self {
+            forest,
+            arena,
+            _phantom: core::marker::PhantomData,
+        })
+    }
+
+    /// Returns [`ArenaForest`], contained in the given slice
+    ///
+    /// # Safety
+    /// This function must be called only on slices, previously initialized as [`ArenaForest`]
+    /// using [`ArenaForest::init_slice`]
+    pub unsafe fn from_slice(slice: &'a mut [u8]) -> Result<Self, Error> {
+        if slice.len() <= mem::size_of::<Header>() {
+            return Err(Error::TooSmall);
+        }
+        let header: &Header = from_bytes(&slice[..mem::size_of::<Header>()]);
+        if !header.check_magic() {
+            return Err(Error::WrongMagic);
+        }
+
+        let forest_len = nodes_size(
+            header.k_size() as usize,
+            header.max_roots() as usize,
+            header.max_nodes() as usize,
+        );
+        if slice.len() < forest_len {
+            return Err(Error::TooSmall);
+        }
+        let (nodes, arena) = slice.split_at_mut(forest_len);
+
+        let arena = Arena::from_slice(arena)?;
+        arena.check_head()?;
+        let forest = unsafe { RBForest::from_slice(nodes)? };
+
+        Ok(Self {
+            forest,
+            arena,
+            _phantom: core::marker::PhantomData,
+        })
+    }
+
+    /// Returns the number of entries in the tree
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn len(&self, tree_id: usize) -> Result<usize, Error> {
+        self.forest.len(tree_id)
+    }
+
+    /// Returns `true` if the tree contains no elements
+    #[must_use]
+    pub fn is_empty(&self, tree_id: usize) -> bool {
+        self.forest.is_empty(tree_id)
+    }
+
+    /// Returns the number of free arena slots
+    ///
+    /// This function runs in `O(s)`, where `s` - is the number of free slots
+    #[must_use]
+    pub fn free_slots(&self) -> usize {
+        self.arena.free_slots()
+    }
+
+    /// Returns `true` if the tree contains a value for the specified key
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn contains_key<Q>(&self, tree_id: usize, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.forest.contains_key(tree_id, k)
+    }
+
+    /// Returns the value corresponding to the key
+    ///
+    /// This function runs in `O(log(n) + m)`, where `n` - is the number of nodes and `m` - is the
+    /// number of slots taken by the value
+    #[must_use]
+    pub fn get<Q>(&self, tree_id: usize, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.forest
+            .get(tree_id, k)
+            .map(|value_ref| self.read_value(value_ref))
+    }
+
+    /// Inserts a new entry and returns the old value if the key was present
+    ///
+    /// If there are not enough free arena slots for the value, [`Error::NoNodesLeft`] is returned
+    /// and the forest is left unchanged.
+    ///
+    /// This function runs in `O(log(n) + m)`, where `n` - is the number of nodes and `m` - is the
+    /// number of slots taken by the old and the new values
+    pub fn insert(&mut self, tree_id: usize, key: K, value: V) -> Result<Option<V>, Error> {
+        let bytes = value
+            .try_to_vec()
+            .map_err(|_| Error::ValueSerializationError)?;
+        let value_ref = self.arena.alloc(&bytes)?;
+
+        match self.forest.insert(tree_id, key, value_ref) {
+            Ok(old_ref) => Ok(old_ref.map(|old_ref| {
+                let old_value = self.read_value(old_ref);
+                self.arena.free(old_ref);
+                old_value
+            })),
+            Err(e) => {
+                self.arena.free(value_ref);
+                Err(e)
+            }
+        }
+    }
+
+    /// Deletes the entry and returns its value
+    ///
+    /// This function runs in `O(log(n) + m)`, where `n` - is the number of nodes and `m` - is the
+    /// number of slots taken by the value
+    pub fn remove<Q>(&mut self, tree_id: usize, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let value_ref = self.forest.remove(tree_id, k)?;
+        let value = self.read_value(value_ref);
+        self.arena.free(value_ref);
+        Some(value)
+    }
+
+    /// Creates an iterator over key-value pairs, in order by key
+    pub fn pairs<'b>(&'b self, tree_id: usize) -> Result<impl Iterator<Item = (K, V)> + 'b, Error> {
+        Ok(self
+            .forest
+            .pairs(tree_id)?
+            .map(|(k, value_ref)| (k, self.read_value(value_ref))))
+    }
+
+    fn read_value(&self, value_ref: ValueRef) -> V {
+        V::deserialize(&mut self.arena.read(value_ref).as_slice()).expect("Value corrupted")
+    }
+}
+
+impl<'a, K, V, const KSIZE: usize, const SSIZE: usize> fmt::Debug
+    for ArenaForest<'a, K, V, KSIZE, SSIZE>
+where
+    K: Ord + BorshDeserialize + BorshSerialize + fmt::Debug,
+    V: BorshDeserialize + BorshSerialize + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        let max_roots = self.forest.max_roots();
+        f.debug_map()
+            .entries((0..max_roots).filter_map(|i| {
+                self.pairs(i)
+                    .ok()
+                    .map(|pairs| (i, DebugTree(pairs.collect())))
+            }))
+            .finish()
+    }
+}
+
+struct DebugTree<K, V>(Vec<(K, V)>);
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for DebugTree<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.debug_map()
+            .entries(self.0.iter().map(|(k, v)| (k, v)))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests;
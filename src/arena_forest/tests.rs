@@ -0,0 +1,181 @@
+use super::*;
+use crate::forest::tests as forest_helpers;
+use crate::forest::{forest_size, ForestParams};
+use pretty_assertions::assert_eq;
+
+type Forest<'a> = ArenaForest<'a, u8, Vec<u8>, 1, 16>;
+
+fn create_vec(num_nodes: usize, num_slots: usize, max_roots: usize) -> Vec<u8> {
+    vec![0; arena_forest_size(1, max_roots, num_nodes, 16, num_slots)]
+}
+
+/// Mostly small values with an occasional big one
+fn value_for(key: u8) -> Vec<u8> {
+    let len = if key % 16 == 0 {
+        200
+    } else {
+        usize::from(key % 4)
+    };
+    vec![key; len]
+}
+
+#[test]
+fn mixed_values() {
+    let mut vec = create_vec(256, 512, 1);
+    let mut forest = Forest::init_slice(&mut vec, 1, 256).unwrap();
+    let total_slots = forest.free_slots();
+
+    for &key in forest_helpers::INSERT_KEYS.iter() {
+        assert_eq!(forest.insert(0, key, value_for(key)), Ok(None));
+    }
+    assert_eq!(forest.len(0), Ok(256));
+
+    for key in 0..=255 {
+        assert_eq!(forest.get(0, &key), Some(value_for(key)));
+    }
+    assert!(forest
+        .pairs(0)
+        .unwrap()
+        .eq((0..=255).map(|key| (key, value_for(key)))));
+
+    // Overwriting returns the old value and reuses its slots
+    let free_slots = forest.free_slots();
+    assert_eq!(forest.insert(0, 16, vec![1]), Ok(Some(value_for(16))));
+    assert!(forest.free_slots() > free_slots);
+    assert_eq!(forest.insert(0, 16, value_for(16)), Ok(Some(vec![1])));
+    assert_eq!(forest.free_slots(), free_slots);
+
+    for key in 0..=255 {
+        assert_eq!(forest.remove(0, &key), Some(value_for(key)));
+        assert_eq!(forest.get(0, &key), None);
+    }
+    assert!(forest.is_empty(0));
+    assert_eq!(forest.free_slots(), total_slots);
+}
+
+#[test]
+fn no_slots_left() {
+    let mut vec = create_vec(10, 4, 1);
+    let mut forest = Forest::init_slice(&mut vec, 1, 10).unwrap();
+
+    assert_eq!(forest.insert(0, 1, vec![1; 30]), Ok(None));
+    // 30 bytes and the length prefix take 3 slots, so the next big value does not fit
+    assert_eq!(forest.insert(0, 2, vec![2; 30]), Err(Error::NoNodesLeft));
+    assert!(!forest.contains_key(0, &2));
+    assert_eq!(forest.free_slots(), 1);
+
+    assert_eq!(forest.insert(0, 2, vec![2; 3]), Ok(None));
+    assert_eq!(forest.free_slots(), 0);
+
+    // The forest itself is full, so the allocated slots are released
+    let mut vec = create_vec(1, 4, 1);
+    let mut forest = Forest::init_slice(&mut vec, 1, 1).unwrap();
+    assert_eq!(forest.insert(0, 1, vec![1]), Ok(None));
+    assert_eq!(forest.insert(0, 2, vec![2]), Err(Error::NoNodesLeft));
+    assert_eq!(forest.free_slots(), 3);
+    assert_eq!(forest.get(0, &1), Some(vec![1]));
+}
+
+#[test]
+fn reopen() {
+    let mut vec = create_vec(256, 512, 2);
+    {
+        let mut forest = Forest::init_slice(&mut vec, 2, 256).unwrap();
+        for key in 0..=127_u8 {
+            forest
+                .insert(usize::from(key % 2), key, value_for(key))
+                .unwrap();
+        }
+    }
+
+    let forest = unsafe { Forest::from_slice(&mut vec).unwrap() };
+    for key in 0..=127 {
+        assert_eq!(forest.get(usize::from(key % 2), &key), Some(value_for(key)));
+    }
+    assert_eq!(forest.len(0), Ok(64));
+    assert_eq!(forest.len(1), Ok(64));
+}
+
+#[test]
+fn space_savings() {
+    let entries = 256;
+    let biggest_value = value_for(0).try_to_vec().unwrap().len();
+    let needed_slots: usize = (0..=255)
+        .map(|key| value_for(key).try_to_vec().unwrap().len().div_ceil(16))
+        .sum();
+
+    let inline_size = forest_size(
+        ForestParams {
+            k_size: 1,
+            v_size: biggest_value,
+            max_roots: 1,
+        },
+        entries,
+    );
+    let mut vec = create_vec(entries, needed_slots, 1);
+    assert!(vec.len() < inline_size / 3);
+
+    let mut forest = Forest::init_slice(&mut vec, 1, entries).unwrap();
+    for key in 0..=255 {
+        assert_eq!(forest.insert(0, key, value_for(key)), Ok(None));
+    }
+    assert_eq!(forest.free_slots(), 0);
+}
+
+#[test]
+fn wrong_arena_size() {
+    let mut vec = vec![0; arena_forest_size(1, 1, 10, 16, 3) + 1];
+    assert_eq!(
+        Forest::init_slice(&mut vec, 1, 10).err(),
+        Some(Error::WrongSliceSize)
+    );
+
+    let mut vec = vec![
+        0;
+        forest_size(
+            ForestParams {
+                k_size: 1,
+                v_size: VALUE_REF_SIZE,
+                max_roots: 1
+            },
+            10
+        ) + 3
+    ];
+    assert_eq!(
+        Forest::init_slice(&mut vec, 1, 10).err(),
+        Some(Error::TooSmall)
+    );
+    assert_eq!(
+        Forest::init_slice(&mut vec, 1, 11).err(),
+        Some(Error::TooSmall)
+    );
+}
+
+#[test]
+fn corrupted_arena() {
+    let mut vec = create_vec(10, 4, 1);
+    Forest::init_slice(&mut vec, 1, 10).unwrap();
+
+    let mut truncated = vec[..vec.len() - 1].to_vec();
+    assert_eq!(
+        unsafe { Forest::from_slice(&mut truncated) }.err(),
+        Some(Error::WrongSliceSize)
+    );
+
+    let mut wrong_magic = vec.clone();
+    wrong_magic[0] = 0;
+    assert_eq!(
+        unsafe { Forest::from_slice(&mut wrong_magic) }.err(),
+        Some(Error::WrongMagic)
+    );
+
+    // The head of the arena free-list follows the forest
+    let head = vec.len() - arena_size(16, 4);
+    vec[head..head + 4].copy_from_slice(&4_u32.to_be_bytes());
+    assert_eq!(
+        unsafe { Forest::from_slice(&mut vec) }.err(),
+        Some(Error::IndexOutOfRange)
+    );
+    vec[head..head + 4].copy_from_slice(&NO_SLOT.to_be_bytes());
+    assert!(unsafe { Forest::from_slice(&mut vec) }.is_ok());
+}
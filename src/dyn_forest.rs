@@ -0,0 +1,473 @@
+//! A forest of Red-Black trees with key and value sizes known only at runtime
+//!
+//! [`RBForest`](super::forest::RBForest) requires `KSIZE` and `VSIZE` to be known at compile time,
+//! which causes code bloat, when a program has to work with a lot of different forests.
+//! [`DynForest`] uses the same binary layout, but reads key and value sizes from the header, so
+//! a single type can work with forests of any sizes. The price is that [`DynForest`] works only with
+//! raw bytes: it is up to the caller to serialize keys and deserialize values.
+//!
+//! Inserts and removes use the same allocator and balancing code as
+//! [`RBForest`](super::forest::RBForest), so both types produce identical slices for the same
+//! sequence of operations.
+//!
+//! Keys are compared lexicographically as raw bytes, so the byte order of the serialized keys
+//! must match the [`Ord`] implementation of the key type (e.g. `[u8; N]` or big-endian encoded
+//! integers).
+//!
+//! ```
+//! use slice_rbtree::dyn_forest::DynForest;
+//! use slice_rbtree::forest::{forest_size, ForestParams, RBForest};
+//!
+//! let params = ForestParams {
+//!     k_size: 3,
+//!     v_size: 7,
+//!     max_roots: 2,
+//! };
+//! let mut buffer = vec![0; forest_size(params, 10)];
+//! let mut forest = RBForest::<[u8; 3], [u8; 7], 3, 7>::init_slice(&mut buffer, 2).unwrap();
+//! forest.insert(0, *b"abc", *b"value\0\0").unwrap();
+//!
+//! let mut forest = unsafe { DynForest::from_slice(&mut buffer) }.unwrap();
+//! assert_eq!(forest.get_bytes(0, b"abc"), Some(b"value\0\0".as_slice()));
+//! // keys and values shorter than `k_size` and `v_size` are padded with zeroes
+//! assert_eq!(forest.insert_bytes(1, b"ab", b"other"), Ok(false));
+//! assert!(forest.contains_key(1, b"ab\0"));
+//! assert!(forest.remove(0, b"abc"));
+//! assert!(forest.is_empty(0));
+//! ```
+use core::cmp::Ordering;
+use core::fmt;
+use core::iter::FusedIterator;
+use core::mem;
+
+use bytemuck::{cast_slice_mut, from_bytes_mut};
+
+use super::forest::{
+    init_forest, node_stride, ForestParams, Header, RawNodes, EMPTY_TREE, TREE_SLOT_SIZE,
+};
+use super::Error;
+
+/// A slice-based forest of Red-Black trees with runtime key and value sizes
+///
+/// See [module](super::dyn_forest) level documentation for more info.
+pub struct DynForest<'a> {
+    header: &'a mut Header,
+    nodes: &'a mut [u8],
+    roots: &'a mut [[u8; 4]],
+    lens: &'a mut [[u8; 4]],
+    k_size: usize,
+    v_size: usize,
+}
+
+impl<'a> DynForest<'a> {
+    /// Initializes [`DynForest`] in a given slice
+    ///
+    /// The resulting slice is identical to the one, initialized with
+    /// [`init_forest`].
+    pub fn init_slice(slice: &'a mut [u8], params: ForestParams) -> Result<Self, Error> {
+        init_forest(params, slice)?;
+
+        // SAFETY: the slice was initialized right above
+        unsafe { Self::from_slice(slice) }
+    }
+
+    /// Returns [`DynForest`], contained in the given slice
+    ///
+    /// # Safety
+    /// This function must be called only on slices, previously initialized as
+    /// [`RBForest`](super::forest::RBForest) using [`init_forest`],
+    /// [`RBForest::init_slice`](super::forest::RBForest::init_slice) or
+    /// [`DynForest::init_slice`]
+    pub unsafe fn from_slice(slice: &'a mut [u8]) -> Result<Self, Error> {
+        if slice.len() <= mem::size_of::<Header>() {
+            return Err(Error::TooSmall);
+        }
+
+        let (header, tail) = slice.split_at_mut(mem::size_of::<Header>());
+        let header: &mut Header = from_bytes_mut(header);
+
+        if !header.check_magic() {
+            return Err(Error::WrongMagic);
+        }
+
+        let max_roots = header.max_roots() as usize;
+        if tail.len() <= max_roots * TREE_SLOT_SIZE {
+            return Err(Error::TooSmall);
+        }
+
+        let (nodes, slots) = tail.split_at_mut(tail.len() - max_roots * TREE_SLOT_SIZE);
+        let (roots, lens) = slots.split_at_mut(max_roots * 4);
+        let roots: &mut [[u8; 4]] = cast_slice_mut(roots);
+        let lens: &mut [[u8; 4]] = cast_slice_mut(lens);
+
+        let k_size = header.k_size() as usize;
+        let v_size = header.v_size() as usize;
+        let node_size = node_stride(k_size, v_size);
+
+        if nodes.len() % node_size != 0 {
+            return Err(Error::WrongSliceSize);
+        }
+
+        if nodes.len() / node_size > EMPTY_TREE as usize {
+            return Err(Error::TooBig);
+        }
+
+        if header.max_nodes() as usize != nodes.len() / node_size {
+            return Err(Error::WrongNodePoolSize);
+        }
+
+        Ok(Self {
+            header,
+            nodes,
+            roots,
+            lens,
+            k_size,
+            v_size,
+        })
+    }
+
+    /// Returns the size of the key buffer
+    #[must_use]
+    pub fn k_size(&self) -> usize {
+        self.k_size
+    }
+
+    /// Returns the size of the value buffer
+    #[must_use]
+    pub fn v_size(&self) -> usize {
+        self.v_size
+    }
+
+    /// Returns the maximum number of trees in the forest
+    #[must_use]
+    pub fn max_roots(&self) -> usize {
+        self.roots.len()
+    }
+
+    /// Returns the number of occupied nodes
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn len(&self, tree_id: usize) -> Result<usize, Error> {
+        if self.roots.len() > tree_id {
+            Ok(self.pairs(tree_id)?.count())
+        } else {
+            Err(Error::TooBigTreeId)
+        }
+    }
+
+    /// Returns `true` if the tree contains no elements
+    #[must_use]
+    pub fn is_empty(&self, tree_id: usize) -> bool {
+        if self.roots.len() > tree_id {
+            self.root(tree_id).is_none()
+        } else {
+            true
+        }
+    }
+
+    /// Returns the number of free nodes
+    ///
+    /// The number is stored in the header, so this function runs in `O(1)`
+    #[must_use]
+    pub fn free_nodes_left(&self) -> usize {
+        self.header.free() as usize
+    }
+
+    /// Returns true if the map contains a value for the specified key
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn contains_key(&self, tree_id: usize, key: &[u8]) -> bool {
+        if self.roots.len() > tree_id {
+            self.get_key_index(tree_id, key).is_some()
+        } else {
+            false
+        }
+    }
+
+    /// Returns the value buffer corresponding to the key
+    ///
+    /// The returned slice always has the length of [`v_size()`](DynForest::v_size).
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn get_bytes(&self, tree_id: usize, key: &[u8]) -> Option<&[u8]> {
+        if self.roots.len() > tree_id {
+            self.get_key_index(tree_id, key).map(|id| self.value(id))
+        } else {
+            None
+        }
+    }
+
+    /// Inserts a new key-value pair and returns `true` if the key was already present
+    ///
+    /// Keys and values shorter than [`k_size()`](DynForest::k_size) and
+    /// [`v_size()`](DynForest::v_size) are padded with zeroes, longer ones are rejected with
+    /// [`Error::KeySerializationError`] and [`Error::ValueSerializationError`] respectively.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn insert_bytes(
+        &mut self,
+        tree_id: usize,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<bool, Error> {
+        if self.roots.len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        if key.len() > self.k_size {
+            return Err(Error::KeySerializationError);
+        }
+
+        if value.len() > self.v_size {
+            return Err(Error::ValueSerializationError);
+        }
+
+        let (id, is_present) = self.put_bytes(tree_id, self.root(tree_id), None, key, value)?;
+        self.set_root(tree_id, Some(id));
+        self.set_is_red(id as usize, false);
+        Ok(is_present)
+    }
+
+    /// Deletes entry and returns `true` if it was present
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn remove(&mut self, tree_id: usize, key: &[u8]) -> bool {
+        if self.roots.len() > tree_id {
+            self.get_key_index(tree_id, key)
+                .map(|id| {
+                    self.delete_node(tree_id, id as usize);
+                })
+                .is_some()
+        } else {
+            false
+        }
+    }
+
+    /// Creates an iterator over key-value buffers, in order by key
+    pub fn pairs<'b>(&'b self, tree_id: usize) -> Result<DynPairsIterator<'b, 'a>, Error> {
+        if self.roots.len() > tree_id {
+            Ok(DynPairsIterator {
+                next_node: self.root(tree_id).map(|root_id| self.min(root_id)),
+                forest: self,
+            })
+        } else {
+            Err(Error::TooBigTreeId)
+        }
+    }
+
+    fn put_bytes(
+        &mut self,
+        tree_id: usize,
+        maybe_id: Option<u32>,
+        parent: Option<u32>,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<(u32, bool), Error> {
+        if let Some(mut id) = maybe_id {
+            let is_present;
+            match cmp_key(key, self.key(id)) {
+                Ordering::Less => {
+                    let (child_id, present) =
+                        self.put_bytes(tree_id, self.left(id as usize), Some(id), key, value)?;
+                    is_present = present;
+                    self.set_left(id as usize, Some(child_id));
+                }
+                Ordering::Greater => {
+                    let (child_id, present) =
+                        self.put_bytes(tree_id, self.right(id as usize), Some(id), key, value)?;
+                    is_present = present;
+                    self.set_right(id as usize, Some(child_id));
+                }
+                Ordering::Equal => {
+                    is_present = true;
+                    write_padded(self.value_mut(id), value);
+                }
+            }
+
+            id = self.balance_after_put(tree_id, id);
+
+            Ok((id, is_present))
+        } else {
+            let new_id = self.allocate_node().ok_or(Error::NoNodesLeft)?;
+            self.invalidate_len(tree_id);
+            self.init_links(new_id, parent);
+            write_padded(self.key_mut(new_id as u32), key);
+            write_padded(self.value_mut(new_id as u32), value);
+
+            Ok((new_id as u32, false))
+        }
+    }
+
+    fn get_key_index(&self, tree_id: usize, key: &[u8]) -> Option<u32> {
+        if key.len() > self.k_size {
+            return None;
+        }
+
+        let mut maybe_id = self.root(tree_id);
+        while let Some(id) = maybe_id {
+            match cmp_key(key, self.key(id)) {
+                Ordering::Equal => return Some(id),
+                Ordering::Less => maybe_id = self.left(id as usize),
+                Ordering::Greater => maybe_id = self.right(id as usize),
+            }
+        }
+        None
+    }
+
+    fn min(&self, mut min_id: u32) -> u32 {
+        while let Some(id) = self.left(min_id as usize) {
+            min_id = id;
+        }
+        min_id
+    }
+
+    fn node_size(&self) -> usize {
+        node_stride(self.k_size, self.v_size)
+    }
+
+    fn node(&self, id: u32) -> &[u8] {
+        let node_size = self.node_size();
+        let start = id as usize * node_size;
+        &self.nodes[start..start + node_size]
+    }
+
+    fn node_mut(&mut self, id: u32) -> &mut [u8] {
+        let node_size = self.node_size();
+        let start = id as usize * node_size;
+        &mut self.nodes[start..start + node_size]
+    }
+
+    fn key(&self, id: u32) -> &[u8] {
+        &self.node(id)[..self.k_size]
+    }
+
+    fn key_mut(&mut self, id: u32) -> &mut [u8] {
+        let k_size = self.k_size;
+        &mut self.node_mut(id)[..k_size]
+    }
+
+    fn value(&self, id: u32) -> &[u8] {
+        &self.node(id)[self.k_size..self.k_size + self.v_size]
+    }
+
+    fn value_mut(&mut self, id: u32) -> &mut [u8] {
+        let entry_size = self.k_size + self.v_size;
+        let k_size = self.k_size;
+        &mut self.node_mut(id)[k_size..entry_size]
+    }
+}
+
+impl<'a> RawNodes for DynForest<'a> {
+    fn header(&self) -> &Header {
+        self.header
+    }
+
+    fn header_mut(&mut self) -> &mut Header {
+        self.header
+    }
+
+    fn links(&self, id: usize) -> &[u8] {
+        &self.node(id as u32)[self.k_size + self.v_size..]
+    }
+
+    fn links_mut(&mut self, id: usize) -> &mut [u8] {
+        let entry_size = self.k_size + self.v_size;
+        &mut self.node_mut(id as u32)[entry_size..]
+    }
+
+    fn swap_entries(&mut self, a: usize, b: usize) {
+        let node_size = self.node_size();
+        for i in 0..self.k_size + self.v_size {
+            self.nodes.swap(a * node_size + i, b * node_size + i);
+        }
+    }
+
+    fn roots(&self) -> &[[u8; 4]] {
+        self.roots
+    }
+
+    fn roots_mut(&mut self) -> &mut [[u8; 4]] {
+        self.roots
+    }
+
+    fn lens_mut(&mut self) -> &mut [[u8; 4]] {
+        self.lens
+    }
+}
+
+/// Copies `bytes` to the beginning of `buffer` and fills the rest of it with zeroes
+fn write_padded(buffer: &mut [u8], bytes: &[u8]) {
+    buffer[..bytes.len()].copy_from_slice(bytes);
+    buffer[bytes.len()..].fill(0);
+}
+
+/// Compares a (possibly shorter) key with a key buffer as if the key was padded with zeroes
+fn cmp_key(key: &[u8], node_key: &[u8]) -> Ordering {
+    let (prefix, padding) = node_key.split_at(key.len());
+    key.cmp(prefix).then_with(|| {
+        if padding.iter().all(|&b| b == 0) {
+            Ordering::Equal
+        } else {
+            Ordering::Less
+        }
+    })
+}
+
+impl<'a> fmt::Debug for DynForest<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.debug_map()
+            .entries((0..self.roots.len()).map(|i| (i, self.pairs(i))))
+            .finish()
+    }
+}
+
+/// An iterator over key-value buffers of [`DynForest`] ordered by key
+pub struct DynPairsIterator<'a, 'b> {
+    next_node: Option<u32>,
+    forest: &'a DynForest<'b>,
+}
+
+impl<'a, 'b> Iterator for DynPairsIterator<'a, 'b> {
+    type Item = (&'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_node.map(|id| {
+            let forest = self.forest;
+
+            // find next
+            if let Some(right_id) = forest.right(id as usize) {
+                self.next_node = Some(forest.min(right_id));
+            } else {
+                self.next_node = None;
+                let mut child_id = id;
+                while let Some(parent_id) = forest.parent(child_id as usize) {
+                    if Some(child_id) == forest.left(parent_id as usize) {
+                        self.next_node = Some(parent_id);
+                        break;
+                    } else {
+                        child_id = parent_id;
+                    }
+                }
+            }
+
+            (forest.key(id), forest.value(id))
+        })
+    }
+}
+
+impl<'a, 'b> FusedIterator for DynPairsIterator<'a, 'b> {}
+
+impl<'a, 'b> fmt::Debug for DynPairsIterator<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        let new_iter = DynPairsIterator {
+            next_node: self.next_node,
+            forest: self.forest,
+        };
+        f.debug_map().entries(new_iter).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,291 @@
+use super::*;
+use crate::forest::tests::{create_vec, INSERT_KEYS};
+use crate::forest::{init_forest, ForestParams, RBForest};
+use pretty_assertions::assert_eq;
+use std::collections::BTreeMap;
+
+const PARAMS: ForestParams = ForestParams {
+    k_size: 3,
+    v_size: 7,
+    max_roots: 2,
+};
+
+fn make_key(i: u8) -> [u8; 3] {
+    [i, i.wrapping_mul(3), 7]
+}
+
+fn make_value(i: u8) -> [u8; 7] {
+    [i; 7]
+}
+
+#[test]
+fn init() {
+    let mut vec = create_vec(3, 7, 10, 2);
+    init_forest(PARAMS, vec.as_mut_slice()).unwrap();
+
+    let forest = unsafe { DynForest::from_slice(vec.as_mut_slice()).unwrap() };
+    assert_eq!(forest.k_size(), 3);
+    assert_eq!(forest.v_size(), 7);
+    assert_eq!(forest.max_roots(), 2);
+    assert_eq!(forest.free_nodes_left(), 10);
+    assert!(forest.is_empty(0));
+    assert!(forest.is_empty(2));
+    assert_eq!(forest.len(2), Err(Error::TooBigTreeId));
+
+    let mut too_small = vec![0; 3];
+    assert_eq!(
+        unsafe { DynForest::from_slice(too_small.as_mut_slice()) }.err(),
+        Some(Error::TooSmall)
+    );
+
+    let mut wrong_magic = vec.clone();
+    wrong_magic[0] = b's';
+    assert_eq!(
+        unsafe { DynForest::from_slice(wrong_magic.as_mut_slice()) }.err(),
+        Some(Error::WrongMagic)
+    );
+
+    let len = vec.len();
+    assert_eq!(
+        unsafe { DynForest::from_slice(&mut vec[..len - 1]) }.err(),
+        Some(Error::WrongSliceSize)
+    );
+
+    let mut vec = create_vec(3, 7, 10, 2);
+    let forest = DynForest::init_slice(vec.as_mut_slice(), PARAMS).unwrap();
+    assert_eq!(forest.free_nodes_left(), 10);
+    assert!(forest.is_empty(1));
+    assert_eq!(
+        DynForest::init_slice(too_small.as_mut_slice(), PARAMS).err(),
+        Some(Error::TooSmall)
+    );
+}
+
+#[test]
+fn insert_get_remove() {
+    let mut vec = create_vec(3, 7, 5, 2);
+    let mut forest = DynForest::init_slice(vec.as_mut_slice(), PARAMS).unwrap();
+
+    assert_eq!(forest.insert_bytes(0, b"abc", b"1234567"), Ok(false));
+    assert_eq!(forest.insert_bytes(0, b"abd", b"12"), Ok(false));
+    assert_eq!(forest.insert_bytes(0, b"ab", b"short"), Ok(false));
+    assert_eq!(forest.insert_bytes(1, b"abc", b"other"), Ok(false));
+    assert_eq!(forest.insert_bytes(0, b"abc", b"new"), Ok(true));
+
+    assert_eq!(forest.get_bytes(0, b"abc"), Some(b"new\0\0\0\0".as_slice()));
+    assert_eq!(forest.get_bytes(0, b"ab\0"), Some(b"short\0\0".as_slice()));
+    assert_eq!(forest.get_bytes(1, b"abc"), Some(b"other\0\0".as_slice()));
+    assert_eq!(forest.get_bytes(0, b"abcd"), None);
+    assert_eq!(forest.get_bytes(1, b"abd"), None);
+    assert_eq!(forest.get_bytes(2, b"abc"), None);
+    assert_eq!(forest.len(0), Ok(3));
+
+    assert_eq!(
+        forest.insert_bytes(0, b"abcd", b"1"),
+        Err(Error::KeySerializationError)
+    );
+    assert_eq!(
+        forest.insert_bytes(0, b"abc", b"12345678"),
+        Err(Error::ValueSerializationError)
+    );
+    assert_eq!(
+        forest.insert_bytes(2, b"abc", b"1"),
+        Err(Error::TooBigTreeId)
+    );
+
+    assert_eq!(forest.insert_bytes(1, b"xyz", b"1"), Ok(false));
+    assert_eq!(forest.free_nodes_left(), 0);
+    assert_eq!(
+        forest.insert_bytes(1, b"xyw", b"1"),
+        Err(Error::NoNodesLeft)
+    );
+
+    assert!(forest.remove(0, b"abc"));
+    assert!(!forest.remove(0, b"abc"));
+    assert!(!forest.remove(2, b"abc"));
+    assert!(!forest.contains_key(0, b"abc"));
+    assert!(forest.contains_key(1, b"abc"));
+    assert_eq!(forest.free_nodes_left(), 1);
+
+    let keys: Vec<_> = forest.pairs(0).unwrap().map(|(k, _)| k.to_vec()).collect();
+    assert_eq!(keys, vec![b"ab\0".to_vec(), b"abd".to_vec()]);
+}
+
+#[test]
+fn random_operations() {
+    let mut vec = create_vec(3, 7, 256, 2);
+    let mut reference = [BTreeMap::new(), BTreeMap::new()];
+    {
+        let mut forest = DynForest::init_slice(vec.as_mut_slice(), PARAMS).unwrap();
+
+        for (i, key) in INSERT_KEYS.iter().enumerate() {
+            let tree_id = i % 2;
+            assert_eq!(
+                forest.insert_bytes(tree_id, &make_key(*key), &make_value(*key)),
+                Ok(false)
+            );
+            reference[tree_id].insert(make_key(*key), make_value(*key));
+        }
+
+        for (i, key) in INSERT_KEYS.iter().enumerate().step_by(3) {
+            let tree_id = i % 2;
+            assert!(forest.remove(tree_id, &make_key(*key)));
+            reference[tree_id].remove(&make_key(*key));
+        }
+
+        for (i, key) in INSERT_KEYS.iter().enumerate().step_by(5) {
+            let tree_id = i % 2;
+            forest
+                .insert_bytes(tree_id, &make_key(*key), &make_value(!*key))
+                .unwrap();
+            reference[tree_id].insert(make_key(*key), make_value(!*key));
+        }
+
+        for (tree_id, reference_tree) in reference.iter().enumerate() {
+            assert!(forest
+                .pairs(tree_id)
+                .unwrap()
+                .eq(reference_tree.iter().map(|(k, v)| (&k[..], &v[..]))));
+        }
+    }
+
+    // The layout is shared with `RBForest`, so its invariant checkers can be used here
+    let forest = unsafe { RBForest::<[u8; 3], [u8; 7], 3, 7>::from_slice(&mut vec).unwrap() };
+    for (tree_id, reference_tree) in reference.iter().enumerate() {
+        assert!(forest.is_balanced(tree_id));
+        assert!(forest.no_double_red(tree_id));
+        assert!(forest.is_child_parent_links_consistent(tree_id));
+        assert!(forest
+            .pairs(tree_id)
+            .unwrap()
+            .eq(reference_tree.clone().into_iter()));
+    }
+}
+
+#[test]
+fn remove_all() {
+    let mut vec = create_vec(3, 7, 256, 2);
+    let mut forest = DynForest::init_slice(vec.as_mut_slice(), PARAMS).unwrap();
+
+    for key in &INSERT_KEYS {
+        forest
+            .insert_bytes(0, &make_key(*key), &make_value(*key))
+            .unwrap();
+    }
+
+    let mut len = INSERT_KEYS.len();
+    for key in INSERT_KEYS.iter().rev() {
+        assert!(forest.remove(0, &make_key(*key)));
+        len -= 1;
+        assert_eq!(forest.len(0), Ok(len));
+    }
+    assert!(forest.is_empty(0));
+    assert_eq!(forest.free_nodes_left(), 256);
+}
+
+#[test]
+fn same_bytes_as_rbforest() {
+    let mut dyn_vec = create_vec(3, 7, 256, 2);
+    let mut vec = create_vec(3, 7, 256, 2);
+    let mut dyn_forest = DynForest::init_slice(dyn_vec.as_mut_slice(), PARAMS).unwrap();
+    let mut forest = RBForest::<[u8; 3], [u8; 7], 3, 7>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    for (i, key) in INSERT_KEYS.iter().enumerate() {
+        dyn_forest
+            .insert_bytes(i % 2, &make_key(*key), &make_value(*key))
+            .unwrap();
+        forest
+            .insert(i % 2, make_key(*key), make_value(*key))
+            .unwrap();
+    }
+    for (i, key) in INSERT_KEYS.iter().enumerate().step_by(3) {
+        assert!(dyn_forest.remove(i % 2, &make_key(*key)));
+        assert!(forest.delete(i % 2, &make_key(*key)));
+    }
+
+    // The generation and the cached lengths are in the slice too, so they are compared as well
+    assert_eq!(dyn_vec, vec);
+}
+
+#[test]
+fn get_bytes() {
+    let mut vec = create_vec(3, 7, 5, 2);
+    {
+        let mut forest =
+            RBForest::<[u8; 3], [u8; 7], 3, 7>::init_slice(vec.as_mut_slice(), 2).unwrap();
+        forest.insert(0, *b"abc", *b"1234567").unwrap();
+        forest.insert(0, *b"abd", *b"12\0\0\0\0\0").unwrap();
+        forest.insert(0, *b"ab\0", *b"short\0\0").unwrap();
+        forest.insert(1, *b"abc", *b"other\0\0").unwrap();
+    }
+
+    let forest = unsafe { DynForest::from_slice(vec.as_mut_slice()).unwrap() };
+    assert_eq!(forest.get_bytes(0, b"abc"), Some(b"1234567".as_slice()));
+    assert_eq!(forest.get_bytes(0, b"ab"), Some(b"short\0\0".as_slice()));
+    assert_eq!(forest.get_bytes(0, b"ab\0"), Some(b"short\0\0".as_slice()));
+    assert_eq!(forest.get_bytes(1, b"abc"), Some(b"other\0\0".as_slice()));
+    assert_eq!(forest.get_bytes(0, b"abcd"), None);
+    assert_eq!(forest.get_bytes(1, b"abd"), None);
+    assert_eq!(forest.get_bytes(2, b"abc"), None);
+    assert_eq!(forest.len(0), Ok(3));
+    assert_eq!(forest.free_nodes_left(), 1);
+
+    assert!(forest.contains_key(0, b"abd"));
+    assert!(!forest.contains_key(0, b"a"));
+    assert!(!forest.contains_key(2, b"abc"));
+
+    let keys: Vec<_> = forest.pairs(0).unwrap().map(|(k, _)| k.to_vec()).collect();
+    assert_eq!(
+        keys,
+        vec![b"ab\0".to_vec(), b"abc".to_vec(), b"abd".to_vec()]
+    );
+}
+
+#[test]
+fn open_rbforest() {
+    let mut vec = create_vec(3, 7, 256, 2);
+    let mut reference = [BTreeMap::new(), BTreeMap::new()];
+    {
+        let mut forest =
+            RBForest::<[u8; 3], [u8; 7], 3, 7>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+        for (i, key) in INSERT_KEYS.iter().enumerate() {
+            let tree_id = i % 2;
+            forest
+                .insert(tree_id, make_key(*key), make_value(*key))
+                .unwrap();
+            reference[tree_id].insert(make_key(*key), make_value(*key));
+        }
+
+        for (i, key) in INSERT_KEYS.iter().enumerate().step_by(3) {
+            let tree_id = i % 2;
+            assert!(forest.delete(tree_id, &make_key(*key)));
+            reference[tree_id].remove(&make_key(*key));
+        }
+    }
+
+    let mut forest = unsafe { DynForest::from_slice(vec.as_mut_slice()).unwrap() };
+    for (tree_id, reference_tree) in reference.iter().enumerate() {
+        assert_eq!(forest.len(tree_id), Ok(reference_tree.len()));
+        assert!(forest
+            .pairs(tree_id)
+            .unwrap()
+            .eq(reference_tree.iter().map(|(k, v)| (&k[..], &v[..]))));
+        for (key, value) in reference_tree {
+            assert_eq!(forest.get_bytes(tree_id, key), Some(value.as_slice()));
+        }
+    }
+    for key in INSERT_KEYS.iter().step_by(3) {
+        assert!(!forest.contains_key(0, &make_key(*key)));
+        assert!(!forest.contains_key(1, &make_key(*key)));
+    }
+
+    // The trees written by `RBForest` can be modified too
+    for (tree_id, reference_tree) in reference.iter().enumerate() {
+        for key in reference_tree.keys() {
+            assert!(forest.remove(tree_id, key));
+        }
+        assert!(forest.is_empty(tree_id));
+    }
+    assert_eq!(forest.free_nodes_left(), 256);
+}
@@ -69,23 +69,44 @@
 // Added this, because this lint ignores, that len() method also has different signature
 #![allow(clippy::len_without_is_empty)]
 
+use borsh::maybestd::boxed::Box;
+use borsh::maybestd::io::Write;
+use borsh::maybestd::vec;
+use borsh::maybestd::vec::Vec;
 use borsh::{BorshDeserialize, BorshSerialize};
-use bytemuck::{cast_mut, cast_slice_mut};
+use bytemuck::{
+    bytes_of, bytes_of_mut, cast_mut, cast_slice, cast_slice_mut, from_bytes, from_bytes_mut,
+};
+use core::any::Any;
 use core::borrow::Borrow;
 use core::cmp::Ord;
 use core::cmp::Ordering;
 use core::fmt;
+use core::iter::StepBy;
 use core::marker::PhantomData;
 use core::mem;
+use core::ops::{Add, Bound, ControlFlow, RangeBounds};
 
 mod internals;
 pub mod iterators;
 
-pub(crate) use internals::{Header, Node};
+pub(crate) use internals::{Header, Node, RawNodes};
 
-use iterators::{KeysIterator, PairsIterator, ValuesIterator};
+use iterators::{
+    KeysIterator, MergedPairsIterator, PairsIterator, TryPairsIterator, ValuesIterator,
+};
 
-use super::Error;
+use super::tree::RBTree;
+use super::{CloneInto, Error};
+
+/// Value of a root pointer of a tree slot, which is not allocated
+pub(crate) const FREE_TREE: u32 = u32::MAX;
+/// Value of a root pointer of an allocated, but empty tree
+pub(crate) const EMPTY_TREE: u32 = u32::MAX - 1;
+/// Value of a cached length of a tree, which has not been computed, see [`RBForest::len_cached`]
+const NO_LEN: u32 = u32::MAX;
+/// Number of bytes per tree slot at the end of the slice: the root pointer and the cached length
+pub(crate) const TREE_SLOT_SIZE: usize = 8;
 
 /// Parameters required to calculate [`RBForest`] size
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
@@ -98,13 +119,68 @@ pub struct ForestParams {
     pub max_roots: usize,
 }
 
+/// Returns the size of a single node in bytes for the given key and value buffer sizes
+///
+/// Nodes are laid out in the slice right after the header one after another, so the node with
+/// index `i` starts at byte `size_of::<Header>() + i * node_stride(k_size, v_size)`.
+#[must_use]
+#[inline]
+pub const fn node_stride(k_size: usize, v_size: usize) -> usize {
+    mem::size_of::<Node<0, 0>>() + k_size + v_size
+}
+
 /// Returns the required size of the slice
 #[must_use]
 #[inline]
 pub const fn forest_size(params: ForestParams, max_nodes: usize) -> usize {
     mem::size_of::<Header>()
-        + (mem::size_of::<Node<0, 0>>() + params.k_size + params.v_size) * max_nodes
-        + 4 * params.max_roots
+        + node_stride(params.k_size, params.v_size) * max_nodes
+        + TREE_SLOT_SIZE * params.max_roots
+}
+
+/// Serializes `item` to the start of `buffer` and returns the length of the encoding
+///
+/// `item` must have been deserialized from a buffer of the same size, so it fits in `buffer` again.
+/// `corrupted` is the panic message for the case when it does not.
+fn serialized_len(item: &impl BorshSerialize, buffer: &mut [u8], corrupted: &str) -> usize {
+    let mut writer = &mut buffer[..];
+    item.serialize(&mut writer).expect(corrupted);
+    let unused = writer.len();
+    buffer.len() - unused
+}
+
+/// Writes `bytes` prefixed with their length as little-endian `u32`
+fn write_frame(out: &mut impl Write, bytes: &[u8]) -> Result<(), Error> {
+    out.write_all(&(bytes.len() as u32).to_le_bytes())
+        .and_then(|()| out.write_all(bytes))
+        .map_err(|_| Error::WriteError)
+}
+
+/// Splits a frame, written by [`write_frame`], off the start of `bytes`
+fn read_frame<'b>(bytes: &mut &'b [u8]) -> Result<&'b [u8], Error> {
+    let len = u32::deserialize(bytes).map_err(|_| Error::DeserializationError)? as usize;
+    if bytes.len() < len {
+        return Err(Error::DeserializationError);
+    }
+    let (frame, tail) = bytes.split_at(len);
+    *bytes = tail;
+    Ok(frame)
+}
+
+/// Version of the format, written by [`RBForest::compute_delta`]
+const DELTA_VERSION: u8 = 1;
+/// Tag of a delta record, which inserts or overwrites an entry
+const DELTA_UPSERT: u8 = 0;
+/// Tag of a delta record, which removes an entry
+const DELTA_REMOVE: u8 = 1;
+
+/// Advances the state of SplitMix64 generator and returns the next pseudo-random number
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
 }
 
 /// Initializes [`RBForest`] in the given slice without returning it
@@ -119,25 +195,27 @@ pub fn init_forest(params: ForestParams, slice: &mut [u8]) -> Result<(), Error>
 
     let (header, tail) = slice.split_at_mut(mem::size_of::<Header>());
 
-    if tail.len() <= params.max_roots * 4 {
+    if tail.len() <= params.max_roots * TREE_SLOT_SIZE {
         return Err(Error::TooSmall);
     }
 
-    let (nodes, roots) = tail.split_at_mut(tail.len() - params.max_roots * 4);
+    let (nodes, slots) = tail.split_at_mut(tail.len() - params.max_roots * TREE_SLOT_SIZE);
+    let (roots, lens) = slots.split_at_mut(params.max_roots * 4);
 
-    if nodes.len() % (mem::size_of::<Node<0, 0>>() + params.k_size + params.v_size) != 0 {
+    let node_size = node_stride(params.k_size, params.v_size);
+
+    if nodes.len() % node_size != 0 {
         return Err(Error::WrongSliceSize);
     }
 
-    if nodes.len() / (mem::size_of::<Node<0, 0>>() + params.k_size + params.v_size)
-        > u32::MAX as usize
-    {
+    if nodes.len() / node_size > EMPTY_TREE as usize {
         return Err(Error::TooBig);
     }
 
     let header: &mut [[u8; mem::size_of::<Header>()]] = cast_slice_mut(header);
     let header: &mut Header = cast_mut(&mut header[0]);
     let roots: &mut [[u8; 4]] = cast_slice_mut(roots);
+    let lens: &mut [[u8; 4]] = cast_slice_mut(lens);
 
     // Allocator initialization
 
@@ -147,8 +225,7 @@ pub fn init_forest(params: ForestParams, slice: &mut [u8]) -> Result<(), Error>
     // Since size_of<Node<k,v>> depends on k and v, which is unknown at compile-time, we can not
     // cast from &[u8] to &[Node<_,_>]. However, Node memory layout is stabilized, so here we will
     // properly initialize nodes by offsetting to the needed fields.
-    let mut nodes =
-        nodes.chunks_exact_mut(mem::size_of::<Node<0, 0>>() + params.k_size + params.v_size);
+    let mut nodes = nodes.chunks_exact_mut(node_size);
 
     let nodes_len = nodes.len() as u32;
 
@@ -167,7 +244,10 @@ pub fn init_forest(params: ForestParams, slice: &mut [u8]) -> Result<(), Error>
 
     // Roots initialization
     for root in roots.iter_mut() {
-        *root = u32::to_be_bytes(u32::MAX);
+        *root = u32::to_be_bytes(FREE_TREE);
+    }
+    for len in lens.iter_mut() {
+        *len = u32::to_be_bytes(NO_LEN);
     }
 
     header.fill(
@@ -180,20 +260,381 @@ pub fn init_forest(params: ForestParams, slice: &mut [u8]) -> Result<(), Error>
     Ok(())
 }
 
+/// Checks if the given slice contains an initialized [`RBForest`]
+///
+/// This function checks the magic string and that the slice size is consistent with the
+/// parameters stored in the header. It does not require `KSIZE` and `VSIZE` to be known, so it can
+/// be used to probe untyped buffers before calling [`RBForest::from_slice`].
+#[must_use]
+pub fn is_forest(slice: &[u8]) -> bool {
+    if slice.len() <= mem::size_of::<Header>() {
+        return false;
+    }
+
+    let (header, tail) = slice.split_at(mem::size_of::<Header>());
+    let header: &Header = from_bytes(header);
+
+    if !header.check_magic() {
+        return false;
+    }
+
+    let roots_len = header.max_roots() as usize * TREE_SLOT_SIZE;
+    if tail.len() <= roots_len {
+        return false;
+    }
+
+    let nodes_len = tail.len() - roots_len;
+    let node_size = node_stride(header.k_size() as usize, header.v_size() as usize);
+
+    nodes_len == node_size * header.max_nodes() as usize
+}
+
+/// Checks if the slice contains an empty forest, exactly as it is left by [`init_forest`] or
+/// [`RBForest::init_slice`]
+///
+/// The sizes are read from the header, then the slice is compared to a copy, initialized anew
+/// with the same parameters: all the tree slots must be free and the free list must be threaded
+/// through all the nodes in the initial order. Key and value buffers are not touched by the
+/// initialization, so they are ignored. This detects buffers, which were written only
+/// partially. Returns `false` if the slice does not pass [`is_forest`].
+///
+/// This function runs in `O(n)` and allocates `O(n)` memory, where `n` - is the length of the
+/// slice
+#[must_use]
+pub fn is_canonically_initialized(slice: &[u8]) -> bool {
+    if !is_forest(slice) {
+        return false;
+    }
+
+    let header: &Header = from_bytes(&slice[..mem::size_of::<Header>()]);
+    let params = ForestParams {
+        k_size: header.k_size() as usize,
+        v_size: header.v_size() as usize,
+        max_roots: header.max_roots() as usize,
+    };
+    let mut expected = slice.to_vec();
+    init_forest(params, &mut expected).is_ok() && expected == slice
+}
+
+/// Checks if two slices contain initialized forests with the same layout
+///
+/// Forests have the same layout if their key sizes, value sizes and maximum numbers of trees are
+/// equal. The number of nodes, the contents and the free list are ignored, so the nodes of such
+/// forests can be copied between them as is. Returns `false` if either slice does not pass
+/// [`is_forest`].
+#[must_use]
+pub fn same_format(a: &[u8], b: &[u8]) -> bool {
+    if !is_forest(a) || !is_forest(b) {
+        return false;
+    }
+
+    let a: &Header = from_bytes(&a[..mem::size_of::<Header>()]);
+    let b: &Header = from_bytes(&b[..mem::size_of::<Header>()]);
+
+    a.k_size() == b.k_size() && a.v_size() == b.v_size() && a.max_roots() == b.max_roots()
+}
+
+/// Part of the slice of [`RBForest`], which contains a given byte, see [`buffer_diff`]
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum ByteRegion {
+    /// the header of the forest
+    Header,
+    /// the node `index`, `offset` is the position of the byte inside the node
+    Node {
+        /// index of the node in the node pool
+        index: u32,
+        /// offset of the byte from the start of the node
+        offset: usize,
+    },
+    /// the root pointer of the tree `tree_id`
+    Root {
+        /// id of the tree
+        tree_id: usize,
+    },
+    /// the cached length of the tree `tree_id`, see [`RBForest::len_cached`]
+    CachedLen {
+        /// id of the tree
+        tree_id: usize,
+    },
+    /// the slice is not a forest, so the byte can not be attributed
+    Unknown,
+}
+
+/// Returns every byte, which differs between `a` and `b`, as `(offset, a_byte, b_byte, region)`
+///
+/// The region is computed from the layout of `a`, so the differences can be traced to the
+/// header, a root pointer, a cached length or a specific node. If `a` does not pass [`is_forest`], all the
+/// regions are [`ByteRegion::Unknown`]. Only the common prefix of the slices is compared, so they
+/// are expected to have the same length.
+///
+/// This function runs in `O(n)`, where `n` - is the length of the slices
+#[must_use]
+pub fn buffer_diff(a: &[u8], b: &[u8]) -> Vec<(usize, u8, u8, ByteRegion)> {
+    let layout = is_forest(a).then(|| {
+        let header: &Header = from_bytes(&a[..mem::size_of::<Header>()]);
+        let stride = node_stride(header.k_size() as usize, header.v_size() as usize);
+        (
+            stride,
+            stride * header.max_nodes() as usize,
+            header.max_roots() as usize,
+        )
+    });
+
+    let region = |offset: usize| {
+        let Some((stride, nodes_len, max_roots)) = layout else {
+            return ByteRegion::Unknown;
+        };
+        let Some(offset) = offset.checked_sub(mem::size_of::<Header>()) else {
+            return ByteRegion::Header;
+        };
+        if offset < nodes_len {
+            ByteRegion::Node {
+                index: (offset / stride) as u32,
+                offset: offset % stride,
+            }
+        } else if offset < nodes_len + 4 * max_roots {
+            ByteRegion::Root {
+                tree_id: (offset - nodes_len) / 4,
+            }
+        } else {
+            ByteRegion::CachedLen {
+                tree_id: (offset - nodes_len - 4 * max_roots) / 4,
+            }
+        }
+    };
+
+    a.iter()
+        .zip(b)
+        .enumerate()
+        .filter(|(_, (a_byte, b_byte))| a_byte != b_byte)
+        .map(|(offset, (&a_byte, &b_byte))| (offset, a_byte, b_byte, region(offset)))
+        .collect()
+}
+
+/// Identifier of the layout of [`RBForest`], see [`RBForest::open_with_schema`]
+///
+/// It is derived from the key size, the value size and the maximum number of trees, so forests
+/// have equal ids if and only if they have the same layout.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct SchemaId(u64);
+
+impl SchemaId {
+    /// Returns the id of forests with the given parameters
+    #[must_use]
+    pub const fn new(params: ForestParams) -> Self {
+        Self(
+            (params.k_size as u16 as u64) << 48
+                | (params.v_size as u16 as u64) << 32
+                | params.max_roots as u32 as u64,
+        )
+    }
+}
+
+/// Parsed contents of the [`RBForest`] header, see [`RBForest::header_snapshot`]
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct HeaderSnapshot {
+    /// key buffer size
+    pub k_size: u16,
+    /// value buffer size
+    pub v_size: u16,
+    /// total number of nodes
+    pub max_nodes: u32,
+    /// maximum number of trees in the forest
+    pub max_roots: u32,
+    /// index of the first node in the list of free nodes
+    pub head: Option<u32>,
+    /// generation of the forest, see [`RBForest::insert_checked`]
+    pub generation: u32,
+}
+
+/// Statistics of the node allocator of [`RBForest`], see [`RBForest::allocator_stats`]
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct AllocatorStats {
+    /// number of free nodes
+    pub free: usize,
+    /// number of nodes, occupied by all the trees
+    pub used: usize,
+    /// total number of nodes
+    pub capacity: usize,
+    /// index of the first node in the list of free nodes
+    pub head: Option<u32>,
+}
+
+/// Head of the free list and roots of all the trees of [`RBForest`], see
+/// [`RBForest::allocator_state`]
+///
+/// Together with a copy of the node pool, this is enough to roll the forest back.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct AllocatorState {
+    head: Option<u32>,
+    free: u32,
+    roots: Vec<[u8; 4]>,
+}
+
+/// Number of entries in all the trees of [`RBForest`] at some moment, see
+/// [`RBForest::checkpoint`]
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Checkpoint {
+    total_len: usize,
+}
+
+/// Total serialized sizes of the entries of a tree, see [`RBForest::size_stats`]
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
+pub struct SizeStats {
+    /// sum of serialized lengths of all the keys
+    pub key_bytes: usize,
+    /// sum of serialized lengths of all the values
+    pub value_bytes: usize,
+    /// number of entries
+    pub entries: usize,
+}
+
+/// Overview of a single tree of [`RBForest`], see [`RBForest::summaries`]
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct TreeSummary<K> {
+    /// id of the tree
+    pub tree_id: usize,
+    /// number of entries
+    pub len: usize,
+    /// the smallest key, `None` for an empty tree
+    pub min_key: Option<K>,
+    /// the biggest key, `None` for an empty tree
+    pub max_key: Option<K>,
+}
+
+/// Cached position of an entry in a tree of [`RBForest`], see [`RBForest::node_ref`]
+///
+/// Node contents are moved around during deletion, so a reference may become stale after the
+/// tree is modified. Stale references are detected by [`RBForest::pairs_from_ref`].
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct NodeRef<const KSIZE: usize> {
+    tree_id: usize,
+    index: u32,
+    key: [u8; KSIZE],
+}
+
+/// Free nodes of [`RBForest`], held back by [`RBForest::reserve_nodes`]
+///
+/// The reservation is a number of nodes, counted in the header of the forest, rather than a set
+/// of particular nodes, so the free list is never modified. Ordinary inserts leave the reserved
+/// number of nodes free, until the reservation is consumed by [`RBForest::extend_reserved`] or
+/// returned by [`RBForest::release_nodes`]. A reservation must be used only with the forest it
+/// was taken from: the token is not tied to the forest, and only a reservation bigger than the
+/// number of reserved nodes is detected, see [`Error::WrongReservation`].
+#[derive(Debug, Eq, PartialEq)]
+#[must_use]
+pub struct NodeReservation {
+    len: usize,
+}
+
+impl NodeReservation {
+    /// Returns the number of reserved nodes
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no nodes are reserved
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Read-only view of a single tree of [`RBForest`], see [`RBForest::split_readonly`]
+///
+/// Views only borrow the forest immutably, so views of different trees (or of the same tree) can
+/// be used from different threads at the same time.
+pub struct RBTreeView<'b, 'a, K, V, const KSIZE: usize, const VSIZE: usize>
+where
+    K: Ord + BorshDeserialize + BorshSerialize,
+    V: BorshDeserialize + BorshSerialize,
+{
+    forest: &'b RBForest<'a, K, V, KSIZE, VSIZE>,
+    tree_id: usize,
+}
+
+/// Order, in which free nodes are allocated, see [`RBForest::init_slice_with`]
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
+pub enum FreeListOrder {
+    /// Nodes are allocated from the start of the slice
+    Ascending,
+    /// Nodes are allocated from the end of the slice, this is the order used by
+    /// [`RBForest::init_slice`] and [`init_forest`]
+    #[default]
+    Descending,
+}
+
+/// Children of a node, which should be visited next, returned by the visitor of [`RBForest::visit`]
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Descend {
+    /// visit both subtrees
+    Both,
+    /// visit only the left subtree, which contains smaller keys
+    Left,
+    /// visit only the right subtree, which contains bigger keys
+    Right,
+    /// skip both subtrees
+    Neither,
+}
+
+/// A single operation of a batch, applied by [`RBForest::apply_batch`]
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum BatchOp<K, V> {
+    /// `Insert(tree_id, key, value)`, see [`RBForest::insert`]
+    Insert(usize, K, V),
+    /// `Remove(tree_id, key)`, see [`RBForest::delete`]
+    Remove(usize, K),
+}
+
+/// Integer-like keys, which can be advanced by a fixed stride, used by
+/// [`RBForest::keys_stepped`], [`RBForest::longest_run`], [`RBForest::present_bitmap`] and
+/// [`RBForest::gaps`]
+pub trait KeyStep: Sized {
+    /// Returns `self + step`, or `None` if the result is not representable
+    fn checked_step(&self, step: &Self) -> Option<Self>;
+
+    /// Returns `self + 1`, or `None` if the result is not representable
+    fn checked_next(&self) -> Option<Self>;
+}
+
+macro_rules! impl_key_step {
+    ($($t:ty),*) => {
+        $(
+            impl KeyStep for $t {
+                fn checked_step(&self, step: &Self) -> Option<Self> {
+                    self.checked_add(*step)
+                }
+
+                fn checked_next(&self) -> Option<Self> {
+                    self.checked_add(1)
+                }
+            }
+        )*
+    };
+}
+
+impl_key_step!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
 /// A slice-based forest of Red-Black trees
 pub struct RBForest<'a, K, V, const KSIZE: usize, const VSIZE: usize>
 where
     K: Ord + BorshDeserialize + BorshSerialize,
     V: BorshDeserialize + BorshSerialize,
 {
-    header: &'a mut Header,
-    nodes: &'a mut [Node<KSIZE, VSIZE>],
-    roots: &'a mut [[u8; 4]],
+    // The whole slice of the forest: the header, the nodes and the roots in this order without
+    // gaps. The parts are borrowed on demand, so the slice can be viewed as a whole.
+    slice: &'a mut [u8],
+    // Number of nodes in the node pool, checked against the header on creation
+    node_count: usize,
     _phantom_key: PhantomData<K>,
     _phantom_value: PhantomData<V>,
     // This field is used to check if new value fits the existing node
     // See put() method
     buffer: [u8; VSIZE],
+    // Number of rotations since the creation or the last reset
+    #[cfg(any(test, fuzzing, feature = "checks"))]
+    rotations: usize,
 }
 
 impl<'a, K, V, const KSIZE: usize, const VSIZE: usize> RBForest<'a, K, V, KSIZE, VSIZE>
@@ -201,22 +642,42 @@ where
     K: Ord + BorshDeserialize + BorshSerialize,
     V: BorshDeserialize + BorshSerialize,
 {
+    /// Size of the key buffer of every node, equal to `KSIZE`
+    pub const KEY_SIZE: usize = KSIZE;
+    /// Size of the value buffer of every node, equal to `VSIZE`
+    pub const VALUE_SIZE: usize = VSIZE;
+
     /// Initializes [`RBForest`] in a given slice
     ///
     /// This function runs in `O(n)` where `n` is a number of nodes, however it is very fast,
     /// because it have to write only 4 bytes per node
     pub fn init_slice(slice: &'a mut [u8], max_roots: usize) -> Result<Self, Error> {
+        Self::init_slice_with(slice, max_roots, FreeListOrder::default())
+    }
+
+    /// Initializes [`RBForest`] in a given slice with the given order of the free list
+    ///
+    /// The order of the free list determines the placement of new nodes in the slice, see
+    /// [`FreeListOrder`].
+    ///
+    /// This function runs in `O(n)` where `n` is a number of nodes
+    pub fn init_slice_with(
+        slice: &'a mut [u8],
+        max_roots: usize,
+        free_list_order: FreeListOrder,
+    ) -> Result<Self, Error> {
         if slice.len() <= mem::size_of::<Header>() {
             return Err(Error::TooSmall);
         }
 
         let (header, tail) = slice.split_at_mut(mem::size_of::<Header>());
 
-        if tail.len() <= max_roots * 4 {
+        if tail.len() <= max_roots * TREE_SLOT_SIZE {
             return Err(Error::TooSmall);
         }
 
-        let (nodes, roots) = tail.split_at_mut(tail.len() - max_roots * 4);
+        let (nodes, slots) = tail.split_at_mut(tail.len() - max_roots * TREE_SLOT_SIZE);
+        let (roots, lens) = slots.split_at_mut(max_roots * 4);
 
         if nodes.len() % mem::size_of::<Node<KSIZE, VSIZE>>() != 0 {
             return Err(Error::WrongSliceSize);
@@ -226,21 +687,41 @@ where
         let header: &mut [[u8; mem::size_of::<Header>()]] = cast_slice_mut(header);
         let header: &mut Header = cast_mut(&mut header[0]);
         let roots: &mut [[u8; 4]] = cast_slice_mut(roots);
+        let lens: &mut [[u8; 4]] = cast_slice_mut(lens);
 
-        if nodes.len() > u32::MAX as usize {
+        if nodes.len() > EMPTY_TREE as usize {
             return Err(Error::TooBig);
         }
 
         // Allocator initialization
-        nodes[0].set_parent(None);
+        let last = nodes.len() - 1;
+        let head = match free_list_order {
+            FreeListOrder::Descending => {
+                nodes[0].set_parent(None);
 
-        for (i, node) in nodes.iter_mut().enumerate().skip(1) {
-            node.set_parent(Some((i - 1) as u32));
-        }
+                for (i, node) in nodes.iter_mut().enumerate().skip(1) {
+                    node.set_parent(Some((i - 1) as u32));
+                }
+
+                last
+            }
+            FreeListOrder::Ascending => {
+                nodes[last].set_parent(None);
+
+                for (i, node) in nodes.iter_mut().enumerate().take(last) {
+                    node.set_parent(Some((i + 1) as u32));
+                }
+
+                0
+            }
+        };
 
         // Roots initialization
         for root in roots.iter_mut() {
-            *root = u32::to_be_bytes(u32::MAX);
+            *root = u32::to_be_bytes(FREE_TREE);
+        }
+        for len in lens.iter_mut() {
+            *len = u32::to_be_bytes(NO_LEN);
         }
 
         header.fill(
@@ -248,16 +729,18 @@ where
             VSIZE as u16,
             nodes.len() as u32,
             max_roots as u32,
-            Some((nodes.len() - 1) as u32),
+            Some(head as u32),
         );
+        let node_count = nodes.len();
 
         Ok(Self {
-            header,
-            nodes,
-            roots,
+            slice,
+            node_count,
             _phantom_key: PhantomData::<K>,
             _phantom_value: PhantomData::<V>,
             buffer: [0; VSIZE],
+            #[cfg(any(test, fuzzing, feature = "checks"))]
+            rotations: 0,
         })
     }
 
@@ -280,12 +763,12 @@ where
             return Err(Error::WrongMagic);
         }
 
-        if tail.len() <= (header.max_roots() as usize) * 4 {
+        if tail.len() <= (header.max_roots() as usize) * TREE_SLOT_SIZE {
             return Err(Error::TooSmall);
         }
 
-        let (nodes, roots) = tail.split_at_mut(tail.len() - (header.max_roots() as usize) * 4);
-        let roots: &mut [[u8; 4]] = cast_slice_mut(roots);
+        let (nodes, _slots) =
+            tail.split_at_mut(tail.len() - (header.max_roots() as usize) * TREE_SLOT_SIZE);
 
         if nodes.len() % mem::size_of::<Node<KSIZE, VSIZE>>() != 0 {
             return Err(Error::WrongSliceSize);
@@ -293,7 +776,7 @@ where
 
         let nodes: &mut [Node<KSIZE, VSIZE>] = cast_slice_mut(nodes);
 
-        if nodes.len() > u32::MAX as usize {
+        if nodes.len() > EMPTY_TREE as usize {
             return Err(Error::TooBig);
         }
 
@@ -308,326 +791,3345 @@ where
         if header.max_nodes() as usize != nodes.len() {
             return Err(Error::WrongNodePoolSize);
         }
+        let node_count = nodes.len();
 
         Ok(Self {
-            header,
-            nodes,
-            roots,
+            slice,
+            node_count,
             _phantom_key: PhantomData::<K>,
             _phantom_value: PhantomData::<V>,
             buffer: [0; VSIZE],
+            #[cfg(any(test, fuzzing, feature = "checks"))]
+            rotations: 0,
+        })
+    }
+
+    /// Returns [`RBForest`], contained in the given slice, if its layout matches `expected`
+    ///
+    /// In addition to the checks of [`from_slice()`](RBForest::from_slice), the maximum number of
+    /// trees is checked, so a buffer of the right size, but with a different split between the
+    /// nodes and the roots, is rejected with [`Error::WrongSchema`].
+    ///
+    /// # Safety
+    /// This function must be called only on slices, previously initialized as [`RBForest`] using
+    /// [`init_forest`] or [`RBForest::init_slice`]
+    pub unsafe fn open_with_schema(slice: &'a mut [u8], expected: SchemaId) -> Result<Self, Error> {
+        let forest = unsafe { Self::from_slice(slice)? };
+        if forest.schema_id() != expected {
+            return Err(Error::WrongSchema);
+        }
+        Ok(forest)
+    }
+
+    /// Returns the identifier of the layout of the forest
+    #[must_use]
+    pub fn schema_id(&self) -> SchemaId {
+        SchemaId::new(ForestParams {
+            k_size: KSIZE,
+            v_size: VSIZE,
+            max_roots: self.max_roots(),
         })
     }
 
+    /// Initializes a new [`RBForest`] in `dst` and moves all the trees there
+    ///
+    /// `dst` must be big enough to hold at least as many nodes as the current forest, and
+    /// `new_max_roots` must not be smaller than [`max_roots()`](RBForest::max_roots). Since node
+    /// layout of both forests is the same, the node pool is copied as is, so all node indices
+    /// are preserved and the additional nodes are just appended to the list of free nodes.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes in `dst`
+    pub fn grow_into<'b>(
+        &self,
+        dst: &'b mut [u8],
+        new_max_roots: usize,
+    ) -> Result<RBForest<'b, K, V, KSIZE, VSIZE>, Error> {
+        if new_max_roots < self.roots().len() {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let mut forest = RBForest::<'b, K, V, KSIZE, VSIZE>::init_slice(dst, new_max_roots)?;
+
+        let old_len = self.nodes().len();
+        if forest.nodes().len() < old_len {
+            return Err(Error::TooSmall);
+        }
+
+        forest.nodes_mut()[..old_len].copy_from_slice(self.nodes());
+        forest.roots_mut()[..self.roots().len()].copy_from_slice(self.roots());
+        forest.lens_mut()[..self.lens().len()].copy_from_slice(self.lens());
+
+        // init_slice() links each node to the previous one, so the first new node is the tail of
+        // the list of new free nodes. Appending the old free list there.
+        if forest.nodes().len() > old_len {
+            forest.nodes_mut()[old_len].set_parent(self.header().head());
+        } else {
+            forest.header_mut().set_head(self.header().head());
+        }
+        let free = self.header().free() as usize + forest.nodes().len() - old_len;
+        forest.header_mut().set_free(free as u32);
+        forest.header_mut().set_reserved(self.header().reserved());
+        forest
+            .header_mut()
+            .set_generation(self.header().generation());
+
+        Ok(forest)
+    }
+
+    /// Initializes a new [`RBTree`] in `dst` and copies the tree `tree_id` there
+    ///
+    /// The tree structure is copied node by node, so nothing is deserialized. Returns
+    /// [`Error::TooSmall`] if `dst` can not hold all the entries of the tree.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes in `dst`
+    pub fn export_tree_into<'b>(
+        &self,
+        tree_id: usize,
+        dst: &'b mut [u8],
+    ) -> Result<RBTree<'b, K, V, KSIZE, VSIZE>, Error> {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let mut tree = RBTree::<'b, K, V, KSIZE, VSIZE>::init_slice(dst)?;
+        if tree.0.nodes().len() < self.size(self.root(tree_id)) {
+            return Err(Error::TooSmall);
+        }
+
+        let root = self.copy_subtree(&mut tree.0, self.root(tree_id), None);
+        tree.0.set_root(0, root);
+        Ok(tree)
+    }
+
     /// Returns the number of occupied nodes
     ///
     /// This function runs in `O(n)`, where `n` - is the number of nodes
     pub fn len(&self, tree_id: usize) -> Result<usize, Error> {
-        if self.roots.len() > tree_id {
+        if self.roots().len() > tree_id {
             Ok(self.size(self.root(tree_id)))
         } else {
             Err(Error::TooBigTreeId)
         }
     }
 
-    /// Returns the maximum number of trees in the forest
+    /// Returns the number of occupied nodes in all the trees
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
     #[must_use]
-    pub fn max_roots(&self) -> usize {
-        self.header.max_roots() as usize
+    pub fn total_len(&self) -> usize {
+        (0..self.roots().len())
+            .map(|tree_id| self.size(self.root(tree_id)))
+            .sum()
     }
 
-    /// Returns the number of free nodes
+    /// Returns the fraction of the backing slice, which holds the keys and values of the entries
+    ///
+    /// The rest of the slice is taken by the header, the roots, the node links and the free
+    /// nodes. The ratio is computed from the full `KSIZE` and `VSIZE` buffers of the occupied
+    /// nodes, so padding inside the buffers is counted as payload. A low ratio of a full forest
+    /// means, that a tighter layout would help.
     ///
     /// This function runs in `O(n)`, where `n` - is the number of nodes
     #[must_use]
-    pub fn free_nodes_left(&self) -> usize {
-        let mut counter = 0;
-        let mut maybe_id = self.header.head();
-        while let Some(id) = maybe_id {
-            counter += 1;
-            maybe_id = self.nodes[id as usize].parent();
+    pub fn payload_ratio(&self) -> f64 {
+        (self.total_len() * (KSIZE + VSIZE)) as f64 / self.slice.len() as f64
+    }
+
+    /// Saves the current [`total_len()`](RBForest::total_len) to measure the growth of the
+    /// forest with [`growth_since()`](RBForest::growth_since)
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            total_len: self.total_len(),
         }
-        counter
     }
 
-    /// Clears the forest
+    /// Returns the signed change of the number of occupied nodes since `checkpoint` was taken
     ///
     /// This function runs in `O(n)`, where `n` - is the number of nodes
-    pub fn clear(&mut self) {
-        // Allocator reinitialization
-        self.nodes[0].set_parent(None);
+    #[must_use]
+    pub fn growth_since(&self, checkpoint: Checkpoint) -> isize {
+        self.total_len() as isize - checkpoint.total_len as isize
+    }
 
-        for (i, node) in self.nodes.iter_mut().enumerate().skip(1) {
-            node.set_parent(Some((i - 1) as u32));
+    /// Returns the number of occupied nodes, caching it in the slice until the tree is modified
+    ///
+    /// The first call computes the length like [`len()`](RBForest::len) does and stores it next
+    /// to the root of the tree, subsequent calls return the stored value until a node is inserted
+    /// into or deleted from the tree or its root is changed. The stored length survives reopening
+    /// the forest with [`from_slice()`](RBForest::from_slice). Storing the length does not advance
+    /// the [`generation()`](RBForest::generation). Returns `0` if the tree does not exist.
+    ///
+    /// This function runs in `O(n)` on a cache miss and in `O(1)` otherwise, where `n` - is the
+    /// number of nodes
+    pub fn len_cached(&mut self, tree_id: usize) -> usize {
+        if self.roots().len() <= tree_id {
+            return 0;
         }
 
-        for tree_id in 0..self.roots.len() {
-            self.set_root(tree_id, None);
+        match u32::from_be_bytes(self.lens()[tree_id]) {
+            NO_LEN => {
+                let len = self.size(self.root(tree_id));
+                self.lens_mut()[tree_id] = u32::to_be_bytes(len as u32);
+                len
+            }
+            len => len as usize,
         }
-        self.header.set_head(Some((self.nodes.len() - 1) as u32));
     }
 
-    /// Returns true if the map contains a value for the specified key
+    /// Returns a read-only view of every tree slot, so that different trees can be read
+    /// concurrently
     ///
-    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    /// All the trees share the node pool, so the forest can not be split into independent
+    /// mutable parts, but reading disjoint trees through shared references is safe. The view at
+    /// index `i` shows the tree `i`, unallocated slots are shown as empty trees.
+    ///
+    /// This function runs in `O(r)`, where `r` - is the maximum number of trees
     #[must_use]
-    pub fn contains_key<Q>(&self, tree_id: usize, k: &Q) -> bool
-    where
-        K: Borrow<Q> + Ord,
-        Q: Ord + ?Sized,
-    {
-        if self.roots.len() > tree_id {
-            self.get_key_index(tree_id, k).is_some()
-        } else {
-            false
-        }
+    pub fn split_readonly<'b>(&'b self) -> Vec<RBTreeView<'b, 'a, K, V, KSIZE, VSIZE>> {
+        (0..self.roots().len())
+            .map(|tree_id| RBTreeView {
+                forest: self,
+                tree_id,
+            })
+            .collect()
     }
 
-    /// Returns a key-value pair corresponding to the supplied key
+    /// Returns the maximum number of trees in the forest
+    #[must_use]
+    pub fn max_roots(&self) -> usize {
+        self.header().max_roots() as usize
+    }
+
+    /// Returns the number of free nodes
     ///
-    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    /// The number is stored in the header, so this function runs in `O(1)`
     #[must_use]
-    pub fn get_entry<Q>(&self, tree_id: usize, k: &Q) -> Option<(K, V)>
-    where
-        K: Borrow<Q> + Ord,
-        Q: Ord + ?Sized,
-    {
-        if self.roots.len() > tree_id {
-            self.get_key_index(tree_id, k).map(|id| {
-                let node = &self.nodes[id];
-                let node_key = K::deserialize(&mut node.key.as_slice()).expect("Key corrupted");
-                let node_value =
-                    V::deserialize(&mut node.value.as_slice()).expect("Value corrupted");
-                (node_key, node_value)
-            })
-        } else {
-            None
-        }
+    pub fn free_nodes_left(&self) -> usize {
+        self.header().free() as usize
     }
 
-    /// Returns the value corresponding to the key
+    /// Returns the whole backing slice of the forest: the header, the nodes and the roots
     ///
-    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    /// This can be used to hash or copy the forest without keeping the original slice around.
     #[must_use]
-    pub fn get<Q>(&self, tree_id: usize, k: &Q) -> Option<V>
-    where
-        K: Borrow<Q> + Ord,
-        Q: Ord + ?Sized,
-    {
-        if self.roots.len() > tree_id {
-            self.get_key_index(tree_id, k).map(|id| {
-                let node = &self.nodes[id];
-                let node_value =
-                    V::deserialize(&mut node.value.as_slice()).expect("Value corrupted");
-                node_value
-            })
-        } else {
-            None
+    pub fn as_bytes(&self) -> &[u8] {
+        self.slice
+    }
+
+    /// Returns a copy of the parsed header of the forest
+    #[must_use]
+    pub fn header_snapshot(&self) -> HeaderSnapshot {
+        HeaderSnapshot {
+            k_size: self.header().k_size(),
+            v_size: self.header().v_size(),
+            max_nodes: self.header().max_nodes(),
+            max_roots: self.header().max_roots(),
+            head: self.header().head(),
+            generation: self.header().generation(),
         }
     }
 
-    /// Inserts a new key-value pair and returns the old value if it was present
+    /// Returns the number of new keys, which could be inserted before the node pool is full
     ///
-    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
-    pub fn insert(&mut self, tree_id: usize, key: K, value: V) -> Result<Option<V>, Error> {
-        if self.roots.len() > tree_id {
-            let result = self.put(tree_id, self.root(tree_id), None, key, value);
-            match result {
+    /// Inserts, which overwrite existing keys, do not consume any capacity. The node pool is shared
+    /// by all the trees of the forest, so inserts to any tree reduce the capacity. It is
+    /// [`free_nodes_left()`](RBForest::free_nodes_left) minus the nodes, held back by
+    /// [`reserve_nodes()`](RBForest::reserve_nodes).
+    ///
+    /// This function runs in `O(1)`
+    #[must_use]
+    pub fn spare_capacity(&self) -> usize {
+        self.free_nodes_left()
+            .saturating_sub(self.header().reserved() as usize)
+    }
+
+    /// Checks that the fields of the header are consistent with each other and with the forest
+    ///
+    /// The key and value sizes must match `KSIZE` and `VSIZE`, the numbers of nodes and trees must
+    /// be non-zero and match the slice, and the head of the free list must point inside the node
+    /// pool. A forest, which fails this check, may panic on use, so it can be used to validate
+    /// untrusted buffers.
+    #[must_use]
+    pub fn header_consistent(&self) -> bool {
+        let max_nodes = self.header().max_nodes();
+        let max_roots = self.header().max_roots();
+        usize::from(self.header().k_size()) == KSIZE
+            && usize::from(self.header().v_size()) == VSIZE
+            && max_nodes > 0
+            && max_nodes as usize == self.nodes().len()
+            && max_roots > 0
+            && max_roots as usize == self.roots().len()
+            && self.header().head().map_or(true, |head| head < max_nodes)
+            && self.header().free() <= max_nodes
+    }
+
+    /// Returns the share of the node pool, occupied by all the trees
+    ///
+    /// The node pool is shared by all the trees, so this is the load of the whole forest.
+    ///
+    /// This function runs in `O(1)`
+    #[must_use]
+    pub fn capacity_pressure(&self) -> f64 {
+        (self.nodes().len() - self.free_nodes_left()) as f64 / self.nodes().len() as f64
+    }
+
+    /// Holds `n` free nodes back, so that they can not be allocated by other trees
+    ///
+    /// The nodes are kept for a later [`extend_reserved()`](RBForest::extend_reserved), which is
+    /// then guaranteed to have room for `n` new keys, or returned with
+    /// [`release_nodes()`](RBForest::release_nodes). Until then, ordinary inserts fail with
+    /// [`Error::NoNodesLeft`] instead of taking the reserved nodes. Returns
+    /// [`Error::NoNodesLeft`] and reserves nothing if the
+    /// [`spare_capacity()`](RBForest::spare_capacity) is less than `n`.
+    ///
+    /// The number of reserved nodes is stored in the header, so it survives reopening the slice.
+    /// Reserved nodes stay in the free list, and no particular indices are reserved.
+    ///
+    /// This function runs in `O(1)`
+    pub fn reserve_nodes(&mut self, n: usize) -> Result<NodeReservation, Error> {
+        if self.spare_capacity() < n {
+            return Err(Error::NoNodesLeft);
+        }
+
+        // `reserved + n` does not exceed the number of free nodes, so it fits in `u32`
+        let reserved = self.header().reserved() as usize + n;
+        self.header_mut().set_reserved(reserved as u32);
+        Ok(NodeReservation { len: n })
+    }
+
+    /// Returns the reserved nodes to the pool of nodes, available for all the trees
+    ///
+    /// Returns [`Error::WrongReservation`] and changes nothing if the reservation is bigger than
+    /// the number of [`reserved_nodes()`](RBForest::reserved_nodes), e.g. if it was taken from
+    /// another forest or the reservations were cancelled.
+    ///
+    /// This function runs in `O(1)`
+    pub fn release_nodes(&mut self, reservation: NodeReservation) -> Result<(), Error> {
+        let reserved = self.header().reserved() as usize;
+        if reservation.len > reserved {
+            return Err(Error::WrongReservation);
+        }
+        self.header_mut()
+            .set_reserved((reserved - reservation.len) as u32);
+        Ok(())
+    }
+
+    /// Returns the number of nodes, held back by [`reserve_nodes()`](RBForest::reserve_nodes)
+    #[must_use]
+    pub fn reserved_nodes(&self) -> usize {
+        self.header().reserved() as usize
+    }
+
+    /// Releases all the reservations of the forest at once
+    ///
+    /// The reservations are stored in the header, so they outlive their [`NodeReservation`]
+    /// tokens. This allows to recover the nodes if the tokens were lost, e.g. if the process,
+    /// which reserved them, has crashed.
+    pub fn cancel_reservations(&mut self) {
+        self.header_mut().set_reserved(0);
+    }
+
+    /// Inserts all the pairs of `iter` into the tree, using the reserved nodes first
+    ///
+    /// The reservation is released right before the insertion, so the first `reservation.len()`
+    /// new keys are guaranteed to fit. The nodes, left unused, become available for all the
+    /// trees. Further new keys take the nodes of the free list as usual and may fail with
+    /// [`Error::NoNodesLeft`], in which case the pairs before the failed one stay inserted.
+    /// Returns [`Error::WrongReservation`] before inserting anything if the reservation can not be
+    /// released, see [`release_nodes()`](RBForest::release_nodes).
+    ///
+    /// This function runs in `O(m * log(n))`, where `n` - is the number of nodes and `m` - is the
+    /// number of pairs
+    pub fn extend_reserved<I>(
+        &mut self,
+        tree_id: usize,
+        reservation: NodeReservation,
+        iter: I,
+    ) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        self.release_nodes(reservation)?;
+        for (key, value) in iter {
+            self.insert(tree_id, key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if `additional` new keys would not fit in the node pool
+    ///
+    /// The nodes, held back by [`reserve_nodes()`](RBForest::reserve_nodes), are not available.
+    ///
+    /// This function runs in `O(1)`
+    #[must_use]
+    pub fn would_overflow(&self, additional: usize) -> bool {
+        additional > self.spare_capacity()
+    }
+
+    /// Returns statistics of the node allocator
+    ///
+    /// `free + used == capacity` always holds.
+    ///
+    /// This function runs in `O(1)`
+    #[must_use]
+    pub fn allocator_stats(&self) -> AllocatorStats {
+        let free = self.free_nodes_left();
+        AllocatorStats {
+            free,
+            used: self.nodes().len() - free,
+            capacity: self.nodes().len(),
+            head: self.header().head(),
+        }
+    }
+
+    /// Returns the head of the free list and the roots of all the trees
+    ///
+    /// This function runs in `O(r)`, where `r` - is the maximum number of trees
+    #[must_use]
+    pub fn allocator_state(&self) -> AllocatorState {
+        AllocatorState {
+            head: self.header().head(),
+            free: self.header().free(),
+            roots: self.roots().to_vec(),
+        }
+    }
+
+    /// Restores the head of the free list and the roots of all the trees, saved with
+    /// [`allocator_state()`](RBForest::allocator_state)
+    ///
+    /// The nodes are not touched, so the node pool must be restored separately (e.g. by copying
+    /// the raw bytes back), otherwise the forest becomes inconsistent. Returns
+    /// [`Error::WrongMaxRoots`] if the state was saved from a forest with another number of
+    /// trees and [`Error::IndexOutOfRange`] if some index does not fit in the node pool. In both
+    /// cases the forest is left untouched.
+    ///
+    /// This function runs in `O(r)`, where `r` - is the maximum number of trees
+    pub fn restore_allocator_state(&mut self, state: AllocatorState) -> Result<(), Error> {
+        if state.roots.len() != self.roots().len() {
+            return Err(Error::WrongMaxRoots);
+        }
+        let is_valid = |id: u32| (id as usize) < self.nodes().len();
+        let roots_valid = state.roots.iter().all(|root| {
+            let root = u32::from_be_bytes(*root);
+            root == FREE_TREE || root == EMPTY_TREE || is_valid(root)
+        });
+        if !roots_valid
+            || !state.head.map_or(true, is_valid)
+            || state.free as usize > self.nodes().len()
+        {
+            return Err(Error::IndexOutOfRange);
+        }
+
+        self.roots_mut().copy_from_slice(&state.roots);
+        self.invalidate_lens();
+        self.header_mut().set_head(state.head);
+        self.header_mut().set_free(state.free);
+        self.bump_generation();
+        Ok(())
+    }
+
+    /// Clears the forest
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn clear(&mut self) {
+        // Allocator reinitialization
+        self.nodes_mut()[0].set_parent(None);
+
+        for (i, node) in self.nodes_mut().iter_mut().enumerate().skip(1) {
+            node.set_parent(Some((i - 1) as u32));
+        }
+
+        for tree_id in 0..self.roots().len() {
+            self.set_root(tree_id, None);
+        }
+        let last = self.nodes().len() - 1;
+        self.header_mut().set_head(Some(last as u32));
+        self.header_mut().set_free(last as u32 + 1);
+    }
+
+    /// Clears the forest and zero-fills the key and value buffers of all the nodes
+    ///
+    /// [`clear()`](RBForest::clear) leaves the old keys and values in the nodes until they are
+    /// reused, so this should be used if the entries must not linger in the slice, e.g. if they
+    /// contain secrets. The resulting free list is the same as after `clear()`.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn clear_zeroed(&mut self) {
+        self.clear();
+        for node in self.nodes_mut().iter_mut() {
+            node.key.fill(0);
+            node.value.fill(0);
+        }
+    }
+
+    /// Detaches all the trees without touching the nodes
+    ///
+    /// This is a building block for recovery, when the node pool is intact, but the roots are
+    /// clobbered. Unlike [`clear()`](RBForest::clear), the free list is not rethreaded, so the
+    /// nodes of the detached trees are neither in a tree nor in the free list, until they are
+    /// reclaimed by [`rebuild_free_list()`](RBForest::rebuild_free_list).
+    ///
+    /// This function runs in `O(r)`, where `r` - is the maximum number of trees
+    pub fn reset_roots(&mut self) {
+        for tree_id in 0..self.roots().len() {
+            self.set_root(tree_id, None);
+        }
+    }
+
+    /// Rethreads the free list through all the nodes, which are not reachable from the roots, and
+    /// returns the number of free nodes
+    ///
+    /// This reclaims nodes, which were leaked by a corrupted free list or detached by
+    /// [`reset_roots()`](RBForest::reset_roots). The free list is ordered as in a freshly
+    /// initialized forest, so the nodes with bigger indices are allocated first.
+    ///
+    /// This function runs in `O(n)` and allocates `O(N)` memory, where `n` - is the number of
+    /// occupied nodes and `N` - is the total number of nodes
+    pub fn rebuild_free_list(&mut self) -> usize {
+        let mut ids = Vec::new();
+        for tree_id in 0..self.roots().len() {
+            self.collect_inorder(self.root(tree_id), &mut ids);
+        }
+        let mut is_live = Vec::new();
+        is_live.resize(self.nodes().len(), false);
+        for id in ids {
+            is_live[id as usize] = true;
+        }
+
+        let mut head = None;
+        let mut free = 0;
+        for (id, node) in self.nodes_mut().iter_mut().enumerate() {
+            if !is_live[id] {
+                node.set_parent(head);
+                head = Some(id as u32);
+                free += 1;
+            }
+        }
+        self.header_mut().set_head(head);
+        self.header_mut().set_free(free as u32);
+        free
+    }
+
+    /// Moves all the occupied nodes by `delta` positions in the node pool, rewriting the links
+    ///
+    /// Nodes with index `i` are moved to `i + delta`, and all the child, parent and root links
+    /// are shifted accordingly, so the trees stay the same. The free list is rebuilt from the
+    /// nodes, which are left unoccupied (see [`rebuild_free_list()`](RBForest::rebuild_free_list)).
+    /// This allows to free a region of the pool, e.g. to splice the nodes of another buffer
+    /// there. Returns [`Error::IndexOutOfRange`] and leaves the forest untouched if some node
+    /// would leave the pool.
+    ///
+    /// This function runs in `O(N)` and allocates `O(N)` memory, where `N` - is the total number
+    /// of nodes
+    pub fn rebase_indices(&mut self, delta: i64) -> Result<(), Error> {
+        let mut ids = Vec::new();
+        for tree_id in 0..self.roots().len() {
+            self.collect_inorder(self.root(tree_id), &mut ids);
+        }
+
+        let pool_size = self.nodes().len() as i64;
+        let shift = |id: u32| -> Result<u32, Error> {
+            let new_id = i64::from(id) + delta;
+            if (0..pool_size).contains(&new_id) {
+                Ok(new_id as u32)
+            } else {
+                Err(Error::IndexOutOfRange)
+            }
+        };
+        for &id in &ids {
+            shift(id)?;
+        }
+
+        let mut moved = Vec::with_capacity(ids.len());
+        for id in ids {
+            let mut node = self.nodes()[id as usize];
+            node.set_left(node.left().map(shift).transpose()?);
+            node.set_right(node.right().map(shift).transpose()?);
+            node.set_parent(node.parent().map(shift).transpose()?);
+            moved.push((shift(id)?, node));
+        }
+        for (id, node) in moved {
+            self.nodes_mut()[id as usize] = node;
+        }
+        for tree_id in 0..self.roots().len() {
+            if let Some(root) = self.root(tree_id) {
+                self.set_root(tree_id, Some(shift(root)?));
+            }
+        }
+
+        self.rebuild_free_list();
+        Ok(())
+    }
+
+    /// Returns the id of the first tree, which contains `k`, or `None` if no tree contains it
+    ///
+    /// This allows to keep keys unique across all the trees, e.g. if the trees are partitions of
+    /// a single index.
+    ///
+    /// This function runs in `O(r * log(n))`, where `r` - is the maximum number of trees and
+    /// `n` - is the number of nodes
+    #[must_use]
+    pub fn key_exists_anywhere<Q>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        (0..self.roots().len()).find(|&tree_id| self.get_key_index(tree_id, k).is_some())
+    }
+
+    /// Returns true if the map contains a value for the specified key
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn contains_key<Q>(&self, tree_id: usize, k: &Q) -> bool
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() > tree_id {
+            self.get_key_index(tree_id, k).is_some()
+        } else {
+            false
+        }
+    }
+
+    /// Returns true if the map contains a value for the key with the given serialized
+    /// representation
+    ///
+    /// Key buffers are compared lexicographically as raw bytes, so this method gives correct
+    /// results only if the byte order of the serialized keys matches the [`Ord`] implementation
+    /// of `K` (e.g. for `[u8; N]` keys). Returns `false` if `key_bytes.len() != KSIZE`.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn contains_key_bytes(&self, tree_id: usize, key_bytes: &[u8]) -> bool {
+        if self.roots().len() > tree_id && key_bytes.len() == KSIZE {
+            self.get_key_bytes_index(tree_id, key_bytes).is_some()
+        } else {
+            false
+        }
+    }
+
+    /// Looks up raw value buffers for a batch of serialized keys
+    ///
+    /// For each `i`, `out[i]` is set to the raw value buffer of the key `keys[i]` or to `None` if
+    /// the key is absent. Only the first `min(keys.len(), out.len())` keys are looked up. Returns
+    /// the number of found keys.
+    ///
+    /// Nothing is deserialized and key buffers are compared lexicographically as raw bytes, so the
+    /// same restrictions as for [`contains_key_bytes()`](RBForest::contains_key_bytes) apply.
+    ///
+    /// This function runs in `O(m * log(n))`, where `m` - is the number of keys and `n` - is the
+    /// number of nodes
+    pub fn get_bytes_many<'b>(
+        &'b self,
+        tree_id: usize,
+        keys: &[&[u8]],
+        out: &mut [Option<&'b [u8]>],
+    ) -> usize {
+        let is_tree_valid = self.roots().len() > tree_id;
+        let mut hits = 0;
+        for (key_bytes, value) in keys.iter().zip(out.iter_mut()) {
+            *value = if is_tree_valid && key_bytes.len() == KSIZE {
+                self.get_key_bytes_index(tree_id, key_bytes)
+                    .map(|id| self.nodes()[id].value.as_slice())
+            } else {
+                None
+            };
+            if value.is_some() {
+                hits += 1;
+            }
+        }
+        hits
+    }
+
+    /// Returns a key-value pair corresponding to the supplied key
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn get_entry<Q>(&self, tree_id: usize, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() > tree_id {
+            self.get_key_index(tree_id, k).map(|id| {
+                let node = &self.nodes()[id];
+                let node_key = K::deserialize(&mut node.key.as_slice()).expect("Key corrupted");
+                let node_value =
+                    V::deserialize(&mut node.value.as_slice()).expect("Value corrupted");
+                (node_key, node_value)
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the value corresponding to the key
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn get<Q>(&self, tree_id: usize, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() > tree_id {
+            self.get_key_index(tree_id, k).map(|id| {
+                let node = &self.nodes()[id];
+                let node_value =
+                    V::deserialize(&mut node.value.as_slice()).expect("Value corrupted");
+                node_value
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the value corresponding to the owned key
+    ///
+    /// This is the same as [`get()`](RBForest::get), but takes the key by value, which is
+    /// convenient when no suitable borrowed form of the key is at hand.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn get_owned(&self, tree_id: usize, k: K) -> Option<V> {
+        self.get(tree_id, &k)
+    }
+
+    /// Returns the value associated with the given key, without panicking on corrupted data
+    ///
+    /// Unlike [`get()`](RBForest::get), returns [`Error::KeySerializationError`] or
+    /// [`Error::ValueSerializationError`] if a key or the value can not be deserialized.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn try_get<Q>(&self, tree_id: usize, k: &Q) -> Result<Option<V>, Error>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        match self.try_get_key_index(tree_id, k)? {
+            Some(id) => V::deserialize(&mut self.nodes()[id].value.as_slice())
+                .map(Some)
+                .map_err(|_| Error::ValueSerializationError),
+            None => Ok(None),
+        }
+    }
+
+    /// Compares the beginning of the raw value buffer of the given key with `expected`
+    ///
+    /// Returns `None` if there is no such key, otherwise returns `Some(true)` if the value buffer
+    /// starts with `expected`. The value is not deserialized, so `expected` should be a Borsh
+    /// encoding of the value (a prefix of it) to be meaningful.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn value_bytes_eq<Q>(&self, tree_id: usize, k: &Q, expected: &[u8]) -> Option<bool>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() > tree_id {
+            self.get_key_index(tree_id, k)
+                .map(|id| self.nodes()[id].value.starts_with(expected))
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if `k` is present and its value equals `v`, so inserting `v` would not
+    /// change the tree
+    ///
+    /// This allows to skip redundant writes, e.g. if each modified account costs a fee.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn is_unchanged_by<Q>(&self, tree_id: usize, k: &Q, v: &V) -> bool
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+        V: PartialEq,
+    {
+        self.get(tree_id, k).is_some_and(|value| value == *v)
+    }
+
+    /// Inserts a new key-value pair and returns the old value if it was present
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn insert(&mut self, tree_id: usize, key: K, value: V) -> Result<Option<V>, Error> {
+        if self.roots().len() > tree_id {
+            let result = self.put(tree_id, self.root(tree_id), None, key, value, None, None);
+            match result {
                 Ok((id, old_val)) => {
                     self.set_root(tree_id, Some(id));
-                    self.nodes[id as usize].set_is_red(false);
+                    self.nodes_mut()[id as usize].set_is_red(false);
                     Ok(old_val)
                 }
                 Err(e) => Err(e),
             }
-        } else {
-            Err(Error::TooBigTreeId)
+        } else {
+            Err(Error::TooBigTreeId)
+        }
+    }
+
+    /// Returns the generation of the forest, which is incremented (wrapping on overflow) by every
+    /// modification of the trees
+    ///
+    /// The generation is stored in the header, so it is shared by all the users of the slice. All
+    /// the modifying methods advance it, possibly by more than one per call, while reservations
+    /// and the order of the free list do not affect it. A failed call may also advance it, which
+    /// only leads to a spurious [`Error::VersionConflict`] in
+    /// [`insert_checked()`](RBForest::insert_checked).
+    #[must_use]
+    pub fn generation(&self) -> u32 {
+        self.header().generation()
+    }
+
+    /// Inserts a new key-value pair if the generation of the forest equals `expected_gen`
+    ///
+    /// On success the generation is advanced and the old value is returned like in
+    /// [`insert()`](RBForest::insert). If the generation differs, the forest is left unchanged and
+    /// [`Error::VersionConflict`] is returned, so several writers can coordinate optimistically:
+    /// read [`generation()`](RBForest::generation), prepare the change and retry on conflict.
+    /// Since every modifying method advances the generation, writers, which do not check it, are
+    /// detected too.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn insert_checked(
+        &mut self,
+        tree_id: usize,
+        key: K,
+        value: V,
+        expected_gen: u32,
+    ) -> Result<Option<V>, Error> {
+        if self.header().generation() != expected_gen {
+            return Err(Error::VersionConflict);
+        }
+        self.insert(tree_id, key, value)
+    }
+
+    /// Replaces the value of an existing key and returns the old value
+    ///
+    /// Unlike [`insert()`](RBForest::insert), a new entry is never created: if there is no such
+    /// key, `Ok(None)` is returned and the tree is left unchanged. If the new value can not be
+    /// serialized, [`Error::ValueSerializationError`] is returned and the old value is kept.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn replace<Q>(&mut self, tree_id: usize, k: &Q, value: V) -> Result<Option<V>, Error>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let Some(id) = self.get_key_index(tree_id, k) else {
+            return Ok(None);
+        };
+        let old_value = self.entry_at(id).1;
+        self.write_value(id, &value)?;
+        Ok(Some(old_value))
+    }
+
+    /// Checks that `key` and `value` fit into the node buffers without modifying the forest
+    ///
+    /// Returns the same serialization errors as [`insert()`](RBForest::insert) would:
+    /// [`Error::KeySerializationError`] if the key does not fit into `KSIZE` bytes and
+    /// [`Error::ValueSerializationError`] if the value does not fit into `VSIZE` bytes.
+    pub fn would_fit(&self, key: &K, value: &V) -> Result<(), Error> {
+        let mut key_buffer = [0; KSIZE];
+        if key.serialize(&mut key_buffer.as_mut_slice()).is_err() {
+            return Err(Error::KeySerializationError);
+        }
+
+        let mut value_buffer = [0; VSIZE];
+        if value.serialize(&mut value_buffer.as_mut_slice()).is_err() {
+            return Err(Error::ValueSerializationError);
+        }
+
+        Ok(())
+    }
+
+    /// Predicts if inserting `k` would require rebalancing of the tree
+    ///
+    /// A new key is inserted as a red leaf. Since the tree is left-leaning, it can be left as is
+    /// only if it becomes the left child of a black node, otherwise rotations or color flips are
+    /// required. Overwriting an existing key never rebalances the tree. This is a hint for
+    /// latency-sensitive code: a rebalance may be just a single color flip.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn insert_causes_rebalance<Q>(&self, tree_id: usize, k: &Q) -> bool
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() <= tree_id {
+            return false;
+        }
+
+        let mut maybe_id = self.root(tree_id);
+        while let Some(id) = maybe_id {
+            let node = &self.nodes()[id as usize];
+            let node_key = self.entry_key(id as usize);
+            match k.cmp(node_key.borrow()) {
+                Ordering::Equal => return false,
+                Ordering::Less => match node.left() {
+                    Some(left_id) => maybe_id = Some(left_id),
+                    None => return node.is_red(),
+                },
+                Ordering::Greater => match node.right() {
+                    Some(right_id) => maybe_id = Some(right_id),
+                    None => return true,
+                },
+            }
+        }
+
+        false
+    }
+
+    /// Inserts raw key and value buffers and returns the old value buffer if the key was present
+    ///
+    /// This is a fast path for fixed-width data: nothing is serialized or deserialized, and the
+    /// buffers are stored as is. Keys are compared lexicographically as raw bytes, so this method
+    /// may be used only if the byte order of the serialized keys matches the [`Ord`]
+    /// implementation of `K` (e.g. for `[u8; N]` keys), otherwise the tree becomes inconsistent
+    /// for typed methods. Note, that Borsh encodes integers as little-endian, so their byte order
+    /// does not match the numeric one.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn insert_arrays(
+        &mut self,
+        tree_id: usize,
+        key: [u8; KSIZE],
+        value: [u8; VSIZE],
+    ) -> Result<Option<[u8; VSIZE]>, Error> {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let (id, old_val) = self.put_bytes(tree_id, self.root(tree_id), None, &key, &value)?;
+        self.set_root(tree_id, Some(id));
+        self.nodes_mut()[id as usize].set_is_red(false);
+        Ok(old_val)
+    }
+
+    /// Inserts a new key-value pair only if the key is absent
+    ///
+    /// Unlike [`insert()`](RBForest::insert), an existing entry is never overwritten: if the key
+    /// is already present, the tree is left unchanged and the stored value is returned. `Ok(None)`
+    /// means that the new pair was inserted.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn insert_no_overwrite(
+        &mut self,
+        tree_id: usize,
+        key: K,
+        value: V,
+    ) -> Result<Option<V>, Error> {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        if let Some(id) = self.get_key_index(tree_id, &key) {
+            let (_, existing) = self.entry_at(id);
+            return Ok(Some(existing));
+        }
+
+        self.insert(tree_id, key, value)
+    }
+
+    /// Inserts pairs of keys and values, taken from two parallel slices, and returns the number of
+    /// inserted new keys
+    ///
+    /// `keys[i]` is inserted with `values[i]`; overwritten keys are not counted. The pairs are
+    /// inserted one by one, so if an error (e.g. [`Error::NoNodesLeft`]) occurs in the middle,
+    /// the pairs before the failed one stay inserted.
+    ///
+    /// Returns [`Error::LengthMismatch`] if `keys` and `values` have different lengths, in this
+    /// case nothing is inserted.
+    ///
+    /// This function runs in `O(m * log(n))`, where `n` - is the number of nodes and `m` - is the
+    /// number of pairs
+    pub fn insert_columns(
+        &mut self,
+        tree_id: usize,
+        keys: &[K],
+        values: &[V],
+    ) -> Result<usize, Error>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+        if keys.len() != values.len() {
+            return Err(Error::LengthMismatch);
+        }
+
+        let mut inserted = 0;
+        for (key, value) in keys.iter().zip(values) {
+            if self.insert(tree_id, key.clone(), value.clone())?.is_none() {
+                inserted += 1;
+            }
+        }
+        Ok(inserted)
+    }
+
+    /// Inserts all the pairs of `iter` and returns the previous value for every inserted key
+    ///
+    /// The report contains `(key, Some(old_value))` for overwritten keys and `(key, None)` for the
+    /// new ones, in the order of insertion. The pairs are inserted one by one, so if an error (e.g.
+    /// [`Error::NoNodesLeft`]) occurs in the middle, the pairs before the failed one stay
+    /// inserted, and the error is returned together with their report.
+    ///
+    /// This function runs in `O(m * log(n))`, where `n` - is the number of nodes and `m` - is the
+    /// number of pairs
+    #[allow(clippy::type_complexity)]
+    pub fn insert_many_report<I>(
+        &mut self,
+        tree_id: usize,
+        iter: I,
+    ) -> Result<Vec<(K, Option<V>)>, (Error, Vec<(K, Option<V>)>)>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Clone,
+    {
+        let mut report = Vec::new();
+        if self.roots().len() <= tree_id {
+            return Err((Error::TooBigTreeId, report));
+        }
+
+        for (key, value) in iter {
+            match self.insert(tree_id, key.clone(), value) {
+                Ok(old_value) => report.push((key, old_value)),
+                Err(err) => return Err((err, report)),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Inserts placeholder entries with the default value for the keys, which are not present yet
+    ///
+    /// This front-loads the structural work: later [`replace()`](RBForest::replace) calls on these
+    /// keys overwrite the values in place and never rebalance the tree, so their latency is
+    /// predictable. Values of the keys, which are already present, are left unchanged. If an error
+    /// occurs in the middle, the placeholders before the failed one stay inserted.
+    ///
+    /// This function runs in `O(m * log(n))`, where `n` - is the number of nodes and `m` - is the
+    /// number of keys
+    pub fn warm_up(&mut self, tree_id: usize, keys: &[K]) -> Result<(), Error>
+    where
+        K: Clone,
+        V: Default,
+    {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        for key in keys {
+            if !self.contains_key(tree_id, key) {
+                self.insert(tree_id, key.clone(), V::default())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Inserts a new key-value pair, evicting the entry with the smallest key if there are no
+    /// free nodes left
+    ///
+    /// This is intended for bounded caches, which keep the biggest keys. Returns the evicted pair,
+    /// or `None` if nothing was evicted. If the node pool is full and `key` is smaller than all
+    /// the keys of the tree, the insertion is rejected and the new pair is returned back. If `key`
+    /// is already present, its value is replaced without eviction and `None` is returned.
+    ///
+    /// Returns [`Error::NoNodesLeft`] if the node pool is full and the tree is empty, so there is
+    /// nothing to evict. The new pair is checked with [`would_fit()`](RBForest::would_fit) before
+    /// the eviction, so a serialization error leaves the tree untouched.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn insert_bounded(
+        &mut self,
+        tree_id: usize,
+        key: K,
+        value: V,
+    ) -> Result<Option<(K, V)>, Error> {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        if self.header().free() > self.header().reserved() || self.contains_key(tree_id, &key) {
+            self.insert(tree_id, key, value)?;
+            return Ok(None);
+        }
+
+        let (min_key, min_value) = self.first_entry(tree_id).ok_or(Error::NoNodesLeft)?;
+        if key < min_key {
+            return Ok(Some((key, value)));
+        }
+
+        self.would_fit(&key, &value)?;
+        self.delete(tree_id, &min_key);
+        self.insert(tree_id, key, value)?;
+        Ok(Some((min_key, min_value)))
+    }
+
+    /// Applies a batch of operations in order, either all of them or none
+    ///
+    /// The batch is validated before any modification is made, so if an error is returned, the
+    /// forest is left exactly as it was before the call. The following errors are detected:
+    /// - [`Error::TooBigTreeId`] if any operation refers to a nonexistent tree;
+    /// - [`Error::KeySerializationError`] or [`Error::ValueSerializationError`] if any inserted
+    ///   key or value does not fit its buffer;
+    /// - [`Error::NoNodesLeft`] if the number of `Insert` operations with keys, absent from their
+    ///   trees before the batch, exceeds [`spare_capacity()`](RBForest::spare_capacity).
+    ///
+    /// The capacity check is conservative: nodes freed by `Remove` operations of the same batch
+    /// are not taken into account, and repeated inserts of the same new key are counted
+    /// separately.
+    ///
+    /// This function runs in `O(m * log(n) + n)`, where `m` - is the number of operations and `n` -
+    /// is the number of nodes
+    pub fn apply_batch(&mut self, ops: &[BatchOp<K, V>]) -> Result<(), Error>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut new_nodes = 0;
+        for op in ops {
+            match op {
+                BatchOp::Insert(tree_id, key, value) => {
+                    if self.roots().len() <= *tree_id {
+                        return Err(Error::TooBigTreeId);
+                    }
+
+                    self.would_fit(key, value)?;
+
+                    if !self.contains_key(*tree_id, key) {
+                        new_nodes += 1;
+                    }
+                }
+                BatchOp::Remove(tree_id, _) => {
+                    if self.roots().len() <= *tree_id {
+                        return Err(Error::TooBigTreeId);
+                    }
+                }
+            }
+        }
+
+        if new_nodes > self.spare_capacity() {
+            return Err(Error::NoNodesLeft);
+        }
+
+        for op in ops {
+            match op {
+                BatchOp::Insert(tree_id, key, value) => {
+                    self.insert(*tree_id, key.clone(), value.clone())?;
+                }
+                BatchOp::Remove(tree_id, key) => {
+                    self.delete(*tree_id, key);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Marks the first free tree slot as allocated and returns its `tree_id`
+    ///
+    /// Allocation state is stored in the root pointer of a tree, so it does not require any
+    /// additional space. A tree is also allocated implicitly by the first insertion into it.
+    /// Returns [`Error::NoTreesLeft`] if all the tree slots are allocated.
+    ///
+    /// This function runs in `O(max_roots)`
+    pub fn allocate_tree(&mut self) -> Result<usize, Error> {
+        let tree_id = (0..self.roots().len())
+            .find(|&tree_id| !self.is_allocated(tree_id))
+            .ok_or(Error::NoTreesLeft)?;
+        self.roots_mut()[tree_id] = u32::to_be_bytes(EMPTY_TREE);
+        self.invalidate_len(tree_id);
+        self.bump_generation();
+        Ok(tree_id)
+    }
+
+    /// Marks the empty tree `tree_id` as not allocated
+    ///
+    /// Returns [`Error::NonEmptyTree`] if the tree is not empty.
+    pub fn free_tree(&mut self, tree_id: usize) -> Result<(), Error> {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        if !self.is_empty(tree_id) {
+            return Err(Error::NonEmptyTree);
+        }
+
+        self.roots_mut()[tree_id] = u32::to_be_bytes(FREE_TREE);
+        self.invalidate_len(tree_id);
+        self.bump_generation();
+        Ok(())
+    }
+
+    /// Returns `true` if the tree slot is in use: it was allocated with
+    /// [`allocate_tree()`](RBForest::allocate_tree) or something was inserted into it, and it was
+    /// not freed with [`free_tree()`](RBForest::free_tree) since then
+    ///
+    /// Unlike [`is_empty()`](RBForest::is_empty), this distinguishes an existing, but empty tree
+    /// from an unused slot. [`clear()`](RBForest::clear) does not change the allocation state.
+    #[must_use]
+    pub fn is_allocated(&self, tree_id: usize) -> bool {
+        self.roots().len() > tree_id && u32::from_be_bytes(self.roots()[tree_id]) != FREE_TREE
+    }
+
+    /// Swaps the contents of the trees `a` and `b`
+    ///
+    /// Nodes do not store the id of their tree, so only the root pointers are swapped: no entry
+    /// is moved and every entry keeps its node index. This makes it suitable for double
+    /// buffering, e.g. a tree can be rebuilt in a spare slot and then swapped with the live one.
+    /// Exchanging the node storage of the trees would give the same trees, as all of them share
+    /// the node pool.
+    ///
+    /// This function runs in `O(1)`
+    pub fn swap_trees(&mut self, a: usize, b: usize) -> Result<(), Error> {
+        if self.roots().len() <= a || self.roots().len() <= b {
+            return Err(Error::TooBigTreeId);
+        }
+
+        self.roots_mut().swap(a, b);
+        self.lens_mut().swap(a, b);
+        self.bump_generation();
+        Ok(())
+    }
+
+    /// Moves all the entries of the tree `from` to the empty tree `to`
+    ///
+    /// Returns [`Error::NonEmptyTree`] if `to` is not empty. Only the root pointers are
+    /// modified.
+    ///
+    /// This function runs in `O(1)`
+    pub fn move_tree(&mut self, from: usize, to: usize) -> Result<(), Error> {
+        if self.roots().len() <= from || self.roots().len() <= to {
+            return Err(Error::TooBigTreeId);
+        }
+
+        if !self.is_empty(to) {
+            return Err(Error::NonEmptyTree);
+        }
+
+        self.roots_mut().swap(from, to);
+        self.lens_mut().swap(from, to);
+        self.bump_generation();
+        Ok(())
+    }
+
+    /// Moves every entry of the tree `src`, for which `f` returns `true`, to the empty tree `dst`
+    ///
+    /// Entries are moved one by one, so this operation requires no free nodes. Returns
+    /// [`Error::NonEmptyTree`] if `dst` is not empty.
+    ///
+    /// This function runs in `O(n * log(n))`, where `n` - is the number of nodes
+    pub fn partition<F>(&mut self, src: usize, dst: usize, f: F) -> Result<(), Error>
+    where
+        F: Fn(&K, &V) -> bool,
+    {
+        if self.roots().len() <= src || self.roots().len() <= dst {
+            return Err(Error::TooBigTreeId);
+        }
+
+        if !self.is_empty(dst) {
+            return Err(Error::NonEmptyTree);
+        }
+
+        let mut maybe_id = self.root(src).map(|root_id| self.min(root_id as usize));
+        while let Some(id) = maybe_id {
+            let (key, value) = self.entry_at(id);
+            if f(&key, &value) {
+                // Node contents are moved around during deletion, so the next node is found by key
+                self.delete(src, &key);
+                maybe_id = self.surrounding_indices(src, &key).1;
+                self.insert(dst, key, value)?;
+            } else {
+                maybe_id = self.successor(id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Calls `f` for every entry in order by key, removing the entries it asks to remove
+    ///
+    /// If `f` returns `ControlFlow::Continue(true)` the entry is removed, `Continue(false)` keeps
+    /// it and `ControlFlow::Break(())` stops the traversal. Returns the number of removed
+    /// entries.
+    ///
+    /// This function runs in `O(n * log(n))`, where `n` - is the number of nodes
+    pub fn for_each_removable<F>(&mut self, tree_id: usize, mut f: F) -> Result<usize, Error>
+    where
+        F: FnMut(&K, &V) -> ControlFlow<(), bool>,
+    {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let mut removed = 0;
+        let mut maybe_id = self.root(tree_id).map(|root_id| self.min(root_id as usize));
+        while let Some(id) = maybe_id {
+            let (key, value) = self.entry_at(id);
+            match f(&key, &value) {
+                ControlFlow::Continue(true) => {
+                    // Node contents are moved around during deletion, so the next node is found
+                    // by key
+                    self.delete(tree_id, &key);
+                    maybe_id = self.surrounding_indices(tree_id, &key).1;
+                    removed += 1;
+                }
+                ControlFlow::Continue(false) => maybe_id = self.successor(id),
+                ControlFlow::Break(()) => break,
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Calls `f` for every entry in order by key, until it returns `ControlFlow::Break`
+    ///
+    /// Returns the break value of `f`, or `ControlFlow::Continue(())` if the whole tree was
+    /// visited. A nonexistent tree is treated as an empty one. This is the same as
+    /// `pairs(tree_id)?.try_for_each(...)`, but `f` receives the entry by reference.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn try_for_each<B, F>(&self, tree_id: usize, mut f: F) -> ControlFlow<B>
+    where
+        F: FnMut(&K, &V) -> ControlFlow<B>,
+    {
+        if self.roots().len() <= tree_id {
+            return ControlFlow::Continue(());
+        }
+
+        let mut maybe_id = self.root(tree_id).map(|root_id| self.min(root_id as usize));
+        while let Some(id) = maybe_id {
+            let (key, value) = self.entry_at(id);
+            f(&key, &value)?;
+            maybe_id = self.successor(id);
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    /// Replaces every value of the tree with the result of `f`, in order by key
+    ///
+    /// Values are updated in place, so no nodes are allocated. If a new value can not be
+    /// serialized, the walk stops and [`Error::ValueSerializationError`] is returned: the entries
+    /// before the failed one are already updated, while the failed one and the rest are left
+    /// untouched.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn map_values<F>(&mut self, tree_id: usize, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&K, V) -> V,
+    {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let mut maybe_id = self.root(tree_id).map(|root_id| self.min(root_id as usize));
+        while let Some(id) = maybe_id {
+            let (key, value) = self.entry_at(id);
+            let new_value = f(&key, value);
+
+            self.write_value(id, &new_value)?;
+
+            maybe_id = self.successor(id);
+        }
+
+        Ok(())
+    }
+
+    /// Applies `f` to every value with a key in `range`, in order by key, and returns the number
+    /// of updated values
+    ///
+    /// Values are updated in place, so no nodes are allocated. If an updated value can not be
+    /// serialized, the walk stops and [`Error::ValueSerializationError`] is returned: the entries
+    /// before the failed one are already updated, while the failed one and the rest are left
+    /// untouched.
+    ///
+    /// This function runs in `O(log(n) + m)`, where `n` - is the number of nodes and `m` - is
+    /// the number of updated values
+    pub fn update_range<R, Q, F>(
+        &mut self,
+        tree_id: usize,
+        range: R,
+        mut f: F,
+    ) -> Result<usize, Error>
+    where
+        K: Borrow<Q>,
+        R: RangeBounds<Q>,
+        Q: Ord + ?Sized,
+        F: FnMut(&K, &mut V),
+    {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let mut maybe_id = match range.start_bound() {
+            Bound::Included(start) => self.surrounding_indices(tree_id, start).1,
+            Bound::Excluded(start) => self.strict_bound_index(tree_id, start, Ordering::Greater),
+            Bound::Unbounded => self.root(tree_id).map(|root_id| self.min(root_id as usize)),
+        };
+
+        let mut updated = 0;
+        while let Some(id) = maybe_id {
+            let (key, mut value) = self.entry_at(id);
+            if !range.contains(key.borrow()) {
+                break;
+            }
+            f(&key, &mut value);
+            self.write_value(id, &value)?;
+            updated += 1;
+
+            maybe_id = self.successor(id);
+        }
+
+        Ok(updated)
+    }
+
+    /// Rebuilds the tree into a tree of minimal height
+    ///
+    /// Red-Black trees are at most twice as high as the perfectly balanced ones, so lookups in a
+    /// tree, which is not modified anymore, can be sped up by rebuilding it once. All the levels
+    /// except the last one are full and colored black, and the nodes of the last incomplete level
+    /// are red. The nodes are only relinked, so node indices are preserved and no free nodes are
+    /// required.
+    ///
+    /// This function runs in `O(n)` and allocates `O(n)` memory, where `n` - is the number of
+    /// nodes
+    pub fn rebuild_balanced(&mut self, tree_id: usize) -> Result<(), Error> {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let mut ids = Vec::new();
+        self.collect_inorder(self.root(tree_id), &mut ids);
+
+        let full_levels = (ids.len() + 1).ilog2();
+        let root = self.build_balanced(&ids, None, 0, full_levels);
+        self.set_root(tree_id, root);
+
+        Ok(())
+    }
+
+    /// Fills an empty tree with the entries of `iter`, sorted by key
+    ///
+    /// Adjacent entries with equal keys are collapsed into one: `combine(prev, new)` is called
+    /// with the value accumulated so far and the new one, e.g. `|_, new| new` keeps the last
+    /// value. The tree is built bottom-up the same way as in
+    /// [`rebuild_balanced()`](RBForest::rebuild_balanced), so no rotations are done.
+    ///
+    /// Returns [`Error::NonEmptyTree`] if the tree already contains entries and
+    /// [`Error::UnsortedInput`] if a key is less than the previous one. On any error all the
+    /// allocated nodes are returned to the free list and the tree is left empty.
+    ///
+    /// This function runs in `O(n)` and allocates `O(n)` memory, where `n` - is the number of
+    /// entries
+    pub fn fill_sorted_dedup<I, F>(
+        &mut self,
+        tree_id: usize,
+        iter: I,
+        mut combine: F,
+    ) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        F: FnMut(V, V) -> V,
+    {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+        if self.root(tree_id).is_some() {
+            return Err(Error::NonEmptyTree);
+        }
+
+        let mut ids = Vec::new();
+        let mut pending: Option<(K, V)> = None;
+        let mut result = Ok(());
+        for (key, value) in iter {
+            pending = match pending {
+                Some((prev_key, prev_value)) => match prev_key.cmp(&key) {
+                    Ordering::Equal => Some((prev_key, combine(prev_value, value))),
+                    Ordering::Less => {
+                        result = self.push_unlinked(&prev_key, &prev_value, &mut ids);
+                        Some((key, value))
+                    }
+                    Ordering::Greater => {
+                        result = Err(Error::UnsortedInput);
+                        None
+                    }
+                },
+                None => Some((key, value)),
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+        if let (Ok(()), Some((key, value))) = (result, pending) {
+            result = self.push_unlinked(&key, &value, &mut ids);
+        }
+
+        if result.is_err() {
+            for &id in ids.iter().rev() {
+                self.deallocate_node(id as usize);
+            }
+            return result;
+        }
+
+        let full_levels = (ids.len() + 1).ilog2();
+        let root = self.build_balanced(&ids, None, 0, full_levels);
+        self.set_root(tree_id, root);
+
+        Ok(())
+    }
+
+    /// Merges adjacent entries in a single pass in key order
+    ///
+    /// For each pair of adjacent entries `can_merge` is called with both of them. If it returns
+    /// `Some(merged)`, the second entry is removed and the value of the first one is replaced with
+    /// `merged`, so the merged entry may be merged again with the next one. Returns the number of
+    /// merges.
+    ///
+    /// If `merged` can not be serialized, [`Error::ValueSerializationError`] is returned and the
+    /// pair being merged is left untouched, while the merges done before are kept.
+    ///
+    /// This function runs in `O(n * log(n))`, where `n` - is the number of nodes
+    pub fn coalesce<F>(&mut self, tree_id: usize, can_merge: F) -> Result<usize, Error>
+    where
+        F: Fn(&K, &V, &K, &V) -> Option<V>,
+    {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let mut merges = 0;
+        let mut maybe_id = self.root(tree_id).map(|root_id| self.min(root_id as usize));
+        while let Some(id) = maybe_id {
+            let Some(next_id) = self.successor(id) else {
+                break;
+            };
+            let (key, value) = self.entry_at(id);
+            let (next_key, next_value) = self.entry_at(next_id);
+
+            if let Some(merged) = can_merge(&key, &value, &next_key, &next_value) {
+                // The value is written before the deletion, so that a failure leaves the pair
+                // untouched
+                self.write_value(id, &merged)?;
+
+                // Node contents are moved around during deletion, so the node is found by key
+                self.delete(tree_id, &next_key);
+                maybe_id = self.get_key_index(tree_id, &key);
+                merges += 1;
+            } else {
+                maybe_id = Some(next_id);
+            }
+        }
+
+        Ok(merges)
+    }
+
+    /// Returns `true` if the tree contains no elements
+    #[must_use]
+    pub fn is_empty(&self, tree_id: usize) -> bool {
+        if self.roots().len() > tree_id {
+            self.root(tree_id).is_none()
+        } else {
+            true
+        }
+    }
+
+    /// Deletes entry and returns deserialized value
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn remove<Q>(&mut self, tree_id: usize, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() > tree_id {
+            self.get_key_index(tree_id, key).map(|id| {
+                let deallocated_node_id = { self.delete_node(tree_id, id) };
+
+                let value = V::deserialize(&mut self.nodes()[deallocated_node_id].value.as_slice())
+                    .expect("Value corrupted");
+                value
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Deletes entry and returns deserialized key-value pair
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn remove_entry<Q>(&mut self, tree_id: usize, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() > tree_id {
+            self.get_key_index(tree_id, key).map(|id| {
+                let deallocated_node_id = { self.delete_node(tree_id, id) };
+
+                let key = K::deserialize(&mut self.nodes()[deallocated_node_id].key.as_slice())
+                    .expect("Key corrupted");
+                let value = V::deserialize(&mut self.nodes()[deallocated_node_id].value.as_slice())
+                    .expect("Value corrupted");
+                (key, value)
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Deletes entry and copies its raw value bytes to `out`
+    ///
+    /// At most `out.len()` bytes are copied. Returns the number of copied bytes or `None` if there
+    /// is no such key. The value is not deserialized, so the bytes can be forwarded as is.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn remove_bytes<Q>(&mut self, tree_id: usize, key: &Q, out: &mut [u8]) -> Option<usize>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() > tree_id {
+            self.get_key_index(tree_id, key).map(|id| {
+                let deallocated_node_id = self.delete_node(tree_id, id);
+
+                let value = &self.nodes()[deallocated_node_id].value;
+                let len = value.len().min(out.len());
+                out[..len].copy_from_slice(&value[..len]);
+                len
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Removes the entry and returns the stored key together with the value
+    ///
+    /// The returned key is deserialized from the tree, so it may differ from the borrowed form
+    /// used for the lookup. This mirrors `BTreeSet::take()` and is equivalent to
+    /// [`remove_entry()`](RBForest::remove_entry).
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn take<Q>(&mut self, tree_id: usize, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.remove_entry(tree_id, k)
+    }
+
+    /// Deletes entry without deserializing the value.
+    ///
+    /// Return `true` if there was a value with the given `key`.
+    pub fn delete<Q>(&mut self, tree_id: usize, key: &Q) -> bool
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() > tree_id {
+            self.get_key_index(tree_id, key)
+                .map(|id| {
+                    self.delete_node(tree_id, id);
+                })
+                .is_some()
+        } else {
+            false
+        }
+    }
+
+    /// Removes the entries with the biggest keys until at most `n` entries are left
+    ///
+    /// Returns the number of removed entries, `0` if there is no tree with such `tree_id`.
+    ///
+    /// This function runs in `O(N + m * log(N))`, where `N` - is the number of nodes and `m` - is
+    /// the number of removed entries
+    pub fn truncate_to(&mut self, tree_id: usize, n: usize) -> usize {
+        if self.roots().len() <= tree_id {
+            return 0;
+        }
+
+        let len = self.size(self.root(tree_id));
+        let excess = len.saturating_sub(n);
+        for _ in 0..excess {
+            let root_id = self
+                .root(tree_id)
+                .expect("the tree has more than n entries");
+            let max_id = self.max(root_id as usize);
+            self.delete_node(tree_id, max_id);
+        }
+        excess
+    }
+
+    /// Creates an iterator over key-value pairs, in order by key
+    pub fn pairs<'b>(
+        &'b self,
+        tree_id: usize,
+    ) -> Result<PairsIterator<'b, 'a, K, V, KSIZE, VSIZE>, Error> {
+        if self.roots().len() > tree_id {
+            Ok(PairsIterator::from_raw_parts(
+                self,
+                self.root(tree_id).map(|root_id| self.min(root_id as usize)),
+            ))
+        } else {
+            Err(Error::TooBigTreeId)
+        }
+    }
+
+    /// Returns a reference to the node, containing the key
+    ///
+    /// The reference can be used to resume iteration with [`pairs_from_ref()`](RBForest::pairs_from_ref)
+    /// without searching for the key again.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn node_ref<Q>(&self, tree_id: usize, k: &Q) -> Option<NodeRef<KSIZE>>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() <= tree_id {
+            return None;
+        }
+
+        self.get_key_index(tree_id, k).map(|id| NodeRef {
+            tree_id,
+            index: id as u32,
+            key: self.nodes()[id].key,
+        })
+    }
+
+    /// Creates an iterator over key-value pairs, starting from the referenced entry, in order by
+    /// key
+    ///
+    /// The reference is validated: the node must still hold the same key and belong to the same
+    /// tree. If the reference is stale, the iterator is empty.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn pairs_from_ref<'b>(
+        &'b self,
+        r: NodeRef<KSIZE>,
+    ) -> PairsIterator<'b, 'a, K, V, KSIZE, VSIZE> {
+        let index = r.index as usize;
+        let is_valid = self.roots().len() > r.tree_id
+            && index < self.nodes().len()
+            && self.nodes()[index].key == r.key
+            && self.is_in_tree(r.tree_id, index);
+
+        PairsIterator::from_raw_parts(self, is_valid.then_some(index))
+    }
+
+    /// Creates an iterator over key-value pairs, starting from the position, saved with
+    /// [`PairsIterator::position`]
+    ///
+    /// The iteration continues from the least key, which is greater or equal to the saved one,
+    /// so the tree may be modified between the calls. Returns [`Error::DeserializationError`] if
+    /// the token can not be decoded.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn pairs_resume<'b>(
+        &'b self,
+        tree_id: usize,
+        mut token: &[u8],
+    ) -> Result<PairsIterator<'b, 'a, K, V, KSIZE, VSIZE>, Error> {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let key = K::deserialize(&mut token).map_err(|_| Error::DeserializationError)?;
+        Ok(PairsIterator::from_raw_parts(
+            self,
+            self.surrounding_indices(tree_id, &key).1,
+        ))
+    }
+
+    /// Creates an iterator over key-value pairs in order by key, skipping the first `offset`
+    /// entries
+    ///
+    /// Nodes do not store the sizes of their subtrees, so the skipped nodes are walked one by one,
+    /// but unlike `pairs(tree_id)?.skip(offset)` they are not deserialized. If the tree has at
+    /// most `offset` entries, the iterator is empty.
+    ///
+    /// This function runs in `O(log(n) + offset)`, where `n` - is the number of nodes
+    pub fn pairs_skip<'b>(
+        &'b self,
+        tree_id: usize,
+        offset: usize,
+    ) -> Result<PairsIterator<'b, 'a, K, V, KSIZE, VSIZE>, Error> {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let mut maybe_id = self.root(tree_id).map(|root_id| self.min(root_id as usize));
+        for _ in 0..offset {
+            let Some(id) = maybe_id else {
+                break;
+            };
+            maybe_id = self.successor(id);
+        }
+        Ok(PairsIterator::from_raw_parts(self, maybe_id))
+    }
+
+    /// Creates an iterator over entries of all the trees, merged in global order by key
+    ///
+    /// Each entry is tagged with the id of its tree. See [`MergedPairsIterator`] for details.
+    ///
+    /// Each step of the iteration runs in `O(max_roots)`
+    #[must_use]
+    pub fn merged_pairs<'b>(&'b self) -> MergedPairsIterator<'b, 'a, K, V, KSIZE, VSIZE> {
+        MergedPairsIterator::new(self)
+    }
+
+    /// Creates an iterator over pairs of neighboring keys, which are not adjacent according to
+    /// `is_adjacent`
+    ///
+    /// For each two consecutive keys `a < b` of the tree, `(a, b)` is yielded if
+    /// `is_adjacent(&a, &b)` returns `false`.
+    pub fn gaps_by<'b, F>(
+        &'b self,
+        tree_id: usize,
+        mut is_adjacent: F,
+    ) -> Result<impl Iterator<Item = (K, K)> + 'b, Error>
+    where
+        F: FnMut(&K, &K) -> bool + 'b,
+    {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let mut maybe_id = self.root(tree_id).map(|root_id| self.min(root_id as usize));
+        let mut prev_key = maybe_id.map(|id| self.entry_key(id));
+        Ok(core::iter::from_fn(move || loop {
+            let id = self.successor(maybe_id?)?;
+            maybe_id = Some(id);
+            let prev = prev_key.replace(self.entry_key(id))?;
+            if !is_adjacent(&prev, prev_key.as_ref()?) {
+                // The key is deserialized once again to avoid `K: Clone` bound
+                return Some((prev, self.entry_key(id)));
+            }
+        }))
+    }
+
+    /// Creates an iterator over pairs of neighboring integer keys, which differ by more than one
+    ///
+    /// For keys `{1, 2, 5, 6, 10}` it yields `(2, 5)` and `(6, 10)`. See
+    /// [`gaps_by()`](RBForest::gaps_by) for arbitrary keys. Keys are compared with
+    /// [`KeyStep::checked_next`], so this works for the whole range of every integer type.
+    pub fn gaps<'b>(&'b self, tree_id: usize) -> Result<impl Iterator<Item = (K, K)> + 'b, Error>
+    where
+        K: KeyStep,
+    {
+        self.gaps_by(tree_id, |a, b| a.checked_next().as_ref() == Some(b))
+    }
+
+    /// Returns the share of the integer keys between the minimum and the maximum keys
+    /// (inclusive), which are present in the tree
+    ///
+    /// For keys `{0, 2, 4}` it returns `3 / 5`. Returns `None` if the tree is empty or there is no
+    /// tree with such `tree_id`.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn density(&self, tree_id: usize) -> Option<f64>
+    where
+        K: Copy + Into<i128>,
+    {
+        if self.roots().len() <= tree_id {
+            return None;
+        }
+
+        let root_id = self.root(tree_id)?;
+        let min: i128 = self.entry_key(self.min(root_id as usize)).into();
+        let max: i128 = self.entry_key(self.max(root_id as usize)).into();
+        // The distance between `i128::MIN` and `i128::MAX` fits only into `u128`, and the width
+        // of such a range does not fit even there, so one is added after the conversion
+        let width = max.abs_diff(min) as f64 + 1.0;
+        Some(self.size(Some(root_id)) as f64 / width)
+    }
+
+    /// Creates an iterator over keys with running sums of values, in order by key
+    ///
+    /// Each key is yielded together with the sum of values of all the entries up to and including
+    /// it, so the last item contains the total sum.
+    pub fn prefix_sums<'b>(
+        &'b self,
+        tree_id: usize,
+    ) -> Result<impl Iterator<Item = (K, V)> + 'b, Error>
+    where
+        V: Add<Output = V> + Copy + Default + 'b,
+    {
+        self.pairs(tree_id).map(|pairs| {
+            pairs.scan(V::default(), |sum, (key, value)| {
+                *sum = *sum + value;
+                Some((key, *sum))
+            })
+        })
+    }
+
+    /// Creates an iterator over type-erased key-value pairs, in order by key
+    ///
+    /// Each key and value is deserialized and boxed as [`Any`], so consumers, which handle trees
+    /// of different types, can downcast them as needed.
+    #[allow(clippy::type_complexity)]
+    pub fn pairs_dyn<'b>(
+        &'b self,
+        tree_id: usize,
+    ) -> Result<impl Iterator<Item = (Box<dyn Any>, Box<dyn Any>)> + 'b, Error>
+    where
+        K: 'static,
+        V: 'static,
+    {
+        self.pairs(tree_id).map(|pairs| {
+            pairs.map(|(key, value)| {
+                (
+                    Box::new(key) as Box<dyn Any>,
+                    Box::new(value) as Box<dyn Any>,
+                )
+            })
+        })
+    }
+
+    /// Creates an iterator over groups of consecutive entries, which have the same bucket
+    ///
+    /// Entries are visited in order by key, and a new group is started every time `bucket`
+    /// returns a value, which differs from the previous one. So `bucket` should be monotonic in
+    /// key order (e.g. `|key| key / 10`), otherwise the entries of a bucket may be split into
+    /// several groups.
+    ///
+    /// The iteration runs in `O(n)` and allocates `O(g)` memory per group, where `n` - is the
+    /// number of nodes and `g` - is the size of the group
+    #[allow(clippy::type_complexity)]
+    pub fn group_by<'b, B, F>(
+        &'b self,
+        tree_id: usize,
+        bucket: F,
+    ) -> Result<impl Iterator<Item = (B, Vec<(K, V)>)> + 'b, Error>
+    where
+        B: Ord + 'b,
+        F: Fn(&K) -> B + 'b,
+    {
+        let mut pairs = self.pairs(tree_id)?.peekable();
+        Ok(core::iter::from_fn(move || {
+            let (key, value) = pairs.next()?;
+            let current = bucket(&key);
+            let mut group = Vec::new();
+            group.push((key, value));
+            while let Some(pair) = pairs.next_if(|(key, _)| bucket(key) == current) {
+                group.push(pair);
+            }
+            Some((current, group))
+        }))
+    }
+
+    /// Creates an iterator over entries in order by key, together with the minimum and the
+    /// maximum of the values seen so far
+    ///
+    /// Yields `(key, value, running_min, running_max)`, where the extrema include the current
+    /// value, so the first item has `running_min == running_max == value`.
+    pub fn pairs_with_running_extrema<'b>(
+        &'b self,
+        tree_id: usize,
+    ) -> Result<impl Iterator<Item = (K, V, V, V)> + 'b, Error>
+    where
+        V: Ord + Copy + 'b,
+    {
+        self.pairs(tree_id).map(|pairs| {
+            pairs.scan(None, |extrema: &mut Option<(V, V)>, (key, value)| {
+                let (min, max) = match *extrema {
+                    Some((min, max)) => (min.min(value), max.max(value)),
+                    None => (value, value),
+                };
+                *extrema = Some((min, max));
+                Some((key, value, min, max))
+            })
+        })
+    }
+
+    /// Creates an iterator over key-value pairs, for which `f` returns `true`, in order by key
+    ///
+    /// This is the same as `pairs(tree_id)?.filter(...)`, but the predicate receives both the key
+    /// and the value by reference.
+    pub fn pairs_filtered<'b, F>(
+        &'b self,
+        tree_id: usize,
+        f: F,
+    ) -> Result<impl Iterator<Item = (K, V)> + 'b, Error>
+    where
+        F: Fn(&K, &V) -> bool + 'b,
+    {
+        self.pairs(tree_id)
+            .map(|pairs| pairs.filter(move |(key, value)| f(key, value)))
+    }
+
+    /// Creates an iterator over key-value pairs with `key % modulus == residue`, in order by key
+    ///
+    /// This is a filtered scan of the whole tree, intended for sharding entries by key. The
+    /// iterator is empty if `modulus` is zero.
+    pub fn residue_keys<'b>(
+        &'b self,
+        tree_id: usize,
+        modulus: u64,
+        residue: u64,
+    ) -> Result<impl Iterator<Item = (K, V)> + 'b, Error>
+    where
+        K: Copy + Into<u64>,
+    {
+        self.pairs(tree_id).map(move |pairs| {
+            pairs.filter(move |(key, _)| modulus != 0 && (*key).into() % modulus == residue)
+        })
+    }
+
+    /// Creates an iterator over values at keys `start`, `start + step`, `start + 2 * step` and so on
+    ///
+    /// Keys, which are not present in the tree, are skipped: after a ceil lookup misses, the stride
+    /// is advanced straight to the next existing key, so large gaps cost a single lookup. The
+    /// iterator stops at the end of the tree, when the next key overflows or if `step` is not
+    /// positive.
+    ///
+    /// Each call of `next()` runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn keys_stepped<'b>(
+        &'b self,
+        tree_id: usize,
+        start: K,
+        step: K,
+    ) -> Result<impl Iterator<Item = V> + 'b, Error>
+    where
+        K: KeyStep + 'b,
+    {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let mut target = Some(start);
+        Ok(core::iter::from_fn(move || loop {
+            let current = target.take()?;
+            let ceil = self.surrounding_indices(tree_id, &current).1?;
+            let ceil_key = self.entry_key(ceil);
+
+            // The target is advanced up to the ceil key, so every lookup lands on the next
+            // existing key or past it
+            let mut next = current.checked_step(&step).filter(|next| *next > current);
+            while let Some(key) = next.take() {
+                if key >= ceil_key {
+                    next = Some(key);
+                    break;
+                }
+                next = key.checked_step(&step);
+            }
+            target = next;
+
+            if ceil_key == current {
+                return Some(self.entry_at(ceil).1);
+            }
+        }))
+    }
+
+    /// Creates an iterator over overlapping pairs of adjacent entries, in order by key
+    ///
+    /// For entries `e0, e1, e2, ...` the iterator yields `(e0, e1)`, `(e1, e2)` and so on, so a
+    /// tree with fewer than two entries yields nothing. Each entry is deserialized once and cloned
+    /// into the next window.
+    #[allow(clippy::type_complexity)]
+    pub fn windows2<'b>(
+        &'b self,
+        tree_id: usize,
+    ) -> Result<impl Iterator<Item = ((K, V), (K, V))> + 'b, Error>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut pairs = self.pairs(tree_id)?;
+        let mut previous = pairs.next();
+        Ok(core::iter::from_fn(move || {
+            let next = pairs.next()?;
+            let current = previous.replace(next.clone())?;
+            Some((current, next))
+        }))
+    }
+
+    /// Creates an iterator over every `step`-th key-value pair, in order by key
+    ///
+    /// The iterator yields entries with indices `0`, `step`, `2 * step` and so on. It walks over
+    /// all the skipped entries, but does not deserialize them.
+    ///
+    /// Returns [`Error::ZeroLimit`] if `step` is `0`.
+    pub fn sample_every<'b>(
+        &'b self,
+        tree_id: usize,
+        step: usize,
+    ) -> Result<StepBy<PairsIterator<'b, 'a, K, V, KSIZE, VSIZE>>, Error> {
+        let pairs = self.pairs(tree_id)?;
+        if step == 0 {
+            return Err(Error::ZeroLimit);
+        }
+        Ok(pairs.step_by(step))
+    }
+
+    /// Creates an iterator over key-value pairs, which does not panic on corrupted data
+    ///
+    /// See [`TryPairsIterator`] for details.
+    pub fn try_pairs<'b>(
+        &'b self,
+        tree_id: usize,
+    ) -> Result<TryPairsIterator<'b, 'a, K, V, KSIZE, VSIZE>, Error> {
+        if self.roots().len() > tree_id {
+            Ok(TryPairsIterator::from_raw_parts(
+                self,
+                self.root(tree_id).map(|root_id| self.min(root_id as usize)),
+            ))
+        } else {
+            Err(Error::TooBigTreeId)
+        }
+    }
+
+    /// Creates an iterator over keys, from smallest to biggest
+    pub fn keys<'b>(
+        &'b self,
+        tree_id: usize,
+    ) -> Result<KeysIterator<'b, 'a, K, V, KSIZE, VSIZE>, Error> {
+        if self.roots().len() > tree_id {
+            Ok(KeysIterator::from_raw_parts(
+                self,
+                self.root(tree_id).map(|root_id| self.min(root_id as usize)),
+            ))
+        } else {
+            Err(Error::TooBigTreeId)
+        }
+    }
+
+    /// Creates an iterator over values, in order by key
+    pub fn values<'b>(
+        &'b self,
+        tree_id: usize,
+    ) -> Result<ValuesIterator<'b, 'a, K, V, KSIZE, VSIZE>, Error> {
+        if self.roots().len() > tree_id {
+            Ok(ValuesIterator::from_raw_parts(
+                self,
+                self.root(tree_id).map(|root_id| self.min(root_id as usize)),
+            ))
+        } else {
+            Err(Error::TooBigTreeId)
+        }
+    }
+
+    /// Creates an iterator over keys, which are present in both trees `a` and `b`, in order
+    ///
+    /// The trees are merged lazily, so neither set of keys is collected.
+    ///
+    /// This function runs in `O(n + m)`, where `n` and `m` - are the numbers of nodes of the trees
+    pub fn common_keys<'b>(
+        &'b self,
+        a: usize,
+        b: usize,
+    ) -> Result<impl Iterator<Item = K> + 'b, Error> {
+        Ok(self
+            .merged_keys(a, b)?
+            .filter_map(|(key, in_a, in_b)| (in_a && in_b).then_some(key)))
+    }
+
+    /// Creates an iterator over keys, which are present in any of the trees `a` and `b`, in order
+    ///
+    /// Keys present in both trees are yielded once.
+    ///
+    /// This function runs in `O(n + m)`, where `n` and `m` - are the numbers of nodes of the trees
+    pub fn union_keys<'b>(
+        &'b self,
+        a: usize,
+        b: usize,
+    ) -> Result<impl Iterator<Item = K> + 'b, Error> {
+        Ok(self.merged_keys(a, b)?.map(|(key, _, _)| key))
+    }
+
+    /// Creates an iterator over keys of the tree `a`, which are absent from the tree `b`, in order
+    ///
+    /// This function runs in `O(n + m)`, where `n` and `m` - are the numbers of nodes of the trees
+    pub fn difference_keys<'b>(
+        &'b self,
+        a: usize,
+        b: usize,
+    ) -> Result<impl Iterator<Item = K> + 'b, Error> {
+        Ok(self
+            .merged_keys(a, b)?
+            .filter_map(|(key, in_a, in_b)| (in_a && !in_b).then_some(key)))
+    }
+
+    /// Creates an iterator over keys, which are present in both trees `a` and `b`, with their
+    /// values from both trees, in order by key
+    ///
+    /// The trees are merged lazily, so no lookups are made, and values are deserialized only for
+    /// the common keys.
+    ///
+    /// This function runs in `O(n + m)`, where `n` and `m` - are the numbers of nodes of the trees
+    pub fn join<'b>(
+        &'b self,
+        a: usize,
+        b: usize,
+    ) -> Result<impl Iterator<Item = (K, V, V)> + 'b, Error> {
+        if self.roots().len() <= a || self.roots().len() <= b {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let mut a_node = self.root(a).map(|root_id| self.min(root_id as usize));
+        let mut b_node = self.root(b).map(|root_id| self.min(root_id as usize));
+        Ok(core::iter::from_fn(move || loop {
+            let (a_id, b_id) = (a_node?, b_node?);
+            match self.entry_key(a_id).cmp(&self.entry_key(b_id)) {
+                Ordering::Less => a_node = self.successor(a_id),
+                Ordering::Greater => b_node = self.successor(b_id),
+                Ordering::Equal => {
+                    a_node = self.successor(a_id);
+                    b_node = self.successor(b_id);
+                    let (key, a_value) = self.entry_at(a_id);
+                    let (_, b_value) = self.entry_at(b_id);
+                    return Some((key, a_value, b_value));
+                }
+            }
+        }))
+    }
+
+    /// Returns the entry with the maximum value
+    ///
+    /// Values are not indexed, so all the entries are scanned. If several entries have the
+    /// maximum value, the one with the biggest key is returned.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn max_by_value(&self, tree_id: usize) -> Option<(K, V)>
+    where
+        V: Ord,
+    {
+        self.pairs(tree_id).ok()?.max_by(|(_, a), (_, b)| a.cmp(b))
+    }
+
+    /// Returns the entry with the minimum value
+    ///
+    /// Values are not indexed, so all the entries are scanned. If several entries have the
+    /// minimum value, the one with the smallest key is returned.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn min_by_value(&self, tree_id: usize) -> Option<(K, V)>
+    where
+        V: Ord,
+    {
+        self.pairs(tree_id).ok()?.min_by(|(_, a), (_, b)| a.cmp(b))
+    }
+
+    /// Returns the median entry, i.e. the entry with index `(n - 1) / 2` in key order
+    ///
+    /// For an even number of entries the lower median is returned.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn median(&self, tree_id: usize) -> Option<(K, V)> {
+        let len = self.len(tree_id).ok()?;
+        if len == 0 {
+            return None;
+        }
+        self.pairs(tree_id).ok()?.nth((len - 1) / 2)
+    }
+
+    /// Returns the key with index `rank` in key order, starting from `0`
+    ///
+    /// Nodes do not store the sizes of their subtrees, so the keys are counted one by one. Returns
+    /// `None` if the tree has at most `rank` entries or there is no such tree.
+    ///
+    /// This function runs in `O(log(n) + rank)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn key_at_rank(&self, tree_id: usize, rank: usize) -> Option<K> {
+        self.keys(tree_id).ok()?.nth(rank)
+    }
+
+    /// Returns `true` if some value is stored under several keys
+    ///
+    /// Values are not indexed, so all the entries are collected and sorted by value.
+    ///
+    /// This function runs in `O(n * log(n))` and allocates `O(n)` memory, where `n` - is the
+    /// number of nodes
+    #[must_use]
+    pub fn has_duplicate_values(&self, tree_id: usize) -> bool
+    where
+        V: Ord,
+    {
+        self.find_duplicate_value(tree_id).is_some()
+    }
+
+    /// Returns two keys, which share the same value
+    ///
+    /// If there are several duplicates, the keys of the smallest duplicated value are returned,
+    /// the two smallest of them in key order.
+    ///
+    /// This function runs in `O(n * log(n))` and allocates `O(n)` memory, where `n` - is the
+    /// number of nodes
+    #[must_use]
+    pub fn find_duplicate_value(&self, tree_id: usize) -> Option<(K, K)>
+    where
+        V: Ord,
+    {
+        let mut entries: Vec<(V, K)> = self
+            .pairs(tree_id)
+            .ok()?
+            .map(|(key, value)| (value, key))
+            .collect();
+        // The sort is stable, so keys with equal values stay in key order
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let index = entries.windows(2).position(|pair| pair[0].0 == pair[1].0)?;
+        let mut duplicates = entries.drain(index..index + 2).map(|(_, key)| key);
+        Some((duplicates.next()?, duplicates.next()?))
+    }
+
+    /// Folds every entry of the tree into an accumulator, in order by key
+    ///
+    /// This is equivalent to `pairs(tree_id)?.fold(init, f)`. Returns `init` if there is no tree
+    /// with such `tree_id`.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn fold<B, F>(&self, tree_id: usize, init: B, mut f: F) -> B
+    where
+        F: FnMut(B, K, V) -> B,
+    {
+        if self.roots().len() <= tree_id {
+            return init;
+        }
+
+        let mut accumulator = init;
+        let mut maybe_id = self.root(tree_id).map(|root_id| self.min(root_id as usize));
+        while let Some(id) = maybe_id {
+            let (key, value) = self.entry_at(id);
+            accumulator = f(accumulator, key, value);
+            maybe_id = self.successor(id);
+        }
+        accumulator
+    }
+
+    /// Returns the number of entries and the sum of their values, computed in a single walk
+    ///
+    /// Returns `(0, V::default())` if there is no tree with such `tree_id`.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn count_and_sum(&self, tree_id: usize) -> (usize, V)
+    where
+        V: Add<Output = V> + Copy + Default,
+    {
+        self.fold(tree_id, (0, V::default()), |(count, sum), _, value| {
+            (count + 1, sum + value)
+        })
+    }
+
+    /// Creates an iterator over summaries of all the trees of the forest, in order by `tree_id`
+    ///
+    /// Tree slots, which are not allocated, are summarized as empty trees.
+    ///
+    /// Each step of the iteration runs in `O(n)`, where `n` - is the number of nodes of the tree
+    pub fn summaries(&self) -> impl Iterator<Item = TreeSummary<K>> + '_ {
+        (0..self.roots().len()).map(|tree_id| {
+            let root = self.root(tree_id);
+            TreeSummary {
+                tree_id,
+                len: self.size(root),
+                min_key: root.map(|root_id| self.entry_key(self.min(root_id as usize))),
+                max_key: root.map(|root_id| self.entry_key(self.max(root_id as usize))),
+            }
+        })
+    }
+
+    /// Returns the total serialized sizes of keys and values of the tree
+    ///
+    /// Keys and values are re-serialized to measure their actual lengths, so the result shows how
+    /// much space the entries would take without padding to `KSIZE` and `VSIZE`. Returns zeroed
+    /// stats if there is no tree with such `tree_id`.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn size_stats(&self, tree_id: usize) -> SizeStats {
+        let mut key_buffer = [0; KSIZE];
+        let mut value_buffer = [0; VSIZE];
+        self.fold(tree_id, SizeStats::default(), |stats, key, value| {
+            SizeStats {
+                key_bytes: stats.key_bytes + serialized_len(&key, &mut key_buffer, "Key corrupted"),
+                value_bytes: stats.value_bytes
+                    + serialized_len(&value, &mut value_buffer, "Value corrupted"),
+                entries: stats.entries + 1,
+            }
+        })
+    }
+
+    /// Returns the first and the last keys of the longest run of consecutive keys
+    ///
+    /// A run is a maximal sequence of keys, each of which is the successor of the previous one.
+    /// If there are several runs of the same length, the one with the smallest keys is returned.
+    /// Returns `None` if the tree is empty or there is no tree with such `tree_id`.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn longest_run(&self, tree_id: usize) -> Option<(K, K)>
+    where
+        K: KeyStep + Clone,
+    {
+        let mut keys = self.keys(tree_id).ok()?;
+        let first = keys.next()?;
+
+        let mut longest = (first.clone(), first.clone(), 1);
+        let mut current = (first.clone(), first, 1);
+        for key in keys {
+            if current.1.checked_next().as_ref() == Some(&key) {
+                current.1 = key;
+                current.2 += 1;
+            } else {
+                if current.2 > longest.2 {
+                    longest = current;
+                }
+                current = (key.clone(), key, 1);
+            }
+        }
+        if current.2 > longest.2 {
+            longest = current;
+        }
+
+        Some((longest.0, longest.1))
+    }
+
+    /// Sets bit `i` of `out` if the key `base + i` is present in the tree, for `i` in `0..len`
+    ///
+    /// Bits are numbered from the least significant bit of `out[0]`, so key `base + i` maps to
+    /// the bit `1 << (i % 8)` of `out[i / 8]`. The first `(len + 7) / 8` bytes of `out` are
+    /// overwritten, bits for keys, which overflow `K`, are left cleared. Returns
+    /// [`Error::TooSmall`] if `out` is shorter than `(len + 7) / 8` bytes.
+    ///
+    /// This function runs in `O(log(n) + len)`, where `n` - is the number of nodes
+    pub fn present_bitmap(
+        &self,
+        tree_id: usize,
+        base: K,
+        len: usize,
+        out: &mut [u8],
+    ) -> Result<(), Error>
+    where
+        K: KeyStep,
+    {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+        let bytes = len.div_ceil(8);
+        if out.len() < bytes {
+            return Err(Error::TooSmall);
+        }
+        out[..bytes].fill(0);
+
+        // The key of `maybe_id` is never smaller than `target`, because both advance in order
+        let mut maybe_id = self.surrounding_indices(tree_id, &base).1;
+        let mut target = base;
+        for i in 0..len {
+            let Some(id) = maybe_id else {
+                break;
+            };
+            if self.entry_key(id) == target {
+                out[i / 8] |= 1 << (i % 8);
+                maybe_id = self.successor(id);
+            }
+            match target.checked_next() {
+                Some(next) => target = next,
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the maximum serialized length of the keys, stored in the tree
+    ///
+    /// Keys are re-serialized to measure their actual lengths, so the result is the minimal
+    /// `KSIZE` of a tree, which can hold all the current keys. Returns `0` if the tree is empty or
+    /// there is no tree with such `tree_id`.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn required_key_size(&self, tree_id: usize) -> usize {
+        let mut buffer = [0; KSIZE];
+        self.fold(tree_id, 0, |max_size, key, _| {
+            max_size.max(serialized_len(&key, &mut buffer, "Key corrupted"))
+        })
+    }
+
+    /// Returns the maximum serialized length of the values, stored in the tree
+    ///
+    /// Values are re-serialized to measure their actual lengths, so the result is the minimal
+    /// `VSIZE` of a tree, which can hold all the current values. Returns `0` if the tree is empty
+    /// or there is no tree with such `tree_id`.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn required_value_size(&self, tree_id: usize) -> usize {
+        let mut buffer = [0; VSIZE];
+        self.fold(tree_id, 0, |max_size, _, value| {
+            max_size.max(serialized_len(&value, &mut buffer, "Value corrupted"))
+        })
+    }
+
+    /// Walks the tree from the root, letting the visitor decide which subtrees to descend into
+    ///
+    /// `f` is called for a node before its subtrees (the left one is visited first), so a subtree
+    /// can be pruned by comparing the key of its parent with a bound. This allows selective scans,
+    /// which skip whole subtrees outside of the range of interest.
+    ///
+    /// This function runs in `O(m)`, where `m` - is the number of visited nodes
+    pub fn visit<F>(&self, tree_id: usize, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&K, &V) -> Descend,
+    {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        self.visit_subtree(self.root(tree_id), &mut f);
+        Ok(())
+    }
+
+    /// Checks if both forests hold the same trees, regardless of their ids
+    ///
+    /// The forests are compared as multisets of maps: every non-empty tree of `self` must be
+    /// matched by a distinct tree of `other` with equal contents, and vice versa. Empty trees are
+    /// ignored, so the forests may have different [`max_roots()`](RBForest::max_roots).
+    ///
+    /// This function runs in `O(t^2 * n)`, where `t` - is the number of trees and `n` - is the
+    /// number of nodes
+    #[must_use]
+    pub fn content_eq_unordered(&self, other: &RBForest<'_, K, V, KSIZE, VSIZE>) -> bool
+    where
+        V: Eq,
+    {
+        let mut matched = Vec::new();
+        matched.resize(other.roots().len(), false);
+
+        for tree_id in (0..self.roots().len()).filter(|&tree_id| !self.is_empty(tree_id)) {
+            let len = self.size(self.root(tree_id));
+            let found = (0..other.roots().len()).find(|&other_id| {
+                !matched[other_id]
+                    && !other.is_empty(other_id)
+                    && other.size(other.root(other_id)) == len
+                    && self
+                        .pairs(tree_id)
+                        .expect("tree_id is always in range")
+                        .eq(other.pairs(other_id).expect("tree_id is always in range"))
+            });
+            match found {
+                Some(other_id) => matched[other_id] = true,
+                None => return false,
+            }
+        }
+
+        (0..other.roots().len()).all(|other_id| matched[other_id] || other.is_empty(other_id))
+    }
+
+    /// Returns the number of entries, for which `f` returns `true`
+    ///
+    /// Both the key and the value of every entry are deserialized, so this is
+    /// `pairs(tree_id)?.filter(...).count()` without building the tuples. If the entries can be
+    /// rejected by the key alone, use [`count_where_lazy()`](RBForest::count_where_lazy).
+    /// Returns `0` if there is no tree with such `tree_id`.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn count_where<F>(&self, tree_id: usize, f: F) -> usize
+    where
+        F: Fn(&K, &V) -> bool,
+    {
+        self.count_where_lazy(tree_id, |_| true, f)
+    }
+
+    /// Returns the number of entries, for which both `key_filter` and `f` return `true`
+    ///
+    /// The value is deserialized only if `key_filter` accepts the key, so entries with rejected
+    /// keys cost just a key deserialization. Returns `0` if there is no tree with such `tree_id`.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn count_where_lazy<P, F>(&self, tree_id: usize, key_filter: P, f: F) -> usize
+    where
+        P: Fn(&K) -> bool,
+        F: Fn(&K, &V) -> bool,
+    {
+        if self.roots().len() <= tree_id {
+            return 0;
+        }
+
+        let mut counter = 0;
+        let mut maybe_id = self.root(tree_id).map(|root_id| self.min(root_id as usize));
+        while let Some(id) = maybe_id {
+            let key = self.entry_key(id);
+            if key_filter(&key) {
+                let value = V::deserialize(&mut self.nodes()[id].value.as_slice())
+                    .expect("Value corrupted");
+                if f(&key, &value) {
+                    counter += 1;
+                }
+            }
+            maybe_id = self.successor(id);
+        }
+        counter
+    }
+
+    /// Returns the first key-value pair in the map
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn first_entry(&self, tree_id: usize) -> Option<(K, V)> {
+        if self.roots().len() > tree_id {
+            self.root(tree_id).map(|root_id| {
+                let node = &self.nodes()[self.min(root_id as usize)];
+                let key = K::deserialize(&mut node.key.as_slice()).expect("Key corrupted");
+                let value = V::deserialize(&mut node.value.as_slice()).expect("Value corrupted");
+                (key, value)
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the last key-value pair in the map
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn last_entry(&self, tree_id: usize) -> Option<(K, V)> {
+        if self.roots().len() > tree_id {
+            self.root(tree_id).map(|root_id| {
+                let node = &self.nodes()[self.max(root_id as usize)];
+                let key = K::deserialize(&mut node.key.as_slice()).expect("Key corrupted");
+                let value = V::deserialize(&mut node.value.as_slice()).expect("Value corrupted");
+                (key, value)
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the smallest and the biggest keys of the tree
+    ///
+    /// Values are not deserialized. Returns `None` if the tree is empty.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn key_bounds(&self, tree_id: usize) -> Option<(K, K)> {
+        if self.roots().len() <= tree_id {
+            return None;
+        }
+
+        let root_id = self.root(tree_id)? as usize;
+        Some((
+            self.entry_key(self.min(root_id)),
+            self.entry_key(self.max(root_id)),
+        ))
+    }
+
+    /// Returns the greatest entry with key `<= k` and the least entry with key `>= k`
+    ///
+    /// Both entries are found in a single descent. If `k` is present in the tree, both of them are
+    /// the entry with this key.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    #[allow(clippy::type_complexity)]
+    pub fn surrounding<Q>(&self, tree_id: usize, k: &Q) -> (Option<(K, V)>, Option<(K, V)>)
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() <= tree_id {
+            return (None, None);
+        }
+
+        let (floor, ceil) = self.surrounding_indices(tree_id, k);
+        (
+            floor.map(|id| self.entry_at(id)),
+            ceil.map(|id| self.entry_at(id)),
+        )
+    }
+
+    /// Returns the entry with the key nearest to `k` according to the distance function `dist`
+    ///
+    /// The nearest key is either the greatest key `<= k` or the least key `>= k`, so `dist` is
+    /// called at most twice. Ties are broken toward the smaller key.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn nearest<Q, F>(&self, tree_id: usize, k: &Q, dist: F) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+        F: Fn(&K, &Q) -> u64,
+    {
+        if self.roots().len() <= tree_id {
+            return None;
+        }
+
+        let nearest_id = match self.surrounding_indices(tree_id, k) {
+            (Some(floor), Some(ceil)) => {
+                let floor_dist = dist(&self.entry_key(floor), k);
+                let ceil_dist = dist(&self.entry_key(ceil), k);
+                if ceil_dist < floor_dist {
+                    ceil
+                } else {
+                    floor
+                }
+            }
+            (Some(id), None) | (None, Some(id)) => id,
+            (None, None) => return None,
+        };
+        Some(self.entry_at(nearest_id))
+    }
+
+    /// Returns the entry with the greatest key strictly less than `k`
+    ///
+    /// `k` itself does not have to be present in the tree.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn last_before<Q>(&self, tree_id: usize, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() > tree_id {
+            self.strict_bound_index(tree_id, k, Ordering::Less)
+                .map(|id| self.entry_at(id))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the entry with the least key strictly greater than `k`
+    ///
+    /// `k` itself does not have to be present in the tree.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn first_after<Q>(&self, tree_id: usize, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() > tree_id {
+            self.strict_bound_index(tree_id, k, Ordering::Greater)
+                .map(|id| self.entry_at(id))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the value of the entry with the least key strictly greater than `k`
+    ///
+    /// This is the same as [`first_after()`](RBForest::first_after), but only the value is
+    /// deserialized. `k` itself does not have to be present in the tree.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn next_value<Q>(&self, tree_id: usize, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() > tree_id {
+            self.strict_bound_index(tree_id, k, Ordering::Greater)
+                .map(|id| {
+                    V::deserialize(&mut self.nodes()[id].value.as_slice()).expect("Value corrupted")
+                })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the keys, which precede and follow the existing key `k` in key order
+    ///
+    /// Returns `None` if there is no such key in the tree.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn neighbors<Q>(&self, tree_id: usize, k: &Q) -> Option<(Option<K>, Option<K>)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() <= tree_id {
+            return None;
+        }
+
+        let id = self.get_key_index(tree_id, k)?;
+        Some((
+            self.predecessor(id).map(|prev_id| self.entry_key(prev_id)),
+            self.successor(id).map(|next_id| self.entry_key(next_id)),
+        ))
+    }
+
+    /// Collects all the entries of the tree and sorts them with the comparator `f`
+    ///
+    /// The tree is physically ordered by key, so ordering by anything else requires collecting
+    /// and sorting all the entries. The sort is stable, so entries, which are equal according to
+    /// `f`, are kept in key order.
+    ///
+    /// This function runs in `O(n * log(n))` and allocates `O(n)` memory, where `n` - is the
+    /// number of nodes
+    pub fn sorted_by<F>(&self, tree_id: usize, f: F) -> Result<Vec<(K, V)>, Error>
+    where
+        F: FnMut(&(K, V), &(K, V)) -> Ordering,
+    {
+        let mut pairs: Vec<_> = self.pairs(tree_id)?.collect();
+        pairs.sort_by(f);
+        Ok(pairs)
+    }
+
+    /// Collects all the entries of the tree in order by key
+    ///
+    /// This is the same as `pairs(tree_id)?.collect()`, but the vector is allocated once with the
+    /// exact capacity, so a big tree is never copied on reallocation.
+    ///
+    /// This function runs in `O(n)` and allocates `O(n)` memory, where `n` - is the number of
+    /// nodes
+    pub fn collect_pairs(&self, tree_id: usize) -> Result<Vec<(K, V)>, Error> {
+        let mut pairs = Vec::with_capacity(self.len(tree_id)?);
+        pairs.extend(self.pairs(tree_id)?);
+        Ok(pairs)
+    }
+
+    /// Copies all the entries of the tree to the start of `out` in order by key and returns their
+    /// number
+    ///
+    /// Nothing is allocated, so this works without an allocator. Returns [`Error::TooSmall`] and
+    /// leaves `out` untouched if the tree has more than `N` entries.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn snapshot_into<const N: usize>(
+        &self,
+        tree_id: usize,
+        out: &mut [(K, V); N],
+    ) -> Result<usize, Error> {
+        let len = self.len(tree_id)?;
+        if len > N {
+            return Err(Error::TooSmall);
+        }
+
+        for (slot, entry) in out.iter_mut().zip(self.pairs(tree_id)?) {
+            *slot = entry;
+        }
+        Ok(len)
+    }
+
+    /// Collects up to `k` entries with the smallest keys, in ascending order by key
+    ///
+    /// This function runs in `O(log(n) + k)`, where `n` - is the number of nodes
+    pub fn smallest(&self, tree_id: usize, k: usize) -> Result<Vec<(K, V)>, Error> {
+        Ok(self.pairs(tree_id)?.take(k).collect())
+    }
+
+    /// Collects up to `k` entries with the biggest keys, in descending order by key
+    ///
+    /// This function runs in `O(log(n) + k)`, where `n` - is the number of nodes
+    pub fn largest(&self, tree_id: usize, k: usize) -> Result<Vec<(K, V)>, Error> {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let mut entries = Vec::new();
+        let mut maybe_id = self.root(tree_id).map(|root_id| self.max(root_id as usize));
+        while let Some(id) = maybe_id {
+            if entries.len() == k {
+                break;
+            }
+            entries.push(self.entry_at(id));
+            maybe_id = self.predecessor(id);
         }
+        Ok(entries)
     }
 
-    /// Returns `true` if the tree contains no elements
-    #[must_use]
-    pub fn is_empty(&self, tree_id: usize) -> bool {
-        if self.roots.len() > tree_id {
-            self.root(tree_id).is_none()
-        } else {
-            true
+    /// Collects all the entries of the tree in a pseudo-random order, determined by `seed`
+    ///
+    /// The same seed always yields the same order, so this can be used for reproducible sampling
+    /// and testing. The shuffle uses a small built-in generator, it is not suitable for anything
+    /// security-related.
+    ///
+    /// This function runs in `O(n)` and allocates `O(n)` memory, where `n` - is the number of
+    /// nodes
+    pub fn shuffled_pairs(&self, tree_id: usize, seed: u64) -> Result<Vec<(K, V)>, Error> {
+        let mut pairs: Vec<_> = self.pairs(tree_id)?.collect();
+        let mut state = seed;
+        // Fisher-Yates shuffle
+        for i in (1..pairs.len()).rev() {
+            let j = (splitmix64(&mut state) % (i as u64 + 1)) as usize;
+            pairs.swap(i, j);
         }
+        Ok(pairs)
     }
 
-    /// Deletes entry and returns deserialized value
+    /// Collects up to `limit` entries with keys strictly greater than `last_key`, in order by key
     ///
-    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
-    pub fn remove<Q>(&mut self, tree_id: usize, key: &Q) -> Option<V>
+    /// If `last_key` is `None`, entries are collected from the start of the tree. Along with the
+    /// entries, the key to resume from is returned: it is the key of the last collected entry if
+    /// there are more entries after it, and `None` otherwise. This allows to process a big tree
+    /// in chunks of bounded size, e.g. a few entries per transaction.
+    ///
+    /// Returns [`Error::ZeroLimit`] if `limit` is `0`: an empty chunk can not be told apart from
+    /// the end of the tree.
+    ///
+    /// This function runs in `O(log(n) + limit)`, where `n` - is the number of nodes
+    #[allow(clippy::type_complexity)]
+    pub fn pairs_chunked<Q>(
+        &self,
+        tree_id: usize,
+        last_key: Option<&Q>,
+        limit: usize,
+    ) -> Result<(Vec<(K, V)>, Option<K>), Error>
     where
         K: Borrow<Q> + Ord,
         Q: Ord + ?Sized,
     {
-        if self.roots.len() > tree_id {
-            self.get_key_index(tree_id, key).map(|id| {
-                let deallocated_node_id = { self.delete_node(tree_id, id) };
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+        if limit == 0 {
+            return Err(Error::ZeroLimit);
+        }
 
-                let value = V::deserialize(&mut self.nodes[deallocated_node_id].value.as_slice())
-                    .expect("Value corrupted");
+        let mut maybe_id = match last_key {
+            Some(k) => self.strict_bound_index(tree_id, k, Ordering::Greater),
+            None => self.root(tree_id).map(|root_id| self.min(root_id as usize)),
+        };
+
+        let mut chunk = Vec::new();
+        let mut last_id = None;
+        while let Some(id) = maybe_id {
+            if chunk.len() == limit {
+                break;
+            }
+            chunk.push(self.entry_at(id));
+            last_id = Some(id);
+            maybe_id = self.successor(id);
+        }
+
+        // The key is deserialized once again to avoid `K: Clone` bound
+        let resume_key = maybe_id.and(last_id).map(|id| self.entry_key(id));
+        Ok((chunk, resume_key))
+    }
+
+    /// Serializes the contents of the forest as Borsh-encoded `Vec<BTreeMap<K, V>>`
+    ///
+    /// Unlike the raw slice, this representation contains only the stored key-value pairs, so it
+    /// is much smaller for sparse forests. The output can be decoded with a stock Borsh
+    /// implementation: the `n`-th map holds the contents of the `n`-th tree.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn to_borsh_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        (self.roots().len() as u32)
+            .serialize(&mut bytes)
+            .expect("Writing to Vec never fails");
+
+        for tree_id in 0..self.roots().len() {
+            let len = self.size(self.root(tree_id)) as u32;
+            len.serialize(&mut bytes)
+                .expect("Writing to Vec never fails");
+
+            // Borsh encodes `BTreeMap` as a sequence of pairs in ascending key order, which
+            // is exactly the order of the tree nodes, so the raw key and value buffers could be
+            // reencoded without sorting
+            for (key, value) in self.pairs(tree_id).expect("tree_id is always in range") {
+                key.serialize(&mut bytes)
+                    .expect("Writing to Vec never fails");
                 value
-            })
-        } else {
-            None
+                    .serialize(&mut bytes)
+                    .expect("Writing to Vec never fails");
+            }
         }
+        bytes
     }
 
-    /// Deletes entry and returns deserialized key-value pair
+    /// Replaces the contents of the forest with the ones, encoded by
+    /// [`to_borsh_bytes()`](RBForest::to_borsh_bytes)
     ///
-    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
-    pub fn remove_entry<Q>(&mut self, tree_id: usize, key: &Q) -> Option<(K, V)>
-    where
-        K: Borrow<Q> + Ord,
-        Q: Ord + ?Sized,
-    {
-        if self.roots.len() > tree_id {
-            self.get_key_index(tree_id, key).map(|id| {
-                let deallocated_node_id = { self.delete_node(tree_id, id) };
+    /// The forest is cleared before populating. If an error is returned, the forest may be
+    /// partially populated.
+    ///
+    /// This function runs in `O(n*log(n))`, where `n` - is the number of encoded key-value pairs
+    pub fn populate_from_borsh_bytes(&mut self, mut bytes: &[u8]) -> Result<(), Error> {
+        let trees_num =
+            u32::deserialize(&mut bytes).map_err(|_| Error::DeserializationError)? as usize;
+        if trees_num > self.roots().len() {
+            return Err(Error::TooBigTreeId);
+        }
 
-                let key = K::deserialize(&mut self.nodes[deallocated_node_id].key.as_slice())
-                    .expect("Key corrupted");
-                let value = V::deserialize(&mut self.nodes[deallocated_node_id].value.as_slice())
-                    .expect("Value corrupted");
-                (key, value)
-            })
+        self.clear();
+
+        for tree_id in 0..trees_num {
+            let len = u32::deserialize(&mut bytes).map_err(|_| Error::DeserializationError)?;
+            for _ in 0..len {
+                let key = K::deserialize(&mut bytes).map_err(|_| Error::DeserializationError)?;
+                let value = V::deserialize(&mut bytes).map_err(|_| Error::DeserializationError)?;
+                self.insert(tree_id, key, value)?;
+            }
+        }
+
+        if bytes.is_empty() {
+            Ok(())
         } else {
-            None
+            Err(Error::DeserializationError)
         }
     }
 
-    /// Deletes entry without deserializing the value.
+    /// Writes every entry of the tree to `out` as a length-delimited frame, in order by key
     ///
-    /// Return `true` if there was a value with the given `key`.
-    pub fn delete<Q>(&mut self, tree_id: usize, key: &Q) -> bool
-    where
-        K: Borrow<Q> + Ord,
-        Q: Ord + ?Sized,
-    {
-        if self.roots.len() > tree_id {
-            self.get_key_index(tree_id, key)
-                .map(|id| {
-                    self.delete_node(tree_id, id);
-                })
-                .is_some()
-        } else {
-            false
+    /// Each entry is written as `[key_len][key bytes][value_len][value bytes]`, where the lengths
+    /// are little-endian `u32` and the bytes are the Borsh encodings of the key and the value
+    /// without padding to `KSIZE` and `VSIZE`. Returns the number of written frames. The frames can
+    /// be read back with [`read_frames_into()`](RBForest::read_frames_into).
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn stream_frames(&self, tree_id: usize, out: &mut impl Write) -> Result<usize, Error> {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
         }
-    }
 
-    /// Creates an iterator over key-value pairs, in order by key
-    pub fn pairs<'b>(
-        &'b self,
-        tree_id: usize,
-    ) -> Result<PairsIterator<'b, 'a, K, V, KSIZE, VSIZE>, Error> {
-        if self.roots.len() > tree_id {
-            Ok(PairsIterator::from_raw_parts(
-                self,
-                self.root(tree_id).map(|root_id| self.min(root_id as usize)),
-            ))
-        } else {
-            Err(Error::TooBigTreeId)
+        let mut key_buffer = [0; KSIZE];
+        let mut value_buffer = [0; VSIZE];
+        let mut frames = 0;
+        for (key, value) in self.pairs(tree_id)? {
+            let key_len = serialized_len(&key, &mut key_buffer, "Key corrupted");
+            let value_len = serialized_len(&value, &mut value_buffer, "Value corrupted");
+
+            write_frame(out, &key_buffer[..key_len])?;
+            write_frame(out, &value_buffer[..value_len])?;
+            frames += 1;
         }
+        Ok(frames)
     }
 
-    /// Creates an iterator over keys, from smallest to biggest
-    pub fn keys<'b>(
-        &'b self,
-        tree_id: usize,
-    ) -> Result<KeysIterator<'b, 'a, K, V, KSIZE, VSIZE>, Error> {
-        if self.roots.len() > tree_id {
-            Ok(KeysIterator::from_raw_parts(
-                self,
-                self.root(tree_id).map(|root_id| self.min(root_id as usize)),
-            ))
-        } else {
-            Err(Error::TooBigTreeId)
+    /// Inserts the entries, written by [`stream_frames()`](RBForest::stream_frames), to the tree
+    ///
+    /// Frames are read until `bytes` are exhausted. Existing entries with the same keys are
+    /// overwritten, other entries of the tree are kept. Returns the number of read frames. If an
+    /// error is returned, the frames read before it are already inserted.
+    ///
+    /// This function runs in `O(m * log(n))`, where `m` - is the number of frames and `n` - is
+    /// the number of nodes
+    pub fn read_frames_into(&mut self, tree_id: usize, mut bytes: &[u8]) -> Result<usize, Error> {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
         }
+
+        let mut frames = 0;
+        while !bytes.is_empty() {
+            let key = K::try_from_slice(read_frame(&mut bytes)?)
+                .map_err(|_| Error::DeserializationError)?;
+            let value = V::try_from_slice(read_frame(&mut bytes)?)
+                .map_err(|_| Error::DeserializationError)?;
+
+            self.insert(tree_id, key, value)?;
+            frames += 1;
+        }
+        Ok(frames)
     }
 
-    /// Creates an iterator over values, in order by key
-    pub fn values<'b>(
-        &'b self,
+    /// Encodes the changes, which turn the tree `base_tree_id` of `base` into the tree `tree_id`
+    ///
+    /// The delta lists only the added, changed and removed keys, so it is much smaller than the
+    /// whole tree if the trees are similar. It can be applied to a copy of the base tree with
+    /// [`apply_delta()`](RBForest::apply_delta). The format is:
+    ///
+    /// - `[version: u8][KSIZE: u32][VSIZE: u32]` header, the current version is `1`;
+    /// - a sequence of records, in order by key: `[0][key: KSIZE bytes][value: VSIZE bytes]` for
+    ///   added and changed entries and `[1][key: KSIZE bytes]` for removed ones.
+    ///
+    /// Integers are little-endian, keys and values are copied from the node buffers as is. Values
+    /// are compared by their buffers, so a value is considered changed if its padding differs.
+    ///
+    /// This function runs in `O(n + m)`, where `n` and `m` - are the numbers of nodes of the trees
+    pub fn compute_delta(
+        &self,
         tree_id: usize,
-    ) -> Result<ValuesIterator<'b, 'a, K, V, KSIZE, VSIZE>, Error> {
-        if self.roots.len() > tree_id {
-            Ok(ValuesIterator::from_raw_parts(
-                self,
-                self.root(tree_id).map(|root_id| self.min(root_id as usize)),
-            ))
-        } else {
-            Err(Error::TooBigTreeId)
+        base: &Self,
+        base_tree_id: usize,
+    ) -> Result<Vec<u8>, Error> {
+        if self.roots().len() <= tree_id || base.roots().len() <= base_tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let mut ids = Vec::new();
+        self.collect_inorder(self.root(tree_id), &mut ids);
+        let mut base_ids = Vec::new();
+        base.collect_inorder(base.root(base_tree_id), &mut base_ids);
+
+        let mut delta = Vec::new();
+        delta.push(DELTA_VERSION);
+        delta.extend_from_slice(&(KSIZE as u32).to_le_bytes());
+        delta.extend_from_slice(&(VSIZE as u32).to_le_bytes());
+
+        let mut ids = ids.into_iter().peekable();
+        let mut base_ids = base_ids.into_iter().peekable();
+        loop {
+            let order = match (ids.peek(), base_ids.peek()) {
+                (Some(&id), Some(&base_id)) => self
+                    .entry_key(id as usize)
+                    .cmp(&base.entry_key(base_id as usize)),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => break,
+            };
+            match order {
+                Ordering::Less => {
+                    let node = &self.nodes()[ids.next().unwrap() as usize];
+                    delta.push(DELTA_UPSERT);
+                    delta.extend_from_slice(&node.key);
+                    delta.extend_from_slice(&node.value);
+                }
+                Ordering::Greater => {
+                    let base_node = &base.nodes()[base_ids.next().unwrap() as usize];
+                    delta.push(DELTA_REMOVE);
+                    delta.extend_from_slice(&base_node.key);
+                }
+                Ordering::Equal => {
+                    let node = &self.nodes()[ids.next().unwrap() as usize];
+                    let base_node = &base.nodes()[base_ids.next().unwrap() as usize];
+                    if node.value != base_node.value {
+                        delta.push(DELTA_UPSERT);
+                        delta.extend_from_slice(&node.key);
+                        delta.extend_from_slice(&node.value);
+                    }
+                }
+            }
         }
+        Ok(delta)
     }
 
-    /// Returns the first key-value pair in the map
+    /// Applies a delta, produced by [`compute_delta()`](RBForest::compute_delta), to the tree
     ///
-    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
-    #[must_use]
-    pub fn first_entry(&self, tree_id: usize) -> Option<(K, V)> {
-        if self.roots.len() > tree_id {
-            self.root(tree_id).map(|root_id| {
-                let node = &self.nodes[self.min(root_id as usize)];
-                let key = K::deserialize(&mut node.key.as_slice()).expect("Key corrupted");
-                let value = V::deserialize(&mut node.value.as_slice()).expect("Value corrupted");
-                (key, value)
-            })
-        } else {
-            None
+    /// The whole delta is decoded before any change is made, so a malformed delta leaves the tree
+    /// unchanged and returns [`Error::DeserializationError`], while a delta for other key or
+    /// value sizes returns [`Error::WrongKeySize`] or [`Error::WrongValueSize`]. If an insertion
+    /// fails (e.g. with [`Error::NoNodesLeft`]), the records before it stay applied.
+    ///
+    /// This function runs in `O(m * log(n))`, where `m` - is the number of records and `n` - is
+    /// the number of nodes
+    pub fn apply_delta(&mut self, tree_id: usize, delta: &[u8]) -> Result<(), Error> {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
         }
+
+        let (&version, mut header) = delta.split_first().ok_or(Error::DeserializationError)?;
+        if version != DELTA_VERSION || header.len() < 8 {
+            return Err(Error::DeserializationError);
+        }
+        let k_size = u32::deserialize(&mut header).map_err(|_| Error::DeserializationError)?;
+        let v_size = u32::deserialize(&mut header).map_err(|_| Error::DeserializationError)?;
+        if k_size as usize != KSIZE {
+            return Err(Error::WrongKeySize);
+        }
+        if v_size as usize != VSIZE {
+            return Err(Error::WrongValueSize);
+        }
+
+        let mut records = header;
+        while Self::next_delta_record(&mut records)?.is_some() {}
+
+        let mut records = header;
+        while let Some((key, maybe_value)) = Self::next_delta_record(&mut records)? {
+            match maybe_value {
+                Some(value) => {
+                    self.insert(tree_id, key, value)?;
+                }
+                None => {
+                    self.delete(tree_id, &key);
+                }
+            }
+        }
+        Ok(())
     }
 
-    /// Returns the last key-value pair in the map
+    /// Writes the forest to `out` as a compact copy of its raw slice without the free nodes
     ///
-    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
-    #[must_use]
-    pub fn last_entry(&self, tree_id: usize) -> Option<(K, V)> {
-        if self.roots.len() > tree_id {
-            self.root(tree_id).map(|root_id| {
-                let node = &self.nodes[self.max(root_id as usize)];
-                let key = K::deserialize(&mut node.key.as_slice()).expect("Key corrupted");
-                let value = V::deserialize(&mut node.value.as_slice()).expect("Value corrupted");
-                (key, value)
-            })
-        } else {
-            None
+    /// The output consists of the header, the roots and the occupied nodes. The nodes are
+    /// renumbered to be contiguous, so the free nodes are omitted. Unlike
+    /// [`to_borsh_bytes()`](RBForest::to_borsh_bytes), the tree structure is preserved, so
+    /// [`unpack_into()`](RBForest::unpack_into) does not need to rebalance anything. Returns
+    /// [`Error::WriteError`] if `out` fails, in which case a part of the output may be already
+    /// written.
+    ///
+    /// This function runs in `O(n)` and allocates `O(N)` memory, where `n` - is the number of
+    /// occupied nodes and `N` - is the total number of nodes
+    pub fn pack(&self, out: &mut impl Write) -> Result<(), Error> {
+        let mut ids = Vec::new();
+        for tree_id in 0..self.roots().len() {
+            self.collect_inorder(self.root(tree_id), &mut ids);
         }
+
+        let mut new_ids = vec![0; self.nodes().len()];
+        for (new_id, &id) in ids.iter().enumerate() {
+            new_ids[id as usize] = new_id as u32;
+        }
+        let remap = |maybe_id: Option<u32>| maybe_id.map(|id| new_ids[id as usize]);
+
+        let mut write = |bytes: &[u8]| out.write_all(bytes).map_err(|_| Error::WriteError);
+        write(bytes_of(self.header()))?;
+        for (tree_id, root) in self.roots().iter().enumerate() {
+            match self.root(tree_id) {
+                Some(id) => write(&u32::to_be_bytes(new_ids[id as usize]))?,
+                None => write(root)?,
+            }
+        }
+        for &id in &ids {
+            let mut node = self.nodes()[id as usize];
+            node.set_left(remap(node.left()));
+            node.set_right(remap(node.right()));
+            node.set_parent(remap(node.parent()));
+            write(bytes_of(&node))?;
+        }
+        Ok(())
     }
 
-    fn root(&self, id: usize) -> Option<u32> {
-        let num = u32::from_be_bytes(self.roots[id]);
-        if num == u32::MAX {
-            None
+    /// Initializes a new [`RBForest`] in `slice` and fills it with the contents, encoded by
+    /// [`pack()`](RBForest::pack)
+    ///
+    /// `slice` may have a different number of nodes than the packed forest, but it must be
+    /// able to hold all the packed nodes and keep the packed number of
+    /// [`reserved_nodes()`](RBForest::reserved_nodes) free, otherwise [`Error::TooSmall`] is
+    /// returned. The maximum number of trees, the reservations and the generation are taken from
+    /// the packed header. [`Error::DeserializationError`] is
+    /// returned if `packed` is malformed.
+    ///
+    /// This function runs in `O(N)`, where `N` - is the total number of nodes in `slice`
+    pub fn unpack_into(slice: &'a mut [u8], packed: &[u8]) -> Result<Self, Error> {
+        if packed.len() < mem::size_of::<Header>() {
+            return Err(Error::DeserializationError);
+        }
+        let (header, tail) = packed.split_at(mem::size_of::<Header>());
+        let header: &Header = from_bytes(header);
+
+        if !header.check_magic() {
+            return Err(Error::WrongMagic);
+        }
+        if header.k_size() as usize != KSIZE {
+            return Err(Error::WrongKeySize);
+        }
+        if header.v_size() as usize != VSIZE {
+            return Err(Error::WrongValueSize);
+        }
+
+        let max_roots = header.max_roots() as usize;
+        if tail.len() < max_roots * 4 {
+            return Err(Error::DeserializationError);
+        }
+        let (roots, nodes) = tail.split_at(max_roots * 4);
+        if nodes.len() % mem::size_of::<Node<KSIZE, VSIZE>>() != 0 {
+            return Err(Error::DeserializationError);
+        }
+        let roots: &[[u8; 4]] = cast_slice(roots);
+        let nodes: &[Node<KSIZE, VSIZE>] = cast_slice(nodes);
+
+        let is_valid =
+            |maybe_id: Option<u32>| maybe_id.map_or(true, |id| (id as usize) < nodes.len());
+        let roots_valid = roots.iter().all(|root| {
+            let root = u32::from_be_bytes(*root);
+            root == FREE_TREE || root == EMPTY_TREE || (root as usize) < nodes.len()
+        });
+        let nodes_valid = nodes
+            .iter()
+            .all(|node| is_valid(node.left()) && is_valid(node.right()) && is_valid(node.parent()));
+        if !roots_valid || !nodes_valid {
+            return Err(Error::DeserializationError);
+        }
+
+        let mut forest = Self::init_slice(slice, max_roots)?;
+        if forest.nodes().len() < nodes.len() {
+            return Err(Error::TooSmall);
+        }
+
+        forest.roots_mut().copy_from_slice(roots);
+        forest.nodes_mut()[..nodes.len()].copy_from_slice(nodes);
+
+        // The free list is threaded from the last node down to the first one, see `init_slice()`,
+        // so it just has to be cut after the unpacked nodes
+        if nodes.len() == forest.nodes().len() {
+            forest.header_mut().set_head(None);
         } else {
-            Some(num)
+            forest.nodes_mut()[nodes.len()].set_parent(None);
+        }
+        let free = forest.nodes().len() - nodes.len();
+        if header.reserved() as usize > free {
+            return Err(Error::TooSmall);
         }
+        forest.header_mut().set_free(free as u32);
+        forest.header_mut().set_reserved(header.reserved());
+        forest.header_mut().set_generation(header.generation());
+
+        Ok(forest)
     }
 
-    pub(super) fn set_root(&mut self, id: usize, root: Option<u32>) {
-        match root {
-            Some(idx) => {
-                assert!(idx < u32::MAX);
-                self.roots[id] = u32::to_be_bytes(idx);
-            }
-            None => {
-                self.roots[id] = u32::to_be_bytes(u32::MAX);
-            }
+    fn nodes(&self) -> &[Node<KSIZE, VSIZE>] {
+        cast_slice(&self.slice[mem::size_of::<Header>()..self.roots_offset()])
+    }
+
+    fn nodes_mut(&mut self) -> &mut [Node<KSIZE, VSIZE>] {
+        let roots_offset = self.roots_offset();
+        cast_slice_mut(&mut self.slice[mem::size_of::<Header>()..roots_offset])
+    }
+
+    fn lens(&self) -> &[[u8; 4]] {
+        cast_slice(&self.slice[self.lens_offset()..])
+    }
+
+    fn roots_offset(&self) -> usize {
+        mem::size_of::<Header>() + self.node_count * mem::size_of::<Node<KSIZE, VSIZE>>()
+    }
+
+    /// The cached lengths take the second half of the tree slots
+    fn lens_offset(&self) -> usize {
+        let roots_offset = self.roots_offset();
+        roots_offset + (self.slice.len() - roots_offset) / 2
+    }
+
+    /// Drops the cached lengths of all the trees
+    fn invalidate_lens(&mut self) {
+        for len in self.lens_mut() {
+            *len = u32::to_be_bytes(NO_LEN);
         }
     }
 
     #[must_use]
     fn size(&self, maybe_id: Option<u32>) -> usize {
         if let Some(id) = maybe_id {
-            let node = self.nodes[id as usize];
+            let node = self.nodes()[id as usize];
             self.size(node.left()) + self.size(node.right()) + 1
         } else {
             0
         }
     }
 
+    // `lower` and `upper` are the keys of the nearest nodes, from which the descent went right and
+    // left respectively. They are used only to check consistency of the `Ord` implementation in
+    // debug builds.
+    #[allow(clippy::too_many_arguments)]
+    #[cfg_attr(
+        not(debug_assertions),
+        allow(unused_variables, clippy::only_used_in_recursion)
+    )]
     fn put(
         &mut self,
         tree_id: usize,
@@ -635,25 +4137,32 @@ where
         parent: Option<u32>,
         key: K,
         value: V,
+        lower: Option<&K>,
+        upper: Option<&K>,
     ) -> Result<(u32, Option<V>), Error> {
         if let Some(mut id) = maybe_id {
             let old_val;
-            let node = &self.nodes[id as usize];
+            let node = &self.nodes()[id as usize];
             let node_key = K::deserialize(&mut node.key.as_slice()).expect("Key corrupted");
-            match key.cmp(node_key.borrow()) {
+            let ordering = key.cmp(node_key.borrow());
+            #[cfg(debug_assertions)]
+            debug_check_ord(&key, &node_key, ordering, lower, upper);
+            match ordering {
                 Ordering::Less => {
                     let left_result = self.put(
                         tree_id,
-                        self.nodes[id as usize].left(),
+                        self.nodes()[id as usize].left(),
                         Some(id),
                         key,
                         value,
+                        lower,
+                        Some(&node_key),
                     );
                     match left_result {
                         Ok((child_id, val)) => {
                             old_val = val;
                             {
-                                self.nodes[id as usize].set_left(Some(child_id));
+                                self.nodes_mut()[id as usize].set_left(Some(child_id));
                             }
                         }
                         Err(e) => return Err(e),
@@ -662,73 +4171,29 @@ where
                 Ordering::Greater => {
                     let right_result = self.put(
                         tree_id,
-                        self.nodes[id as usize].right(),
+                        self.nodes()[id as usize].right(),
                         Some(id),
                         key,
                         value,
+                        Some(&node_key),
+                        upper,
                     );
                     match right_result {
                         Ok((child_id, val)) => {
                             old_val = val;
                             {
-                                self.nodes[id as usize].set_right(Some(child_id));
+                                self.nodes_mut()[id as usize].set_right(Some(child_id));
                             }
                         }
                         Err(e) => return Err(e),
                     }
                 }
                 Ordering::Equal => {
-                    old_val = V::deserialize(&mut self.nodes[id as usize].value.as_slice()).ok();
-                    // This is needed to check if the value fits in the slice
-                    // Otherwise we can invalidate data in the node
-                    let serialization_container = &mut self.buffer;
-                    let serialization_result =
-                        value.serialize(&mut serialization_container.as_mut_slice());
-
-                    match serialization_result {
-                        Ok(()) => self.nodes[id as usize]
-                            .value
-                            .copy_from_slice(serialization_container.as_slice()),
-                        Err(_) => return Err(Error::ValueSerializationError),
-                    }
+                    old_val = V::deserialize(&mut self.nodes()[id as usize].value.as_slice()).ok();
+                    self.write_value(id as usize, &value)?;
                 }
             }
-            let right_left_subnode = match self.nodes[id as usize].right() {
-                Some(sub_id) => self.nodes[sub_id as usize].left(),
-                None => None,
-            };
-
-            if self.is_red(self.nodes[id as usize].right()) && self.is_red(right_left_subnode) {
-                self.rotate_right(tree_id, self.nodes[id as usize].right().unwrap());
-            }
-
-            if self.is_red(self.nodes[id as usize].right())
-                && !self.is_red(self.nodes[id as usize].left())
-            {
-                id = self.rotate_left(tree_id, id);
-            }
-
-            let left_subnode = match self.nodes[id as usize].left() {
-                Some(sub_id) => self.nodes[sub_id as usize].left(),
-                None => None,
-            };
-
-            if self.is_red(self.nodes[id as usize].left()) && self.is_red(left_subnode) {
-                id = self.rotate_right(tree_id, id);
-            }
-
-            if self.is_red(self.nodes[id as usize].right())
-                && self.is_red(self.nodes[id as usize].left())
-            {
-                // If nodes are red, they are not Option::None, so unwrap will never fail
-                let left_id = self.nodes[id as usize].left().unwrap() as usize;
-                let right_id = self.nodes[id as usize].right().unwrap() as usize;
-
-                // Color swap
-                self.nodes[left_id].set_is_red(false);
-                self.nodes[right_id].set_is_red(false);
-                self.nodes[id as usize].set_is_red(true);
-            }
+            id = self.balance_after_put(tree_id, id);
 
             Ok((id, old_val))
         } else {
@@ -736,8 +4201,11 @@ where
                 Some(id) => id,
                 None => return Err(Error::NoNodesLeft),
             };
-            let new_node = &mut self.nodes[new_id];
+            self.invalidate_len(tree_id);
+            let new_node = &mut self.nodes_mut()[new_id];
 
+            // The key and the value are zeroed here, so no stale bytes of the previous entry are
+            // left after the serialized data
             new_node.init_node(parent);
 
             // Here it is ok to write directly to slice, because in case of error the node
@@ -758,12 +4226,77 @@ where
         }
     }
 
-    #[must_use]
-    fn is_red(&self, maybe_id: Option<u32>) -> bool {
-        match maybe_id {
-            Some(id) => self.nodes[id as usize].is_red(),
-            None => false,
-        }
+    fn put_bytes(
+        &mut self,
+        tree_id: usize,
+        maybe_id: Option<u32>,
+        parent: Option<u32>,
+        key: &[u8; KSIZE],
+        value: &[u8; VSIZE],
+    ) -> Result<(u32, Option<[u8; VSIZE]>), Error> {
+        if let Some(mut id) = maybe_id {
+            let old_val;
+            let node = &self.nodes()[id as usize];
+            match key.cmp(&node.key) {
+                Ordering::Less => {
+                    let (child_id, val) =
+                        self.put_bytes(tree_id, node.left(), Some(id), key, value)?;
+                    old_val = val;
+                    self.nodes_mut()[id as usize].set_left(Some(child_id));
+                }
+                Ordering::Greater => {
+                    let (child_id, val) =
+                        self.put_bytes(tree_id, node.right(), Some(id), key, value)?;
+                    old_val = val;
+                    self.nodes_mut()[id as usize].set_right(Some(child_id));
+                }
+                Ordering::Equal => {
+                    old_val = Some(node.value);
+                    self.nodes_mut()[id as usize].value = *value;
+                }
+            }
+
+            id = self.balance_after_put(tree_id, id);
+
+            Ok((id, old_val))
+        } else {
+            let new_id = self.allocate_node().ok_or(Error::NoNodesLeft)?;
+            self.invalidate_len(tree_id);
+            let new_node = &mut self.nodes_mut()[new_id];
+
+            new_node.init_node(parent);
+            new_node.key = *key;
+            new_node.value = *value;
+
+            Ok((new_id as u32, None))
+        }
+    }
+
+    /// Copies the subtree into newly allocated nodes of `dst` and returns the index of its root
+    ///
+    /// `dst` must have enough free nodes.
+    fn copy_subtree(
+        &self,
+        dst: &mut RBForest<'_, K, V, KSIZE, VSIZE>,
+        maybe_id: Option<u32>,
+        parent: Option<u32>,
+    ) -> Option<u32> {
+        let node = &self.nodes()[maybe_id? as usize];
+        let new_id = dst.allocate_node().expect("dst has enough free nodes");
+        let new_id_u32 = new_id as u32;
+
+        let left = self.copy_subtree(dst, node.left(), Some(new_id_u32));
+        let right = self.copy_subtree(dst, node.right(), Some(new_id_u32));
+
+        let new_node = &mut dst.nodes_mut()[new_id];
+        new_node.key = node.key;
+        new_node.value = node.value;
+        new_node.set_left(left);
+        new_node.set_right(right);
+        new_node.set_parent(parent);
+        new_node.set_is_red(node.is_red());
+
+        Some(new_id_u32)
     }
 
     fn get_key_index<Q>(&self, tree_id: usize, k: &Q) -> Option<usize>
@@ -771,11 +4304,59 @@ where
         K: Borrow<Q> + Ord,
         Q: Ord + ?Sized,
     {
+        self.try_get_key_index(tree_id, k).expect("Key corrupted")
+    }
+
+    fn try_get_key_index<Q>(&self, tree_id: usize, k: &Q) -> Result<Option<usize>, Error>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        // keys of the nearest nodes, from which the descent went right and left respectively
+        #[cfg(debug_assertions)]
+        let (mut lower, mut upper): (Option<K>, Option<K>) = (None, None);
         let mut maybe_id = self.root(tree_id);
         while let Some(id) = maybe_id {
-            let node = &self.nodes[id as usize];
-            let node_key = K::deserialize(&mut node.key.as_slice()).expect("Key corrupted");
-            match k.cmp(node_key.borrow()) {
+            let node = &self.nodes()[id as usize];
+            let node_key = K::deserialize(&mut node.key.as_slice())
+                .map_err(|_| Error::KeySerializationError)?;
+            let ordering = k.cmp(node_key.borrow());
+            #[cfg(debug_assertions)]
+            debug_check_ord(
+                k,
+                node_key.borrow(),
+                ordering,
+                lower.as_ref().map(Borrow::borrow),
+                upper.as_ref().map(Borrow::borrow),
+            );
+            match ordering {
+                Ordering::Equal => {
+                    return Ok(Some(id as usize));
+                }
+                Ordering::Less => {
+                    maybe_id = node.left();
+                    #[cfg(debug_assertions)]
+                    {
+                        upper = Some(node_key);
+                    }
+                }
+                Ordering::Greater => {
+                    maybe_id = node.right();
+                    #[cfg(debug_assertions)]
+                    {
+                        lower = Some(node_key);
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn get_key_bytes_index(&self, tree_id: usize, key_bytes: &[u8]) -> Option<usize> {
+        let mut maybe_id = self.root(tree_id);
+        while let Some(id) = maybe_id {
+            let node = &self.nodes()[id as usize];
+            match key_bytes.cmp(node.key.as_slice()) {
                 Ordering::Equal => {
                     return Some(id as usize);
                 }
@@ -786,429 +4367,457 @@ where
         None
     }
 
-    fn rotate_left(&mut self, tree_id: usize, h: u32) -> u32 {
-        let x = self.nodes[h as usize]
-            .right()
-            .expect("RBTree invariants corrupted: rotate_left on subtree without right child");
+    /// Returns indices of the nodes with the greatest key `<= k` and the least key `>= k`
+    fn surrounding_indices<Q>(&self, tree_id: usize, k: &Q) -> (Option<usize>, Option<usize>)
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        let mut floor = None;
+        let mut ceil = None;
+        let mut maybe_id = self.root(tree_id);
+        while let Some(id) = maybe_id {
+            let node = &self.nodes()[id as usize];
+            let node_key = K::deserialize(&mut node.key.as_slice()).expect("Key corrupted");
+            match k.cmp(node_key.borrow()) {
+                Ordering::Equal => {
+                    floor = Some(id as usize);
+                    ceil = Some(id as usize);
+                    break;
+                }
+                Ordering::Less => {
+                    ceil = Some(id as usize);
+                    maybe_id = node.left();
+                }
+                Ordering::Greater => {
+                    floor = Some(id as usize);
+                    maybe_id = node.right();
+                }
+            }
+        }
 
-        self.nodes[h as usize].set_right(self.nodes[x as usize].left());
-        self.nodes[x as usize].set_left(Some(h));
-        self.nodes[x as usize].set_is_red(self.nodes[h as usize].is_red());
-        self.nodes[h as usize].set_is_red(true);
+        (floor, ceil)
+    }
 
-        // fix parents
-        if let Some(parent_id) = self.nodes[h as usize].parent() {
-            let parent_node = &mut self.nodes[parent_id as usize];
-            if parent_node.left() == Some(h) {
-                parent_node.set_left(Some(x));
+    /// Returns the index of the node with the nearest key, which compares to `k` as `side`
+    ///
+    /// `side` must be either `Ordering::Less` or `Ordering::Greater`
+    fn strict_bound_index<Q>(&self, tree_id: usize, k: &Q, side: Ordering) -> Option<usize>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        let mut result = None;
+        let mut maybe_id = self.root(tree_id);
+        while let Some(id) = maybe_id {
+            let node = &self.nodes()[id as usize];
+            let node_key = K::deserialize(&mut node.key.as_slice()).expect("Key corrupted");
+            if node_key.borrow().cmp(k) == side {
+                result = Some(id as usize);
+                maybe_id = if side == Ordering::Less {
+                    node.right()
+                } else {
+                    node.left()
+                };
             } else {
-                debug_assert_eq!(parent_node.right(), Some(h));
-
-                parent_node.set_right(Some(x));
+                maybe_id = if side == Ordering::Less {
+                    node.left()
+                } else {
+                    node.right()
+                };
             }
-        } else {
-            self.set_root(tree_id, Some(x));
-        }
-        self.nodes[x as usize].set_parent(self.nodes[h as usize].parent());
-        self.nodes[h as usize].set_parent(Some(x));
-        if let Some(right) = self.nodes[h as usize].right() {
-            self.nodes[right as usize].set_parent(Some(h));
         }
+        result
+    }
 
-        x
+    /// Checks, that the node `id` is reachable from the root of the tree
+    ///
+    /// Parent links of free nodes are not cleared, so each link is checked in both directions.
+    fn is_in_tree(&self, tree_id: usize, id: usize) -> bool {
+        let mut id = id as u32;
+        // The walk is bounded, so corrupted links can not cause an endless loop
+        for _ in 0..self.nodes().len() {
+            match self.nodes()[id as usize].parent() {
+                Some(parent_id) => {
+                    let parent = &self.nodes()[parent_id as usize];
+                    if parent.left() != Some(id) && parent.right() != Some(id) {
+                        return false;
+                    }
+                    id = parent_id;
+                }
+                None => return self.root(tree_id) == Some(id),
+            }
+        }
+        false
     }
 
-    fn rotate_right(&mut self, tree_id: usize, h: u32) -> u32 {
-        let x = self.nodes[h as usize]
-            .left()
-            .expect("RBTree invariants corrupted: rotate_left on subtree without left child");
+    /// Replaces the value, stored in the node `id`
+    ///
+    /// The value is serialized to the zeroed buffer first, so that a failure does not corrupt the
+    /// node and the bytes after the serialized value do not keep stale data.
+    fn write_value(&mut self, id: usize, value: &V) -> Result<(), Error> {
+        self.buffer.fill(0);
+        if value.serialize(&mut self.buffer.as_mut_slice()).is_err() {
+            return Err(Error::ValueSerializationError);
+        }
+        let buffer = self.buffer;
+        self.nodes_mut()[id].value = buffer;
+        self.bump_generation();
+        Ok(())
+    }
 
-        self.nodes[h as usize].set_left(self.nodes[x as usize].right());
-        self.nodes[x as usize].set_right(Some(h));
-        self.nodes[x as usize].set_is_red(self.nodes[h as usize].is_red());
-        self.nodes[h as usize].set_is_red(true);
+    /// Decodes a single record of a delta and advances `records` past it
+    ///
+    /// The value is `None` for removal records. Returns `Ok(None)` if there are no records left.
+    #[allow(clippy::type_complexity)]
+    fn next_delta_record(records: &mut &[u8]) -> Result<Option<(K, Option<V>)>, Error> {
+        let Some((&tag, tail)) = records.split_first() else {
+            return Ok(None);
+        };
+        let value_len = match tag {
+            DELTA_UPSERT => VSIZE,
+            DELTA_REMOVE => 0,
+            _ => return Err(Error::DeserializationError),
+        };
+        if tail.len() < KSIZE + value_len {
+            return Err(Error::DeserializationError);
+        }
 
-        // fix parents
-        if let Some(parent_id) = self.nodes[h as usize].parent() {
-            let parent_node = &mut self.nodes[parent_id as usize];
-            if parent_node.left() == Some(h) {
-                parent_node.set_left(Some(x));
-            } else {
-                debug_assert_eq!(parent_node.right(), Some(h));
+        let (key_bytes, tail) = tail.split_at(KSIZE);
+        let (value_bytes, tail) = tail.split_at(value_len);
+        let key = K::deserialize(&mut &key_bytes[..]).map_err(|_| Error::DeserializationError)?;
+        let maybe_value = if tag == DELTA_UPSERT {
+            Some(V::deserialize(&mut &value_bytes[..]).map_err(|_| Error::DeserializationError)?)
+        } else {
+            None
+        };
+        *records = tail;
+        Ok(Some((key, maybe_value)))
+    }
 
-                parent_node.set_right(Some(x));
+    /// Merges the keys of the trees `a` and `b` in order, yielding each key once together with
+    /// the flags of its presence in `a` and `b`
+    fn merged_keys<'b>(
+        &'b self,
+        a: usize,
+        b: usize,
+    ) -> Result<impl Iterator<Item = (K, bool, bool)> + 'b, Error> {
+        let mut a_keys = self.keys(a)?.peekable();
+        let mut b_keys = self.keys(b)?.peekable();
+        Ok(core::iter::from_fn(move || {
+            let order = match (a_keys.peek(), b_keys.peek()) {
+                (Some(a_key), Some(b_key)) => a_key.cmp(b_key),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => return None,
+            };
+            match order {
+                Ordering::Less => a_keys.next().map(|key| (key, true, false)),
+                Ordering::Greater => b_keys.next().map(|key| (key, false, true)),
+                Ordering::Equal => {
+                    b_keys.next();
+                    a_keys.next().map(|key| (key, true, true))
+                }
             }
-        } else {
-            self.set_root(tree_id, Some(x));
-        }
-        self.nodes[x as usize].set_parent(self.nodes[h as usize].parent());
-        self.nodes[h as usize].set_parent(Some(x));
-        if let Some(left) = self.nodes[h as usize].left() {
-            self.nodes[left as usize].set_parent(Some(h));
-        }
+        }))
+    }
 
-        x
+    /// Deserializes the key, stored in the node `id`
+    fn entry_key(&self, id: usize) -> K {
+        K::deserialize(&mut self.nodes()[id].key.as_slice()).expect("Key corrupted")
     }
 
-    fn delete_node<Q>(&mut self, tree_id: usize, mut id: usize) -> usize
+    /// Deserializes the key-value pair, stored in the node `id`
+    fn entry_at(&self, id: usize) -> (K, V) {
+        let node = &self.nodes()[id];
+        let key = K::deserialize(&mut node.key.as_slice()).expect("Key corrupted");
+        let value = V::deserialize(&mut node.value.as_slice()).expect("Value corrupted");
+        (key, value)
+    }
+
+    fn visit_subtree<F>(&self, maybe_id: Option<u32>, f: &mut F)
     where
-        K: Borrow<Q> + Ord,
-        Q: Ord + ?Sized,
+        F: FnMut(&K, &V) -> Descend,
     {
-        if self.nodes[id].left().is_some() && self.nodes[id].right().is_some() {
-            {
-                id = self.swap_max_left(id);
+        if let Some(id) = maybe_id {
+            let node = &self.nodes()[id as usize];
+            let (key, value) = self.entry_at(id as usize);
+            let descend = f(&key, &value);
+            if matches!(descend, Descend::Both | Descend::Left) {
+                self.visit_subtree(node.left(), f);
+            }
+            if matches!(descend, Descend::Both | Descend::Right) {
+                self.visit_subtree(node.right(), f);
             }
         }
+    }
 
-        match (self.nodes[id].left(), self.nodes[id].right()) {
-            (Some(_), Some(_)) => {
-                unreachable!("swap_max_left() returned a node with two children");
-            }
-            (Some(left), None) => {
-                let left_id = left as usize;
-                // This node has to be black, its child has to be red
-                debug_assert!(!self.nodes[id].is_red());
-                debug_assert!(self.nodes[left_id].is_red());
+    /// Appends the indices of the nodes of the subtree to `ids` in key order
+    fn collect_inorder(&self, maybe_id: Option<u32>, ids: &mut Vec<u32>) {
+        if let Some(id) = maybe_id {
+            self.collect_inorder(self.nodes()[id as usize].left(), ids);
+            ids.push(id);
+            self.collect_inorder(self.nodes()[id as usize].right(), ids);
+        }
+    }
 
-                self.swap_nodes(id, left_id);
+    /// Allocates a node, which is not linked to any tree, and pushes its index to `ids`
+    fn push_unlinked(&mut self, key: &K, value: &V, ids: &mut Vec<u32>) -> Result<(), Error> {
+        let mut key_buffer = [0; KSIZE];
+        if key.serialize(&mut key_buffer.as_mut_slice()).is_err() {
+            return Err(Error::KeySerializationError);
+        }
+        let id = self.allocate_node().ok_or(Error::NoNodesLeft)?;
+        if let Err(err) = self.write_value(id, value) {
+            self.deallocate_node(id);
+            return Err(err);
+        }
+        self.nodes_mut()[id].key = key_buffer;
+        ids.push(id as u32);
+        Ok(())
+    }
 
-                self.nodes[id].set_left(None);
-                self.deallocate_node(left_id);
+    /// Links the nodes `ids`, sorted by key, into a subtree of minimal height and returns its root
+    ///
+    /// Nodes deeper than `full_levels` are colored red, all the others are black.
+    fn build_balanced(
+        &mut self,
+        ids: &[u32],
+        parent: Option<u32>,
+        depth: u32,
+        full_levels: u32,
+    ) -> Option<u32> {
+        if ids.is_empty() {
+            return None;
+        }
 
-                left_id
-            }
-            (None, Some(right)) => {
-                let right_id = right as usize;
-                // This node has to be black, its child has to be red
-                debug_assert!(!self.nodes[id].is_red());
-                debug_assert!(self.nodes[right_id].is_red());
+        let mid = ids.len() / 2;
+        let id = ids[mid];
+        let left = self.build_balanced(&ids[..mid], Some(id), depth + 1, full_levels);
+        let right = self.build_balanced(&ids[mid + 1..], Some(id), depth + 1, full_levels);
 
-                self.swap_nodes(id, right_id);
+        let node = &mut self.nodes_mut()[id as usize];
+        node.set_left(left);
+        node.set_right(right);
+        node.set_parent(parent);
+        node.set_is_red(depth >= full_levels);
 
-                self.nodes[id].set_right(None);
+        Some(id)
+    }
 
-                self.deallocate_node(right_id);
+    /// Returns the index of the node, which precedes `id` in key order
+    fn predecessor(&self, mut id: usize) -> Option<usize> {
+        if let Some(left_id) = self.nodes()[id].left() {
+            return Some(self.max(left_id as usize));
+        }
+        while let Some(parent_id) = self.nodes()[id].parent() {
+            let parent_id = parent_id as usize;
+            if Some(id as u32) == self.nodes()[parent_id].right() {
+                return Some(parent_id);
+            }
+            id = parent_id;
+        }
+        None
+    }
 
-                right_id
+    /// Returns the index of the node, which follows `id` in key order
+    fn successor(&self, mut id: usize) -> Option<usize> {
+        if let Some(right_id) = self.nodes()[id].right() {
+            return Some(self.min(right_id as usize));
+        }
+        while let Some(parent_id) = self.nodes()[id].parent() {
+            let parent_id = parent_id as usize;
+            if Some(id as u32) == self.nodes()[parent_id].left() {
+                return Some(parent_id);
             }
-            (None, None) => {
-                if self.nodes[id].is_red() {
-                    // Root node is always black, so if nodes[id] is red, it always has a parent
-                    let parent_id = self.nodes[id].parent().unwrap();
-                    let parent_node = &mut self.nodes[parent_id as usize];
-
-                    if parent_node.left() == Some(id as u32) {
-                        parent_node.set_left(None);
-                    } else {
-                        debug_assert_eq!(parent_node.right(), Some(id as u32));
-
-                        parent_node.set_right(None);
-                    }
+            id = parent_id;
+        }
+        None
+    }
 
-                    self.deallocate_node(id);
+    fn min(&self, mut min_id: usize) -> usize {
+        while let Some(id) = self.nodes()[min_id].left() {
+            min_id = id as usize;
+        }
+        min_id
+    }
 
-                    id
-                } else {
-                    if let Some(parent_id) = self.nodes[id].parent() {
-                        let parent_node = &mut self.nodes[parent_id as usize];
-                        if parent_node.left() == Some(id as u32) {
-                            parent_node.set_left(None);
-                        } else {
-                            debug_assert_eq!(parent_node.right(), Some(id as u32));
-
-                            parent_node.set_right(None);
-                        }
+    fn max(&self, mut max_id: usize) -> usize {
+        while let Some(id) = self.nodes()[max_id].right() {
+            max_id = id as usize;
+        }
+        max_id
+    }
+}
 
-                        self.balance_subtree(tree_id, parent_id as usize);
-                    } else {
-                        self.set_root(tree_id, None);
-                    }
+impl<'a, K, V, const KSIZE: usize, const VSIZE: usize> RawNodes for RBForest<'a, K, V, KSIZE, VSIZE>
+where
+    K: Ord + BorshDeserialize + BorshSerialize,
+    V: BorshDeserialize + BorshSerialize,
+{
+    fn header(&self) -> &Header {
+        from_bytes(&self.slice[..mem::size_of::<Header>()])
+    }
 
-                    self.deallocate_node(id);
+    fn header_mut(&mut self) -> &mut Header {
+        from_bytes_mut(&mut self.slice[..mem::size_of::<Header>()])
+    }
 
-                    id
-                }
-            }
-        }
+    fn links(&self, id: usize) -> &[u8] {
+        &bytes_of(&self.nodes()[id])[KSIZE + VSIZE..]
     }
 
-    #[must_use]
-    fn swap_max_left(&mut self, id: usize) -> usize {
-        let mut max_id = self.nodes[id]
-            .left()
-            .expect("swap_max_left should only be called on nodes with two children")
-            as usize;
-        while let Some(maybe_max) = self.nodes[max_id].right() {
-            max_id = maybe_max as usize;
-        }
+    fn links_mut(&mut self, id: usize) -> &mut [u8] {
+        &mut bytes_of_mut(&mut self.nodes_mut()[id])[KSIZE + VSIZE..]
+    }
 
-        debug_assert_ne!(id, max_id);
-        self.swap_nodes(id, max_id);
-        max_id
+    fn swap_entries(&mut self, a: usize, b: usize) {
+        let tmp_key = self.nodes()[a].key;
+        self.nodes_mut()[a].key = self.nodes()[b].key;
+        self.nodes_mut()[b].key = tmp_key;
+
+        let tmp_value = self.nodes()[a].value;
+        self.nodes_mut()[a].value = self.nodes()[b].value;
+        self.nodes_mut()[b].value = tmp_value;
     }
 
-    fn swap_nodes(&mut self, a: usize, b: usize) {
-        let tmp_key = self.nodes[a].key;
-        self.nodes[a].key = self.nodes[b].key;
-        self.nodes[b].key = tmp_key;
-
-        let tmp_value = self.nodes[a].value;
-        self.nodes[a].value = self.nodes[b].value;
-        self.nodes[b].value = tmp_value;
-    }
-
-    fn balance_subtree(&mut self, tree_id: usize, id: usize) {
-        let left_child = self.nodes[id].left();
-        let right_child = self.nodes[id].right();
-        let left_depth = self.black_depth(left_child);
-        let right_depth = self.black_depth(right_child);
-        match left_depth.cmp(&right_depth) {
-            Ordering::Greater => {
-                // left_depth is greater than right_depth, so it is >= 1 and therefore left_child
-                // always exists
-                let left_id = left_child.unwrap() as usize;
-                if self.nodes[id].is_red() {
-                    debug_assert!(!self.nodes[left_id].is_red());
-                    let left_grandchild = self.nodes[left_id].left();
-                    let right_grandchild = self.nodes[left_id].right();
-                    match (self.is_red(left_grandchild), self.is_red(right_grandchild)) {
-                        (false, false) => {
-                            self.nodes[id].set_is_red(false);
-                            self.nodes[left_id].set_is_red(true);
-                        }
-                        (true, _) => {
-                            self.rotate_right(tree_id, id as u32);
+    fn roots(&self) -> &[[u8; 4]] {
+        cast_slice(&self.slice[self.roots_offset()..self.lens_offset()])
+    }
 
-                            self.nodes[id].set_is_red(false);
-                            self.nodes[left_id].set_is_red(true);
-                            // left_grandchild is red, so it exists
-                            self.nodes[left_grandchild.unwrap() as usize].set_is_red(false);
-                        }
-                        (false, true) => {
-                            self.rotate_left(tree_id, left_id as u32);
-                            self.rotate_right(tree_id, id as u32);
-                            // right_grandchild is red, so it exists
-                            self.nodes[right_grandchild.unwrap() as usize].set_is_red(false);
-                        }
-                    }
-                } else if self.nodes[left_id].is_red() {
-                    debug_assert!(!self.is_red(self.nodes[left_id].left()));
-                    debug_assert!(!self.is_red(self.nodes[left_id].right()));
-                    // left_depth is greater than right_depth, so it is >= 1
-                    // left_child is red and does not affect black height
-                    // therefore left and right grandchildren exists
-                    let right_grandchild = self.nodes[left_id].right().unwrap() as usize;
-                    let left_grandgrandchild = self.nodes[right_grandchild].left();
-                    let right_grandgrandchild = self.nodes[right_grandchild].right();
-
-                    match (
-                        self.is_red(left_grandgrandchild),
-                        self.is_red(right_grandgrandchild),
-                    ) {
-                        (false, false) => {
-                            self.rotate_right(tree_id, id as u32);
-                            self.nodes[id].set_is_red(false);
-                            self.nodes[right_grandchild].set_is_red(true);
-                        }
-                        (true, _) => {
-                            self.rotate_left(tree_id, left_id as u32);
-                            self.rotate_right(tree_id, id as u32);
-                            // left_grandgrandchild is red, so it always exists
-                            self.nodes[left_grandgrandchild.unwrap() as usize].set_is_red(false);
-                            self.nodes[right_grandchild].set_is_red(false);
-                            self.nodes[id].set_is_red(false);
-                        }
-                        (false, true) => {
-                            self.rotate_left(tree_id, right_grandchild as u32);
-                            self.rotate_left(tree_id, left_id as u32);
-                            self.rotate_right(tree_id, id as u32);
-                            // left_grandgrandchild is red, so it always exists
-                            self.nodes[right_grandgrandchild.unwrap() as usize].set_is_red(false);
-                            self.nodes[right_grandchild].set_is_red(false);
-                            self.nodes[id].set_is_red(false);
-                        }
-                    }
-                } else {
-                    let left_grandchild = self.nodes[left_id].left();
-                    let right_grandchild = self.nodes[left_id].right();
-
-                    match (self.is_red(left_grandchild), self.is_red(right_grandchild)) {
-                        (false, false) => {
-                            self.nodes[left_id].set_is_red(true);
-                            if let Some(parent_id) = self.nodes[id].parent() {
-                                self.balance_subtree(tree_id, parent_id as usize);
-                            }
-                        }
-                        (_, true) => {
-                            self.rotate_left(tree_id, left_id as u32);
-                            self.rotate_right(tree_id, id as u32);
-                            self.nodes[left_id].set_is_red(false);
-                            self.nodes[id].set_is_red(false);
-                        }
-                        (true, false) => {
-                            self.nodes[left_grandchild.unwrap() as usize].set_is_red(false);
-                            self.rotate_right(tree_id, id as u32);
-                            self.nodes[id].set_is_red(false);
-                        }
-                    }
-                }
-            }
-            Ordering::Less => {
-                // right_depth is greater than left_depth, so it >= 1 and therefore right_child
-                // always exists
-                let right_id = right_child.unwrap() as usize;
-                if self.nodes[id].is_red() {
-                    debug_assert!(!self.nodes[right_id].is_red());
-                    let right_grandchild = self.nodes[right_id].right();
-                    let left_grandchild = self.nodes[right_id].left();
-                    match (self.is_red(right_grandchild), self.is_red(left_grandchild)) {
-                        (false, false) => {
-                            self.nodes[id].set_is_red(false);
-                            self.nodes[right_id].set_is_red(true);
-                        }
-                        (true, _) => {
-                            self.rotate_left(tree_id, id as u32);
+    fn roots_mut(&mut self) -> &mut [[u8; 4]] {
+        let roots_offset = self.roots_offset();
+        let lens_offset = self.lens_offset();
+        cast_slice_mut(&mut self.slice[roots_offset..lens_offset])
+    }
 
-                            self.nodes[id].set_is_red(false);
-                            self.nodes[right_id].set_is_red(true);
-                            // right_grandchild is red, so it always exists
-                            self.nodes[right_grandchild.unwrap() as usize].set_is_red(false);
-                        }
-                        (false, true) => {
-                            self.rotate_right(tree_id, right_id as u32);
-                            self.rotate_left(tree_id, id as u32);
-                            // right_grandchild is red, so it always exists
-                            self.nodes[left_grandchild.unwrap() as usize].set_is_red(false);
-                        }
-                    }
-                } else if self.nodes[right_id].is_red() {
-                    debug_assert!(!self.is_red(self.nodes[right_id].right()));
-                    debug_assert!(!self.is_red(self.nodes[right_id].left()));
-                    // right_depth is greater than left_depth, so it is >= 1
-                    // right_child is red and does not affect black height
-                    // therefore left and right grandchildren exists
-                    let left_grandchild = self.nodes[right_id].left().unwrap() as usize;
-                    let right_grandgrandchild = self.nodes[left_grandchild].right();
-                    let left_grandgrandchild = self.nodes[left_grandchild].left();
-
-                    match (
-                        self.is_red(right_grandgrandchild),
-                        self.is_red(left_grandgrandchild),
-                    ) {
-                        (false, false) => {
-                            self.rotate_left(tree_id, id as u32);
-                            self.nodes[id].set_is_red(false);
-                            self.nodes[left_grandchild].set_is_red(true);
-                        }
-                        (true, _) => {
-                            self.rotate_right(tree_id, right_id as u32);
-                            self.rotate_left(tree_id, id as u32);
-                            // right_grandgrandchild is red, so it always exists
-                            self.nodes[right_grandgrandchild.unwrap() as usize].set_is_red(false);
-                            self.nodes[left_grandchild].set_is_red(false);
-                            self.nodes[id].set_is_red(false);
-                        }
-                        (false, true) => {
-                            self.rotate_right(tree_id, left_grandchild as u32);
-                            self.rotate_right(tree_id, right_id as u32);
-                            self.rotate_left(tree_id, id as u32);
-                            // left_grandgrandchild is red, so it always exists
-                            self.nodes[left_grandgrandchild.unwrap() as usize].set_is_red(false);
-                            self.nodes[left_grandchild].set_is_red(false);
-                            self.nodes[id].set_is_red(false);
-                        }
-                    }
-                } else {
-                    let right_grandchild = self.nodes[right_id].right();
-                    let left_grandchild = self.nodes[right_id].left();
-
-                    match (self.is_red(right_grandchild), self.is_red(left_grandchild)) {
-                        (false, false) => {
-                            self.nodes[right_id].set_is_red(true);
-                            if let Some(parent_id) = self.nodes[id].parent() {
-                                self.balance_subtree(tree_id, parent_id as usize);
-                            }
-                        }
-                        (_, true) => {
-                            self.rotate_right(tree_id, right_id as u32);
-                            self.rotate_left(tree_id, id as u32);
-                            self.nodes[right_id].set_is_red(false);
-                            self.nodes[id].set_is_red(false);
-                        }
-                        (true, false) => {
-                            // right_grandchild is red, so it always exists
-                            self.nodes[right_grandchild.unwrap() as usize].set_is_red(false);
-                            self.rotate_left(tree_id, id as u32);
-                            self.nodes[id].set_is_red(false);
-                        }
-                    }
-                }
-            }
-            Ordering::Equal => {
-                unreachable!("balance_subtree() should only be called on non ballanced trees. It could be a sign, that the tree was not previously balanced.");
-            }
+    fn lens_mut(&mut self) -> &mut [[u8; 4]] {
+        let lens_offset = self.lens_offset();
+        cast_slice_mut(&mut self.slice[lens_offset..])
+    }
+
+    fn count_rotation(&mut self) {
+        #[cfg(any(test, fuzzing, feature = "checks"))]
+        {
+            self.rotations += 1;
         }
     }
+}
 
+/// Checks in debug builds, that the result of comparison of a key with a node key is consistent
+/// with the previous comparisons on the path from the root
+///
+/// `lower` and `upper` are the keys of the nearest nodes, from which the descent went right and
+/// left respectively. An inconsistent `Ord` implementation silently corrupts the tree, so it is
+/// better to panic as early as possible.
+#[cfg(debug_assertions)]
+fn debug_check_ord<Q: Ord + ?Sized>(
+    key: &Q,
+    node_key: &Q,
+    ordering: Ordering,
+    lower: Option<&Q>,
+    upper: Option<&Q>,
+) {
+    debug_assert!(
+        node_key.cmp(key) == ordering.reverse()
+            && lower.map_or(true, |lower| node_key.cmp(lower) == Ordering::Greater)
+            && upper.map_or(true, |upper| node_key.cmp(upper) == Ordering::Less),
+        "Ord implementation is inconsistent: comparison results contradict the tree structure"
+    );
+}
+
+impl<'b, 'a, K, V, const KSIZE: usize, const VSIZE: usize> RBTreeView<'b, 'a, K, V, KSIZE, VSIZE>
+where
+    K: Ord + BorshDeserialize + BorshSerialize,
+    V: BorshDeserialize + BorshSerialize,
+{
+    /// Returns the id of the viewed tree
     #[must_use]
-    fn black_depth(&self, mut maybe_id: Option<u32>) -> usize {
-        let mut depth = 0;
-        while let Some(id) = maybe_id {
-            if !self.nodes[id as usize].is_red() {
-                depth += 1;
-            }
-            maybe_id = self.nodes[id as usize].left();
-        }
-        depth
+    pub fn tree_id(&self) -> usize {
+        self.tree_id
     }
 
-    /// Deallocates a node
-    ///
-    /// # Safety
+    /// Returns the number of occupied nodes
     ///
-    /// This function does nothing but deallocation. It should be checked, that the node is
-    /// completely unlinked from the tree.
-    fn deallocate_node(&mut self, index: usize) {
-        let allocator_head = self.header.head();
-        let node_index = Some(index as u32);
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.forest.len(self.tree_id).unwrap()
+    }
 
-        self.nodes[index].set_parent(allocator_head);
-        self.header.set_head(node_index);
+    /// Returns `true` if the tree contains no elements
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.forest.is_empty(self.tree_id)
     }
 
-    /// Allocates a node
+    /// Returns true if the tree contains a value for the specified key
     ///
-    /// # Safety
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.forest.contains_key(self.tree_id, k)
+    }
+
+    /// Returns the value corresponding to the key
     ///
-    /// This function does nothing but allocation. The returned node (if present) is
-    /// completely unlinked from the tree and is in the unknown state. The caller must fill the
-    /// node with correct data.
-    #[must_use]
-    fn allocate_node(&mut self) -> Option<usize> {
-        let allocator_head = self.header.head();
-        match allocator_head {
-            Some(index) => {
-                let new_head = self.nodes[index as usize].parent();
-                self.header.set_head(new_head);
-                Some(index as usize)
-            }
-            None => None,
-        }
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn get<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.forest.get(self.tree_id, k)
     }
 
-    fn min(&self, mut min_id: usize) -> usize {
-        while let Some(id) = self.nodes[min_id].left() {
-            min_id = id as usize;
-        }
-        min_id
+    /// Creates an iterator over key-value pairs, in order by key
+    #[must_use]
+    pub fn pairs(&self) -> PairsIterator<'b, 'a, K, V, KSIZE, VSIZE> {
+        self.forest.pairs(self.tree_id).unwrap()
     }
 
-    fn max(&self, mut max_id: usize) -> usize {
-        while let Some(id) = self.nodes[max_id].right() {
-            max_id = id as usize;
-        }
-        max_id
+    /// Creates an iterator over keys, from smallest to biggest
+    #[must_use]
+    pub fn keys(&self) -> KeysIterator<'b, 'a, K, V, KSIZE, VSIZE> {
+        self.forest.keys(self.tree_id).unwrap()
+    }
+
+    /// Creates an iterator over values, in order by key
+    #[must_use]
+    pub fn values(&self) -> ValuesIterator<'b, 'a, K, V, KSIZE, VSIZE> {
+        self.forest.values(self.tree_id).unwrap()
+    }
+}
+
+impl<'b, 'a, K, V, const KSIZE: usize, const VSIZE: usize> fmt::Debug
+    for RBTreeView<'b, 'a, K, V, KSIZE, VSIZE>
+where
+    K: Ord + BorshDeserialize + BorshSerialize + fmt::Debug,
+    V: BorshDeserialize + BorshSerialize + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.debug_map().entries(self.pairs()).finish()
+    }
+}
+
+impl<'a, K, V, const KSIZE: usize, const VSIZE: usize> CloneInto
+    for RBForest<'a, K, V, KSIZE, VSIZE>
+where
+    K: Ord + BorshDeserialize + BorshSerialize,
+    V: BorshDeserialize + BorshSerialize,
+{
+    type Output<'b> = RBForest<'b, K, V, KSIZE, VSIZE>;
+
+    /// Copies the forest into `dst`, keeping the same number of trees
+    ///
+    /// See [`grow_into()`](RBForest::grow_into) for details.
+    fn clone_into<'b>(&self, dst: &'b mut [u8]) -> Result<Self::Output<'b>, Error> {
+        self.grow_into(dst, self.roots().len())
     }
 }
 
@@ -1229,23 +4838,14 @@ where
     #[cfg(test)]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         f.debug_struct("RBForest")
-            .field("header", self.header)
+            .field("header", self.header())
             .field(
                 "roots",
-                &self
-                    .roots
-                    .iter()
-                    .map(|x| {
-                        let num = u32::from_be_bytes(*x);
-                        if num == u32::MAX {
-                            None
-                        } else {
-                            Some(num)
-                        }
-                    })
+                &(0..self.roots().len())
+                    .map(|id| self.root(id))
                     .collect::<Vec<_>>(),
             )
-            .field("nodes", &self.nodes)
+            .field("nodes", &self.nodes())
             .finish()
     }
 }
@@ -1253,5 +4853,5 @@ where
 #[cfg(test)]
 pub(super) mod tests;
 
-#[cfg(any(test, fuzzing))]
+#[cfg(any(test, fuzzing, feature = "checks"))]
 pub mod internal_checks;
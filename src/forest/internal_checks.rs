@@ -1,5 +1,21 @@
 //! Additional methods for self-consictency checking on [`RBForest`]
 use super::*;
+use borsh::maybestd::vec::Vec;
+
+/// Structural information about a single node, see [`RBForest::nodes_preorder()`]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct NodeInfo<K, V> {
+    /// key, stored in the node
+    pub key: K,
+    /// value, stored in the node
+    pub value: V,
+    /// color of the node
+    pub is_red: bool,
+    /// number of edges between the node and the root of the tree
+    pub depth: usize,
+    /// key of the parent node, `None` for the root
+    pub parent: Option<K>,
+}
 
 #[warn(missing_docs)]
 impl<'a, K, V, const KSIZE: usize, const VSIZE: usize> RBForest<'a, K, V, KSIZE, VSIZE>
@@ -14,10 +30,10 @@ where
         let mut black = 0;
         let mut node = self.root(tree_id);
         while let Some(id) = node {
-            if !self.nodes[id as usize].is_red() {
+            if !self.nodes()[id as usize].is_red() {
                 black += 1;
             }
-            node = self.nodes[id as usize].left();
+            node = self.nodes()[id as usize].left();
         }
         self.node_balanced(self.root(tree_id), black)
     }
@@ -25,14 +41,14 @@ where
     fn node_balanced(&self, maybe_id: Option<u32>, black: i32) -> bool {
         if let Some(id) = maybe_id {
             let id = id as usize;
-            if self.nodes[id].is_red() {
-                let is_left_balanced = self.node_balanced(self.nodes[id].left(), black);
-                let is_right_balanced = self.node_balanced(self.nodes[id].right(), black);
+            if self.nodes()[id].is_red() {
+                let is_left_balanced = self.node_balanced(self.nodes()[id].left(), black);
+                let is_right_balanced = self.node_balanced(self.nodes()[id].right(), black);
 
                 is_left_balanced && is_right_balanced
             } else {
-                let is_left_balanced = self.node_balanced(self.nodes[id].left(), black - 1);
-                let is_right_balanced = self.node_balanced(self.nodes[id].right(), black - 1);
+                let is_left_balanced = self.node_balanced(self.nodes()[id].left(), black - 1);
+                let is_right_balanced = self.node_balanced(self.nodes()[id].right(), black - 1);
 
                 is_left_balanced && is_right_balanced
             }
@@ -41,14 +57,445 @@ where
         }
     }
 
+    /// Returns the index of the root node of the tree, or `None` if the tree is empty or there
+    /// is no such tree
+    #[must_use]
+    pub fn root_index(&self, tree_id: usize) -> Option<u32> {
+        if self.roots().len() > tree_id {
+            self.root(tree_id)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the key-value pair, stored in the node `index`, or `None` if the index is out of
+    /// range
+    ///
+    /// The node is deserialized regardless of the tree it belongs to. Free nodes may contain stale
+    /// or zeroed data, so it is up to the caller to make sure, that the node is in use.
+    #[must_use]
+    pub fn node_entry(&self, index: u32) -> Option<(K, V)> {
+        ((index as usize) < self.nodes().len()).then(|| self.entry_at(index as usize))
+    }
+
+    /// Creates an iterator over keys and colors (`true` for red) of the nodes in key order
+    ///
+    /// This is sufficient for verifying red-black properties externally.
+    pub fn colors(&self, tree_id: usize) -> Result<impl Iterator<Item = (K, bool)> + '_, Error> {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let mut next_node = self.root(tree_id).map(|id| self.min(id as usize));
+        Ok(core::iter::from_fn(move || {
+            let id = next_node?;
+            next_node = self.successor(id);
+            Some((self.entry_key(id), self.nodes()[id].is_red()))
+        }))
+    }
+
+    /// Creates an iterator over key-value pairs in key order, each with the byte offset of its
+    /// node within the slice of the forest
+    ///
+    /// A node with index `id` starts at `size_of::<Header>() + id * node_stride(KSIZE, VSIZE)`,
+    /// so external code can build its own index of offsets into the same buffer.
+    pub fn pairs_with_offset(
+        &self,
+        tree_id: usize,
+    ) -> Result<impl Iterator<Item = (K, V, usize)> + '_, Error> {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let mut next_node = self.root(tree_id).map(|id| self.min(id as usize));
+        Ok(core::iter::from_fn(move || {
+            let id = next_node?;
+            next_node = self.successor(id);
+            let (key, value) = self.entry_at(id);
+            let offset = mem::size_of::<Header>() + id * node_stride(KSIZE, VSIZE);
+            Some((key, value, offset))
+        }))
+    }
+
+    /// Returns the average distance between the nodes of adjacent entries, divided by the size of
+    /// the node pool
+    ///
+    /// The value is close to `0.0` if the entries are stored in the pool in key order and grows
+    /// as the pool gets shuffled by insertions and deletions, so it shows whether compacting the
+    /// forest (e.g. with [`pack()`](RBForest::pack) and [`unpack_into()`](RBForest::unpack_into))
+    /// would improve locality. Returns `0.0` for trees with fewer than two entries and if there is
+    /// no such tree.
+    #[must_use]
+    pub fn fragmentation(&self, tree_id: usize) -> f64 {
+        if self.roots().len() <= tree_id {
+            return 0.0;
+        }
+
+        let mut ids = Vec::new();
+        self.collect_inorder(self.root(tree_id), &mut ids);
+        if ids.len() < 2 {
+            return 0.0;
+        }
+
+        let total_distance: u64 = ids
+            .windows(2)
+            .map(|pair| u64::from(pair[0].abs_diff(pair[1])))
+            .sum();
+        total_distance as f64 / (ids.len() - 1) as f64 / self.nodes().len() as f64
+    }
+
+    /// Creates an iterator over all the node indices of the pool in physical order, each with a
+    /// flag, which is `true` if the node is occupied by some tree and `false` if it is free
+    ///
+    /// This is useful for studying fragmentation and locality of the node pool.
+    pub fn pool_order(&self) -> impl Iterator<Item = (u32, bool)> {
+        let mut is_live = Vec::new();
+        is_live.resize(self.nodes().len(), true);
+
+        let mut maybe_id = self.header().head();
+        while let Some(id) = maybe_id {
+            is_live[id as usize] = false;
+            maybe_id = self.nodes()[id as usize].parent();
+        }
+
+        is_live
+            .into_iter()
+            .enumerate()
+            .map(|(id, is_live)| (id as u32, is_live))
+    }
+
+    /// Checks that no node of the free list is occupied by a tree
+    ///
+    /// A node, which is both in a tree and in the free list, would be allocated twice. The walk
+    /// stops after visiting as many free nodes as there are in the pool, so a cycle in the free
+    /// list is reported as well.
+    #[must_use]
+    pub fn free_list_disjoint(&self) -> bool {
+        let mut ids = Vec::new();
+        for tree_id in 0..self.roots().len() {
+            self.collect_inorder(self.root(tree_id), &mut ids);
+        }
+        let mut is_live = Vec::new();
+        is_live.resize(self.nodes().len(), false);
+        for id in ids {
+            is_live[id as usize] = true;
+        }
+
+        let mut steps = 0;
+        let mut maybe_id = self.header().head();
+        while let Some(id) = maybe_id {
+            if is_live[id as usize] || steps == self.nodes().len() {
+                return false;
+            }
+            steps += 1;
+            maybe_id = self.nodes()[id as usize].parent();
+        }
+        true
+    }
+
+    /// Returns the number of nodes, which are neither in a tree nor in the free list
+    ///
+    /// Such nodes are leaked: they can never be allocated again. The result is always `0` for a
+    /// healthy forest. Nodes, which are both in a tree and in the free list, are counted twice,
+    /// so they may hide leaks, see [`free_list_disjoint()`](RBForest::free_list_disjoint).
+    #[must_use]
+    pub fn leaked_node_count(&self) -> usize {
+        let live: usize = (0..self.roots().len())
+            .map(|tree_id| self.size(self.root(tree_id)))
+            .sum();
+        self.nodes()
+            .len()
+            .saturating_sub(live + self.free_list_len())
+    }
+
+    /// Returns the number of nodes in the free list, walking over it
+    ///
+    /// Unlike [`free_nodes_left()`](RBForest::free_nodes_left), this does not trust the counter in
+    /// the header, so the two are equal for a healthy forest.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn free_list_len(&self) -> usize {
+        let mut counter = 0;
+        let mut maybe_id = self.header().head();
+        while let Some(id) = maybe_id {
+            counter += 1;
+            maybe_id = self.nodes()[id as usize].parent();
+        }
+        counter
+    }
+
+    /// Returns the number of edges between the root and the node, containing `k`
+    ///
+    /// Returns `None` if there is no such key or no such tree.
+    #[must_use]
+    pub fn key_depth<Q>(&self, tree_id: usize, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() <= tree_id {
+            return None;
+        }
+
+        let mut depth = 0;
+        let mut maybe_parent = self.nodes()[self.get_key_index(tree_id, k)?].parent();
+        while let Some(parent_id) = maybe_parent {
+            depth += 1;
+            maybe_parent = self.nodes()[parent_id as usize].parent();
+        }
+        Some(depth)
+    }
+
+    /// Returns the key of the lowest common ancestor of the nodes, containing `a` and `b`
+    ///
+    /// This is the first node, where the search paths of the two keys diverge. Returns `None` if
+    /// any of the keys is absent or there is no such tree.
+    #[must_use]
+    pub fn lowest_common_ancestor<Q>(&self, tree_id: usize, a: &Q, b: &Q) -> Option<K>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        if self.roots().len() <= tree_id {
+            return None;
+        }
+        self.get_key_index(tree_id, a)?;
+        self.get_key_index(tree_id, b)?;
+
+        let mut maybe_id = self.root(tree_id);
+        while let Some(id) = maybe_id {
+            let node = &self.nodes()[id as usize];
+            let key = self.entry_key(id as usize);
+            maybe_id = match (a.cmp(key.borrow()), b.cmp(key.borrow())) {
+                (Ordering::Less, Ordering::Less) => node.left(),
+                (Ordering::Greater, Ordering::Greater) => node.right(),
+                _ => return Some(key),
+            };
+        }
+        unreachable!("both keys are present in the tree")
+    }
+
+    /// Collects the entries of the tree level by level, from the root down, each level from left
+    /// to right
+    ///
+    /// Returns an empty vector if the tree is empty or there is no such tree.
+    #[must_use]
+    pub fn pairs_level_order(&self, tree_id: usize) -> Vec<(K, V)> {
+        let mut queue = Vec::new();
+        if self.roots().len() > tree_id {
+            queue.extend(self.root(tree_id));
+        }
+
+        // The queue is never shrunk, so the visited indices stay before `next`
+        let mut next = 0;
+        while let Some(&id) = queue.get(next) {
+            let node = &self.nodes()[id as usize];
+            queue.extend(node.left());
+            queue.extend(node.right());
+            next += 1;
+        }
+
+        queue
+            .into_iter()
+            .map(|id| self.entry_at(id as usize))
+            .collect()
+    }
+
+    /// Returns the number of nodes at each depth: index `d` holds the number of nodes, which are
+    /// `d` edges away from the root
+    ///
+    /// Returns an empty vector if the tree is empty or there is no such tree.
+    #[must_use]
+    pub fn depth_histogram(&self, tree_id: usize) -> Vec<usize> {
+        let mut histogram = Vec::new();
+        if self.roots().len() > tree_id {
+            self.count_depths(self.root(tree_id), 0, &mut histogram);
+        }
+        histogram
+    }
+
+    fn count_depths(&self, maybe_id: Option<u32>, depth: usize, histogram: &mut Vec<usize>) {
+        if let Some(id) = maybe_id {
+            if histogram.len() == depth {
+                histogram.push(0);
+            }
+            histogram[depth] += 1;
+
+            let node = &self.nodes()[id as usize];
+            self.count_depths(node.left(), depth + 1, histogram);
+            self.count_depths(node.right(), depth + 1, histogram);
+        }
+    }
+
+    /// Checks that the height of the tree (the number of nodes on the longest path from the root)
+    /// is at most `max_height`
+    ///
+    /// The walk stops as soon as a deeper node is found, so this is cheaper than computing the
+    /// whole [`depth_histogram()`](RBForest::depth_histogram). An empty tree has height `0`.
+    /// Returns `true` if there is no such tree.
+    #[must_use]
+    pub fn fits_height(&self, tree_id: usize, max_height: usize) -> bool {
+        self.roots().len() <= tree_id || self.fits_height_from(self.root(tree_id), max_height)
+    }
+
+    fn fits_height_from(&self, maybe_id: Option<u32>, max_height: usize) -> bool {
+        match maybe_id {
+            Some(id) => {
+                let node = &self.nodes()[id as usize];
+                max_height > 0
+                    && self.fits_height_from(node.left(), max_height - 1)
+                    && self.fits_height_from(node.right(), max_height - 1)
+            }
+            None => true,
+        }
+    }
+
+    /// Returns the numbers of red and black nodes of the tree as `(red, black)`
+    ///
+    /// Returns `(0, 0)` if the tree is empty or there is no such tree.
+    #[must_use]
+    pub fn color_counts(&self, tree_id: usize) -> (usize, usize) {
+        let mut counts = (0, 0);
+        if self.roots().len() > tree_id {
+            self.count_colors(self.root(tree_id), &mut counts);
+        }
+        counts
+    }
+
+    fn count_colors(&self, maybe_id: Option<u32>, counts: &mut (usize, usize)) {
+        if let Some(id) = maybe_id {
+            let node = &self.nodes()[id as usize];
+            if node.is_red() {
+                counts.0 += 1;
+            } else {
+                counts.1 += 1;
+            }
+            self.count_colors(node.left(), counts);
+            self.count_colors(node.right(), counts);
+        }
+    }
+
+    /// Creates an iterator over the nodes of the tree in pre-order (node, left subtree, right
+    /// subtree) with their structural information
+    ///
+    /// This is intended for visualization tools, which need to render the tree shape.
+    pub fn nodes_preorder(
+        &self,
+        tree_id: usize,
+    ) -> Result<impl Iterator<Item = NodeInfo<K, V>> + '_, Error> {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let mut next_node = self.root(tree_id).map(|id| (id as usize, 0));
+        Ok(core::iter::from_fn(move || {
+            let (id, depth) = next_node?;
+            let node = &self.nodes()[id];
+
+            // find next: the leftmost existing child, or the right sibling of the nearest
+            // ancestor, which was entered from the left
+            next_node = if let Some(left_id) = node.left() {
+                Some((left_id as usize, depth + 1))
+            } else if let Some(right_id) = node.right() {
+                Some((right_id as usize, depth + 1))
+            } else {
+                let mut child_id = id;
+                let mut child_depth = depth;
+                let mut next = None;
+                while let Some(parent_id) = self.nodes()[child_id].parent() {
+                    let parent = &self.nodes()[parent_id as usize];
+                    if parent.left() == Some(child_id as u32) {
+                        if let Some(right_id) = parent.right() {
+                            next = Some((right_id as usize, child_depth));
+                            break;
+                        }
+                    }
+                    child_id = parent_id as usize;
+                    child_depth -= 1;
+                }
+                next
+            };
+
+            Some(NodeInfo {
+                key: K::deserialize(&mut node.key.as_slice()).expect("Key corrupted"),
+                value: V::deserialize(&mut node.value.as_slice()).expect("Value corrupted"),
+                is_red: node.is_red(),
+                depth,
+                parent: node.parent().map(|parent_id| {
+                    K::deserialize(&mut self.nodes()[parent_id as usize].key.as_slice())
+                        .expect("Key corrupted")
+                }),
+            })
+        }))
+    }
+
+    /// Restores the red-black invariants of a tree with a valid BST ordering, but arbitrary
+    /// colors and shape (e.g. after manual edits with `set_node()`)
+    ///
+    /// Not every BST shape admits a legal coloring, so the tree is rebuilt from its nodes with
+    /// [`rebuild_balanced()`](RBForest::rebuild_balanced), after which
+    /// [`is_balanced()`](RBForest::is_balanced) and [`no_double_red()`](RBForest::no_double_red)
+    /// hold. Only child links are read, parent links and colors are recomputed. The nodes are not
+    /// moved, so node indices are preserved.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn recolor(&mut self, tree_id: usize) -> Result<(), Error> {
+        self.rebuild_balanced(tree_id)
+    }
+
     /// Set all the fields of `id` node to a given value (for testing purposes only)
+    ///
+    /// This may break any invariant of the forest, so it needs the `unchecked-mutators` feature.
+    #[cfg(any(test, fuzzing, feature = "unchecked-mutators"))]
     pub fn set_node(&mut self, id: usize, node: &Node<KSIZE, VSIZE>) {
-        self.nodes[id] = *node;
+        self.nodes_mut()[id] = *node;
+        self.invalidate_lens();
     }
 
     /// Set head of the linked list of free nodes to a given value (for testing purposes only)
+    ///
+    /// This may break any invariant of the forest, so it needs the `unchecked-mutators` feature.
+    #[cfg(any(test, fuzzing, feature = "unchecked-mutators"))]
     pub fn set_head(&mut self, head: Option<u32>) {
-        self.header.set_head(head);
+        self.header_mut().set_head(head);
+    }
+
+    /// Inserts a new key-value pair, placing it into the free node `node_index`, and returns the
+    /// old value if it was present
+    ///
+    /// The node is moved to the head of the free list before the insertion, so the same sequence
+    /// of calls always produces byte-identical slices. If the key is already present, its value is
+    /// overwritten in place and the node stays free. Returns [`Error::NodeNotFree`] if the node is
+    /// occupied or out of range.
+    pub fn insert_at(
+        &mut self,
+        tree_id: usize,
+        key: K,
+        value: V,
+        node_index: u32,
+    ) -> Result<Option<V>, Error> {
+        if self.roots().len() <= tree_id {
+            return Err(Error::TooBigTreeId);
+        }
+
+        let mut previous = None;
+        let mut maybe_id = self.header().head();
+        while maybe_id != Some(node_index) {
+            let id = maybe_id.ok_or(Error::NodeNotFree)?;
+            previous = Some(id);
+            maybe_id = self.nodes()[id as usize].parent();
+        }
+
+        if let Some(previous) = previous {
+            let next = self.nodes()[node_index as usize].parent();
+            self.nodes_mut()[previous as usize].set_parent(next);
+            let head = self.header().head();
+            self.nodes_mut()[node_index as usize].set_parent(head);
+            self.header_mut().set_head(Some(node_index));
+        }
+
+        self.insert(tree_id, key, value)
     }
 
     /// Check that two trees are structualy equal (have the same key-valye pairs ordered in the
@@ -64,33 +511,33 @@ where
                 let self_id = self_id as usize;
                 let other_id = other_id as usize;
 
-                if self.nodes[self_id].is_red() ^ self.nodes[self_id].is_red() {
+                if self.nodes()[self_id].is_red() ^ self.nodes()[self_id].is_red() {
                     return false;
                 }
 
-                let self_key =
-                    K::deserialize(&mut self.nodes[self_id].key.as_slice()).expect("Key corrupted");
-                let other_key = K::deserialize(&mut self.nodes[other_id].key.as_slice())
+                let self_key = K::deserialize(&mut self.nodes()[self_id].key.as_slice())
+                    .expect("Key corrupted");
+                let other_key = K::deserialize(&mut self.nodes()[other_id].key.as_slice())
                     .expect("Key corrupted");
 
                 if self_key != other_key {
                     return false;
                 }
 
-                let self_value = V::deserialize(&mut self.nodes[self_id].value.as_slice())
+                let self_value = V::deserialize(&mut self.nodes()[self_id].value.as_slice())
                     .expect("Value corrupted");
-                let other_value = V::deserialize(&mut self.nodes[other_id].value.as_slice())
+                let other_value = V::deserialize(&mut self.nodes()[other_id].value.as_slice())
                     .expect("Value corrupted");
 
                 if self_value != other_value {
                     return false;
                 }
 
-                let self_left = self.nodes[self_id].left();
-                let other_left = self.nodes[other_id].left();
+                let self_left = self.nodes()[self_id].left();
+                let other_left = self.nodes()[other_id].left();
 
-                let self_right = self.nodes[self_id].right();
-                let other_right = self.nodes[other_id].right();
+                let self_right = self.nodes()[self_id].right();
+                let other_right = self.nodes()[other_id].right();
 
                 self.node_eq(self_left, other_left) && self.node_eq(self_right, other_right)
             }
@@ -103,7 +550,7 @@ where
     /// This function checks that all link pairs (parent-> child and child->parent) are consistent
     pub fn is_child_parent_links_consistent(&self, tree_id: usize) -> bool {
         if let Some(id) = self.root(tree_id) {
-            if self.nodes[id as usize].parent().is_none() {
+            if self.nodes()[id as usize].parent().is_none() {
                 self.is_node_links_consistent(id as usize)
             } else {
                 false
@@ -114,25 +561,25 @@ where
     }
 
     fn is_node_links_consistent(&self, id: usize) -> bool {
-        match (self.nodes[id].left(), self.nodes[id].right()) {
+        match (self.nodes()[id].left(), self.nodes()[id].right()) {
             (None, None) => true,
             (Some(left_id), None) => {
-                if self.nodes[left_id as usize].parent() == Some(id as u32) {
+                if self.nodes()[left_id as usize].parent() == Some(id as u32) {
                     self.is_node_links_consistent(left_id as usize)
                 } else {
                     false
                 }
             }
             (None, Some(right_id)) => {
-                if self.nodes[right_id as usize].parent() == Some(id as u32) {
+                if self.nodes()[right_id as usize].parent() == Some(id as u32) {
                     self.is_node_links_consistent(right_id as usize)
                 } else {
                     false
                 }
             }
             (Some(left_id), Some(right_id)) => {
-                let left_equality = self.nodes[left_id as usize].parent() == Some(id as u32);
-                let right_equality = self.nodes[right_id as usize].parent() == Some(id as u32);
+                let left_equality = self.nodes()[left_id as usize].parent() == Some(id as u32);
+                let right_equality = self.nodes()[right_id as usize].parent() == Some(id as u32);
                 if left_equality & right_equality {
                     self.is_node_links_consistent(right_id as usize)
                         & self.is_node_links_consistent(left_id as usize)
@@ -155,12 +602,12 @@ where
     }
 
     fn does_not_have_red_child(&self, node_id: usize) -> bool {
-        match (self.nodes[node_id].left(), self.nodes[node_id].right()) {
+        match (self.nodes()[node_id].left(), self.nodes()[node_id].right()) {
             (None, None) => true,
             (Some(id), None) => {
                 let id = id as usize;
-                let self_redness = self.nodes[node_id].is_red();
-                let child_redness = self.nodes[id].is_red();
+                let self_redness = self.nodes()[node_id].is_red();
+                let child_redness = self.nodes()[id].is_red();
                 if self_redness & child_redness {
                     false
                 } else {
@@ -169,8 +616,8 @@ where
             }
             (None, Some(id)) => {
                 let id = id as usize;
-                let self_redness = self.nodes[node_id].is_red();
-                let child_redness = self.nodes[id].is_red();
+                let self_redness = self.nodes()[node_id].is_red();
+                let child_redness = self.nodes()[id].is_red();
                 if self_redness & child_redness {
                     false
                 } else {
@@ -180,9 +627,9 @@ where
             (Some(left_id), Some(right_id)) => {
                 let left_id = left_id as usize;
                 let right_id = right_id as usize;
-                let self_redness = self.nodes[node_id].is_red();
-                let left_child_redness = self.nodes[left_id].is_red();
-                let right_child_redness = self.nodes[right_id].is_red();
+                let self_redness = self.nodes()[node_id].is_red();
+                let left_child_redness = self.nodes()[left_id].is_red();
+                let right_child_redness = self.nodes()[right_id].is_red();
                 // If either of the children is red AND self is red, return FALSE
                 if self_redness & (left_child_redness | right_child_redness) {
                     false
@@ -193,6 +640,114 @@ where
         }
     }
 
+    /// Returns the first pair of adjacent keys in iteration order, where the earlier key is not
+    /// strictly less than the later one
+    ///
+    /// Such a pair means that the BST ordering of the nodes is corrupted. Returns `None` if the
+    /// keys are ordered correctly or there is no such tree.
+    #[must_use]
+    pub fn first_order_violation(&self, tree_id: usize) -> Option<(K, K)> {
+        let mut keys = self.keys(tree_id).ok()?;
+        let mut previous = keys.next()?;
+        for key in keys {
+            if previous >= key {
+                return Some((previous, key));
+            }
+            previous = key;
+        }
+        None
+    }
+
+    /// Counts the nodes of the tree by walking it in key order, as the iterators do
+    ///
+    /// Unlike [`len()`](RBForest::len), which follows only the child links, the walk goes up by
+    /// the parent links, so the counts differ if the links are inconsistent. The walk stops after
+    /// `max_nodes + 1` steps, so a cycle yields a count bigger than the pool. Returns `0` if there
+    /// is no such tree.
+    #[must_use]
+    pub fn structural_node_count(&self, tree_id: usize) -> usize {
+        if self.roots().len() <= tree_id {
+            return 0;
+        }
+
+        let mut count = 0;
+        let mut maybe_id = self.root(tree_id).map(|id| self.min(id as usize));
+        while let Some(id) = maybe_id {
+            count += 1;
+            if count > self.nodes().len() {
+                break;
+            }
+            maybe_id = self.successor(id);
+        }
+        count
+    }
+
+    /// Checks that the in-order traversal of the tree yields strictly increasing keys
+    ///
+    /// This is the fundamental property of a binary search tree, which does not depend on colors
+    /// and balance. Unlike [`first_order_violation()`](RBForest::first_order_violation), the
+    /// traversal follows only the child links. Returns `true` if there is no such tree.
+    #[must_use]
+    pub fn is_bst(&self, tree_id: usize) -> bool {
+        if self.roots().len() <= tree_id {
+            return true;
+        }
+
+        let mut ids = Vec::new();
+        self.collect_inorder(self.root(tree_id), &mut ids);
+        ids.windows(2)
+            .all(|pair| self.entry_key(pair[0] as usize) < self.entry_key(pair[1] as usize))
+    }
+
+    /// Returns the number of rotations performed since the forest was created or since the last
+    /// call to [`reset_rotation_counter()`](RBForest::reset_rotation_counter)
+    ///
+    /// The counter is not stored in the slice, so it starts from zero for each [`RBForest`]
+    /// instance.
+    #[must_use]
+    pub fn rotations_since_reset(&self) -> usize {
+        self.rotations
+    }
+
+    /// Resets the rotation counter, see
+    /// [`rotations_since_reset()`](RBForest::rotations_since_reset)
+    pub fn reset_rotation_counter(&mut self) {
+        self.rotations = 0;
+    }
+
+    /// Checks the invariant of left-leaning Red-Black trees: no node has a red right child
+    /// together with a black or absent left child
+    ///
+    /// Insertion maintains this invariant, but deletion rebalances the tree as an ordinary
+    /// Red-Black tree and may leave right-leaning red links, so it only holds for trees, which
+    /// were built by insertions alone.
+    #[must_use]
+    pub fn is_left_leaning(&self, tree_id: usize) -> bool {
+        match self.root(tree_id) {
+            Some(id) => self.node_left_leaning(id as usize),
+            None => true,
+        }
+    }
+
+    fn node_left_leaning(&self, node_id: usize) -> bool {
+        let node = &self.nodes()[node_id];
+        let left_red = node
+            .left()
+            .is_some_and(|id| self.nodes()[id as usize].is_red());
+        let right_red = node
+            .right()
+            .is_some_and(|id| self.nodes()[id as usize].is_red());
+        if right_red && !left_red {
+            return false;
+        }
+
+        node.left()
+            .map_or(true, |id| self.node_left_leaning(id as usize))
+            && node
+                .right()
+                .map_or(true, |id| self.node_left_leaning(id as usize))
+    }
+
     /// Unified way to apply [`RBForest`] methods in the fuzzing harness
     pub fn apply_method(&mut self, method: RBForestMethod<K, V>) {
         use RBForestMethod::*;
@@ -1,7 +1,7 @@
 use bytemuck::{Pod, Zeroable};
 use core::fmt;
 
-pub const HEADER_MAGIC: [u8; 12] = *b"Slice_RBTree";
+pub const HEADER_MAGIC: [u8; 12] = *b"SliceRBTree2";
 
 /// [`RBForest`](crate::forest::RBForest) header struct
 #[repr(C)]
@@ -20,6 +20,14 @@ pub struct Header {
     /// offset: 24 - `Option<u32>`  encoded as big-endian `u32` with `None` value represented by
     /// `u32::MAX`, head of the linked list of empty nodes
     head: [u8; 4],
+    /// offset: 28 - big-endian encoded `u32`, generation of the forest, advanced by every
+    /// modification, see [`generation()`](crate::forest::RBForest::generation)
+    generation: [u8; 4],
+    /// offset: 32 - big-endian encoded `u32`, number of free nodes, held back by
+    /// [`reserve_nodes()`](crate::forest::RBForest::reserve_nodes)
+    reserved: [u8; 4],
+    /// offset: 36 - big-endian encoded `u32`, number of nodes in the linked list of empty nodes
+    free: [u8; 4],
 }
 
 impl Header {
@@ -60,7 +68,33 @@ impl Header {
         }
     }
 
+    pub fn generation(&self) -> u32 {
+        u32::from_be_bytes(self.generation)
+    }
+
+    pub fn set_generation(&mut self, generation: u32) {
+        self.generation = u32::to_be_bytes(generation);
+    }
+
+    pub fn reserved(&self) -> u32 {
+        u32::from_be_bytes(self.reserved)
+    }
+
+    pub fn set_reserved(&mut self, reserved: u32) {
+        self.reserved = u32::to_be_bytes(reserved);
+    }
+
+    pub fn free(&self) -> u32 {
+        u32::from_be_bytes(self.free)
+    }
+
+    pub fn set_free(&mut self, free: u32) {
+        self.free = u32::to_be_bytes(free);
+    }
+
     /// This function guarantees, that the header will be initialized in fully known state
+    ///
+    /// All the nodes are considered free, so `head` must be the head of the list of all the nodes.
     pub fn fill(
         &mut self,
         k_size: u16,
@@ -74,6 +108,9 @@ impl Header {
         self.max_nodes = u32::to_be_bytes(max_nodes);
         self.max_roots = u32::to_be_bytes(max_roots);
         self.magic = HEADER_MAGIC;
+        self.generation = [0; 4];
+        self.reserved = [0; 4];
+        self.free = self.max_nodes;
         {
             self.set_head(head);
         }
@@ -108,6 +145,9 @@ impl Header {
             max_nodes,
             max_roots,
             head,
+            generation: [0; 4],
+            reserved: [0; 4],
+            free: max_nodes,
             magic: HEADER_MAGIC,
         }
     }
@@ -121,6 +161,9 @@ impl fmt::Debug for Header {
             .field("max_nodes", &self.max_nodes())
             .field("max_roots", &self.max_roots())
             .field("head", &self.head())
+            .field("generation", &self.generation())
+            .field("reserved", &self.reserved())
+            .field("free", &self.free())
             .finish()
     }
 }
@@ -155,5 +198,19 @@ mod header_tests {
         assert_eq!(head.max_nodes(), 3);
         assert_eq!(head.max_roots(), 5);
         assert_eq!(head.head(), None);
+        assert_eq!(head.generation(), 0);
+
+        head.set_generation(u32::MAX);
+        assert_eq!(head.generation(), u32::MAX);
+        assert_eq!(head.reserved(), 0);
+
+        head.set_reserved(5);
+        assert_eq!(head.reserved(), 5);
+        assert_eq!(head.head(), None);
+
+        assert_eq!(head.free(), 3);
+        head.set_free(2);
+        assert_eq!(head.free(), 2);
+        assert_eq!(head.reserved(), 5);
     }
 }
@@ -1,16 +1,19 @@
 //! # Intternal structure of the [`RBForest`](super::RBForest)
 //!
-//! Each [`RBForest`](super::RBForest) consists of [`Header`], array of the tree roots and a pool of [`Nodes`](Node).
+//! Each [`RBForest`](super::RBForest) consists of [`Header`], a pool of [`Nodes`](Node), array of the tree roots and array of the cached tree lengths.
 //! All this structs are designed in such a way, that they does not have any alignment requirements
 //! (all of them are byte-aligned).
 //! [`Header`] contains parameters and sizes of sections and a magic string [`HEADER_MAGIC`](header::HEADER_MAGIC) used to check, that the given slice is indeed [`RBForest`](super::RBForest).
 //!
-//! After the [`Header`] the array of `max_roots` (see [`Header`] docs) indices is placed. Indices
-//! are `Option<u32>` encoded as big-endian  `u32` with `None` variant encoded as `u32::MAX`.
+//! After the [`Header`] an array of `max_nodes` (see [`Header`] docs) [`Nodes`](Node) is placed.
 //!
+//! It is followed by the array of `max_roots` (see [`Header`] docs) root indices. Indices
+//! are `Option<u32>` encoded as big-endian  `u32` with `None` variant encoded as `u32::MAX` for
+//! not allocated tree slots and as `u32::MAX - 1` for allocated, but empty trees.
 //!
-//!The last part of the [`RBForest`](crate::forest::RBForest) is an array of `max_nodes` (see [`Header`] docs)
-//![`Nodes`](Node).
+//! The last part of the [`RBForest`](crate::forest::RBForest) is an array of `max_roots` lengths
+//! of the trees, stored by [`len_cached()`](super::RBForest::len_cached). Lengths are big-endian
+//! `u32` with `u32::MAX` for lengths, which are not computed yet.
 //!
 //![`from_slice()`](super::RBForest::from_slice) method checks the following invariants:
 //! * magic string is present
@@ -18,6 +21,8 @@
 //! * node pool contains exactly `max_nodes` [Nodes](Node)
 mod header;
 mod node;
+mod raw_nodes;
 
 pub(crate) use header::Header;
 pub(crate) use node::Node;
+pub(crate) use raw_nodes::RawNodes;
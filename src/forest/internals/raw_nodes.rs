@@ -0,0 +1,604 @@
+use core::cmp::Ordering;
+
+use super::super::{EMPTY_TREE, FREE_TREE, NO_LEN};
+use super::Header;
+
+// Offsets of the node fields relative to the end of the value buffer.
+// See `Node` docs for the description of the layout.
+const LEFT_OFFSET: usize = 0;
+const RIGHT_OFFSET: usize = 4;
+const PARENT_OFFSET: usize = 8;
+const FLAGS_OFFSET: usize = 12;
+
+const LEFT_FLAG: u8 = 0b0001;
+const RIGHT_FLAG: u8 = 0b0010;
+const PARENT_FLAG: u8 = 0b0100;
+const RED_FLAG: u8 = 0b1000;
+
+/// Byte-level access to the nodes and the tree slots of a forest
+///
+/// [`RBForest`](crate::forest::RBForest) and [`DynForest`](crate::dyn_forest::DynForest) share the
+/// binary layout, but only the former knows the sizes of the keys and values at compile time. The
+/// links of a node (the bytes after the value buffer, see [`Node`](super::Node) docs) do not depend
+/// on these sizes, so the allocator and the balancing are implemented once on top of the required
+/// methods.
+pub(crate) trait RawNodes {
+    fn header(&self) -> &Header;
+
+    fn header_mut(&mut self) -> &mut Header;
+
+    /// Returns the bytes of the node `id`, which follow the value buffer
+    fn links(&self, id: usize) -> &[u8];
+
+    fn links_mut(&mut self, id: usize) -> &mut [u8];
+
+    /// Swaps the keys and the values of two nodes, leaving the links in place
+    fn swap_entries(&mut self, a: usize, b: usize);
+
+    fn roots(&self) -> &[[u8; 4]];
+
+    fn roots_mut(&mut self) -> &mut [[u8; 4]];
+
+    fn lens_mut(&mut self) -> &mut [[u8; 4]];
+
+    /// Called on every rotation, so the typed forest can count them
+    fn count_rotation(&mut self) {}
+
+    #[must_use]
+    fn link(&self, id: usize, offset: usize, flag: u8) -> Option<u32> {
+        let links = self.links(id);
+        if links[FLAGS_OFFSET] & flag != 0 {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&links[offset..offset + 4]);
+            Some(u32::from_be_bytes(bytes))
+        } else {
+            None
+        }
+    }
+
+    fn set_link(&mut self, id: usize, offset: usize, flag: u8, link: Option<u32>) {
+        let links = self.links_mut(id);
+        match link {
+            Some(idx) => {
+                links[offset..offset + 4].copy_from_slice(&u32::to_be_bytes(idx));
+                links[FLAGS_OFFSET] |= flag;
+            }
+            None => {
+                links[FLAGS_OFFSET] &= !flag;
+            }
+        }
+    }
+
+    #[must_use]
+    fn left(&self, id: usize) -> Option<u32> {
+        self.link(id, LEFT_OFFSET, LEFT_FLAG)
+    }
+
+    #[must_use]
+    fn right(&self, id: usize) -> Option<u32> {
+        self.link(id, RIGHT_OFFSET, RIGHT_FLAG)
+    }
+
+    #[must_use]
+    fn parent(&self, id: usize) -> Option<u32> {
+        self.link(id, PARENT_OFFSET, PARENT_FLAG)
+    }
+
+    fn set_left(&mut self, id: usize, left: Option<u32>) {
+        self.set_link(id, LEFT_OFFSET, LEFT_FLAG, left);
+    }
+
+    fn set_right(&mut self, id: usize, right: Option<u32>) {
+        self.set_link(id, RIGHT_OFFSET, RIGHT_FLAG, right);
+    }
+
+    fn set_parent(&mut self, id: usize, parent: Option<u32>) {
+        self.set_link(id, PARENT_OFFSET, PARENT_FLAG, parent);
+    }
+
+    #[must_use]
+    fn is_red(&self, maybe_id: Option<u32>) -> bool {
+        match maybe_id {
+            Some(id) => self.node_is_red(id as usize),
+            None => false,
+        }
+    }
+
+    #[must_use]
+    fn node_is_red(&self, id: usize) -> bool {
+        self.links(id)[FLAGS_OFFSET] & RED_FLAG != 0
+    }
+
+    fn set_is_red(&mut self, id: usize, is_red: bool) {
+        let flags = &mut self.links_mut(id)[FLAGS_OFFSET];
+        if is_red {
+            *flags |= RED_FLAG;
+        } else {
+            *flags &= !RED_FLAG;
+        }
+    }
+
+    /// Makes the node a red leaf with the given parent, the key and the value are not touched
+    fn init_links(&mut self, id: usize, parent: Option<u32>) {
+        self.links_mut(id)[FLAGS_OFFSET] = RED_FLAG;
+        self.set_parent(id, parent);
+    }
+
+    #[must_use]
+    fn root(&self, id: usize) -> Option<u32> {
+        let num = u32::from_be_bytes(self.roots()[id]);
+        if num == FREE_TREE || num == EMPTY_TREE {
+            None
+        } else {
+            Some(num)
+        }
+    }
+
+    /// Sets the root of the tree, emptying a tree does not free its slot
+    fn set_root(&mut self, id: usize, root: Option<u32>) {
+        self.bump_generation();
+        self.invalidate_len(id);
+        match root {
+            Some(idx) => {
+                assert!(idx < EMPTY_TREE);
+                self.roots_mut()[id] = u32::to_be_bytes(idx);
+            }
+            None => {
+                if u32::from_be_bytes(self.roots()[id]) != FREE_TREE {
+                    self.roots_mut()[id] = u32::to_be_bytes(EMPTY_TREE);
+                }
+            }
+        }
+    }
+
+    /// Drops the cached length of the tree, see [`RBForest::len_cached`](crate::forest::RBForest::len_cached)
+    fn invalidate_len(&mut self, tree_id: usize) {
+        self.lens_mut()[tree_id] = u32::to_be_bytes(NO_LEN);
+    }
+
+    /// Advances the generation of the forest, see [`RBForest::generation()`](crate::forest::RBForest::generation)
+    fn bump_generation(&mut self) {
+        let generation = self.header().generation();
+        self.header_mut().set_generation(generation.wrapping_add(1));
+    }
+
+    /// Allocates a node
+    ///
+    /// # Safety
+    ///
+    /// This function does nothing but allocation. The returned node (if present) is
+    /// completely unlinked from the tree and is in the unknown state. The caller must fill the
+    /// node with correct data.
+    #[must_use]
+    fn allocate_node(&mut self) -> Option<usize> {
+        let free = self.header().free();
+        if free <= self.header().reserved() {
+            return None;
+        }
+
+        let allocator_head = self.header().head();
+        match allocator_head {
+            Some(index) => {
+                let new_head = self.parent(index as usize);
+                self.header_mut().set_head(new_head);
+                self.header_mut().set_free(free - 1);
+                self.bump_generation();
+                Some(index as usize)
+            }
+            None => None,
+        }
+    }
+
+    /// Deallocates a node
+    ///
+    /// # Safety
+    ///
+    /// This function does nothing but deallocation. It should be checked, that the node is
+    /// completely unlinked from the tree.
+    fn deallocate_node(&mut self, index: usize) {
+        let allocator_head = self.header().head();
+        let node_index = Some(index as u32);
+
+        self.set_parent(index, allocator_head);
+        self.header_mut().set_head(node_index);
+        let free = self.header().free();
+        self.header_mut().set_free(free + 1);
+        self.bump_generation();
+    }
+
+    fn balance_after_put(&mut self, tree_id: usize, mut id: u32) -> u32 {
+        let right_left_subnode = match self.right(id as usize) {
+            Some(sub_id) => self.left(sub_id as usize),
+            None => None,
+        };
+
+        if self.is_red(self.right(id as usize)) && self.is_red(right_left_subnode) {
+            self.rotate_right(tree_id, self.right(id as usize).unwrap());
+        }
+
+        if self.is_red(self.right(id as usize)) && !self.is_red(self.left(id as usize)) {
+            id = self.rotate_left(tree_id, id);
+        }
+
+        let left_subnode = match self.left(id as usize) {
+            Some(sub_id) => self.left(sub_id as usize),
+            None => None,
+        };
+
+        if self.is_red(self.left(id as usize)) && self.is_red(left_subnode) {
+            id = self.rotate_right(tree_id, id);
+        }
+
+        if self.is_red(self.right(id as usize)) && self.is_red(self.left(id as usize)) {
+            // If nodes are red, they are not Option::None, so unwrap will never fail
+            let left_id = self.left(id as usize).unwrap() as usize;
+            let right_id = self.right(id as usize).unwrap() as usize;
+
+            // Color swap
+            self.set_is_red(left_id, false);
+            self.set_is_red(right_id, false);
+            self.set_is_red(id as usize, true);
+        }
+
+        id
+    }
+
+    /// Replaces `old` with `new` among the children of `parent_id`
+    fn replace_child(&mut self, parent_id: usize, old: u32, new: Option<u32>) {
+        if self.left(parent_id) == Some(old) {
+            self.set_left(parent_id, new);
+        } else {
+            debug_assert_eq!(self.right(parent_id), Some(old));
+
+            self.set_right(parent_id, new);
+        }
+    }
+
+    fn rotate_left(&mut self, tree_id: usize, h: u32) -> u32 {
+        self.count_rotation();
+
+        let x = self
+            .right(h as usize)
+            .expect("RBTree invariants corrupted: rotate_left on subtree without right child");
+
+        let x_left = self.left(x as usize);
+        self.set_right(h as usize, x_left);
+        self.set_left(x as usize, Some(h));
+        let h_is_red = self.node_is_red(h as usize);
+        self.set_is_red(x as usize, h_is_red);
+        self.set_is_red(h as usize, true);
+
+        // fix parents
+        if let Some(parent_id) = self.parent(h as usize) {
+            self.replace_child(parent_id as usize, h, Some(x));
+        } else {
+            self.set_root(tree_id, Some(x));
+        }
+        let h_parent = self.parent(h as usize);
+        self.set_parent(x as usize, h_parent);
+        self.set_parent(h as usize, Some(x));
+        if let Some(right) = self.right(h as usize) {
+            self.set_parent(right as usize, Some(h));
+        }
+
+        x
+    }
+
+    fn rotate_right(&mut self, tree_id: usize, h: u32) -> u32 {
+        self.count_rotation();
+
+        let x = self
+            .left(h as usize)
+            .expect("RBTree invariants corrupted: rotate_left on subtree without left child");
+
+        let x_right = self.right(x as usize);
+        self.set_left(h as usize, x_right);
+        self.set_right(x as usize, Some(h));
+        let h_is_red = self.node_is_red(h as usize);
+        self.set_is_red(x as usize, h_is_red);
+        self.set_is_red(h as usize, true);
+
+        // fix parents
+        if let Some(parent_id) = self.parent(h as usize) {
+            self.replace_child(parent_id as usize, h, Some(x));
+        } else {
+            self.set_root(tree_id, Some(x));
+        }
+        let h_parent = self.parent(h as usize);
+        self.set_parent(x as usize, h_parent);
+        self.set_parent(h as usize, Some(x));
+        if let Some(left) = self.left(h as usize) {
+            self.set_parent(left as usize, Some(h));
+        }
+
+        x
+    }
+
+    /// Unlinks and deallocates the node `id` of the tree, returns the index of the deallocated node
+    ///
+    /// The entry of `id` may be moved to another node, so the returned index may differ from `id`.
+    fn delete_node(&mut self, tree_id: usize, mut id: usize) -> usize {
+        self.invalidate_len(tree_id);
+        if self.left(id).is_some() && self.right(id).is_some() {
+            {
+                id = self.swap_max_left(id);
+            }
+        }
+
+        match (self.left(id), self.right(id)) {
+            (Some(_), Some(_)) => {
+                unreachable!("swap_max_left() returned a node with two children");
+            }
+            (Some(left), None) => {
+                let left_id = left as usize;
+                // This node has to be black, its child has to be red
+                debug_assert!(!self.node_is_red(id));
+                debug_assert!(self.node_is_red(left_id));
+
+                self.swap_entries(id, left_id);
+
+                self.set_left(id, None);
+                self.deallocate_node(left_id);
+
+                left_id
+            }
+            (None, Some(right)) => {
+                let right_id = right as usize;
+                // This node has to be black, its child has to be red
+                debug_assert!(!self.node_is_red(id));
+                debug_assert!(self.node_is_red(right_id));
+
+                self.swap_entries(id, right_id);
+
+                self.set_right(id, None);
+
+                self.deallocate_node(right_id);
+
+                right_id
+            }
+            (None, None) => {
+                if self.node_is_red(id) {
+                    // Root node is always black, so if nodes[id] is red, it always has a parent
+                    let parent_id = self.parent(id).unwrap();
+                    self.replace_child(parent_id as usize, id as u32, None);
+
+                    self.deallocate_node(id);
+
+                    id
+                } else {
+                    if let Some(parent_id) = self.parent(id) {
+                        self.replace_child(parent_id as usize, id as u32, None);
+
+                        self.balance_subtree(tree_id, parent_id as usize);
+                    } else {
+                        self.set_root(tree_id, None);
+                    }
+
+                    self.deallocate_node(id);
+
+                    id
+                }
+            }
+        }
+    }
+
+    #[must_use]
+    fn swap_max_left(&mut self, id: usize) -> usize {
+        let mut max_id = self
+            .left(id)
+            .expect("swap_max_left should only be called on nodes with two children")
+            as usize;
+        while let Some(maybe_max) = self.right(max_id) {
+            max_id = maybe_max as usize;
+        }
+
+        debug_assert_ne!(id, max_id);
+        self.swap_entries(id, max_id);
+        max_id
+    }
+
+    fn balance_subtree(&mut self, tree_id: usize, id: usize) {
+        let left_child = self.left(id);
+        let right_child = self.right(id);
+        let left_depth = self.black_depth(left_child);
+        let right_depth = self.black_depth(right_child);
+        match left_depth.cmp(&right_depth) {
+            Ordering::Greater => {
+                // left_depth is greater than right_depth, so it is >= 1 and therefore left_child
+                // always exists
+                let left_id = left_child.unwrap() as usize;
+                if self.node_is_red(id) {
+                    debug_assert!(!self.node_is_red(left_id));
+                    let left_grandchild = self.left(left_id);
+                    let right_grandchild = self.right(left_id);
+                    match (self.is_red(left_grandchild), self.is_red(right_grandchild)) {
+                        (false, false) => {
+                            self.set_is_red(id, false);
+                            self.set_is_red(left_id, true);
+                        }
+                        (true, _) => {
+                            self.rotate_right(tree_id, id as u32);
+
+                            self.set_is_red(id, false);
+                            self.set_is_red(left_id, true);
+                            // left_grandchild is red, so it exists
+                            self.set_is_red(left_grandchild.unwrap() as usize, false);
+                        }
+                        (false, true) => {
+                            self.rotate_left(tree_id, left_id as u32);
+                            self.rotate_right(tree_id, id as u32);
+                            // right_grandchild is red, so it exists
+                            self.set_is_red(right_grandchild.unwrap() as usize, false);
+                        }
+                    }
+                } else if self.node_is_red(left_id) {
+                    debug_assert!(!self.is_red(self.left(left_id)));
+                    debug_assert!(!self.is_red(self.right(left_id)));
+                    // left_depth is greater than right_depth, so it is >= 1
+                    // left_child is red and does not affect black height
+                    // therefore left and right grandchildren exists
+                    let right_grandchild = self.right(left_id).unwrap() as usize;
+                    let left_grandgrandchild = self.left(right_grandchild);
+                    let right_grandgrandchild = self.right(right_grandchild);
+
+                    match (
+                        self.is_red(left_grandgrandchild),
+                        self.is_red(right_grandgrandchild),
+                    ) {
+                        (false, false) => {
+                            self.rotate_right(tree_id, id as u32);
+                            self.set_is_red(id, false);
+                            self.set_is_red(right_grandchild, true);
+                        }
+                        (true, _) => {
+                            self.rotate_left(tree_id, left_id as u32);
+                            self.rotate_right(tree_id, id as u32);
+                            // left_grandgrandchild is red, so it always exists
+                            self.set_is_red(left_grandgrandchild.unwrap() as usize, false);
+                            self.set_is_red(right_grandchild, false);
+                            self.set_is_red(id, false);
+                        }
+                        (false, true) => {
+                            self.rotate_left(tree_id, right_grandchild as u32);
+                            self.rotate_left(tree_id, left_id as u32);
+                            self.rotate_right(tree_id, id as u32);
+                            // left_grandgrandchild is red, so it always exists
+                            self.set_is_red(right_grandgrandchild.unwrap() as usize, false);
+                            self.set_is_red(right_grandchild, false);
+                            self.set_is_red(id, false);
+                        }
+                    }
+                } else {
+                    let left_grandchild = self.left(left_id);
+                    let right_grandchild = self.right(left_id);
+
+                    match (self.is_red(left_grandchild), self.is_red(right_grandchild)) {
+                        (false, false) => {
+                            self.set_is_red(left_id, true);
+                            if let Some(parent_id) = self.parent(id) {
+                                self.balance_subtree(tree_id, parent_id as usize);
+                            }
+                        }
+                        (_, true) => {
+                            self.rotate_left(tree_id, left_id as u32);
+                            self.rotate_right(tree_id, id as u32);
+                            self.set_is_red(left_id, false);
+                            self.set_is_red(id, false);
+                        }
+                        (true, false) => {
+                            self.set_is_red(left_grandchild.unwrap() as usize, false);
+                            self.rotate_right(tree_id, id as u32);
+                            self.set_is_red(id, false);
+                        }
+                    }
+                }
+            }
+            Ordering::Less => {
+                // right_depth is greater than left_depth, so it >= 1 and therefore right_child
+                // always exists
+                let right_id = right_child.unwrap() as usize;
+                if self.node_is_red(id) {
+                    debug_assert!(!self.node_is_red(right_id));
+                    let right_grandchild = self.right(right_id);
+                    let left_grandchild = self.left(right_id);
+                    match (self.is_red(right_grandchild), self.is_red(left_grandchild)) {
+                        (false, false) => {
+                            self.set_is_red(id, false);
+                            self.set_is_red(right_id, true);
+                        }
+                        (true, _) => {
+                            self.rotate_left(tree_id, id as u32);
+
+                            self.set_is_red(id, false);
+                            self.set_is_red(right_id, true);
+                            // right_grandchild is red, so it always exists
+                            self.set_is_red(right_grandchild.unwrap() as usize, false);
+                        }
+                        (false, true) => {
+                            self.rotate_right(tree_id, right_id as u32);
+                            self.rotate_left(tree_id, id as u32);
+                            // right_grandchild is red, so it always exists
+                            self.set_is_red(left_grandchild.unwrap() as usize, false);
+                        }
+                    }
+                } else if self.node_is_red(right_id) {
+                    debug_assert!(!self.is_red(self.right(right_id)));
+                    debug_assert!(!self.is_red(self.left(right_id)));
+                    // right_depth is greater than left_depth, so it is >= 1
+                    // right_child is red and does not affect black height
+                    // therefore left and right grandchildren exists
+                    let left_grandchild = self.left(right_id).unwrap() as usize;
+                    let right_grandgrandchild = self.right(left_grandchild);
+                    let left_grandgrandchild = self.left(left_grandchild);
+
+                    match (
+                        self.is_red(right_grandgrandchild),
+                        self.is_red(left_grandgrandchild),
+                    ) {
+                        (false, false) => {
+                            self.rotate_left(tree_id, id as u32);
+                            self.set_is_red(id, false);
+                            self.set_is_red(left_grandchild, true);
+                        }
+                        (true, _) => {
+                            self.rotate_right(tree_id, right_id as u32);
+                            self.rotate_left(tree_id, id as u32);
+                            // right_grandgrandchild is red, so it always exists
+                            self.set_is_red(right_grandgrandchild.unwrap() as usize, false);
+                            self.set_is_red(left_grandchild, false);
+                            self.set_is_red(id, false);
+                        }
+                        (false, true) => {
+                            self.rotate_right(tree_id, left_grandchild as u32);
+                            self.rotate_right(tree_id, right_id as u32);
+                            self.rotate_left(tree_id, id as u32);
+                            // left_grandgrandchild is red, so it always exists
+                            self.set_is_red(left_grandgrandchild.unwrap() as usize, false);
+                            self.set_is_red(left_grandchild, false);
+                            self.set_is_red(id, false);
+                        }
+                    }
+                } else {
+                    let right_grandchild = self.right(right_id);
+                    let left_grandchild = self.left(right_id);
+
+                    match (self.is_red(right_grandchild), self.is_red(left_grandchild)) {
+                        (false, false) => {
+                            self.set_is_red(right_id, true);
+                            if let Some(parent_id) = self.parent(id) {
+                                self.balance_subtree(tree_id, parent_id as usize);
+                            }
+                        }
+                        (_, true) => {
+                            self.rotate_right(tree_id, right_id as u32);
+                            self.rotate_left(tree_id, id as u32);
+                            self.set_is_red(right_id, false);
+                            self.set_is_red(id, false);
+                        }
+                        (true, false) => {
+                            // right_grandchild is red, so it always exists
+                            self.set_is_red(right_grandchild.unwrap() as usize, false);
+                            self.rotate_left(tree_id, id as u32);
+                            self.set_is_red(id, false);
+                        }
+                    }
+                }
+            }
+            Ordering::Equal => {
+                unreachable!("balance_subtree() should only be called on non ballanced trees. It could be a sign, that the tree was not previously balanced.");
+            }
+        }
+    }
+
+    #[must_use]
+    fn black_depth(&self, mut maybe_id: Option<u32>) -> usize {
+        let mut depth = 0;
+        while let Some(id) = maybe_id {
+            if !self.node_is_red(id as usize) {
+                depth += 1;
+            }
+            maybe_id = self.left(id as usize);
+        }
+        depth
+    }
+}
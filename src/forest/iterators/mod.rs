@@ -1,10 +1,12 @@
 //! Iterators over [`RBTree`](crate::tree::RBTree) and [`RBForest`](crate::forest::RBForest)
+use borsh::maybestd::vec::Vec;
 use borsh::{BorshDeserialize, BorshSerialize};
 use core::cmp::Ord;
 use core::fmt;
 use core::iter::FusedIterator;
 
-use super::RBForest;
+use super::{RBForest, RawNodes};
+use crate::Error;
 
 /// An iterator over key-value pairs ordered by key
 pub struct PairsIterator<'a, 'b, K, V, const KSIZE: usize, const VSIZE: usize>
@@ -13,6 +15,8 @@ where
     V: BorshDeserialize + BorshSerialize,
 {
     next_node: Option<usize>,
+    // the pair, returned by the last `peek()` and not yet consumed by `next()`
+    peeked: Option<(K, V)>,
     tree: &'a RBForest<'b, K, V, KSIZE, VSIZE>,
 }
 impl<'a, 'b, K, V, const KSIZE: usize, const VSIZE: usize> PairsIterator<'a, 'b, K, V, KSIZE, VSIZE>
@@ -24,21 +28,46 @@ where
         tree: &'a RBForest<'b, K, V, KSIZE, VSIZE>,
         next_node: Option<usize>,
     ) -> Self {
-        Self { next_node, tree }
+        Self {
+            next_node,
+            peeked: None,
+            tree,
+        }
     }
-}
 
-impl<'a, 'b, K, V, const KSIZE: usize, const VSIZE: usize> Iterator
-    for PairsIterator<'a, 'b, K, V, KSIZE, VSIZE>
-where
-    K: Ord + BorshDeserialize + BorshSerialize,
-    V: BorshDeserialize + BorshSerialize,
-{
-    type Item = (K, V);
+    /// Returns a reference to the next pair without advancing the iterator
+    ///
+    /// The pair is deserialized once and cached until the following call to `next()`, so this is
+    /// a `no_std`-friendly replacement for [`core::iter::Peekable`].
+    pub fn peek(&mut self) -> Option<&(K, V)> {
+        if self.peeked.is_none() {
+            self.peeked = self.advance();
+        }
+        self.peeked.as_ref()
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    /// Returns the serialized key of the next pair as an opaque resume token
+    ///
+    /// Unlike the iterator itself, the token does not borrow the forest, so it can be stored
+    /// between calls and passed to [`RBForest::pairs_resume`] to continue the iteration. Returns
+    /// `None` if the iterator is exhausted.
+    #[must_use]
+    pub fn position(&self) -> Option<Vec<u8>> {
+        match &self.peeked {
+            Some((key, _)) => {
+                // The key is padded with zeroes just like the keys in the nodes
+                let mut token = [0; KSIZE];
+                key.serialize(&mut token.as_mut_slice())
+                    .expect("Key corrupted");
+                Some(token.to_vec())
+            }
+            None => self.next_node.map(|id| self.tree.nodes()[id].key.to_vec()),
+        }
+    }
+
+    fn advance(&mut self) -> Option<(K, V)> {
         self.next_node.map(|mut id| {
-            let nodes = &self.tree.nodes;
+            let nodes = &self.tree.nodes();
 
             let key = K::deserialize(&mut nodes[id].key.as_slice()).expect("Key corrupted");
             let value = V::deserialize(&mut nodes[id].value.as_slice()).expect("Value corrupted");
@@ -64,6 +93,30 @@ where
     }
 }
 
+impl<'a, 'b, K, V, const KSIZE: usize, const VSIZE: usize> Iterator
+    for PairsIterator<'a, 'b, K, V, KSIZE, VSIZE>
+where
+    K: Ord + BorshDeserialize + BorshSerialize,
+    V: BorshDeserialize + BorshSerialize,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.peeked.take().or_else(|| self.advance())
+    }
+
+    fn nth(&mut self, mut n: usize) -> Option<Self::Item> {
+        if n > 0 && self.peeked.take().is_some() {
+            n -= 1;
+        }
+        // Skipped nodes are not deserialized
+        for _ in 0..n {
+            self.next_node = self.tree.successor(self.next_node?);
+        }
+        self.next()
+    }
+}
+
 impl<'a, 'b, K, V, const KSIZE: usize, const VSIZE: usize> FusedIterator
     for PairsIterator<'a, 'b, K, V, KSIZE, VSIZE>
 where
@@ -79,12 +132,179 @@ where
     V: BorshDeserialize + BorshSerialize + fmt::Debug,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
-        let PairsIterator { next_node, tree } = self;
-        let new_iter = PairsIterator {
+        let PairsIterator {
+            next_node,
+            peeked,
+            tree,
+        } = self;
+        let new_iter = PairsIterator::from_raw_parts(tree, *next_node);
+        f.debug_map()
+            .entries(peeked.iter().map(|(k, v)| (k, v)))
+            .entries(new_iter)
+            .finish()
+    }
+}
+
+/// A fallible iterator over key-value pairs ordered by key
+///
+/// Unlike [`PairsIterator`], it yields [`Error::KeySerializationError`] or
+/// [`Error::ValueSerializationError`] instead of panicking, if an entry can not be deserialized.
+/// The iteration continues after such an error with the next entry.
+pub struct TryPairsIterator<'a, 'b, K, V, const KSIZE: usize, const VSIZE: usize>
+where
+    K: Ord + BorshDeserialize + BorshSerialize,
+    V: BorshDeserialize + BorshSerialize,
+{
+    next_node: Option<usize>,
+    tree: &'a RBForest<'b, K, V, KSIZE, VSIZE>,
+}
+
+impl<'a, 'b, K, V, const KSIZE: usize, const VSIZE: usize>
+    TryPairsIterator<'a, 'b, K, V, KSIZE, VSIZE>
+where
+    K: Ord + BorshDeserialize + BorshSerialize,
+    V: BorshDeserialize + BorshSerialize,
+{
+    pub(super) fn from_raw_parts(
+        tree: &'a RBForest<'b, K, V, KSIZE, VSIZE>,
+        next_node: Option<usize>,
+    ) -> Self {
+        Self { next_node, tree }
+    }
+}
+
+impl<'a, 'b, K, V, const KSIZE: usize, const VSIZE: usize> Iterator
+    for TryPairsIterator<'a, 'b, K, V, KSIZE, VSIZE>
+where
+    K: Ord + BorshDeserialize + BorshSerialize,
+    V: BorshDeserialize + BorshSerialize,
+{
+    type Item = Result<(K, V), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_node.map(|id| {
+            let node = &self.tree.nodes()[id];
+            self.next_node = self.tree.successor(id);
+
+            let key = K::deserialize(&mut node.key.as_slice())
+                .map_err(|_| Error::KeySerializationError)?;
+            let value = V::deserialize(&mut node.value.as_slice())
+                .map_err(|_| Error::ValueSerializationError)?;
+
+            Ok((key, value))
+        })
+    }
+}
+
+impl<'a, 'b, K, V, const KSIZE: usize, const VSIZE: usize> FusedIterator
+    for TryPairsIterator<'a, 'b, K, V, KSIZE, VSIZE>
+where
+    K: Ord + BorshDeserialize + BorshSerialize,
+    V: BorshDeserialize + BorshSerialize,
+{
+}
+
+impl<'a, 'b, K, V, const KSIZE: usize, const VSIZE: usize> fmt::Debug
+    for TryPairsIterator<'a, 'b, K, V, KSIZE, VSIZE>
+where
+    K: Ord + BorshDeserialize + BorshSerialize + fmt::Debug,
+    V: BorshDeserialize + BorshSerialize + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        let TryPairsIterator { next_node, tree } = self;
+        let new_iter = TryPairsIterator {
             next_node: *next_node,
             tree,
         };
-        f.debug_map().entries(new_iter).finish()
+        f.debug_list().entries(new_iter).finish()
+    }
+}
+
+/// An iterator over entries of all the trees of a forest, merged in global key order
+///
+/// Each item is tagged with the id of the tree it comes from. Equal keys from different trees are
+/// yielded in ascending order of `tree_id`.
+pub struct MergedPairsIterator<'a, 'b, K, V, const KSIZE: usize, const VSIZE: usize>
+where
+    K: Ord + BorshDeserialize + BorshSerialize,
+    V: BorshDeserialize + BorshSerialize,
+{
+    // the next node of each tree and its deserialized key
+    cursors: Vec<Option<(usize, K)>>,
+    tree: &'a RBForest<'b, K, V, KSIZE, VSIZE>,
+}
+
+impl<'a, 'b, K, V, const KSIZE: usize, const VSIZE: usize>
+    MergedPairsIterator<'a, 'b, K, V, KSIZE, VSIZE>
+where
+    K: Ord + BorshDeserialize + BorshSerialize,
+    V: BorshDeserialize + BorshSerialize,
+{
+    pub(super) fn new(tree: &'a RBForest<'b, K, V, KSIZE, VSIZE>) -> Self {
+        let cursors = (0..tree.roots().len())
+            .map(|tree_id| {
+                tree.root(tree_id)
+                    .map(|root_id| Self::cursor(tree, tree.min(root_id as usize)))
+            })
+            .collect();
+        Self { cursors, tree }
+    }
+
+    fn cursor(tree: &RBForest<'b, K, V, KSIZE, VSIZE>, id: usize) -> (usize, K) {
+        let key = K::deserialize(&mut tree.nodes()[id].key.as_slice()).expect("Key corrupted");
+        (id, key)
+    }
+}
+
+impl<'a, 'b, K, V, const KSIZE: usize, const VSIZE: usize> Iterator
+    for MergedPairsIterator<'a, 'b, K, V, KSIZE, VSIZE>
+where
+    K: Ord + BorshDeserialize + BorshSerialize,
+    V: BorshDeserialize + BorshSerialize,
+{
+    type Item = (usize, K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut min: Option<(usize, &K)> = None;
+        for (tree_id, cursor) in self.cursors.iter().enumerate() {
+            if let Some((_, key)) = cursor {
+                if min.map_or(true, |(_, min_key)| key < min_key) {
+                    min = Some((tree_id, key));
+                }
+            }
+        }
+
+        let (tree_id, _) = min?;
+        let (id, key) = self.cursors[tree_id].take()?;
+        let value =
+            V::deserialize(&mut self.tree.nodes()[id].value.as_slice()).expect("Value corrupted");
+        self.cursors[tree_id] = self
+            .tree
+            .successor(id)
+            .map(|next_id| Self::cursor(self.tree, next_id));
+
+        Some((tree_id, key, value))
+    }
+}
+
+impl<'a, 'b, K, V, const KSIZE: usize, const VSIZE: usize> FusedIterator
+    for MergedPairsIterator<'a, 'b, K, V, KSIZE, VSIZE>
+where
+    K: Ord + BorshDeserialize + BorshSerialize,
+    V: BorshDeserialize + BorshSerialize,
+{
+}
+
+impl<'a, 'b, K, V, const KSIZE: usize, const VSIZE: usize> fmt::Debug
+    for MergedPairsIterator<'a, 'b, K, V, KSIZE, VSIZE>
+where
+    K: Ord + BorshDeserialize + BorshSerialize + fmt::Debug,
+    V: BorshDeserialize + BorshSerialize + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.debug_struct("MergedPairsIterator")
+            .field("cursors", &self.cursors)
+            .finish_non_exhaustive()
     }
 }
 
@@ -121,7 +341,7 @@ where
 
     fn next(&mut self) -> Option<Self::Item> {
         self.next_node.map(|mut id| {
-            let nodes = &self.tree.nodes;
+            let nodes = &self.tree.nodes();
 
             let key = K::deserialize(&mut nodes[id].key.as_slice()).expect("Key corrupted");
 
@@ -204,7 +424,7 @@ where
 
     fn next(&mut self) -> Option<Self::Item> {
         self.next_node.map(|mut id| {
-            let nodes = &self.tree.nodes;
+            let nodes = &self.tree.nodes();
 
             let value = V::deserialize(&mut nodes[id].value.as_slice()).expect("Value corrupted");
 
@@ -371,7 +371,18 @@ fn fractional_node_count() {
 }
 
 // This is an example of byte-packed forest used to check binary compatibility
-const FOREST_BYTES: [u8; 160] = [
+const FOREST_BYTES: [u8; 184] = [
+    83, 108, 105, 99, 101, 82, 66, 84, 114, 101, 101, 50, 0, 1, 0, 1, 0, 0, 0, 8, 0, 0, 0, 3, 255,
+    255, 255, 255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4,
+    12, 1, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 4,
+    2, 5, 0, 0, 0, 2, 0, 0, 0, 4, 0, 0, 0, 4, 3, 5, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 5, 5, 1,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 7, 4, 2, 9, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 7, 4, 4, 3, 0, 0,
+    0, 6, 0, 0, 0, 5, 0, 0, 0, 6, 3, 0, 0, 0, 7, 0, 0, 0, 3, 0, 0, 0, 1, 255, 255, 255, 255, 255,
+    255, 255, 255, 255, 255, 255, 255,
+];
+
+// The same forest in the layout before the generation was added to the header
+const FOREST_BYTES_V1: [u8; 160] = [
     83, 108, 105, 99, 101, 95, 82, 66, 84, 114, 101, 101, 0, 1, 0, 1, 0, 0, 0, 8, 0, 0, 0, 3, 255,
     255, 255, 255, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 12, 1, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0,
     0, 0, 0, 0, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 4, 2, 5, 0, 0, 0, 2, 0, 0, 0, 4, 0, 0, 0,
@@ -405,6 +416,12 @@ fn deserialization() {
             assert_eq!(key_value, expected_key_value);
         }
     }
+
+    let old_bytes = &mut FOREST_BYTES_V1.clone();
+    assert_eq!(
+        unsafe { RBForest::<u8, u8, 1, 1>::from_slice(old_bytes) }.err(),
+        Some(Error::WrongMagic)
+    );
 }
 
 #[test]
@@ -417,7 +434,7 @@ fn too_big() {
 
 pub fn create_vec(k_size: usize, v_size: usize, num_entries: usize, max_roots: usize) -> Vec<u8> {
     let len = mem::size_of::<Header>()
-        + 4 * max_roots
+        + TREE_SLOT_SIZE * max_roots
         + (mem::size_of::<Node<0, 0>>() + k_size + v_size) * num_entries;
     vec![0; len]
 }
@@ -467,6 +484,38 @@ mod init_forest_tests {
         .unwrap();
 
         assert_eq!(testing_vec, reference_vec);
+        assert!(is_canonically_initialized(&testing_vec));
+    }
+
+    #[test]
+    fn canonical_initialization() {
+        let mut vec = create_vec(4, 4, 10, 2);
+        assert!(!is_canonically_initialized(&vec));
+        RBForest::<u32, u32, 4, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+        assert!(is_canonically_initialized(&vec));
+
+        // The parent link of the node 3, which threads the free list
+        let parent_offset = mem::size_of::<Header>() + 3 * super::super::node_stride(4, 4) + 16;
+        vec[parent_offset + 3] ^= 1;
+        assert!(!is_canonically_initialized(&vec));
+        vec[parent_offset + 3] ^= 1;
+        assert!(is_canonically_initialized(&vec));
+
+        let mut forest =
+            unsafe { RBForest::<u32, u32, 4, 4>::from_slice(vec.as_mut_slice()) }.unwrap();
+        forest.allocate_tree().unwrap();
+        assert!(!is_canonically_initialized(&vec));
+
+        // A cleared forest is empty, but its tree slots stay allocated and its nodes keep stale
+        // links
+        let mut vec = create_vec(4, 4, 10, 2);
+        let mut forest = RBForest::<u32, u32, 4, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+        let tree_id = forest.allocate_tree().unwrap();
+        for key in 0..5 {
+            forest.insert(tree_id, key, key).unwrap();
+        }
+        forest.clear();
+        assert!(!is_canonically_initialized(&vec));
     }
 
     #[test]
@@ -562,3 +611,4576 @@ mod init_forest {
         assert_eq!(tree, Error::WrongSliceSize);
     }
 }
+
+#[test]
+fn nodes_preorder() {
+    let mut vec = create_vec(1, 1, 256, 2);
+
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(forest.nodes_preorder(0).unwrap().count(), 0);
+    assert_eq!(forest.nodes_preorder(2).err(), Some(Error::TooBigTreeId));
+
+    for key in &INSERT_KEYS {
+        forest.insert(0, *key, *key).unwrap();
+    }
+
+    let nodes: Vec<_> = forest.nodes_preorder(0).unwrap().collect();
+    assert_eq!(nodes.len(), INSERT_KEYS.len());
+
+    let root = &nodes[0];
+    assert!(!root.is_red);
+    assert_eq!(root.depth, 0);
+    assert_eq!(root.parent, None);
+
+    let mut depths = std::collections::BTreeMap::new();
+    for node in &nodes {
+        assert_eq!(node.key, node.value);
+        // in pre-order a parent is always visited before its children
+        if let Some(parent) = node.parent {
+            assert_eq!(depths[&parent] + 1, node.depth);
+        }
+        depths.insert(node.key, node.depth);
+    }
+}
+
+#[test]
+fn borsh_bytes() {
+    use std::collections::BTreeMap;
+
+    let mut vec = create_vec(1, 1, 256, 3);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 3).unwrap();
+
+    let mut expected = vec![BTreeMap::new(); 3];
+    for (i, key) in INSERT_KEYS.iter().enumerate().take(100) {
+        let tree_id = if i % 3 == 0 { 0 } else { 2 };
+        forest.insert(tree_id, *key, i as u8).unwrap();
+        expected[tree_id].insert(*key, i as u8);
+    }
+
+    let bytes = forest.to_borsh_bytes();
+    let decoded = Vec::<BTreeMap<u8, u8>>::try_from_slice(&bytes).unwrap();
+    assert_eq!(decoded, expected);
+    assert!(bytes.len() < vec.len());
+
+    let mut new_vec = create_vec(1, 1, 100, 3);
+    let mut new_forest = RBForest::<u8, u8, 1, 1>::init_slice(new_vec.as_mut_slice(), 3).unwrap();
+    new_forest.insert(1, 1, 1).unwrap();
+    new_forest.populate_from_borsh_bytes(&bytes).unwrap();
+
+    for (tree_id, expected_tree) in expected.iter().enumerate() {
+        let pairs: Vec<_> = new_forest.pairs(tree_id).unwrap().collect();
+        let expected_pairs: Vec<_> = expected_tree.clone().into_iter().collect();
+        assert_eq!(pairs, expected_pairs);
+        assert!(new_forest.is_balanced(tree_id));
+    }
+
+    assert_eq!(
+        new_forest.populate_from_borsh_bytes(&bytes[..bytes.len() - 1]),
+        Err(Error::DeserializationError)
+    );
+
+    let mut small_vec = create_vec(1, 1, 10, 3);
+    let mut small_forest =
+        RBForest::<u8, u8, 1, 1>::init_slice(small_vec.as_mut_slice(), 3).unwrap();
+    assert_eq!(
+        small_forest.populate_from_borsh_bytes(&bytes),
+        Err(Error::NoNodesLeft)
+    );
+
+    let mut narrow_vec = create_vec(1, 1, 100, 2);
+    let mut narrow_forest =
+        RBForest::<u8, u8, 1, 1>::init_slice(narrow_vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(
+        narrow_forest.populate_from_borsh_bytes(&bytes),
+        Err(Error::TooBigTreeId)
+    );
+}
+
+#[test]
+fn contains_key_bytes() {
+    let mut vec = create_vec(32, 1, 64, 1);
+    let mut forest = RBForest::<[u8; 32], u8, 32, 1>::init_slice(vec.as_mut_slice(), 1).unwrap();
+
+    for key in INSERT_KEYS.iter().take(64) {
+        let mut pubkey = [*key; 32];
+        pubkey[0] = key.wrapping_mul(7);
+        forest.insert(0, pubkey, *key).unwrap();
+    }
+
+    for key in &INSERT_KEYS {
+        let mut pubkey = [*key; 32];
+        pubkey[0] = key.wrapping_mul(7);
+        assert_eq!(
+            forest.contains_key_bytes(0, &pubkey),
+            forest.contains_key(0, &pubkey)
+        );
+    }
+
+    let mut present = [INSERT_KEYS[0]; 32];
+    present[0] = INSERT_KEYS[0].wrapping_mul(7);
+    assert!(forest.contains_key_bytes(0, &present));
+    assert!(!forest.contains_key_bytes(0, &present[..31]));
+    assert!(!forest.contains_key_bytes(1, &present));
+}
+
+#[test]
+fn grow_into() {
+    let mut vec = create_vec(1, 1, 100, 2);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    for (i, key) in INSERT_KEYS.iter().enumerate().take(100) {
+        forest.insert(i % 2, *key, i as u8).unwrap();
+    }
+    assert_eq!(forest.free_nodes_left(), 0);
+    // free some nodes to check that the old free list is preserved
+    for key in INSERT_KEYS.iter().take(10) {
+        assert!(forest.delete(0, key) || forest.delete(1, key));
+    }
+
+    let mut too_small_vec = create_vec(1, 1, 99, 3);
+    assert_eq!(
+        forest.grow_into(too_small_vec.as_mut_slice(), 3).err(),
+        Some(Error::TooSmall)
+    );
+    let mut same_vec = create_vec(1, 1, 100, 1);
+    assert_eq!(
+        forest.grow_into(same_vec.as_mut_slice(), 1).err(),
+        Some(Error::TooBigTreeId)
+    );
+
+    let mut new_vec = create_vec(1, 1, 300, 3);
+    {
+        let mut new_forest = forest.grow_into(new_vec.as_mut_slice(), 3).unwrap();
+
+        assert_eq!(new_forest.max_roots(), 3);
+        assert_eq!(new_forest.free_nodes_left(), 210);
+        for tree_id in 0..2 {
+            assert!(forest
+                .pairs(tree_id)
+                .unwrap()
+                .eq(new_forest.pairs(tree_id).unwrap()));
+        }
+        assert!(new_forest.is_empty(2));
+
+        for key in INSERT_KEYS.iter().take(200) {
+            new_forest.insert(2, *key, *key).unwrap();
+        }
+        assert_eq!(new_forest.free_nodes_left(), 10);
+        for tree_id in 0..3 {
+            assert!(new_forest.is_balanced(tree_id));
+            assert!(new_forest.is_child_parent_links_consistent(tree_id));
+        }
+    }
+
+    let mut exact_vec = create_vec(1, 1, 100, 2);
+    {
+        let exact_forest = forest.grow_into(exact_vec.as_mut_slice(), 2).unwrap();
+        assert_eq!(exact_forest.free_nodes_left(), 10);
+    }
+    assert_eq!(exact_vec, vec);
+}
+
+#[test]
+fn is_forest() {
+    let params = ForestParams {
+        k_size: 3,
+        v_size: 7,
+        max_roots: 4,
+    };
+    let mut vec = vec![0; forest_size(params, 10)];
+    assert!(!super::is_forest(&vec));
+
+    init_forest(params, vec.as_mut_slice()).unwrap();
+    assert!(super::is_forest(&vec));
+    assert!(crate::is_forest(&FOREST_BYTES));
+
+    let mut wrong_magic = vec.clone();
+    wrong_magic[0] = b's';
+    assert!(!super::is_forest(&wrong_magic));
+
+    assert!(!super::is_forest(&vec[..vec.len() - 1]));
+    assert!(!super::is_forest(&vec[..mem::size_of::<Header>() + 4]));
+    assert!(!super::is_forest(&vec[..mem::size_of::<Header>()]));
+    assert!(!super::is_forest(&[]));
+}
+
+/// A key with `Ord` implementation, which is not a total order
+#[cfg(debug_assertions)]
+#[derive(BorshDeserialize, BorshSerialize, Debug, PartialEq, Eq)]
+struct InconsistentKey(u8);
+
+#[cfg(debug_assertions)]
+impl PartialOrd for InconsistentKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Ord for InconsistentKey {
+    fn cmp(&self, _other: &Self) -> Ordering {
+        Ordering::Less
+    }
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "Ord implementation is inconsistent")]
+fn inconsistent_ord_insert() {
+    let mut vec = create_vec(1, 1, 10, 1);
+    let mut forest =
+        RBForest::<InconsistentKey, u8, 1, 1>::init_slice(vec.as_mut_slice(), 1).unwrap();
+
+    forest.insert(0, InconsistentKey(1), 1).unwrap();
+    let _ = forest.insert(0, InconsistentKey(2), 2);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "Ord implementation is inconsistent")]
+fn inconsistent_ord_get() {
+    let mut vec = create_vec(1, 1, 10, 1);
+    let mut forest =
+        RBForest::<InconsistentKey, u8, 1, 1>::init_slice(vec.as_mut_slice(), 1).unwrap();
+
+    forest.insert(0, InconsistentKey(1), 1).unwrap();
+    let _ = forest.get(0, &InconsistentKey(1));
+}
+
+#[test]
+fn apply_batch() {
+    let mut vec = create_vec(4, 4, 10, 2);
+    let mut forest = RBForest::<i32, u32, 4, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    for key in 0..6 {
+        forest.insert(0, key, key as u32).unwrap();
+    }
+
+    forest
+        .apply_batch(&[
+            BatchOp::Insert(1, 10, 10),
+            BatchOp::Remove(0, 3),
+            BatchOp::Insert(0, 0, 100),
+            BatchOp::Remove(1, 10),
+            BatchOp::Insert(1, 11, 11),
+        ])
+        .unwrap();
+
+    assert_eq!(
+        forest.pairs(0).unwrap().collect::<Vec<_>>(),
+        vec![(0, 100), (1, 1), (2, 2), (4, 4), (5, 5)]
+    );
+    assert_eq!(forest.pairs(1).unwrap().collect::<Vec<_>>(), vec![(11, 11)]);
+    assert!(forest.is_balanced(0));
+    assert!(forest.is_balanced(1));
+}
+
+#[test]
+fn apply_batch_rollback() {
+    let mut vec = create_vec(4, 8, 10, 2);
+    let expected_pairs;
+    {
+        let mut forest = RBForest::<i32, String, 4, 8>::init_slice(vec.as_mut_slice(), 2).unwrap();
+        for key in 0..6 {
+            forest.insert(0, key, String::new()).unwrap();
+        }
+        expected_pairs = forest.pairs(0).unwrap().collect::<Vec<_>>();
+    }
+    let expected_bytes = vec.clone();
+
+    {
+        let mut forest =
+            unsafe { RBForest::<i32, String, 4, 8>::from_slice(vec.as_mut_slice()) }.unwrap();
+
+        // 5 new keys do not fit into 4 free nodes, even though one key is removed first
+        let too_many: Vec<_> = core::iter::once(BatchOp::Remove(0, 0))
+            .chain((10..15).map(|key| BatchOp::Insert(1, key, String::new())))
+            .collect();
+        assert_eq!(forest.apply_batch(&too_many), Err(Error::NoNodesLeft));
+
+        assert_eq!(
+            forest.apply_batch(&[
+                BatchOp::Remove(0, 1),
+                BatchOp::Insert(0, 20, "too long value".to_string())
+            ]),
+            Err(Error::ValueSerializationError)
+        );
+
+        assert_eq!(
+            forest.apply_batch(&[BatchOp::Remove(0, 1), BatchOp::Remove(2, 1)]),
+            Err(Error::TooBigTreeId)
+        );
+
+        assert_eq!(forest.pairs(0).unwrap().collect::<Vec<_>>(), expected_pairs);
+        assert!(forest.is_empty(1));
+    }
+    assert_eq!(vec, expected_bytes);
+
+    // Replacing existing values does not need any new nodes
+    let mut forest =
+        unsafe { RBForest::<i32, String, 4, 8>::from_slice(vec.as_mut_slice()) }.unwrap();
+    let replace: Vec<_> = (0..6)
+        .map(|key| BatchOp::Insert(0, key, String::new()))
+        .chain((10..14).map(|key| BatchOp::Insert(1, key, String::new())))
+        .collect();
+    assert_eq!(forest.apply_batch(&replace), Ok(()));
+    assert_eq!(forest.free_nodes_left(), 0);
+}
+
+#[test]
+fn sample_every() {
+    let mut vec = create_vec(4, 4, 100, 2);
+    let mut forest = RBForest::<u32, u32, 4, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    for key in (0..100).rev() {
+        forest.insert(0, key, key * 2).unwrap();
+    }
+
+    assert_eq!(
+        forest.sample_every(0, 10).unwrap().collect::<Vec<_>>(),
+        (0..100)
+            .step_by(10)
+            .map(|key| (key, key * 2))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        forest.sample_every(0, 1).unwrap().collect::<Vec<_>>(),
+        forest.pairs(0).unwrap().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        forest.sample_every(0, 33).unwrap().collect::<Vec<_>>(),
+        vec![(0, 0), (33, 66), (66, 132), (99, 198)]
+    );
+    assert_eq!(forest.sample_every(0, 1000).unwrap().count(), 1);
+    assert_eq!(forest.sample_every(1, 10).unwrap().count(), 0);
+    assert!(forest.sample_every(2, 10).is_err());
+    assert!(matches!(forest.sample_every(0, 0), Err(Error::ZeroLimit)));
+}
+
+#[test]
+fn surrounding() {
+    let mut vec = create_vec(4, 4, 100, 2);
+    let mut forest = RBForest::<i32, u32, 4, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    for &key in INSERT_KEYS.iter().take(100) {
+        forest.insert(0, key as i32 * 3, key as u32).unwrap();
+    }
+
+    let pairs: Vec<_> = forest.pairs(0).unwrap().collect();
+    for probe in -5..800 {
+        let floor = pairs.iter().rev().find(|(key, _)| *key <= probe).copied();
+        let ceil = pairs.iter().find(|(key, _)| *key >= probe).copied();
+        assert_eq!(forest.surrounding(0, &probe), (floor, ceil));
+    }
+
+    assert_eq!(forest.surrounding(1, &5), (None, None));
+    assert_eq!(forest.surrounding(2, &5), (None, None));
+}
+
+#[test]
+fn try_get_corrupted() {
+    let mut vec = create_vec(8, 8, 10, 1);
+    let mut forest = RBForest::<String, String, 8, 8>::init_slice(vec.as_mut_slice(), 1).unwrap();
+
+    for key in ["a", "b", "c", "d"] {
+        forest.insert(0, key.to_string(), key.repeat(2)).unwrap();
+    }
+    assert_eq!(forest.try_get(0, "b"), Ok(Some("bb".to_string())));
+    assert_eq!(forest.try_get(0, "e"), Ok(None));
+    assert_eq!(forest.try_get(1, "b"), Err(Error::TooBigTreeId));
+
+    // A length prefix, which does not fit into the buffer
+    let id = forest.get_key_index(0, "b").unwrap();
+    forest.nodes_mut()[id].value[0] = 0xff;
+    assert_eq!(forest.try_get(0, "b"), Err(Error::ValueSerializationError));
+    assert_eq!(forest.try_get(0, "a"), Ok(Some("aa".to_string())));
+
+    assert_eq!(
+        forest.try_pairs(0).unwrap().collect::<Vec<_>>(),
+        vec![
+            Ok(("a".to_string(), "aa".to_string())),
+            Err(Error::ValueSerializationError),
+            Ok(("c".to_string(), "cc".to_string())),
+            Ok(("d".to_string(), "dd".to_string())),
+        ]
+    );
+
+    let root_id = forest.root(0).unwrap() as usize;
+    forest.nodes_mut()[root_id].key[0] = 0xff;
+    assert_eq!(forest.try_get(0, "a"), Err(Error::KeySerializationError));
+    assert!(forest
+        .try_pairs(0)
+        .unwrap()
+        .any(|pair| pair == Err(Error::KeySerializationError)));
+}
+
+#[test]
+fn count_where() {
+    let mut vec = create_vec(1, 4, 256, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    for (i, &key) in INSERT_KEYS.iter().enumerate() {
+        forest.insert(0, key, i as u32).unwrap();
+    }
+
+    for threshold in [0, 1, 100, 200, 255, 256] {
+        let expected = forest
+            .pairs(0)
+            .unwrap()
+            .filter(|(_, value)| *value > threshold)
+            .count();
+        assert_eq!(
+            forest.count_where(0, |_, value| *value > threshold),
+            expected
+        );
+    }
+
+    assert_eq!(forest.count_where(0, |key, _| key % 2 == 0), 128);
+    assert_eq!(forest.count_where(1, |_, _| true), 0);
+    assert_eq!(forest.count_where(2, |_, _| true), 0);
+}
+
+#[test]
+fn count_where_lazy() {
+    let mut vec = create_vec(1, 8, 20, 2);
+    let mut forest = RBForest::<u8, String, 1, 8>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for key in 0..10 {
+        forest
+            .insert(0, key, "a".repeat(usize::from(key % 4)))
+            .unwrap();
+    }
+
+    assert_eq!(
+        forest.count_where_lazy(0, |key| *key >= 5, |_, value| value.len() > 1),
+        2
+    );
+
+    // Values of the rejected keys are not deserialized
+    let id = forest.get_key_index(0, &2).unwrap();
+    forest.nodes_mut()[id].value[0] = 0xff;
+    assert_eq!(
+        forest.count_where_lazy(0, |key| *key != 2, |_, value| value.is_empty()),
+        3
+    );
+    assert_eq!(forest.count_where_lazy(1, |_| true, |_, _| true), 0);
+    assert_eq!(forest.count_where_lazy(2, |_| true, |_, _| true), 0);
+}
+
+#[test]
+fn take() {
+    let mut vec = create_vec(16, 4, 10, 2);
+    let mut forest = RBForest::<String, u32, 16, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    for (i, key) in ["one", "two", "three"].iter().enumerate() {
+        forest.insert(0, key.to_string(), i as u32).unwrap();
+    }
+    let free_nodes = forest.free_nodes_left();
+
+    assert_eq!(forest.take(0, "two"), Some(("two".to_string(), 1)));
+    assert!(!forest.contains_key(0, "two"));
+    assert_eq!(forest.free_nodes_left(), free_nodes + 1);
+    assert_eq!(forest.len(0), Ok(2));
+    assert!(forest.is_balanced(0));
+
+    assert_eq!(forest.take(0, "two"), None);
+    assert_eq!(forest.take(1, "one"), None);
+    assert_eq!(forest.take(2, "one"), None);
+    assert_eq!(forest.get(0, "one"), Some(0));
+}
+
+#[test]
+fn recolor() {
+    let mut vec = create_vec(4, 4, 20, 2);
+    let mut forest = RBForest::<i32, u32, 4, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    // A right-leaning chain of 7 nodes with alternating colors: 0 -> 1 -> ... -> 6
+    for i in 0..7 {
+        let node = Node::from_raw_parts(
+            i32::to_le_bytes(i as i32 * 10),
+            u32::to_le_bytes(i),
+            None,
+            (i < 6).then_some(i + 1),
+            i.checked_sub(1),
+            i % 2 == 1,
+        );
+        forest.set_node(i as usize, &node);
+    }
+    forest.set_root(0, Some(0));
+    forest.set_head(Some(19));
+    for i in 7..20 {
+        forest.nodes_mut()[i].set_parent((i > 7).then_some(i as u32 - 1));
+    }
+
+    assert!(!forest.is_balanced(0));
+
+    assert_eq!(forest.recolor(0), Ok(()));
+    assert!(forest.is_balanced(0));
+    assert!(forest.no_double_red(0));
+    assert!(forest.is_child_parent_links_consistent(0));
+    assert_eq!(
+        forest.pairs(0).unwrap().collect::<Vec<_>>(),
+        (0..7).map(|i| (i as i32 * 10, i)).collect::<Vec<_>>()
+    );
+
+    // The tree is still usable after recoloring
+    for key in [5, 15, 25, 35, 45, 55, 65, 75, 0, 30, 60] {
+        forest.insert(0, key, 0).unwrap();
+        assert!(forest.is_balanced(0));
+        assert!(forest.no_double_red(0));
+    }
+    for key in [0, 10, 20, 75, 5, 45] {
+        assert!(forest.delete(0, &key));
+        assert!(forest.is_balanced(0));
+        assert!(forest.no_double_red(0));
+    }
+
+    // Each size must be colorable
+    for len in 0..16 {
+        let mut vec = create_vec(4, 4, 20, 1);
+        let mut forest = RBForest::<i32, u32, 4, 4>::init_slice(vec.as_mut_slice(), 1).unwrap();
+        for key in 0..len {
+            forest.insert(0, key, 0).unwrap();
+        }
+        for i in 0..len as usize {
+            forest.nodes_mut()[i].set_is_red(true);
+        }
+        forest.recolor(0).unwrap();
+        assert!(forest.is_balanced(0));
+        assert!(forest.no_double_red(0));
+        assert_eq!(forest.len(0), Ok(len as usize));
+    }
+
+    assert_eq!(forest.recolor(1), Ok(()));
+    assert_eq!(forest.recolor(2), Err(Error::TooBigTreeId));
+}
+
+#[test]
+fn partition() {
+    let mut vec = create_vec(1, 4, 256, 3);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 3).unwrap();
+
+    for &key in INSERT_KEYS.iter() {
+        forest.insert(0, key, key as u32 * 2).unwrap();
+    }
+    assert_eq!(forest.free_nodes_left(), 0);
+
+    forest.partition(0, 1, |key, _| key % 2 == 1).unwrap();
+
+    assert_eq!(
+        forest.pairs(0).unwrap().collect::<Vec<_>>(),
+        (0..=255)
+            .step_by(2)
+            .map(|key| (key, key as u32 * 2))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        forest.pairs(1).unwrap().collect::<Vec<_>>(),
+        (1..=255)
+            .step_by(2)
+            .map(|key| (key, key as u32 * 2))
+            .collect::<Vec<_>>()
+    );
+    for tree_id in 0..2 {
+        assert!(forest.is_balanced(tree_id));
+        assert!(forest.no_double_red(tree_id));
+        assert!(forest.is_child_parent_links_consistent(tree_id));
+    }
+    assert_eq!(forest.free_nodes_left(), 0);
+
+    assert_eq!(
+        forest.partition(0, 1, |_, _| true),
+        Err(Error::NonEmptyTree)
+    );
+    assert_eq!(
+        forest.partition(0, 3, |_, _| true),
+        Err(Error::TooBigTreeId)
+    );
+
+    // Moving everything by value
+    forest.partition(1, 2, |_, value| *value > 0).unwrap();
+    assert!(forest.is_empty(1));
+    assert_eq!(forest.len(2), Ok(128));
+    assert!(forest.is_balanced(2));
+
+    forest.partition(1, 1, |_, _| true).unwrap();
+    assert!(forest.is_empty(1));
+}
+
+#[test]
+fn merged_pairs() {
+    let mut vec = create_vec(4, 4, 260, 4);
+    let mut forest = RBForest::<i32, u32, 4, 4>::init_slice(vec.as_mut_slice(), 4).unwrap();
+
+    for (i, &key) in INSERT_KEYS.iter().enumerate() {
+        let tree_id = [0, 1, 3][i % 3];
+        forest.insert(tree_id, key as i32, i as u32).unwrap();
+    }
+    // A duplicate key in several trees
+    forest.insert(3, 1000, 0).unwrap();
+    forest.insert(0, 1000, 0).unwrap();
+
+    let merged: Vec<_> = forest.merged_pairs().collect();
+    assert_eq!(merged.len(), 258);
+    assert!(merged
+        .windows(2)
+        .all(|pair| (pair[0].1, pair[0].0) < (pair[1].1, pair[1].0)));
+    for (tree_id, key, value) in &merged {
+        assert_eq!(forest.get(*tree_id, key), Some(*value));
+    }
+    assert_eq!(merged[256], (0, 1000, 0));
+    assert_eq!(merged[257], (3, 1000, 0));
+
+    forest.clear();
+    assert_eq!(forest.merged_pairs().next(), None);
+}
+
+#[test]
+fn last_before_first_after() {
+    let mut vec = create_vec(4, 4, 100, 2);
+    let mut forest = RBForest::<i32, u32, 4, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    for &key in INSERT_KEYS.iter().take(100) {
+        forest.insert(0, key as i32 * 3, key as u32).unwrap();
+    }
+
+    let pairs: Vec<_> = forest.pairs(0).unwrap().collect();
+    // Probes between, equal to and outside of the stored keys
+    for probe in -5..800 {
+        let before = pairs.iter().rev().find(|(key, _)| *key < probe).copied();
+        let after = pairs.iter().find(|(key, _)| *key > probe).copied();
+        assert_eq!(forest.last_before(0, &probe), before);
+        assert_eq!(forest.first_after(0, &probe), after);
+    }
+
+    let (min_key, _) = forest.first_entry(0).unwrap();
+    let (max_key, _) = forest.last_entry(0).unwrap();
+    assert_eq!(forest.last_before(0, &min_key), None);
+    assert_eq!(forest.first_after(0, &max_key), None);
+
+    assert_eq!(forest.last_before(1, &5), None);
+    assert_eq!(forest.first_after(2, &5), None);
+}
+
+#[test]
+fn allocator_stats() {
+    let mut vec = create_vec(1, 1, 200, 3);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 3).unwrap();
+
+    assert_eq!(
+        forest.allocator_stats(),
+        AllocatorStats {
+            free: 200,
+            used: 0,
+            capacity: 200,
+            head: Some(199),
+        }
+    );
+
+    for (i, &key) in INSERT_KEYS.iter().cycle().take(1000).enumerate() {
+        let tree_id = (i * 7 + key as usize) % 3;
+        if key % 3 == 0 {
+            forest.remove(tree_id, &key);
+        } else {
+            let _ = forest.insert(tree_id, key, key);
+        }
+
+        let stats = forest.allocator_stats();
+        assert_eq!(stats.free + stats.used, stats.capacity);
+        assert_eq!(stats.free, forest.free_nodes_left());
+        assert_eq!(
+            stats.used,
+            (0..3)
+                .map(|tree_id| forest.len(tree_id).unwrap())
+                .sum::<usize>()
+        );
+    }
+
+    forest.clear();
+    assert_eq!(forest.allocator_stats().used, 0);
+}
+
+#[test]
+fn root_index() {
+    let mut vec = create_vec(1, 1, 20, 2);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    assert_eq!(forest.root_index(0), None);
+    assert_eq!(forest.root_index(2), None);
+
+    for &key in INSERT_KEYS.iter().take(10) {
+        forest.insert(0, key, key).unwrap();
+    }
+
+    let root_id = forest.root_index(0).unwrap() as usize;
+    assert!(root_id < 20);
+    assert_eq!(forest.nodes()[root_id].parent(), None);
+    assert_eq!(forest.root_index(1), None);
+}
+
+#[test]
+fn insert_bounded() {
+    let mut vec = create_vec(4, 4, 5, 2);
+    let mut forest = RBForest::<i32, u32, 4, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    // Not full
+    for key in [30, 10, 50, 20] {
+        assert_eq!(forest.insert_bounded(0, key, key as u32), Ok(None));
+    }
+    assert_eq!(forest.insert_bounded(1, 100, 100), Ok(None));
+    assert_eq!(forest.free_nodes_left(), 0);
+
+    // Eviction of the minimum
+    assert_eq!(forest.insert_bounded(0, 40, 40), Ok(Some((10, 10))));
+    assert_eq!(
+        forest.keys(0).unwrap().collect::<Vec<_>>(),
+        vec![20, 30, 40, 50]
+    );
+
+    // Rejection of a key, smaller than the minimum
+    assert_eq!(forest.insert_bounded(0, 15, 15), Ok(Some((15, 15))));
+    assert_eq!(
+        forest.keys(0).unwrap().collect::<Vec<_>>(),
+        vec![20, 30, 40, 50]
+    );
+
+    // Replacement does not evict anything
+    assert_eq!(forest.insert_bounded(0, 20, 0), Ok(None));
+    assert_eq!(forest.get(0, &20), Some(0));
+    assert_eq!(forest.len(0), Ok(4));
+    assert!(forest.is_balanced(0));
+
+    // Only the tree itself is evicted from
+    assert_eq!(forest.insert_bounded(1, 200, 200), Ok(Some((100, 100))));
+    forest.delete(1, &200);
+    forest.insert(0, 60, 60).unwrap();
+    assert_eq!(forest.insert_bounded(1, 200, 200), Err(Error::NoNodesLeft));
+    assert_eq!(forest.insert_bounded(2, 200, 200), Err(Error::TooBigTreeId));
+}
+
+#[test]
+fn insert_bounded_serialization_error() {
+    let mut vec = create_vec(4, 8, 3, 1);
+    let mut forest = RBForest::<u32, String, 4, 8>::init_slice(vec.as_mut_slice(), 1).unwrap();
+    for key in 1..=3 {
+        forest.insert(0, key, key.to_string()).unwrap();
+    }
+
+    // The value does not fit into 8 bytes, so nothing is evicted
+    assert_eq!(
+        forest.insert_bounded(0, 4, "too long value".to_string()),
+        Err(Error::ValueSerializationError)
+    );
+    assert_eq!(
+        forest.pairs(0).unwrap().collect::<Vec<_>>(),
+        [
+            (1, "1".to_string()),
+            (2, "2".to_string()),
+            (3, "3".to_string())
+        ]
+    );
+    assert_eq!(
+        forest.insert_bounded(0, 4, "4".to_string()),
+        Ok(Some((1, "1".to_string())))
+    );
+}
+
+#[test]
+fn gaps() {
+    let mut vec = create_vec(4, 1, 20, 2);
+    let mut forest = RBForest::<u32, u8, 4, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    for key in [10, 2, 5, 1, 6] {
+        forest.insert(0, key, 0).unwrap();
+    }
+
+    assert_eq!(
+        forest.gaps(0).unwrap().collect::<Vec<_>>(),
+        vec![(2, 5), (6, 10)]
+    );
+    assert_eq!(
+        forest
+            .gaps_by(0, |a, b| b - a <= 3)
+            .unwrap()
+            .collect::<Vec<_>>(),
+        vec![(6, 10)]
+    );
+    assert_eq!(
+        forest.gaps_by(0, |_, _| false).unwrap().collect::<Vec<_>>(),
+        vec![(1, 2), (2, 5), (5, 6), (6, 10)]
+    );
+
+    assert_eq!(forest.gaps(1).unwrap().count(), 0);
+    forest.insert(1, 7, 0).unwrap();
+    assert_eq!(forest.gaps(1).unwrap().count(), 0);
+    assert!(forest.gaps(2).is_err());
+}
+
+#[test]
+fn gaps_extreme_keys() {
+    let mut vec = create_vec(16, 1, 10, 2);
+    let mut forest = RBForest::<i128, u8, 16, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for key in [i128::MIN, i128::MIN + 1, 0, i128::MAX - 1, i128::MAX] {
+        forest.insert(0, key, 0).unwrap();
+    }
+    assert_eq!(
+        forest.gaps(0).unwrap().collect::<Vec<_>>(),
+        vec![(i128::MIN + 1, 0), (0, i128::MAX - 1)]
+    );
+
+    let mut vec = create_vec(16, 1, 10, 1);
+    let mut forest = RBForest::<u128, u8, 16, 1>::init_slice(vec.as_mut_slice(), 1).unwrap();
+    for key in [0, 1, u128::MAX - 1, u128::MAX] {
+        forest.insert(0, key, 0).unwrap();
+    }
+    assert_eq!(
+        forest.gaps(0).unwrap().collect::<Vec<_>>(),
+        vec![(1, u128::MAX - 1)]
+    );
+}
+
+#[test]
+fn swap_and_move_trees() {
+    let mut vec = create_vec(1, 1, 20, 3);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 3).unwrap();
+
+    for key in 0..5 {
+        forest.insert(0, key, 0).unwrap();
+    }
+    for key in 10..13 {
+        forest.insert(1, key, 1).unwrap();
+    }
+
+    let index = forest.get_key_index(0, &3);
+    forest.swap_trees(0, 1).unwrap();
+    assert_eq!(
+        forest.pairs(0).unwrap().collect::<Vec<_>>(),
+        vec![(10, 1), (11, 1), (12, 1)]
+    );
+    assert_eq!(
+        forest.keys(1).unwrap().collect::<Vec<_>>(),
+        vec![0, 1, 2, 3, 4]
+    );
+    assert_eq!(forest.get_key_index(1, &3), index);
+    assert!(forest.is_balanced(0) && forest.is_balanced(1));
+    assert!(forest.no_double_red(0) && forest.no_double_red(1));
+
+    forest.swap_trees(1, 1).unwrap();
+    assert_eq!(forest.len(1), Ok(5));
+    assert_eq!(forest.swap_trees(0, 3), Err(Error::TooBigTreeId));
+
+    assert_eq!(forest.move_tree(1, 0), Err(Error::NonEmptyTree));
+    forest.move_tree(1, 2).unwrap();
+    assert!(forest.is_empty(1));
+    assert_eq!(
+        forest.keys(2).unwrap().collect::<Vec<_>>(),
+        vec![0, 1, 2, 3, 4]
+    );
+    assert_eq!(forest.move_tree(3, 1), Err(Error::TooBigTreeId));
+
+    // The trees are still valid after moving around
+    forest.insert(2, 5, 2).unwrap();
+    forest.insert(1, 0, 2).unwrap();
+    assert!(forest.delete(0, &10));
+    for tree_id in 0..3 {
+        assert!(forest.is_balanced(tree_id));
+        assert!(forest.is_child_parent_links_consistent(tree_id));
+    }
+    assert_eq!(forest.free_nodes_left(), 20 - 2 - 6 - 1);
+}
+
+#[test]
+fn sorted_by() {
+    let mut vec = create_vec(1, 4, 256, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    for (i, &key) in INSERT_KEYS.iter().enumerate() {
+        forest.insert(0, key, (i % 50) as u32).unwrap();
+    }
+
+    let sorted = forest.sorted_by(0, |(_, a), (_, b)| b.cmp(a)).unwrap();
+    assert_eq!(sorted.len(), 256);
+    // Descending by value and then ascending by key, since the sort is stable
+    assert!(sorted
+        .windows(2)
+        .all(|pair| (pair[1].1, pair[0].0) < (pair[0].1, pair[1].0)));
+
+    assert_eq!(forest.sorted_by(1, |a, b| a.cmp(b)), Ok(Vec::new()));
+    assert_eq!(
+        forest.sorted_by(2, |a, b| a.cmp(b)),
+        Err(Error::TooBigTreeId)
+    );
+}
+
+#[test]
+fn node_stride() {
+    assert_eq!(super::node_stride(4, 4), mem::size_of::<Node<4, 4>>());
+    assert_eq!(super::node_stride(0, 7), mem::size_of::<Node<0, 7>>());
+
+    let params = ForestParams {
+        k_size: 3,
+        v_size: 5,
+        max_roots: 2,
+    };
+    let stride = super::node_stride(params.k_size, params.v_size);
+    assert_eq!(forest_size(params, 11) - forest_size(params, 10), stride);
+
+    let mut vec = vec![0; forest_size(params, 10)];
+    init_forest(params, vec.as_mut_slice()).unwrap();
+    {
+        let mut forest =
+            unsafe { RBForest::<[u8; 3], [u8; 5], 3, 5>::from_slice(vec.as_mut_slice()) }.unwrap();
+        forest.insert(0, [1, 2, 3], [4, 5, 6, 7, 8]).unwrap();
+    }
+
+    // The first allocated node is the last one in the pool
+    let offset = mem::size_of::<Header>() + 9 * stride;
+    assert_eq!(&vec[offset..offset + 8], &[1, 2, 3, 4, 5, 6, 7, 8]);
+}
+
+#[test]
+fn value_bytes_eq() {
+    let mut vec = create_vec(1, 8, 10, 2);
+    let mut forest = RBForest::<u8, String, 1, 8>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    forest.insert(0, 1, "abc".to_string()).unwrap();
+    forest.insert(0, 2, "abd".to_string()).unwrap();
+
+    let expected = "abc".to_string().try_to_vec().unwrap();
+    assert_eq!(forest.value_bytes_eq(0, &1, &expected), Some(true));
+    assert_eq!(forest.value_bytes_eq(0, &2, &expected), Some(false));
+    assert_eq!(forest.value_bytes_eq(0, &1, &expected[..5]), Some(true));
+    assert_eq!(forest.value_bytes_eq(0, &1, &[]), Some(true));
+    assert_eq!(forest.value_bytes_eq(0, &1, &[0; 9]), Some(false));
+    assert_eq!(forest.value_bytes_eq(0, &3, &expected), None);
+    assert_eq!(forest.value_bytes_eq(1, &1, &expected), None);
+    assert_eq!(forest.value_bytes_eq(2, &1, &expected), None);
+}
+
+#[test]
+fn insert_no_overwrite() {
+    let mut vec = create_vec(1, 1, 10, 2);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    for key in 0..5 {
+        assert_eq!(forest.insert_no_overwrite(0, key, key), Ok(None));
+    }
+    assert_eq!(forest.insert_no_overwrite(1, 3, 33), Ok(None));
+
+    let bytes = vec.clone();
+    {
+        let mut forest =
+            unsafe { RBForest::<u8, u8, 1, 1>::from_slice(vec.as_mut_slice()) }.unwrap();
+        assert_eq!(forest.insert_no_overwrite(0, 3, 100), Ok(Some(3)));
+        assert_eq!(forest.insert_no_overwrite(1, 3, 100), Ok(Some(33)));
+        assert_eq!(forest.get(0, &3), Some(3));
+        assert_eq!(
+            forest.insert_no_overwrite(2, 3, 100),
+            Err(Error::TooBigTreeId)
+        );
+    }
+    assert_eq!(vec, bytes);
+}
+
+#[test]
+fn spare_capacity() {
+    let mut vec = create_vec(1, 1, 10, 2);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(forest.spare_capacity(), 10);
+
+    for (i, key) in [1, 2, 1, 3, 2, 2, 4].into_iter().enumerate() {
+        let before = forest.spare_capacity();
+        let is_new = !forest.contains_key(0, &key);
+        forest.insert(0, key, i as u8).unwrap();
+        assert_eq!(before - forest.spare_capacity(), is_new as usize);
+    }
+    assert_eq!(forest.spare_capacity(), 6);
+
+    forest.insert(1, 1, 0).unwrap();
+    assert_eq!(forest.spare_capacity(), 5);
+    assert_eq!(forest.spare_capacity(), forest.free_nodes_left());
+
+    forest.delete(0, &1);
+    assert_eq!(forest.spare_capacity(), 6);
+}
+
+#[test]
+fn colors() {
+    let mut vec = create_vec(1, 1, 256, 2);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    for &key in INSERT_KEYS.iter() {
+        forest.insert(0, key, key).unwrap();
+    }
+    for &key in INSERT_KEYS.iter().step_by(3) {
+        forest.delete(0, &key);
+    }
+
+    let colors: Vec<_> = forest.colors(0).unwrap().collect();
+    assert_eq!(
+        colors.iter().map(|(key, _)| *key).collect::<Vec<_>>(),
+        forest.keys(0).unwrap().collect::<Vec<_>>()
+    );
+
+    let len = colors.len();
+    let red = colors.iter().filter(|(_, is_red)| *is_red).count();
+    let black = len - red;
+
+    let mut black_height = 0;
+    let mut maybe_id = forest.root(0);
+    while let Some(id) = maybe_id {
+        if !forest.nodes()[id as usize].is_red() {
+            black_height += 1;
+        }
+        maybe_id = forest.nodes()[id as usize].left();
+    }
+
+    // Every red node has a black parent, and all the levels up to the black height are full
+    assert!(red <= 2 * black);
+    assert!(black >= (1 << black_height) - 1);
+    assert!(len < 1 << (2 * black_height));
+
+    assert_eq!(forest.colors(1).unwrap().count(), 0);
+    assert!(forest.colors(2).is_err());
+}
+
+#[test]
+fn header_snapshot() {
+    let mut vec = create_vec(3, 7, 10, 4);
+    let mut forest = RBForest::<[u8; 3], [u8; 7], 3, 7>::init_slice(vec.as_mut_slice(), 4).unwrap();
+
+    assert_eq!(
+        forest.header_snapshot(),
+        HeaderSnapshot {
+            k_size: 3,
+            v_size: 7,
+            max_nodes: 10,
+            max_roots: 4,
+            head: Some(9),
+            generation: 0,
+        }
+    );
+
+    forest.insert(0, [1; 3], [2; 7]).unwrap();
+    assert_eq!(forest.header_snapshot().head, Some(8));
+
+    for key in 2..11 {
+        forest.insert(1, [key; 3], [0; 7]).unwrap();
+    }
+    assert_eq!(forest.header_snapshot().head, None);
+}
+
+#[test]
+fn get_bytes_many() {
+    let mut vec = create_vec(2, 4, 50, 2);
+    let mut forest = RBForest::<[u8; 2], u32, 2, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    for &key in INSERT_KEYS.iter().take(50) {
+        forest.insert(0, [key, 1], key as u32 * 3).unwrap();
+    }
+
+    let probes: Vec<[u8; 2]> = INSERT_KEYS
+        .iter()
+        .take(70)
+        .map(|&key| [key, 1])
+        .chain([[0, 0], [255, 255]])
+        .collect();
+    let keys: Vec<&[u8]> = probes.iter().map(|key| key.as_slice()).collect();
+    let mut out = vec![None; keys.len()];
+
+    assert_eq!(forest.get_bytes_many(0, &keys, &mut out), 50);
+    for (key, value) in probes.iter().zip(&out) {
+        let expected = forest.get(0, key).map(|value| value.try_to_vec().unwrap());
+        assert_eq!(value.map(|bytes| bytes.to_vec()), expected);
+    }
+
+    // Only the common prefix of the slices is processed
+    let mut short_out = [Some(&[0u8][..]); 3];
+    assert_eq!(forest.get_bytes_many(0, &keys[60..], &mut short_out), 0);
+    assert_eq!(short_out, [None; 3]);
+
+    let mut out = [Some(&[0u8][..]); 2];
+    assert_eq!(forest.get_bytes_many(0, &[&[1], keys[0]], &mut out), 1);
+    assert_eq!(out[0], None);
+    assert_eq!(forest.get_bytes_many(1, &keys[..2], &mut out), 0);
+    assert_eq!(forest.get_bytes_many(2, &keys[..2], &mut out), 0);
+    assert_eq!(out, [None; 2]);
+}
+
+#[test]
+fn clone_into() {
+    let mut vec = create_vec(1, 4, 100, 3);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 3).unwrap();
+    for (i, &key) in INSERT_KEYS.iter().take(90).enumerate() {
+        forest.insert(i % 3, key, i as u32).unwrap();
+    }
+
+    let mut dst = create_vec(1, 4, 120, 3);
+    let copy = CloneInto::clone_into(&forest, dst.as_mut_slice()).unwrap();
+    assert_eq!(copy.max_roots(), 3);
+    assert_eq!(copy.free_nodes_left(), 30);
+    for tree_id in 0..3 {
+        assert!(copy
+            .pairs(tree_id)
+            .unwrap()
+            .eq(forest.pairs(tree_id).unwrap()));
+        assert!(copy.struct_eq(tree_id, &forest, tree_id));
+    }
+
+    let mut too_small = create_vec(1, 4, 50, 3);
+    assert_eq!(
+        CloneInto::clone_into(&forest, too_small.as_mut_slice()).err(),
+        Some(Error::TooSmall)
+    );
+}
+
+#[test]
+fn max_min_by_value() {
+    let mut vec = create_vec(1, 4, 256, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    assert_eq!(forest.max_by_value(0), None);
+    assert_eq!(forest.min_by_value(0), None);
+
+    for (i, &key) in INSERT_KEYS.iter().enumerate() {
+        forest.insert(0, key, (i * 37 % 101) as u32).unwrap();
+    }
+
+    assert_eq!(
+        forest.max_by_value(0),
+        forest.pairs(0).unwrap().max_by_key(|(_, value)| *value)
+    );
+    assert_eq!(
+        forest.min_by_value(0),
+        forest.pairs(0).unwrap().min_by_key(|(_, value)| *value)
+    );
+    assert_eq!(forest.max_by_value(0).unwrap().1, 100);
+    assert_eq!(forest.min_by_value(0).unwrap().1, 0);
+
+    assert_eq!(forest.max_by_value(1), None);
+    assert_eq!(forest.min_by_value(2), None);
+}
+
+#[test]
+fn would_fit() {
+    let mut vec = create_vec(8, 8, 10, 1);
+    let forest = RBForest::<String, String, 8, 8>::init_slice(vec.as_mut_slice(), 1).unwrap();
+    let bytes = forest.to_borsh_bytes();
+
+    let short = "four".to_string();
+    let long = "five!".to_string();
+
+    assert_eq!(forest.would_fit(&short, &short), Ok(()));
+    assert_eq!(
+        forest.would_fit(&long, &short),
+        Err(Error::KeySerializationError)
+    );
+    assert_eq!(
+        forest.would_fit(&short, &long),
+        Err(Error::ValueSerializationError)
+    );
+    assert_eq!(
+        forest.would_fit(&long, &long),
+        Err(Error::KeySerializationError)
+    );
+    assert_eq!(forest.to_borsh_bytes(), bytes);
+
+    let mut vec = create_vec(8, 8, 10, 1);
+    let mut forest = RBForest::<String, String, 8, 8>::init_slice(vec.as_mut_slice(), 1).unwrap();
+    for (key, value) in [(&short, &short), (&long, &short), (&short, &long)] {
+        assert_eq!(
+            forest.would_fit(key, value),
+            forest.insert(0, key.clone(), value.clone()).map(|_| ())
+        );
+    }
+}
+
+#[test]
+fn pool_order() {
+    let mut vec = create_vec(1, 1, 50, 2);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    assert!(forest
+        .pool_order()
+        .enumerate()
+        .all(|(i, (id, is_live))| id as usize == i && !is_live));
+    assert_eq!(forest.pool_order().count(), 50);
+
+    for (i, &key) in INSERT_KEYS.iter().take(40).enumerate() {
+        forest.insert(i % 2, key, key).unwrap();
+    }
+    for &key in INSERT_KEYS.iter().take(40).step_by(3) {
+        forest.delete(0, &key);
+        forest.delete(1, &key);
+    }
+
+    let live = forest.pool_order().filter(|(_, is_live)| *is_live).count();
+    assert_eq!(live, forest.len(0).unwrap() + forest.len(1).unwrap());
+    assert_eq!(live + forest.free_nodes_left(), 50);
+}
+
+/// A `u32` key, which is serialized as big-endian, so its byte order matches the numeric one
+#[derive(BorshDeserialize, BorshSerialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+struct BigEndianKey([u8; 4]);
+
+#[test]
+fn insert_arrays() {
+    let mut typed_vec = create_vec(4, 4, 256, 1);
+    let mut typed =
+        RBForest::<BigEndianKey, u32, 4, 4>::init_slice(typed_vec.as_mut_slice(), 1).unwrap();
+    let mut raw_vec = create_vec(4, 4, 256, 2);
+    let mut raw =
+        RBForest::<BigEndianKey, u32, 4, 4>::init_slice(raw_vec.as_mut_slice(), 2).unwrap();
+
+    for (i, &key) in INSERT_KEYS.iter().enumerate() {
+        let key = (key as u32) * 1000;
+        let value = i as u32;
+        let typed_old = typed
+            .insert(0, BigEndianKey(key.to_be_bytes()), value)
+            .unwrap();
+        let raw_old = raw
+            .insert_arrays(0, key.to_be_bytes(), value.to_le_bytes())
+            .unwrap();
+        assert_eq!(raw_old, typed_old.map(u32::to_le_bytes));
+    }
+    // Overwrites
+    for &key in INSERT_KEYS.iter().step_by(7) {
+        let key = (key as u32) * 1000;
+        let typed_old = typed.insert(0, BigEndianKey(key.to_be_bytes()), 1).unwrap();
+        let raw_old = raw
+            .insert_arrays(0, key.to_be_bytes(), 1u32.to_le_bytes())
+            .unwrap();
+        assert_eq!(raw_old, typed_old.map(u32::to_le_bytes));
+    }
+
+    assert!(raw.struct_eq(0, &typed, 0));
+    assert!(raw.pairs(0).unwrap().eq(typed.pairs(0).unwrap()));
+    assert!(raw.is_balanced(0));
+    assert!(raw.no_double_red(0));
+    assert!(raw.is_child_parent_links_consistent(0));
+
+    assert_eq!(
+        raw.insert_arrays(1, [0; 4], [0; 4]),
+        Err(Error::NoNodesLeft)
+    );
+    assert_eq!(
+        raw.insert_arrays(2, [0; 4], [0; 4]),
+        Err(Error::TooBigTreeId)
+    );
+}
+
+#[test]
+fn allocate_tree() {
+    let mut vec = create_vec(1, 1, 10, 3);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 3).unwrap();
+
+    assert!((0..4).all(|tree_id| !forest.is_allocated(tree_id)));
+
+    assert_eq!(forest.allocate_tree(), Ok(0));
+    assert!(forest.is_allocated(0));
+    assert!(forest.is_empty(0));
+
+    // Insertion allocates a tree implicitly
+    forest.insert(1, 1, 1).unwrap();
+    assert!(forest.is_allocated(1));
+    assert_eq!(forest.allocate_tree(), Ok(2));
+    assert_eq!(forest.allocate_tree(), Err(Error::NoTreesLeft));
+
+    // Emptying or clearing does not free a tree
+    forest.delete(1, &1);
+    assert!(forest.is_empty(1));
+    assert!(forest.is_allocated(1));
+    forest.insert(0, 1, 1).unwrap();
+    forest.clear();
+    assert!((0..3).all(|tree_id| forest.is_allocated(tree_id) && forest.is_empty(tree_id)));
+
+    forest.insert(2, 5, 5).unwrap();
+    assert_eq!(forest.free_tree(2), Err(Error::NonEmptyTree));
+    assert_eq!(forest.free_tree(3), Err(Error::TooBigTreeId));
+    forest.delete(2, &5);
+    assert_eq!(forest.free_tree(2), Ok(()));
+    assert!(!forest.is_allocated(2));
+    assert_eq!(forest.free_tree(1), Ok(()));
+    assert_eq!(forest.allocate_tree(), Ok(1));
+
+    // The state survives reopening
+    let mut forest = unsafe { RBForest::<u8, u8, 1, 1>::from_slice(vec.as_mut_slice()) }.unwrap();
+    assert!(forest.is_allocated(0));
+    assert!(forest.is_allocated(1));
+    assert!(!forest.is_allocated(2));
+    assert_eq!(forest.allocate_tree(), Ok(2));
+}
+
+#[test]
+fn export_tree_into() {
+    let mut vec = create_vec(1, 4, 256, 3);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 3).unwrap();
+    for (i, &key) in INSERT_KEYS.iter().enumerate() {
+        forest.insert(i % 3, key, i as u32).unwrap();
+    }
+    for &key in INSERT_KEYS.iter().step_by(4) {
+        forest.delete(1, &key);
+    }
+
+    let len = forest.len(1).unwrap();
+    let mut dst = create_vec(1, 4, len, 1);
+    let tree = forest.export_tree_into(1, dst.as_mut_slice()).unwrap();
+    assert!(tree.pairs().eq(forest.pairs(1).unwrap()));
+    assert!(tree
+        .0
+        .nodes_preorder(0)
+        .unwrap()
+        .eq(forest.nodes_preorder(1).unwrap()));
+    assert!(tree.is_balanced());
+    assert!(tree.is_child_parent_links_consistent());
+    assert_eq!(tree.free_nodes_left(), 0);
+
+    let mut too_small = create_vec(1, 4, len - 1, 1);
+    assert_eq!(
+        forest.export_tree_into(1, too_small.as_mut_slice()).err(),
+        Some(Error::TooSmall)
+    );
+    assert_eq!(
+        forest.export_tree_into(3, dst.as_mut_slice()).err(),
+        Some(Error::TooBigTreeId)
+    );
+}
+
+#[test]
+fn pairs_peek() {
+    let mut vec = create_vec(1, 4, 256, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for (i, &key) in INSERT_KEYS.iter().enumerate() {
+        forest.insert(i % 2, key, i as u32).unwrap();
+    }
+
+    let mut iter = forest.pairs(0).unwrap();
+    let mut expected = forest.pairs(0).unwrap();
+    loop {
+        let peeked = iter.peek().cloned();
+        assert_eq!(iter.peek().cloned(), peeked);
+        assert_eq!(iter.next(), peeked);
+        assert_eq!(expected.next(), peeked);
+        if peeked.is_none() {
+            break;
+        }
+    }
+    // the iterator is fused
+    assert_eq!(iter.peek(), None);
+    assert_eq!(iter.next(), None);
+
+    let mut iter = forest.pairs(1).unwrap();
+    let first = iter.peek().cloned();
+    assert_eq!(first, forest.pairs(1).unwrap().next());
+    assert_eq!(iter.nth(2), forest.pairs(1).unwrap().nth(2));
+    assert_eq!(
+        format!("{:?}", iter),
+        format!("{:?}", {
+            let mut rest = forest.pairs(1).unwrap();
+            rest.nth(2);
+            rest
+        })
+    );
+    iter.peek();
+    assert_eq!(
+        format!("{:?}", iter),
+        format!("{:?}", {
+            let mut rest = forest.pairs(1).unwrap();
+            rest.nth(2);
+            rest
+        })
+    );
+}
+
+#[test]
+fn median() {
+    let mut vec = create_vec(1, 1, 256, 3);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 3).unwrap();
+    assert_eq!(forest.median(0), None);
+    assert_eq!(forest.median(3), None);
+
+    for key in (0..101).rev() {
+        forest.insert(0, key, key.wrapping_mul(3)).unwrap();
+    }
+    assert_eq!(forest.median(0), Some((50, 150)));
+
+    for key in 0..10 {
+        forest.insert(1, key, key).unwrap();
+    }
+    assert_eq!(forest.median(1), Some((4, 4)));
+
+    forest.insert(2, 7, 7).unwrap();
+    assert_eq!(forest.median(2), Some((7, 7)));
+}
+
+#[test]
+fn coalesce() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for &key in INSERT_KEYS.iter() {
+        forest.insert(0, key, u32::from(key / 10)).unwrap();
+    }
+    forest.insert(1, 1, 0).unwrap();
+
+    // values of adjacent entries are equal in runs of 10 keys
+    let merges = forest
+        .coalesce(0, |_, a, _, b| if a == b { Some(*a) } else { None })
+        .unwrap();
+    assert_eq!(merges, 256 - 26);
+    assert_eq!(
+        forest.pairs(0).unwrap().collect::<Vec<_>>(),
+        (0..26).map(|i| (i * 10, u32::from(i))).collect::<Vec<_>>()
+    );
+    assert!(forest.is_balanced(0));
+    assert!(forest.is_child_parent_links_consistent(0));
+    assert_eq!(forest.free_nodes_left(), 257 - 27);
+
+    // merged entries are merged again with the next ones
+    let merges = forest.coalesce(0, |_, a, _, b| Some(a + b)).unwrap();
+    assert_eq!(merges, 25);
+    assert_eq!(
+        forest.pairs(0).unwrap().collect::<Vec<_>>(),
+        vec![(0, (0..26).sum())]
+    );
+    assert!(forest.is_balanced(0));
+
+    assert_eq!(forest.coalesce(1, |_, _, _, _| Some(0)), Ok(0));
+    assert_eq!(
+        forest.coalesce(2, |_, _, _, _| Some(0)),
+        Err(Error::TooBigTreeId)
+    );
+}
+
+#[test]
+fn neighbors() {
+    let mut vec = create_vec(1, 1, 256, 2);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for &key in INSERT_KEYS.iter().filter(|&&key| key % 2 == 0) {
+        forest.insert(0, key, key).unwrap();
+    }
+    forest.insert(1, 7, 7).unwrap();
+
+    assert_eq!(forest.neighbors(0, &0), Some((None, Some(2))));
+    assert_eq!(forest.neighbors(0, &254), Some((Some(252), None)));
+    for key in (2..254).step_by(2) {
+        assert_eq!(
+            forest.neighbors(0, &key),
+            Some((Some(key - 2), Some(key + 2)))
+        );
+    }
+    assert_eq!(forest.neighbors(0, &3), None);
+
+    assert_eq!(forest.neighbors(1, &7), Some((None, None)));
+    assert_eq!(forest.neighbors(2, &7), None);
+}
+
+#[test]
+fn same_format() {
+    let params = ForestParams {
+        k_size: 3,
+        v_size: 7,
+        max_roots: 4,
+    };
+    let mut a = vec![0; forest_size(params, 10)];
+    let mut b = vec![0; forest_size(params, 20)];
+    assert!(!super::same_format(&a, &b));
+
+    init_forest(params, a.as_mut_slice()).unwrap();
+    init_forest(params, b.as_mut_slice()).unwrap();
+    assert!(super::same_format(&a, &b));
+    assert!(super::same_format(&b, &a));
+
+    let other_roots = ForestParams {
+        max_roots: 5,
+        ..params
+    };
+    let mut c = vec![0; forest_size(other_roots, 10)];
+    init_forest(other_roots, c.as_mut_slice()).unwrap();
+    assert!(!super::same_format(&a, &c));
+
+    let other_value = ForestParams {
+        v_size: 8,
+        ..params
+    };
+    let mut d = vec![0; forest_size(other_value, 10)];
+    init_forest(other_value, d.as_mut_slice()).unwrap();
+    assert!(!super::same_format(&a, &d));
+}
+
+#[test]
+fn prefix_sums() {
+    let mut vec = create_vec(1, 4, 259, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for (key, value) in [(30, 3), (10, 1), (20, 2)] {
+        forest.insert(0, key, value).unwrap();
+    }
+
+    assert_eq!(
+        forest.prefix_sums(0).unwrap().collect::<Vec<_>>(),
+        vec![(10, 1), (20, 3), (30, 6)]
+    );
+    assert_eq!(forest.prefix_sums(1).unwrap().next(), None);
+    assert!(forest.prefix_sums(2).is_err());
+
+    for &key in INSERT_KEYS.iter() {
+        forest.insert(1, key, u32::from(key)).unwrap();
+    }
+    let mut sum = 0;
+    for (key, prefix_sum) in forest.prefix_sums(1).unwrap() {
+        sum += u32::from(key);
+        assert_eq!(prefix_sum, sum);
+    }
+}
+
+#[test]
+fn is_left_leaning() {
+    let mut vec = create_vec(1, 1, 256, 2);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert!(forest.is_left_leaning(0));
+    for &key in INSERT_KEYS.iter().take(254) {
+        forest.insert(0, key, key).unwrap();
+        assert!(forest.is_left_leaning(0));
+    }
+
+    // Nodes are allocated from the end of the pool, so the first two nodes are still free
+    // Right-leaning structure
+    //  black-> 1
+    //           \
+    //            2 <-red
+    let root = Node::from_raw_parts([1], [1], None, Some(1), None, false);
+    let right = Node::from_raw_parts([2], [2], None, None, Some(0), true);
+    forest.set_node(0, &root);
+    forest.set_node(1, &right);
+    forest.set_root(1, Some(0));
+    forest.set_head(None);
+    assert_eq!(forest.len(0).unwrap(), 254);
+    assert!(forest.is_left_leaning(0));
+    assert!(forest.is_balanced(1));
+    assert!(forest.no_double_red(1));
+    assert!(!forest.is_left_leaning(1));
+}
+
+#[test]
+fn rotation_counter() {
+    let mut vec = create_vec(1, 1, 256, 1);
+    {
+        let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 1).unwrap();
+        assert_eq!(forest.rotations_since_reset(), 0);
+
+        // 1 and 3 cause no rotations, 2 and 4 are red right children, which are rotated to the
+        // left, 5 causes a color flip, which makes 4 a red right child of the root 2
+        let expected = [0, 1, 1, 2, 3];
+        for (key, rotations) in (1..=5).zip(expected) {
+            forest.insert(0, key, key).unwrap();
+            assert_eq!(forest.rotations_since_reset(), rotations);
+        }
+
+        // Overwriting an existing key does not rotate anything
+        forest.insert(0, 3, 0).unwrap();
+        assert_eq!(forest.rotations_since_reset(), 3);
+
+        forest.reset_rotation_counter();
+        assert_eq!(forest.rotations_since_reset(), 0);
+
+        // The counter is not stored in the slice
+        forest.insert(0, 6, 6).unwrap();
+        assert_ne!(forest.rotations_since_reset(), 0);
+    }
+    let forest = unsafe { RBForest::<u8, u8, 1, 1>::from_slice(vec.as_mut_slice()).unwrap() };
+    assert_eq!(forest.rotations_since_reset(), 0);
+}
+
+#[test]
+fn init_slice_with() {
+    fn free_list(forest: &RBForest<u8, u8, 1, 1>) -> Vec<u32> {
+        let mut list = Vec::new();
+        let mut maybe_id = forest.header().head();
+        while let Some(id) = maybe_id {
+            list.push(id);
+            maybe_id = forest.nodes()[id as usize].parent();
+        }
+        list
+    }
+
+    let mut vec = create_vec(1, 1, 8, 2);
+    {
+        let forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+        assert_eq!(free_list(&forest), vec![7, 6, 5, 4, 3, 2, 1, 0]);
+    }
+    {
+        let forest = RBForest::<u8, u8, 1, 1>::init_slice_with(
+            vec.as_mut_slice(),
+            2,
+            FreeListOrder::Descending,
+        )
+        .unwrap();
+        assert_eq!(free_list(&forest), vec![7, 6, 5, 4, 3, 2, 1, 0]);
+    }
+    {
+        let mut forest = RBForest::<u8, u8, 1, 1>::init_slice_with(
+            vec.as_mut_slice(),
+            2,
+            FreeListOrder::Ascending,
+        )
+        .unwrap();
+        assert_eq!(free_list(&forest), vec![0, 1, 2, 3, 4, 5, 6, 7]);
+
+        forest.insert(0, 1, 1).unwrap();
+        forest.insert(1, 2, 2).unwrap();
+        assert_eq!(forest.root_index(0), Some(0));
+        assert_eq!(forest.root_index(1), Some(1));
+        assert_eq!(free_list(&forest), vec![2, 3, 4, 5, 6, 7]);
+    }
+
+    let mut single = create_vec(1, 1, 1, 1);
+    let forest = RBForest::<u8, u8, 1, 1>::init_slice_with(
+        single.as_mut_slice(),
+        1,
+        FreeListOrder::Ascending,
+    )
+    .unwrap();
+    assert_eq!(free_list(&forest), vec![0]);
+}
+
+#[test]
+fn insert_causes_rebalance() {
+    let mut vec = create_vec(1, 1, 256, 2);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert!(!forest.insert_causes_rebalance(0, &10));
+    assert!(!forest.insert_causes_rebalance(2, &10));
+
+    // Structure
+    // black-> 20
+    //        /
+    // red-> 10
+    forest.insert(0, 10, 0).unwrap();
+    forest.insert(0, 20, 0).unwrap();
+    assert!(forest.insert_causes_rebalance(0, &5));
+    assert!(forest.insert_causes_rebalance(0, &15));
+    assert!(forest.insert_causes_rebalance(0, &25));
+    assert!(!forest.insert_causes_rebalance(0, &10));
+
+    // Structure
+    //       20 <-black
+    //      /  \
+    //     10  30 <-black
+    forest.insert(0, 30, 0).unwrap();
+    assert!(!forest.insert_causes_rebalance(0, &5));
+    assert!(!forest.insert_causes_rebalance(0, &25));
+    assert!(forest.insert_causes_rebalance(0, &15));
+    assert!(forest.insert_causes_rebalance(0, &35));
+
+    // If no rebalance is predicted, the insertion does not change the colors of the existing
+    // nodes and does not rotate anything
+    for &key in INSERT_KEYS.iter().step_by(3) {
+        forest.insert(1, key, key).unwrap();
+    }
+    let colors: Vec<_> = forest.colors(1).unwrap().collect();
+    let mut predicted = 0;
+    for key in 0..=255 {
+        if forest.contains_key(1, &key) || forest.insert_causes_rebalance(1, &key) {
+            continue;
+        }
+        predicted += 1;
+
+        let mut copy_vec = create_vec(1, 1, 256, 2);
+        let mut copy = CloneInto::clone_into(&forest, copy_vec.as_mut_slice()).unwrap();
+        copy.insert(1, key, key).unwrap();
+        assert_eq!(copy.rotations_since_reset(), 0);
+        assert_eq!(
+            copy.colors(1)
+                .unwrap()
+                .filter(|(k, _)| *k != key)
+                .collect::<Vec<_>>(),
+            colors
+        );
+    }
+    assert!(predicted > 0);
+}
+
+#[test]
+fn density() {
+    let mut vec = create_vec(4, 1, 256, 3);
+    let mut forest = RBForest::<i32, u8, 4, 1>::init_slice(vec.as_mut_slice(), 3).unwrap();
+    for key in [4, 0, 2] {
+        forest.insert(0, key, 0).unwrap();
+    }
+    assert_eq!(forest.density(0), Some(3.0 / 5.0));
+
+    for key in -100..100 {
+        forest.insert(1, key, 0).unwrap();
+    }
+    assert_eq!(forest.density(1), Some(1.0));
+
+    forest.insert(2, i32::MIN, 0).unwrap();
+    forest.insert(2, i32::MAX, 0).unwrap();
+    assert_eq!(forest.density(2), Some(2.0 / (u32::MAX as f64 + 1.0)));
+    forest.delete(2, &i32::MIN);
+    assert_eq!(forest.density(2), Some(1.0));
+    forest.delete(2, &i32::MAX);
+    assert_eq!(forest.density(2), None);
+    assert_eq!(forest.density(3), None);
+
+    let mut vec = create_vec(16, 1, 10, 1);
+    let mut forest = RBForest::<i128, u8, 16, 1>::init_slice(vec.as_mut_slice(), 1).unwrap();
+    forest.insert(0, i128::MIN, 0).unwrap();
+    forest.insert(0, i128::MAX, 0).unwrap();
+    assert_eq!(forest.density(0), Some(2.0 / (u128::MAX as f64 + 1.0)));
+    forest.delete(0, &i128::MIN);
+    forest.insert(0, i128::MAX - 1, 0).unwrap();
+    assert_eq!(forest.density(0), Some(1.0));
+}
+
+#[test]
+fn pairs_chunked() {
+    let mut vec = create_vec(1, 1, 258, 2);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for &key in INSERT_KEYS.iter() {
+        forest.insert(0, key, key.wrapping_add(1)).unwrap();
+    }
+
+    let mut collected = Vec::new();
+    let mut last_key = None;
+    let mut chunks = 0;
+    loop {
+        let (chunk, resume_key) = forest.pairs_chunked(0, last_key.as_ref(), 10).unwrap();
+        assert!(chunk.len() <= 10);
+        chunks += 1;
+        collected.extend(chunk);
+        match resume_key {
+            Some(key) => {
+                assert_eq!(collected.last().map(|(k, _)| *k), Some(key));
+                last_key = Some(key);
+            }
+            None => break,
+        }
+    }
+    assert_eq!(chunks, 26);
+    assert_eq!(collected, forest.pairs(0).unwrap().collect::<Vec<_>>());
+
+    // The resume key is `None` as soon as the tree is exhausted
+    let (chunk, resume_key) = forest.pairs_chunked(0, Some(&245), 10).unwrap();
+    assert_eq!(chunk.len(), 10);
+    assert_eq!(resume_key, None);
+
+    // `last_key` does not have to be present in the tree
+    forest.insert(1, 10, 0).unwrap();
+    forest.insert(1, 20, 0).unwrap();
+    assert_eq!(
+        forest.pairs_chunked(1, Some(&15), 10).unwrap(),
+        (vec![(20, 0)], None)
+    );
+    assert_eq!(
+        forest.pairs_chunked(1, None::<&u8>, 0),
+        Err(Error::ZeroLimit)
+    );
+    assert_eq!(forest.pairs_chunked(1, Some(&10), 0), Err(Error::ZeroLimit));
+    assert_eq!(
+        forest.pairs_chunked(1, None::<&u8>, 1).unwrap(),
+        (vec![(10, 0)], Some(10))
+    );
+    assert_eq!(
+        forest.pairs_chunked(2, None::<&u8>, 1),
+        Err(Error::TooBigTreeId)
+    );
+}
+
+#[test]
+fn duplicate_values() {
+    let mut vec = create_vec(1, 4, 256, 3);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 3).unwrap();
+    assert!(!forest.has_duplicate_values(0));
+    assert_eq!(forest.find_duplicate_value(0), None);
+    assert_eq!(forest.find_duplicate_value(3), None);
+
+    for &key in INSERT_KEYS.iter().take(200) {
+        forest.insert(0, key, u32::from(key) * 7).unwrap();
+    }
+    assert!(!forest.has_duplicate_values(0));
+    assert_eq!(forest.find_duplicate_value(0), None);
+
+    for (key, value) in [(9, 5), (3, 5), (1, 2), (7, 2), (5, 2), (4, 8)] {
+        forest.insert(1, key, value).unwrap();
+    }
+    assert!(forest.has_duplicate_values(1));
+    assert_eq!(forest.find_duplicate_value(1), Some((1, 5)));
+
+    forest.insert(2, 1, 1).unwrap();
+    assert!(!forest.has_duplicate_values(2));
+}
+
+#[test]
+fn map_values() {
+    let mut vec = create_vec(1, 4, 256, 3);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 3).unwrap();
+    for (i, &key) in INSERT_KEYS.iter().enumerate() {
+        forest.insert(i % 2, key, u32::from(key)).unwrap();
+    }
+    let untouched: Vec<_> = forest.pairs(1).unwrap().collect();
+
+    forest.map_values(0, |_, value| value * 2).unwrap();
+    for (key, value) in forest.pairs(0).unwrap() {
+        assert_eq!(value, u32::from(key) * 2);
+    }
+    assert_eq!(forest.pairs(1).unwrap().collect::<Vec<_>>(), untouched);
+
+    let mut visited = Vec::new();
+    forest
+        .map_values(1, |key, value| {
+            visited.push(*key);
+            value
+        })
+        .unwrap();
+    assert!(visited.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(visited.len(), 128);
+
+    assert_eq!(forest.map_values(2, |_, value| value), Ok(()));
+    assert_eq!(
+        forest.map_values(3, |_, value| value),
+        Err(Error::TooBigTreeId)
+    );
+}
+
+#[test]
+fn map_values_overflow() {
+    let mut vec = create_vec(1, 8, 10, 1);
+    let mut forest = RBForest::<u8, String, 1, 8>::init_slice(vec.as_mut_slice(), 1).unwrap();
+    for key in 0..10 {
+        forest.insert(0, key, "ab".to_string()).unwrap();
+    }
+
+    // Strings are serialized with 4 bytes of length, so only 4 bytes of contents fit
+    assert_eq!(
+        forest.map_values(0, |&key, value| if key < 5 {
+            value.repeat(2)
+        } else {
+            value.repeat(3)
+        }),
+        Err(Error::ValueSerializationError)
+    );
+    for (key, value) in forest.pairs(0).unwrap() {
+        if key < 5 {
+            assert_eq!(value, "abab");
+        } else {
+            assert_eq!(value, "ab");
+        }
+    }
+}
+
+#[test]
+fn nearest() {
+    let mut vec = create_vec(4, 1, 16, 2);
+    let mut forest = RBForest::<i32, u8, 4, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    let dist = |a: &i32, b: &i32| a.abs_diff(*b).into();
+    assert_eq!(forest.nearest(0, &7, dist), None);
+
+    for (value, key) in [0, 10, 20].into_iter().enumerate() {
+        forest.insert(0, key, value as u8).unwrap();
+    }
+    assert_eq!(forest.nearest(0, &7, dist), Some((10, 1)));
+    assert_eq!(forest.nearest(0, &3, dist), Some((0, 0)));
+    assert_eq!(forest.nearest(0, &10, dist), Some((10, 1)));
+    // ties are broken toward the smaller key
+    assert_eq!(forest.nearest(0, &15, dist), Some((10, 1)));
+    assert_eq!(forest.nearest(0, &-100, dist), Some((0, 0)));
+    assert_eq!(forest.nearest(0, &100, dist), Some((20, 2)));
+
+    assert_eq!(forest.nearest(1, &7, dist), None);
+    assert_eq!(forest.nearest(2, &7, dist), None);
+}
+
+#[test]
+fn open_with_schema() {
+    let params = ForestParams {
+        k_size: 1,
+        v_size: 2,
+        max_roots: 1,
+    };
+    // a node takes 16 bytes, which is the size of 2 tree slots
+    let other_params = ForestParams {
+        max_roots: 3,
+        ..params
+    };
+    assert_eq!(forest_size(params, 10), forest_size(other_params, 9));
+
+    let mut vec = create_vec(1, 2, 9, 3);
+    {
+        let mut forest = RBForest::<u8, u16, 1, 2>::init_slice(vec.as_mut_slice(), 3).unwrap();
+        forest.insert(2, 1, 2).unwrap();
+        assert_eq!(forest.schema_id(), SchemaId::new(other_params));
+        assert_ne!(forest.schema_id(), SchemaId::new(params));
+    }
+
+    assert_eq!(
+        unsafe {
+            RBForest::<u8, u16, 1, 2>::open_with_schema(vec.as_mut_slice(), SchemaId::new(params))
+        }
+        .err(),
+        Some(Error::WrongSchema)
+    );
+
+    let forest = unsafe {
+        RBForest::<u8, u16, 1, 2>::open_with_schema(vec.as_mut_slice(), SchemaId::new(other_params))
+    }
+    .unwrap();
+    assert_eq!(forest.get(2, &1), Some(2));
+    assert_ne!(
+        SchemaId::new(params),
+        SchemaId::new(ForestParams {
+            v_size: 3,
+            ..params
+        })
+    );
+}
+
+#[test]
+fn fold() {
+    let mut vec = create_vec(1, 4, 256, 3);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 3).unwrap();
+    for (i, &key) in INSERT_KEYS.iter().enumerate() {
+        forest.insert(i % 2, key, i as u32).unwrap();
+    }
+
+    for tree_id in 0..3 {
+        let sum = forest.fold(tree_id, 0, |sum, _, value| sum + value);
+        let expected = forest
+            .pairs(tree_id)
+            .unwrap()
+            .fold(0, |sum, (_, value)| sum + value);
+        assert_eq!(sum, expected);
+    }
+
+    let keys = forest.fold(1, Vec::new(), |mut keys, key, _| {
+        keys.push(key);
+        keys
+    });
+    assert_eq!(keys, forest.keys(1).unwrap().collect::<Vec<_>>());
+
+    assert_eq!(forest.fold(3, 42, |sum, _, value| sum + value), 42);
+}
+
+#[test]
+fn key_bounds() {
+    let mut vec = create_vec(1, 4, 257, 3);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 3).unwrap();
+    for (i, &key) in INSERT_KEYS.iter().enumerate() {
+        forest.insert(i % 2, key, i as u32).unwrap();
+    }
+
+    for tree_id in 0..2 {
+        let first = forest.first_entry(tree_id).map(|(key, _)| key).unwrap();
+        let last = forest.last_entry(tree_id).map(|(key, _)| key).unwrap();
+        assert_eq!(forest.key_bounds(tree_id), Some((first, last)));
+    }
+
+    assert_eq!(forest.key_bounds(2), None);
+    forest.insert(2, 7, 0).unwrap();
+    assert_eq!(forest.key_bounds(2), Some((7, 7)));
+    assert_eq!(forest.key_bounds(3), None);
+}
+
+#[test]
+fn rebuild_balanced() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for (i, &key) in INSERT_KEYS.iter().enumerate() {
+        forest.insert(0, key, i as u32).unwrap();
+    }
+    for &key in INSERT_KEYS.iter().step_by(3) {
+        forest.delete(0, &key);
+    }
+    forest.insert(1, 1, 1).unwrap();
+
+    let height = |forest: &RBForest<u8, u32, 1, 4>, tree_id| {
+        forest
+            .nodes_preorder(tree_id)
+            .unwrap()
+            .map(|info| info.depth + 1)
+            .max()
+            .unwrap_or(0)
+    };
+
+    let pairs: Vec<_> = forest.pairs(0).unwrap().collect();
+    let free_nodes = forest.free_nodes_left();
+    forest.rebuild_balanced(0).unwrap();
+
+    let len = pairs.len();
+    assert_eq!(
+        height(&forest, 0),
+        (len + 1).next_power_of_two().ilog2() as usize
+    );
+    assert_eq!(forest.pairs(0).unwrap().collect::<Vec<_>>(), pairs);
+    assert_eq!(forest.free_nodes_left(), free_nodes);
+    assert!(forest.is_balanced(0));
+    assert!(forest.no_double_red(0));
+    assert!(forest.is_child_parent_links_consistent(0));
+    assert_eq!(forest.pairs(1).unwrap().collect::<Vec<_>>(), vec![(1, 1)]);
+
+    // The tree is still usable after the rebuild
+    for &key in INSERT_KEYS.iter().step_by(3) {
+        forest.insert(0, key, 0).unwrap();
+        assert!(forest.is_balanced(0));
+        assert!(forest.no_double_red(0));
+    }
+    for &key in INSERT_KEYS.iter().step_by(2) {
+        forest.delete(0, &key);
+        assert!(forest.is_balanced(0));
+        assert!(forest.no_double_red(0));
+    }
+
+    assert_eq!(forest.rebuild_balanced(2), Err(Error::TooBigTreeId));
+}
+
+#[test]
+fn depth_histogram() {
+    let mut vec = create_vec(1, 1, 256, 2);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(forest.depth_histogram(0), Vec::<usize>::new());
+    assert_eq!(forest.depth_histogram(2), Vec::<usize>::new());
+
+    for &key in INSERT_KEYS.iter().take(255) {
+        forest.insert(0, key, key).unwrap();
+    }
+    let histogram = forest.depth_histogram(0);
+    assert_eq!(histogram[0], 1);
+    assert_eq!(histogram.iter().sum::<usize>(), 255);
+
+    forest.rebuild_balanced(0).unwrap();
+    assert_eq!(forest.depth_histogram(0), vec![1, 2, 4, 8, 16, 32, 64, 128]);
+
+    forest.insert(1, 1, 1).unwrap();
+    assert_eq!(forest.depth_histogram(1), vec![1]);
+}
+
+#[test]
+fn pack() {
+    let mut vec = create_vec(1, 4, 256, 4);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 4).unwrap();
+    for (i, &key) in INSERT_KEYS.iter().enumerate().take(60) {
+        forest.insert(i % 3, key, i as u32).unwrap();
+    }
+    for &key in INSERT_KEYS.iter().take(60).step_by(7) {
+        forest.delete(0, &key);
+        forest.delete(1, &key);
+    }
+    forest.allocate_tree().unwrap();
+    assert!(forest.is_allocated(3));
+
+    let mut packed = Vec::new();
+    forest.pack(&mut packed).unwrap();
+    let used = 256 - forest.free_nodes_left();
+    assert_eq!(
+        packed.len(),
+        mem::size_of::<Header>() + 4 * 4 + mem::size_of::<Node<1, 4>>() * used
+    );
+
+    for num_nodes in [used, 256, 300] {
+        let mut dst = create_vec(1, 4, num_nodes, 4);
+        let mut unpacked =
+            RBForest::<u8, u32, 1, 4>::unpack_into(dst.as_mut_slice(), &packed).unwrap();
+        assert_eq!(unpacked.free_nodes_left(), num_nodes - used);
+        for tree_id in 0..4 {
+            assert!(unpacked
+                .nodes_preorder(tree_id)
+                .unwrap()
+                .eq(forest.nodes_preorder(tree_id).unwrap()));
+            assert!(unpacked.is_child_parent_links_consistent(tree_id));
+            assert_eq!(unpacked.is_allocated(tree_id), forest.is_allocated(tree_id));
+        }
+
+        // The free list is consistent with the unpacked nodes
+        let mut keys = 0..=255;
+        while unpacked.free_nodes_left() > 0 {
+            unpacked.insert(3, keys.next().unwrap(), 0).unwrap();
+        }
+        assert_eq!(
+            unpacked.insert(3, keys.next().unwrap(), 0),
+            Err(Error::NoNodesLeft)
+        );
+        for tree_id in 0..3 {
+            assert!(unpacked
+                .pairs(tree_id)
+                .unwrap()
+                .eq(forest.pairs(tree_id).unwrap()));
+        }
+    }
+
+    let mut out = [0; 64];
+    assert_eq!(forest.pack(&mut out.as_mut_slice()), Err(Error::WriteError));
+
+    let mut too_small = create_vec(1, 4, used - 1, 4);
+    assert_eq!(
+        RBForest::<u8, u32, 1, 4>::unpack_into(too_small.as_mut_slice(), &packed).err(),
+        Some(Error::TooSmall)
+    );
+
+    let mut dst = create_vec(1, 4, 256, 4);
+    assert_eq!(
+        RBForest::<u8, u32, 1, 4>::unpack_into(dst.as_mut_slice(), &packed[..packed.len() - 1])
+            .err(),
+        Some(Error::DeserializationError)
+    );
+    assert_eq!(
+        RBForest::<u8, u8, 1, 1>::unpack_into(dst.as_mut_slice(), &packed).err(),
+        Some(Error::WrongValueSize)
+    );
+}
+
+#[test]
+fn content_eq_unordered() {
+    let mut vec = create_vec(1, 4, 256, 4);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 4).unwrap();
+    let mut other_vec = create_vec(1, 4, 200, 5);
+    let mut other = RBForest::<u8, u32, 1, 4>::init_slice(other_vec.as_mut_slice(), 5).unwrap();
+    assert!(forest.content_eq_unordered(&other));
+
+    let permutation = [3, 0, 4, 1];
+    for (i, &key) in INSERT_KEYS.iter().enumerate().take(150) {
+        let tree_id = i % 3;
+        forest.insert(tree_id, key, i as u32).unwrap();
+        other.insert(permutation[tree_id], key, i as u32).unwrap();
+    }
+    assert!(forest.content_eq_unordered(&other));
+    assert!(other.content_eq_unordered(&forest));
+
+    // trees are matched as a multiset
+    forest.insert(3, 1, 1).unwrap();
+    assert!(!forest.content_eq_unordered(&other));
+    assert!(!other.content_eq_unordered(&forest));
+    other.insert(2, 1, 1).unwrap();
+    assert!(forest.content_eq_unordered(&other));
+
+    forest.insert(3, 2, 2).unwrap();
+    other.insert(1, 2, 2).unwrap();
+    assert!(!forest.content_eq_unordered(&other));
+    other.delete(1, &2);
+    other.insert(2, 2, 2).unwrap();
+    assert!(forest.content_eq_unordered(&other));
+
+    // values are compared too
+    other.insert(2, 2, 3).unwrap();
+    assert!(!forest.content_eq_unordered(&other));
+}
+
+#[test]
+fn for_each_removable() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for &key in INSERT_KEYS.iter() {
+        forest.insert(0, key, u32::from(key) * 2).unwrap();
+    }
+    forest.insert(1, 3, 0).unwrap();
+
+    // Odd keys are removed until the sentinel key 200 is reached
+    let mut visited = Vec::new();
+    let removed = forest
+        .for_each_removable(0, |&key, &value| {
+            assert_eq!(value, u32::from(key) * 2);
+            visited.push(key);
+            if key == 200 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(key % 2 == 1)
+            }
+        })
+        .unwrap();
+    assert_eq!(removed, 100);
+    assert_eq!(visited, (0..=200).collect::<Vec<_>>());
+
+    assert_eq!(
+        forest.keys(0).unwrap().collect::<Vec<_>>(),
+        (0..=200).step_by(2).chain(201..=255).collect::<Vec<_>>()
+    );
+    assert!(forest.is_balanced(0));
+    assert!(forest.no_double_red(0));
+    assert!(forest.is_child_parent_links_consistent(0));
+    assert_eq!(forest.get(1, &3), Some(0));
+
+    assert_eq!(
+        forest.for_each_removable(0, |_, _| ControlFlow::Continue(true)),
+        Ok(156)
+    );
+    assert!(forest.is_empty(0));
+    assert_eq!(
+        forest.for_each_removable(2, |_, _| ControlFlow::Continue(true)),
+        Err(Error::TooBigTreeId)
+    );
+}
+
+#[test]
+fn size_stats() {
+    let mut vec = create_vec(4, 8, 257, 2);
+    let mut forest = RBForest::<u16, u32, 4, 8>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(forest.size_stats(0), SizeStats::default());
+
+    for &key in INSERT_KEYS.iter() {
+        forest.insert(0, u16::from(key), u32::from(key)).unwrap();
+    }
+    forest.insert(1, 1, 1).unwrap();
+
+    // Fixed-size types always take the same number of bytes, regardless of the buffer sizes
+    assert_eq!(
+        forest.size_stats(0),
+        SizeStats {
+            key_bytes: 256 * 2,
+            value_bytes: 256 * 4,
+            entries: 256,
+        }
+    );
+    assert_eq!(
+        forest.size_stats(1),
+        SizeStats {
+            key_bytes: 2,
+            value_bytes: 4,
+            entries: 1,
+        }
+    );
+    assert_eq!(forest.size_stats(2), SizeStats::default());
+}
+
+#[test]
+fn pairs_from_ref() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for key in (0..=255).step_by(2) {
+        forest.insert(0, key, u32::from(key)).unwrap();
+    }
+    forest.insert(1, 100, 0).unwrap();
+
+    let r = forest.node_ref(0, &100).unwrap();
+    assert_eq!(forest.node_ref(0, &101), None);
+    assert_eq!(forest.node_ref(2, &100), None);
+
+    // Insertion does not move node contents, so the reference stays valid
+    for key in (1..=255).step_by(2) {
+        forest.insert(0, key, u32::from(key)).unwrap();
+    }
+    assert!(forest
+        .pairs_from_ref(r)
+        .eq((100..=255).map(|key| (key, u32::from(key)))));
+
+    // The reference belongs to the tree 0
+    let other = forest.node_ref(1, &100).unwrap();
+    assert_ne!(other, r);
+    assert!(forest.pairs_from_ref(other).eq([(100, 0)]));
+
+    forest.delete(0, &100);
+    assert_eq!(forest.pairs_from_ref(r).next(), None);
+}
+
+#[test]
+fn capacity_pressure() {
+    let mut vec = create_vec(1, 4, 8, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(forest.capacity_pressure(), 0.0);
+    assert!(!forest.would_overflow(8));
+    assert!(forest.would_overflow(9));
+
+    for key in 0..6 {
+        forest.insert(usize::from(key % 2), key, 0).unwrap();
+        assert!(!forest.would_overflow(usize::from(7 - key)));
+        assert!(forest.would_overflow(usize::from(8 - key)));
+    }
+    assert_eq!(forest.capacity_pressure(), 0.75);
+
+    forest.insert(0, 6, 0).unwrap();
+    forest.insert(1, 7, 0).unwrap();
+    assert_eq!(forest.capacity_pressure(), 1.0);
+    assert!(!forest.would_overflow(0));
+    assert!(forest.would_overflow(1));
+}
+
+#[test]
+fn node_entry() {
+    let mut vec = create_vec(1, 4, 20, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for &key in INSERT_KEYS.iter().take(10) {
+        forest.insert(0, key, u32::from(key) * 3).unwrap();
+    }
+    forest.insert(1, 7, 8).unwrap();
+
+    let root = forest.nodes_preorder(0).unwrap().next().unwrap();
+    assert_eq!(
+        forest.node_entry(forest.root_index(0).unwrap()),
+        Some((root.key, root.value))
+    );
+    assert_eq!(
+        forest.node_entry(forest.root_index(1).unwrap()),
+        Some((7, 8))
+    );
+    assert_eq!(forest.node_entry(20), None);
+    assert_eq!(forest.node_entry(u32::MAX), None);
+}
+
+#[test]
+fn trailing_bytes_are_zeroed() {
+    let mut vec = create_vec(16, 16, 4, 1);
+    let mut forest = RBForest::<String, String, 16, 16>::init_slice(vec.as_mut_slice(), 1).unwrap();
+
+    forest
+        .insert(0, "long key".to_string(), "long value".to_string())
+        .unwrap();
+    let id = forest.get_key_index(0, "long key").unwrap();
+    forest.delete(0, "long key");
+
+    // The freed node is reused for a shorter key
+    forest
+        .insert(0, "key".to_string(), "v".to_string())
+        .unwrap();
+    assert_eq!(forest.get_key_index(0, "key"), Some(id));
+    let mut expected = [0; 16];
+    expected[..7].copy_from_slice(&[3, 0, 0, 0, b'k', b'e', b'y']);
+    assert_eq!(forest.nodes()[id].key, expected);
+    assert_eq!(forest.get(0, "key"), Some("v".to_string()));
+    assert_eq!(forest.get(0, "long key"), None);
+
+    // Overwriting with a shorter value clears the tail of the old one
+    forest
+        .insert(0, "a".to_string(), "long value".to_string())
+        .unwrap();
+    forest.insert(0, "a".to_string(), "s".to_string()).unwrap();
+    let id = forest.get_key_index(0, "a").unwrap();
+    let mut expected = [0; 16];
+    expected[..5].copy_from_slice(&[1, 0, 0, 0, b's']);
+    assert_eq!(forest.nodes()[id].value, expected);
+
+    forest.map_values(0, |_, _| String::new()).unwrap();
+    assert!(forest
+        .nodes()
+        .iter()
+        .take(2)
+        .all(|node| node.value == [0; 16]));
+    assert_eq!(
+        forest.pairs(0).unwrap().collect::<Vec<_>>(),
+        [
+            ("a".to_string(), String::new()),
+            ("key".to_string(), String::new())
+        ]
+    );
+}
+
+#[test]
+fn visit() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for &key in INSERT_KEYS.iter() {
+        forest.insert(0, key, u32::from(key)).unwrap();
+    }
+    forest.insert(1, 75, 0).unwrap();
+
+    let mut visited = 0;
+    let mut in_range = Vec::new();
+    forest
+        .visit(0, |&key, &value| {
+            assert_eq!(value, u32::from(key));
+            visited += 1;
+            match key {
+                0..=49 => Descend::Right,
+                101..=255 => Descend::Left,
+                _ => {
+                    in_range.push(key);
+                    Descend::Both
+                }
+            }
+        })
+        .unwrap();
+    in_range.sort_unstable();
+    assert_eq!(in_range, (50..=100).collect::<Vec<_>>());
+    // Only the nodes on the paths to the bounds are visited outside of the range
+    assert!(visited <= 51 + 2 * 2 * 9);
+
+    let mut visited = Vec::new();
+    forest
+        .visit(0, |&key, _| {
+            visited.push(key);
+            Descend::Neither
+        })
+        .unwrap();
+    assert_eq!(visited.len(), 1);
+
+    let mut all = Vec::new();
+    forest
+        .visit(1, |&key, _| {
+            all.push(key);
+            Descend::Both
+        })
+        .unwrap();
+    assert_eq!(all, [75]);
+    assert_eq!(
+        forest.visit(2, |_, _| Descend::Both),
+        Err(Error::TooBigTreeId)
+    );
+}
+
+#[test]
+fn as_bytes() {
+    let params = ForestParams {
+        k_size: 1,
+        v_size: 4,
+        max_roots: 3,
+    };
+    let mut vec = create_vec(1, 4, 20, 3);
+    let mut copy = {
+        let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 3).unwrap();
+        for &key in INSERT_KEYS.iter().take(20) {
+            forest
+                .insert(usize::from(key % 3), key, u32::from(key))
+                .unwrap();
+        }
+
+        assert_eq!(forest.as_bytes().len(), forest_size(params, 20));
+        forest.as_bytes().to_vec()
+    };
+    assert_eq!(copy, vec);
+
+    let forest = unsafe { RBForest::<u8, u32, 1, 4>::from_slice(copy.as_mut_slice()).unwrap() };
+    for &key in INSERT_KEYS.iter().take(20) {
+        assert_eq!(forest.get(usize::from(key % 3), &key), Some(u32::from(key)));
+    }
+    assert_eq!(forest.as_bytes(), vec.as_slice());
+}
+
+#[test]
+fn pairs_filtered() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for &key in INSERT_KEYS.iter() {
+        forest.insert(0, key, u32::from(key) * 7 % 256).unwrap();
+    }
+    forest.insert(1, 1, 1000).unwrap();
+
+    let threshold = 200;
+    let filtered = forest
+        .pairs_filtered(0, |_, &value| value > threshold)
+        .unwrap()
+        .collect::<Vec<_>>();
+    assert_eq!(
+        filtered,
+        forest
+            .pairs(0)
+            .unwrap()
+            .filter(|&(_, value)| value > threshold)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(filtered.len(), 55);
+
+    assert!(forest
+        .pairs_filtered(1, |_, &value| value > threshold)
+        .unwrap()
+        .eq([(1, 1000)]));
+    assert!(forest.pairs_filtered(2, |_, _| true).is_err());
+}
+
+#[test]
+fn key_depth() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for &key in INSERT_KEYS.iter() {
+        forest.insert(0, key, 0).unwrap();
+    }
+    forest.insert(1, 1, 0).unwrap();
+
+    let root = forest.nodes_preorder(0).unwrap().next().unwrap();
+    assert_eq!(forest.key_depth(0, &root.key), Some(0));
+    assert_eq!(forest.key_depth(1, &1), Some(0));
+
+    // Depths of all the keys match the depth histogram and the depths of the nodes
+    let mut histogram = Vec::new();
+    for key in 0..=255 {
+        let depth = forest.key_depth(0, &key).unwrap();
+        if histogram.len() <= depth {
+            histogram.resize(depth + 1, 0);
+        }
+        histogram[depth] += 1;
+    }
+    assert_eq!(histogram, forest.depth_histogram(0));
+    for info in forest.nodes_preorder(0).unwrap() {
+        assert_eq!(forest.key_depth(0, &info.key), Some(info.depth));
+    }
+
+    assert_eq!(forest.key_depth(1, &2), None);
+    assert_eq!(forest.key_depth(2, &1), None);
+}
+
+#[test]
+fn stream_frames() {
+    let mut vec = create_vec(4, 16, 100, 2);
+    let mut forest = RBForest::<u16, String, 4, 16>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for key in 0..50_u16 {
+        let value = "v".repeat(usize::from(key % 12));
+        forest.insert(usize::from(key % 2), key, value).unwrap();
+    }
+
+    let mut stream = Vec::new();
+    assert_eq!(forest.stream_frames(0, &mut stream), Ok(25));
+    assert_eq!(forest.stream_frames(1, &mut stream), Ok(25));
+    // Frames are not padded to `KSIZE` and `VSIZE`
+    let value_bytes: usize = (0..50).map(|key| 4 + key % 12).sum();
+    assert_eq!(stream.len(), 50 * (4 + 2 + 4) + value_bytes);
+    assert_eq!(
+        forest.stream_frames(2, &mut stream),
+        Err(Error::TooBigTreeId)
+    );
+
+    let mut copy_vec = create_vec(4, 16, 100, 1);
+    let mut copy = RBForest::<u16, String, 4, 16>::init_slice(copy_vec.as_mut_slice(), 1).unwrap();
+    assert_eq!(copy.read_frames_into(0, &stream), Ok(50));
+    assert!(copy
+        .pairs(0)
+        .unwrap()
+        .eq(forest.merged_pairs().map(|(_, key, value)| (key, value))));
+
+    // Truncated streams are rejected, but the complete frames are inserted
+    let mut truncated_vec = create_vec(4, 16, 100, 1);
+    let mut truncated =
+        RBForest::<u16, String, 4, 16>::init_slice(truncated_vec.as_mut_slice(), 1).unwrap();
+    assert_eq!(
+        truncated.read_frames_into(0, &stream[..stream.len() - 1]),
+        Err(Error::DeserializationError)
+    );
+    assert_eq!(truncated.len(0), Ok(49));
+
+    // Frames must contain exactly one key or value
+    let mut frames = Vec::new();
+    frames.extend_from_slice(&3_u32.to_le_bytes());
+    frames.extend_from_slice(&[1, 0, 0]);
+    assert_eq!(
+        truncated.read_frames_into(0, &frames),
+        Err(Error::DeserializationError)
+    );
+}
+
+#[test]
+fn get_owned() {
+    let mut vec = create_vec(32, 4, 10, 2);
+    let mut forest = RBForest::<[u8; 32], u32, 32, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for i in 0..10 {
+        forest
+            .insert(usize::from(i % 2), [i; 32], u32::from(i))
+            .unwrap();
+    }
+
+    for i in 0..10 {
+        let key = [i; 32];
+        assert_eq!(
+            forest.get_owned(usize::from(i % 2), key),
+            forest.get(usize::from(i % 2), &key)
+        );
+        assert_eq!(
+            forest.get_owned(usize::from(i % 2), [i; 32]),
+            Some(u32::from(i))
+        );
+        assert_eq!(forest.get_owned(usize::from(i % 2 + 1), [i; 32]), None);
+    }
+    assert_eq!(forest.get_owned(0, [10; 32]), None);
+}
+
+#[test]
+fn remove_bytes() {
+    let mut vec = create_vec(1, 8, 257, 2);
+    let mut forest = RBForest::<u8, String, 1, 8>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    let mut copy_vec = create_vec(1, 8, 256, 1);
+    let mut copy = RBForest::<u8, String, 1, 8>::init_slice(copy_vec.as_mut_slice(), 1).unwrap();
+    for &key in INSERT_KEYS.iter() {
+        let value = key.to_string();
+        forest.insert(0, key, value.clone()).unwrap();
+        copy.insert(0, key, value).unwrap();
+    }
+    forest.insert(1, 1, "abc".to_string()).unwrap();
+
+    for key in 0..=255 {
+        let mut out = [0xff; 10];
+        assert_eq!(forest.remove_bytes(0, &key, &mut out), Some(8));
+        assert_eq!(
+            String::deserialize(&mut &out[..8]).unwrap(),
+            copy.remove(0, &key).unwrap()
+        );
+        assert_eq!(out[8..], [0xff; 2]);
+        assert!(!forest.contains_key(0, &key));
+    }
+    assert!(forest.is_empty(0));
+
+    // Only the prefix, which fits in `out`, is copied
+    let mut out = [0; 4];
+    assert_eq!(forest.remove_bytes(1, &1, &mut out), Some(4));
+    assert_eq!(out, [3, 0, 0, 0]);
+    assert_eq!(forest.remove_bytes(1, &1, &mut out), None);
+    assert_eq!(forest.remove_bytes(2, &1, &mut out), None);
+}
+
+#[test]
+fn color_counts() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(forest.color_counts(0), (0, 0));
+
+    forest.insert(1, 1, 0).unwrap();
+    assert_eq!(forest.color_counts(1), (0, 1));
+
+    for (i, &key) in INSERT_KEYS.iter().enumerate() {
+        forest.insert(0, key, 0).unwrap();
+        let (red, black) = forest.color_counts(0);
+        assert_eq!(red + black, i + 1);
+        assert_eq!(
+            red,
+            forest
+                .colors(0)
+                .unwrap()
+                .filter(|&(_, is_red)| is_red)
+                .count()
+        );
+        // Each black node has at most one red child after insertions
+        assert!(red <= black);
+    }
+    assert!(!forest.nodes_preorder(0).unwrap().next().unwrap().is_red);
+
+    for key in (0..=255).step_by(3) {
+        forest.delete(0, &key);
+        let (red, black) = forest.color_counts(0);
+        assert_eq!(Ok(red + black), forest.len(0));
+    }
+    assert_eq!(forest.color_counts(2), (0, 0));
+}
+
+#[test]
+fn shuffled_pairs() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for &key in INSERT_KEYS.iter() {
+        forest.insert(0, key, u32::from(key) * 2).unwrap();
+    }
+    forest.insert(1, 1, 1).unwrap();
+
+    let sorted: Vec<_> = forest.pairs(0).unwrap().collect();
+    let shuffled = forest.shuffled_pairs(0, 42).unwrap();
+    assert_eq!(shuffled, forest.shuffled_pairs(0, 42).unwrap());
+    assert_ne!(shuffled, sorted);
+
+    for seed in [0, 1, 43, u64::MAX] {
+        let other = forest.shuffled_pairs(0, seed).unwrap();
+        assert_ne!(other, shuffled);
+
+        let mut other_sorted = other;
+        other_sorted.sort_unstable();
+        assert_eq!(other_sorted, sorted);
+    }
+
+    assert_eq!(forest.shuffled_pairs(1, 42), Ok(vec![(1, 1)]));
+    assert_eq!(forest.shuffled_pairs(2, 42), Err(Error::TooBigTreeId));
+}
+
+#[test]
+fn next_value() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for &key in INSERT_KEYS.iter().filter(|&&key| key % 2 == 0) {
+        forest.insert(0, key, u32::from(key) * 3).unwrap();
+    }
+    forest.insert(1, 200, 1).unwrap();
+
+    // Stepping through the keys yields the values in order
+    let mut stepped = Vec::new();
+    let mut key = forest.keys(0).unwrap().next().unwrap();
+    stepped.push(forest.get(0, &key).unwrap());
+    while let Some(value) = forest.next_value(0, &key) {
+        stepped.push(value);
+        key += 2;
+    }
+    assert_eq!(stepped, forest.values(0).unwrap().collect::<Vec<_>>());
+
+    // Absent keys are allowed
+    assert_eq!(forest.next_value(0, &5), Some(18));
+    assert_eq!(forest.next_value(0, &254), None);
+    assert_eq!(forest.next_value(1, &5), Some(1));
+    assert_eq!(forest.next_value(2, &5), None);
+}
+
+#[test]
+fn truncate_to() {
+    let mut vec = create_vec(1, 4, 101, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for &key in INSERT_KEYS.iter().filter(|&&key| key < 100) {
+        forest.insert(0, key, u32::from(key)).unwrap();
+    }
+    forest.insert(1, 200, 0).unwrap();
+
+    assert_eq!(forest.truncate_to(0, 100), 0);
+    assert_eq!(forest.truncate_to(0, 10), 90);
+    assert!(forest
+        .pairs(0)
+        .unwrap()
+        .eq((0..10).map(|key| (key, u32::from(key)))));
+    assert!(forest.is_balanced(0));
+    assert!(forest.no_double_red(0));
+    assert!(forest.is_child_parent_links_consistent(0));
+    assert_eq!(forest.free_nodes_left(), 90);
+
+    assert_eq!(forest.truncate_to(0, 10), 0);
+    assert_eq!(forest.truncate_to(1, 0), 1);
+    assert!(forest.is_empty(1));
+    assert_eq!(forest.truncate_to(2, 0), 0);
+}
+
+#[test]
+fn pairs_resume() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for &key in INSERT_KEYS.iter() {
+        forest.insert(0, key, u32::from(key) * 2).unwrap();
+    }
+    forest.insert(1, 1, 0).unwrap();
+
+    // Pages of 30 entries, the state between pages is kept only in the token
+    let mut collected = Vec::new();
+    let mut token: Option<Vec<u8>> = None;
+    loop {
+        let mut pairs = match &token {
+            None => forest.pairs(0).unwrap(),
+            Some(token) => forest.pairs_resume(0, token).unwrap(),
+        };
+        collected.extend(pairs.by_ref().take(30));
+        token = pairs.position();
+        if token.is_none() {
+            break;
+        }
+    }
+    assert_eq!(collected, forest.pairs(0).unwrap().collect::<Vec<_>>());
+
+    // The token of a peeked pair points to that pair
+    let mut pairs = forest.pairs(0).unwrap();
+    pairs.nth(9);
+    assert_eq!(pairs.peek(), Some(&(10, 20)));
+    let token = pairs.position().unwrap();
+    assert_eq!(token, [10]);
+    assert_eq!(
+        forest.pairs_resume(0, &token).unwrap().next(),
+        Some((10, 20))
+    );
+
+    // Entries may be removed between the calls
+    forest.delete(0, &10);
+    assert_eq!(
+        forest.pairs_resume(0, &token).unwrap().next(),
+        Some((11, 22))
+    );
+
+    assert_eq!(forest.pairs_resume(1, &token).unwrap().next(), None);
+    assert!(forest.pairs_resume(0, &[0; 0]).is_err());
+    assert!(forest.pairs_resume(2, &token).is_err());
+}
+
+#[test]
+fn replace() {
+    let mut vec = create_vec(1, 8, 10, 2);
+    let mut forest = RBForest::<u8, String, 1, 8>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    forest.insert(0, 1, "one".to_string()).unwrap();
+    forest.insert(1, 1, "uno".to_string()).unwrap();
+
+    assert_eq!(
+        forest.replace(0, &1, "ein".to_string()),
+        Ok(Some("one".to_string()))
+    );
+    assert_eq!(forest.get(0, &1), Some("ein".to_string()));
+    assert_eq!(forest.get(1, &1), Some("uno".to_string()));
+
+    assert_eq!(forest.replace(0, &2, "two".to_string()), Ok(None));
+    assert_eq!(forest.get(0, &2), None);
+    assert_eq!(forest.len(0), Ok(1));
+
+    assert_eq!(
+        forest.replace(0, &1, "too long value".to_string()),
+        Err(Error::ValueSerializationError)
+    );
+    assert_eq!(forest.get(0, &1), Some("ein".to_string()));
+
+    assert_eq!(
+        forest.replace(2, &1, "x".to_string()),
+        Err(Error::TooBigTreeId)
+    );
+}
+
+#[test]
+fn required_sizes() {
+    let mut vec = create_vec(4, 16, 10, 2);
+    let mut forest = RBForest::<u16, [u8; 3], 4, 16>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(forest.required_key_size(0), 0);
+    assert_eq!(forest.required_value_size(0), 0);
+
+    for i in 0..5 {
+        forest.insert(0, i, [i as u8; 3]).unwrap();
+    }
+
+    assert_eq!(forest.required_key_size(0), 2);
+    assert_eq!(forest.required_value_size(0), 3);
+    assert_eq!(forest.required_value_size(1), 0);
+    assert_eq!(forest.required_value_size(2), 0);
+}
+
+#[test]
+fn keys_stepped() {
+    let mut vec = create_vec(4, 4, 100, 2);
+    let mut forest = RBForest::<u32, u32, 4, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for key in 0..100 {
+        forest.insert(0, key, key * 2).unwrap();
+    }
+
+    let values: Vec<_> = forest.keys_stepped(0, 0, 10).unwrap().collect();
+    assert_eq!(
+        values,
+        (0..100).step_by(10).map(|k| k * 2).collect::<Vec<_>>()
+    );
+
+    let values: Vec<_> = forest.keys_stepped(0, 5, 30).unwrap().collect();
+    assert_eq!(values, vec![10, 70, 130, 190]);
+
+    // Missing keys are skipped
+    for key in 20..60 {
+        forest.delete(0, &key);
+    }
+    let values: Vec<_> = forest.keys_stepped(0, 0, 10).unwrap().collect();
+    assert_eq!(values, vec![0, 20, 120, 140, 160, 180]);
+
+    // The iterator stops instead of overflowing the key
+    let values: Vec<_> = forest.keys_stepped(0, 90, u32::MAX).unwrap().collect();
+    assert_eq!(values, vec![180]);
+    assert_eq!(forest.keys_stepped(0, 0, 0).unwrap().count(), 1);
+
+    assert_eq!(forest.keys_stepped(1, 0, 10).unwrap().count(), 0);
+    assert!(forest.keys_stepped(2, 0, 10).is_err());
+}
+
+#[test]
+fn insert_columns() {
+    let mut vec = create_vec(1, 1, 10, 2);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    let keys = [5, 3, 8, 1, 3];
+    let values = [50, 30, 80, 10, 31];
+
+    assert_eq!(forest.insert_columns(0, &keys, &values), Ok(4));
+    assert_eq!(
+        forest.pairs(0).unwrap().collect::<Vec<_>>(),
+        vec![(1, 10), (3, 31), (5, 50), (8, 80)]
+    );
+
+    assert_eq!(forest.insert_columns(0, &[1, 2], &[11, 20]), Ok(1));
+    assert_eq!(forest.get(0, &1), Some(11));
+    assert_eq!(forest.get(0, &2), Some(20));
+
+    // The pairs before the failed one stay inserted
+    let keys: Vec<u8> = (10..20).collect();
+    assert_eq!(
+        forest.insert_columns(1, &keys, &keys),
+        Err(Error::NoNodesLeft)
+    );
+    assert_eq!(forest.len(1), Ok(5));
+    assert_eq!(
+        forest.pairs(1).unwrap().collect::<Vec<_>>(),
+        (10..15).map(|k| (k, k)).collect::<Vec<_>>()
+    );
+
+    assert_eq!(
+        forest.insert_columns(2, &[1], &[1]),
+        Err(Error::TooBigTreeId)
+    );
+}
+
+#[test]
+fn insert_columns_length_mismatch() {
+    let mut vec = create_vec(1, 1, 10, 1);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 1).unwrap();
+    assert_eq!(
+        forest.insert_columns(0, &[1, 2], &[1]),
+        Err(Error::LengthMismatch)
+    );
+    assert!(forest.is_empty(0));
+}
+
+#[test]
+fn longest_run() {
+    let mut vec = create_vec(1, 1, 16, 2);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(forest.longest_run(0), None);
+
+    for key in [1, 2, 3, 7, 8] {
+        forest.insert(0, key, key).unwrap();
+    }
+    assert_eq!(forest.longest_run(0), Some((1, 3)));
+
+    // The last run is taken into account as well
+    forest.insert(0, 9, 9).unwrap();
+    forest.insert(0, 10, 10).unwrap();
+    assert_eq!(forest.longest_run(0), Some((7, 10)));
+
+    // Ties are resolved in favor of the smallest keys
+    forest.insert(1, 5, 5).unwrap();
+    forest.insert(1, 254, 254).unwrap();
+    forest.insert(1, 255, 255).unwrap();
+    forest.insert(1, 0, 0).unwrap();
+    forest.insert(1, 1, 1).unwrap();
+    assert_eq!(forest.longest_run(1), Some((0, 1)));
+    forest.delete(1, &0);
+    assert_eq!(forest.longest_run(1), Some((254, 255)));
+
+    assert_eq!(forest.longest_run(2), None);
+}
+
+#[test]
+fn lowest_common_ancestor() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for &key in INSERT_KEYS.iter() {
+        forest.insert(0, key, 0).unwrap();
+    }
+    forest.insert(1, 1, 0).unwrap();
+
+    let mut preorder = forest.nodes_preorder(0).unwrap();
+    let root = preorder.next().unwrap();
+    let left_child = preorder.next().unwrap();
+    assert_eq!(left_child.parent, Some(root.key));
+
+    // Keys on the opposite sides of the root
+    assert_eq!(forest.lowest_common_ancestor(0, &0, &255), Some(root.key));
+    assert_eq!(
+        forest.lowest_common_ancestor(0, &left_child.key, &255),
+        Some(root.key)
+    );
+    // Both keys are in the left subtree
+    assert_eq!(
+        forest.lowest_common_ancestor(0, &0, &left_child.key),
+        Some(left_child.key)
+    );
+    assert_eq!(forest.lowest_common_ancestor(0, &7, &7), Some(7));
+    assert_eq!(forest.lowest_common_ancestor(1, &1, &1), Some(1));
+
+    assert_eq!(forest.lowest_common_ancestor(1, &1, &2), None);
+    assert_eq!(forest.lowest_common_ancestor(2, &1, &1), None);
+}
+
+#[test]
+fn warm_up() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    forest.insert(0, 7, 70).unwrap();
+
+    let keys: Vec<u8> = INSERT_KEYS.iter().copied().take(100).collect();
+    forest.warm_up(0, &keys).unwrap();
+    assert_eq!(forest.len(0), Ok(100));
+    assert_eq!(forest.get(0, &7), Some(70));
+    assert_eq!(forest.get(0, &keys[0]), Some(0));
+
+    forest.reset_rotation_counter();
+    for (i, key) in keys.iter().enumerate() {
+        assert!(forest.replace(0, key, i as u32).unwrap().is_some());
+    }
+    assert_eq!(forest.rotations_since_reset(), 0);
+    for (i, key) in keys.iter().enumerate() {
+        assert_eq!(forest.get(0, key), Some(i as u32));
+    }
+    assert!(forest.is_balanced(0));
+
+    assert_eq!(forest.warm_up(2, &keys), Err(Error::TooBigTreeId));
+}
+
+#[test]
+fn first_order_violation() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(forest.first_order_violation(0), None);
+    for &key in INSERT_KEYS.iter() {
+        forest.insert(0, key, 0).unwrap();
+    }
+    assert_eq!(forest.first_order_violation(0), None);
+    assert_eq!(forest.first_order_violation(2), None);
+
+    // The key 10 is replaced with 200 in place
+    let id = forest.get_key_index(0, &10).unwrap();
+    forest.nodes_mut()[id].key[0] = 200;
+    assert_eq!(forest.first_order_violation(0), Some((200, 11)));
+}
+
+#[test]
+fn collect_pairs() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(forest.collect_pairs(0), Ok(Vec::new()));
+    for &key in INSERT_KEYS.iter() {
+        forest.insert(0, key, u32::from(key) * 3).unwrap();
+    }
+
+    let pairs = forest.collect_pairs(0).unwrap();
+    assert_eq!(pairs, forest.pairs(0).unwrap().collect::<Vec<_>>());
+    assert_eq!(pairs.capacity(), pairs.len());
+    assert_eq!(forest.collect_pairs(2), Err(Error::TooBigTreeId));
+}
+
+#[test]
+fn delta() {
+    let mut base_vec = create_vec(1, 8, 64, 2);
+    let mut base = RBForest::<u8, String, 1, 8>::init_slice(base_vec.as_mut_slice(), 2).unwrap();
+    let mut target_vec = create_vec(1, 8, 64, 1);
+    let mut target =
+        RBForest::<u8, String, 1, 8>::init_slice(target_vec.as_mut_slice(), 1).unwrap();
+    for key in 0..40 {
+        base.insert(0, key, key.to_string()).unwrap();
+        if key % 5 != 0 {
+            target.insert(0, key, key.to_string()).unwrap();
+        }
+    }
+    for key in [3, 17, 38] {
+        target.insert(0, key, "new".to_string()).unwrap();
+    }
+    for key in 50..55 {
+        target.insert(0, key, key.to_string()).unwrap();
+    }
+
+    let delta = target.compute_delta(0, &base, 0).unwrap();
+    // 8 removed, 3 changed and 5 added keys
+    assert_eq!(delta.len(), 9 + 8 * 2 + 8 * 10);
+    assert_eq!(base.compute_delta(0, &base, 0).unwrap().len(), 9);
+
+    base.apply_delta(0, &delta).unwrap();
+    assert_eq!(
+        base.pairs(0).unwrap().collect::<Vec<_>>(),
+        target.pairs(0).unwrap().collect::<Vec<_>>()
+    );
+
+    // A malformed delta leaves the tree unchanged
+    let mut truncated = target.compute_delta(0, &base, 1).unwrap();
+    truncated.pop();
+    assert_eq!(
+        base.apply_delta(1, &truncated),
+        Err(Error::DeserializationError)
+    );
+    assert!(base.is_empty(1));
+    let mut wrong_version = delta.clone();
+    wrong_version[0] = 2;
+    assert_eq!(
+        base.apply_delta(1, &wrong_version),
+        Err(Error::DeserializationError)
+    );
+    let mut wrong_tag = delta.clone();
+    wrong_tag[9] = 7;
+    assert_eq!(
+        base.apply_delta(1, &wrong_tag),
+        Err(Error::DeserializationError)
+    );
+
+    let mut wide_vec = create_vec(2, 8, 10, 1);
+    let wide = RBForest::<u16, String, 2, 8>::init_slice(wide_vec.as_mut_slice(), 1).unwrap();
+    let wide_delta = wide.compute_delta(0, &wide, 0).unwrap();
+    assert_eq!(base.apply_delta(1, &wide_delta), Err(Error::WrongKeySize));
+
+    assert_eq!(target.compute_delta(1, &base, 0), Err(Error::TooBigTreeId));
+    assert_eq!(base.apply_delta(2, &delta), Err(Error::TooBigTreeId));
+}
+
+#[test]
+fn pairs_with_offset() {
+    let mut vec = create_vec(1, 4, 100, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for key in (0..100).rev() {
+        forest
+            .insert(usize::from(key % 2), key, u32::from(key) * 7)
+            .unwrap();
+    }
+    forest.delete(0, &10);
+
+    let entries: Vec<_> = forest.pairs_with_offset(0).unwrap().collect();
+    assert_eq!(
+        entries
+            .iter()
+            .map(|(key, value, _)| (*key, *value))
+            .collect::<Vec<_>>(),
+        forest.pairs(0).unwrap().collect::<Vec<_>>()
+    );
+
+    let bytes = forest.as_bytes();
+    let stride = super::node_stride(1, 4);
+    for (key, value, offset) in entries {
+        let node: &Node<1, 4> = from_bytes(&bytes[offset..offset + stride]);
+        assert_eq!(u8::deserialize(&mut node.key.as_slice()).unwrap(), key);
+        assert_eq!(u32::deserialize(&mut node.value.as_slice()).unwrap(), value);
+    }
+
+    assert!(forest.pairs_with_offset(2).is_err());
+}
+
+#[test]
+fn free_list_disjoint() {
+    let mut vec = create_vec(1, 4, 20, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert!(forest.free_list_disjoint());
+    for key in 0..10 {
+        forest.insert(usize::from(key % 2), key, 0).unwrap();
+    }
+    forest.delete(0, &4);
+    assert!(forest.free_list_disjoint());
+
+    // A live node is injected after the head of the free list
+    let head = forest.header().head().unwrap();
+    let live = forest.get_key_index(1, &3).unwrap() as u32;
+    let old_next = forest.nodes()[head as usize].parent();
+    forest.nodes_mut()[head as usize].set_parent(Some(live));
+    assert!(!forest.free_list_disjoint());
+
+    // A cycle in the free list
+    forest.nodes_mut()[head as usize].set_parent(Some(head));
+    assert!(!forest.free_list_disjoint());
+
+    forest.nodes_mut()[head as usize].set_parent(old_next);
+    assert!(forest.free_list_disjoint());
+}
+
+#[test]
+fn reset_roots() {
+    let mut vec = create_vec(1, 4, 20, 3);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 3).unwrap();
+    for key in 0..15 {
+        forest.insert(usize::from(key % 3), key, 0).unwrap();
+    }
+    forest.delete(1, &4);
+    assert_eq!(forest.free_nodes_left(), 6);
+
+    // Leaked nodes are reclaimed, live ones are kept
+    forest.header_mut().set_head(None);
+    assert_eq!(forest.rebuild_free_list(), 6);
+    assert_eq!(forest.free_nodes_left(), 6);
+    assert!(forest.free_list_disjoint());
+    assert_eq!(forest.len(1), Ok(4));
+
+    forest.reset_roots();
+    for tree_id in 0..3 {
+        assert!(forest.is_empty(tree_id));
+    }
+    assert_eq!(forest.free_nodes_left(), 6);
+
+    assert_eq!(forest.rebuild_free_list(), 20);
+    assert_eq!(forest.free_nodes_left(), 20);
+    assert!(forest.free_list_disjoint());
+    for key in 0..20 {
+        forest.insert(0, key, u32::from(key)).unwrap();
+    }
+    assert_eq!(forest.insert(0, 20, 0), Err(Error::NoNodesLeft));
+    assert!(forest.is_balanced(0));
+}
+
+#[test]
+fn structural_node_count() {
+    let mut vec = create_vec(1, 4, 100, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(forest.structural_node_count(0), 0);
+
+    let mut state = 42;
+    for _ in 0..1000 {
+        let random = splitmix64(&mut state);
+        let tree_id = (random % 2) as usize;
+        let key = (random >> 8) as u8 % 64;
+        if random & 0x10 == 0 {
+            forest.delete(tree_id, &key);
+        } else {
+            forest.insert(tree_id, key, 0).unwrap();
+        }
+        for tree_id in 0..2 {
+            assert_eq!(
+                Ok(forest.structural_node_count(tree_id)),
+                forest.len(tree_id)
+            );
+        }
+    }
+    assert_eq!(forest.structural_node_count(2), 0);
+
+    // The parent link of the minimum is cut, so the walk stops in its subtree
+    let min_id = forest
+        .get_key_index(0, &forest.first_entry(0).unwrap().0)
+        .unwrap();
+    forest.nodes_mut()[min_id].set_parent(None);
+    assert!(forest.structural_node_count(0) < forest.len(0).unwrap());
+}
+
+#[test]
+fn insert_at() {
+    let mut a_vec = create_vec(1, 4, 20, 2);
+    let mut b_vec = create_vec(1, 4, 20, 2);
+    let mut a = RBForest::<u8, u32, 1, 4>::init_slice(a_vec.as_mut_slice(), 2).unwrap();
+    let mut b = RBForest::<u8, u32, 1, 4>::init_slice(b_vec.as_mut_slice(), 2).unwrap();
+
+    for forest in [&mut a, &mut b] {
+        for (i, key) in [5, 1, 9, 3, 7, 2].into_iter().enumerate() {
+            let node_index = (i as u32 * 7) % 20;
+            assert_eq!(
+                forest.insert_at(0, key, u32::from(key), node_index),
+                Ok(None)
+            );
+        }
+        assert_eq!(forest.insert_at(1, 4, 40, 3), Ok(None));
+        assert_eq!(forest.insert_at(1, 4, 41, 4), Ok(Some(40)));
+        forest.delete(0, &9);
+    }
+    assert_eq!(a.as_bytes(), b.as_bytes());
+    assert_eq!(a.get_key_index(0, &1), Some(7));
+    assert_eq!(a.get_key_index(1, &4), Some(3));
+    assert_eq!(a.len(0), Ok(5));
+    assert!(a.free_list_disjoint());
+    assert_eq!(a.free_nodes_left(), 14);
+
+    assert_eq!(a.insert_at(0, 10, 0, 7), Err(Error::NodeNotFree));
+    assert_eq!(a.insert_at(0, 10, 0, 20), Err(Error::NodeNotFree));
+    assert_eq!(a.insert_at(2, 10, 0, 8), Err(Error::TooBigTreeId));
+    assert_eq!(a.len(0), Ok(5));
+}
+
+#[test]
+fn fragmentation() {
+    let mut vec = create_vec(1, 4, 256, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(forest.fragmentation(0), 0.0);
+    for key in 0..200 {
+        forest.insert(0, key, 0).unwrap();
+    }
+    let fresh = forest.fragmentation(0);
+    assert!(fresh < 0.01, "{fresh}");
+
+    let mut state = 7;
+    for _ in 0..2000 {
+        let key = splitmix64(&mut state) as u8;
+        if !forest.delete(0, &key) {
+            forest.insert(0, key, 0).unwrap();
+        }
+    }
+    let churned = forest.fragmentation(0);
+    assert!(churned > 10.0 * fresh, "{churned} {fresh}");
+
+    assert_eq!(forest.fragmentation(1), 0.0);
+    assert_eq!(forest.fragmentation(2), 0.0);
+}
+
+#[test]
+fn set_operations() {
+    use std::collections::BTreeSet;
+
+    let mut vec = create_vec(1, 1, 256, 3);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 3).unwrap();
+    let mut expected = [BTreeSet::new(), BTreeSet::new()];
+    let mut state = 3;
+    for _ in 0..150 {
+        let random = splitmix64(&mut state);
+        let tree_id = (random % 2) as usize;
+        let key = (random >> 8) as u8 % 128;
+        forest.insert(tree_id, key, 0).unwrap();
+        expected[tree_id].insert(key);
+    }
+
+    assert_eq!(
+        forest.common_keys(0, 1).unwrap().collect::<Vec<_>>(),
+        expected[0]
+            .intersection(&expected[1])
+            .copied()
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        forest.union_keys(0, 1).unwrap().collect::<Vec<_>>(),
+        expected[0].union(&expected[1]).copied().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        forest.difference_keys(0, 1).unwrap().collect::<Vec<_>>(),
+        expected[0]
+            .difference(&expected[1])
+            .copied()
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        forest.difference_keys(1, 0).unwrap().collect::<Vec<_>>(),
+        expected[1]
+            .difference(&expected[0])
+            .copied()
+            .collect::<Vec<_>>()
+    );
+
+    // An empty tree
+    assert_eq!(forest.common_keys(0, 2).unwrap().count(), 0);
+    assert!(forest
+        .union_keys(2, 0)
+        .unwrap()
+        .eq(expected[0].iter().copied()));
+    assert!(forest
+        .difference_keys(0, 2)
+        .unwrap()
+        .eq(expected[0].iter().copied()));
+    assert!(forest
+        .common_keys(0, 0)
+        .unwrap()
+        .eq(expected[0].iter().copied()));
+
+    assert!(forest.common_keys(0, 3).is_err());
+    assert!(forest.union_keys(3, 0).is_err());
+    assert!(forest.difference_keys(3, 3).is_err());
+}
+
+#[test]
+fn header_consistent() {
+    let mut vec = create_vec(1, 4, 10, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert!(forest.header_consistent());
+    for key in 0..10 {
+        forest.insert(0, key, 0).unwrap();
+    }
+    // The pool is full, so the free list is empty
+    assert!(forest.header_consistent());
+    forest.delete(0, &3);
+    assert!(forest.header_consistent());
+
+    forest.set_head(Some(10));
+    assert!(!forest.header_consistent());
+    forest.set_head(Some(u32::MAX - 1));
+    assert!(!forest.header_consistent());
+    forest.set_head(Some(9));
+    assert!(forest.header_consistent());
+}
+
+#[test]
+fn pairs_dyn() {
+    let mut vec = create_vec(1, 8, 10, 2);
+    let mut forest = RBForest::<u8, String, 1, 8>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for key in [3, 1, 2] {
+        forest.insert(0, key, key.to_string()).unwrap();
+    }
+
+    let pairs: Vec<_> = forest
+        .pairs_dyn(0)
+        .unwrap()
+        .map(|(key, value)| {
+            assert!(key.downcast_ref::<String>().is_none());
+            (
+                *key.downcast::<u8>().unwrap(),
+                *value.downcast::<String>().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(pairs, forest.pairs(0).unwrap().collect::<Vec<_>>());
+
+    assert_eq!(forest.pairs_dyn(1).unwrap().count(), 0);
+    assert!(forest.pairs_dyn(2).is_err());
+}
+
+#[test]
+fn update_range() {
+    let mut vec = create_vec(1, 8, 40, 2);
+    let mut forest = RBForest::<u8, String, 1, 8>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for key in 0..30 {
+        forest.insert(0, key, key.to_string()).unwrap();
+    }
+    forest.insert(1, 15, "x".to_string()).unwrap();
+
+    let increment = |_: &u8, value: &mut String| {
+        *value = (value.parse::<u32>().unwrap() + 1).to_string();
+    };
+    assert_eq!(forest.update_range(0, 10..20, increment), Ok(10));
+    for key in 0..30 {
+        let expected = if (10..20).contains(&key) {
+            key + 1
+        } else {
+            key
+        };
+        assert_eq!(forest.get(0, &key), Some(expected.to_string()));
+    }
+    assert_eq!(forest.get(1, &15), Some("x".to_string()));
+
+    assert_eq!(forest.update_range(0, .., |_, _| {}), Ok(30));
+    assert_eq!(forest.update_range(0, 25.., |_, _| {}), Ok(5));
+    assert_eq!(forest.update_range(0, ..=2, |_, _| {}), Ok(3));
+    assert_eq!(
+        forest.update_range(0, (Bound::Excluded(27), Bound::Unbounded), |_, _| {}),
+        Ok(2)
+    );
+    assert_eq!(forest.update_range(0, 40..50, |_, _| {}), Ok(0));
+
+    // The walk stops at the first value, which does not fit
+    assert_eq!(
+        forest.update_range(0, 0..5, |key, value| if *key == 3 {
+            *value = "too long value".to_string();
+        } else {
+            value.push('!');
+        }),
+        Err(Error::ValueSerializationError)
+    );
+    assert_eq!(forest.get(0, &2), Some("2!".to_string()));
+    assert_eq!(forest.get(0, &3), Some("3".to_string()));
+    assert_eq!(forest.get(0, &4), Some("4".to_string()));
+
+    assert_eq!(
+        forest.update_range(2, .., |_, _| {}),
+        Err(Error::TooBigTreeId)
+    );
+}
+
+#[test]
+fn smallest_and_largest() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for &key in INSERT_KEYS.iter() {
+        forest.insert(0, key, u32::from(key) * 2).unwrap();
+    }
+    forest.insert(1, 5, 0).unwrap();
+
+    assert_eq!(forest.smallest(0, 3), Ok(vec![(0, 0), (1, 2), (2, 4)]));
+    assert_eq!(
+        forest.largest(0, 3),
+        Ok(vec![(255, 510), (254, 508), (253, 506)])
+    );
+    assert_eq!(forest.smallest(0, 0), Ok(vec![]));
+    assert_eq!(forest.largest(0, 0), Ok(vec![]));
+    assert_eq!(forest.smallest(1, 3), Ok(vec![(5, 0)]));
+    assert_eq!(forest.largest(1, 3), Ok(vec![(5, 0)]));
+    assert_eq!(forest.smallest(0, 1000).unwrap().len(), 256);
+
+    let mut largest = forest.largest(0, 1000).unwrap();
+    largest.reverse();
+    assert_eq!(largest, forest.collect_pairs(0).unwrap());
+
+    assert_eq!(forest.smallest(2, 3), Err(Error::TooBigTreeId));
+    assert_eq!(forest.largest(2, 3), Err(Error::TooBigTreeId));
+}
+
+#[test]
+fn is_bst() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert!(forest.is_bst(0));
+    for &key in INSERT_KEYS.iter() {
+        forest.insert(0, key, 0).unwrap();
+    }
+    assert!(forest.is_bst(0));
+    assert!(forest.is_bst(2));
+
+    // Swapping the subtrees of the root keeps colors, balance and links consistent
+    let root = forest.root(0).unwrap() as usize;
+    let left = forest.nodes()[root].left();
+    let right = forest.nodes()[root].right();
+    forest.nodes_mut()[root].set_left(right);
+    forest.nodes_mut()[root].set_right(left);
+    assert!(forest.is_balanced(0));
+    assert!(forest.is_child_parent_links_consistent(0));
+    assert!(!forest.is_bst(0));
+}
+
+#[test]
+fn snapshot_into() {
+    let mut vec = create_vec(1, 4, 10, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for key in [4, 2, 3, 1] {
+        forest.insert(0, key, u32::from(key) * 10).unwrap();
+    }
+
+    let mut exact = [(0, 0); 4];
+    assert_eq!(forest.snapshot_into(0, &mut exact), Ok(4));
+    assert_eq!(exact, [(1, 10), (2, 20), (3, 30), (4, 40)]);
+
+    let mut bigger = [(9, 9); 6];
+    assert_eq!(forest.snapshot_into(0, &mut bigger), Ok(4));
+    assert_eq!(bigger, [(1, 10), (2, 20), (3, 30), (4, 40), (9, 9), (9, 9)]);
+
+    let mut too_small = [(0, 0); 3];
+    assert_eq!(
+        forest.snapshot_into(0, &mut too_small),
+        Err(Error::TooSmall)
+    );
+    assert_eq!(too_small, [(0, 0); 3]);
+
+    let mut empty: [(u8, u32); 0] = [];
+    assert_eq!(forest.snapshot_into(1, &mut empty), Ok(0));
+    assert_eq!(
+        forest.snapshot_into(2, &mut exact),
+        Err(Error::TooBigTreeId)
+    );
+}
+
+#[test]
+fn key_at_rank() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for &key in INSERT_KEYS.iter().filter(|key| *key % 3 != 0) {
+        forest.insert(0, key, 0).unwrap();
+    }
+
+    let keys: Vec<_> = forest.keys(0).unwrap().collect();
+    for (rank, key) in keys.iter().enumerate() {
+        assert_eq!(forest.key_at_rank(0, rank), Some(*key));
+    }
+    assert_eq!(forest.key_at_rank(0, keys.len()), None);
+    assert_eq!(forest.key_at_rank(1, 0), None);
+    assert_eq!(forest.key_at_rank(2, 0), None);
+}
+
+#[test]
+fn pairs_level_order() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert!(forest.pairs_level_order(0).is_empty());
+    for &key in INSERT_KEYS.iter() {
+        forest.insert(0, key, u32::from(key) + 1).unwrap();
+    }
+
+    let level_order = forest.pairs_level_order(0);
+    assert_eq!(level_order.len(), 256);
+    let root = forest.nodes_preorder(0).unwrap().next().unwrap();
+    assert_eq!(level_order[0], (root.key, root.value));
+
+    // Depths never decrease, and the parents appear in the same order as their children
+    let infos: std::collections::BTreeMap<_, _> = forest
+        .nodes_preorder(0)
+        .unwrap()
+        .map(|info| (info.key, info))
+        .collect();
+    let depths: Vec<_> = level_order
+        .iter()
+        .map(|(key, _)| infos[key].depth)
+        .collect();
+    assert!(depths.windows(2).all(|pair| pair[0] <= pair[1]));
+    let position = |key: u8| level_order.iter().position(|(k, _)| *k == key).unwrap();
+    let parent_positions: Vec<_> = level_order[1..]
+        .iter()
+        .map(|(key, _)| position(infos[key].parent.unwrap()))
+        .collect();
+    assert!(parent_positions.windows(2).all(|pair| pair[0] <= pair[1]));
+    for (key, value) in &level_order {
+        assert_eq!(*value, u32::from(*key) + 1);
+    }
+
+    assert!(forest.pairs_level_order(1).is_empty());
+    assert!(forest.pairs_level_order(2).is_empty());
+}
+
+#[test]
+fn present_bitmap() {
+    let mut vec = create_vec(1, 1, 20, 2);
+    let mut forest = RBForest::<u8, u8, 1, 1>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for key in [0, 2, 4] {
+        forest.insert(0, key, 0).unwrap();
+    }
+
+    let mut out = [0xff; 2];
+    assert_eq!(forest.present_bitmap(0, 0, 5, &mut out), Ok(()));
+    assert_eq!(out, [0b10101, 0xff]);
+
+    assert_eq!(forest.present_bitmap(0, 1, 4, &mut out), Ok(()));
+    assert_eq!(out, [0b1010, 0xff]);
+
+    for key in [9, 15, 16, 254, 255] {
+        forest.insert(0, key, 0).unwrap();
+    }
+    assert_eq!(forest.present_bitmap(0, 0, 16, &mut out), Ok(()));
+    assert_eq!(out, [0b10101, 0b1000_0010]);
+    assert_eq!(forest.present_bitmap(0, 250, 10, &mut out), Ok(()));
+    assert_eq!(out, [0b11_0000, 0]);
+
+    assert_eq!(
+        forest.present_bitmap(0, 0, 17, &mut out),
+        Err(Error::TooSmall)
+    );
+    assert_eq!(forest.present_bitmap(1, 0, 16, &mut out), Ok(()));
+    assert_eq!(out, [0, 0]);
+    assert_eq!(
+        forest.present_bitmap(2, 0, 16, &mut out),
+        Err(Error::TooBigTreeId)
+    );
+}
+
+#[test]
+fn is_unchanged_by() {
+    let mut vec = create_vec(1, 8, 20, 2);
+    let mut forest = RBForest::<u8, String, 1, 8>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for key in 0..10 {
+        forest.insert(0, key, (key % 3).to_string()).unwrap();
+    }
+
+    for tree_id in 0..3 {
+        for key in 0..12 {
+            for value in ["0", "1", "2", "3"] {
+                let value = value.to_string();
+                assert_eq!(
+                    forest.is_unchanged_by(tree_id, &key, &value),
+                    forest.get(tree_id, &key) == Some(value.clone())
+                );
+            }
+        }
+    }
+    assert!(forest.is_unchanged_by(0, &4, &"1".to_string()));
+    assert!(!forest.is_unchanged_by(0, &4, &"2".to_string()));
+    assert!(!forest.is_unchanged_by(1, &4, &"1".to_string()));
+}
+
+#[test]
+fn windows2() {
+    let mut vec = create_vec(1, 4, 10, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(forest.windows2(0).unwrap().count(), 0);
+    forest.insert(0, 5, 50).unwrap();
+    assert_eq!(forest.windows2(0).unwrap().count(), 0);
+    forest.insert(0, 1, 10).unwrap();
+    forest.insert(0, 3, 30).unwrap();
+
+    assert_eq!(
+        forest.windows2(0).unwrap().collect::<Vec<_>>(),
+        vec![((1, 10), (3, 30)), ((3, 30), (5, 50))]
+    );
+    let deltas: Vec<_> = forest
+        .windows2(0)
+        .unwrap()
+        .map(|((k0, _), (k1, _))| k1 - k0)
+        .collect();
+    assert_eq!(deltas, vec![2, 2]);
+
+    assert!(forest.windows2(2).is_err());
+}
+
+#[test]
+fn clear_zeroed() {
+    let mut vec = create_vec(4, 4, 20, 2);
+    let mut forest = RBForest::<u32, u32, 4, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for key in 1..30 {
+        let tree_id = (key % 2) as usize;
+        forest.insert(tree_id, key, u32::MAX).unwrap();
+        if key % 3 == 0 {
+            forest.delete(tree_id, &key);
+        }
+    }
+
+    forest.clear_zeroed();
+    assert!(forest.is_empty(0));
+    assert!(forest.is_empty(1));
+    assert_eq!(forest.free_nodes_left(), 20);
+    assert!(forest
+        .nodes()
+        .iter()
+        .all(|node| node.key == [0; 4] && node.value == [0; 4]));
+
+    let mut cleared_vec = create_vec(4, 4, 20, 2);
+    let mut cleared =
+        RBForest::<u32, u32, 4, 4>::init_slice(cleared_vec.as_mut_slice(), 2).unwrap();
+    cleared.clear();
+    assert_eq!(forest.header().head(), cleared.header().head());
+    for key in 0..20 {
+        forest.insert(0, key, key).unwrap();
+    }
+}
+
+#[test]
+fn count_and_sum() {
+    let mut vec = create_vec(1, 8, 257, 2);
+    let mut forest = RBForest::<u8, u64, 1, 8>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(forest.count_and_sum(0), (0, 0));
+    for &key in INSERT_KEYS.iter().filter(|key| *key % 5 != 1) {
+        forest.insert(0, key, u64::from(key) * 1000).unwrap();
+    }
+
+    let sum = forest.fold(0, 0, |sum, _, value| sum + value);
+    assert_eq!(forest.count_and_sum(0), (forest.len(0).unwrap(), sum));
+    assert_eq!(forest.count_and_sum(1), (0, 0));
+    assert_eq!(forest.count_and_sum(2), (0, 0));
+}
+
+#[test]
+fn fits_height() {
+    let mut vec = create_vec(4, 4, 257, 2);
+    let mut forest = RBForest::<i32, u32, 4, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert!(forest.fits_height(0, 0));
+    for key in 0..255 {
+        forest.insert(0, key, 0).unwrap();
+    }
+    forest.rebuild_balanced(0).unwrap();
+    let height = forest.depth_histogram(0).len();
+    assert_eq!(height, 8);
+    assert!(forest.fits_height(0, height));
+    assert!(!forest.fits_height(0, height - 1));
+    // The bound of a Red-Black tree: 2 * log2(n + 1) for n = 255 entries
+    assert!(forest.fits_height(0, 2 * 8));
+
+    // A right-leaning chain of 20 nodes in the second tree
+    forest.clear();
+    for i in 0..20 {
+        let node = Node::from_raw_parts(
+            i32::to_le_bytes(i as i32),
+            u32::to_le_bytes(i),
+            None,
+            (i < 19).then_some(i + 1),
+            i.checked_sub(1),
+            false,
+        );
+        forest.set_node(i as usize, &node);
+    }
+    forest.set_root(1, Some(0));
+    assert!(forest.fits_height(1, 20));
+    assert!(!forest.fits_height(1, 2 * 5));
+    assert!(!forest.fits_height(1, 19));
+
+    assert!(forest.fits_height(0, 0));
+    assert!(forest.fits_height(2, 0));
+}
+
+#[test]
+fn pairs_skip() {
+    let mut vec = create_vec(1, 4, 257, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for &key in INSERT_KEYS.iter().filter(|key| *key % 2 == 0) {
+        forest.insert(0, key, u32::from(key) + 1).unwrap();
+    }
+
+    let all: Vec<_> = forest.pairs(0).unwrap().collect();
+    assert_eq!(forest.pairs_skip(0, 5).unwrap().next(), Some((10, 11)));
+    for offset in [0, 1, 5, 127, 128, 129, 1000] {
+        assert_eq!(
+            forest.pairs_skip(0, offset).unwrap().collect::<Vec<_>>(),
+            all.iter().copied().skip(offset).collect::<Vec<_>>()
+        );
+    }
+    assert_eq!(forest.pairs_skip(1, 0).unwrap().count(), 0);
+    assert!(forest.pairs_skip(2, 0).is_err());
+}
+
+#[test]
+fn key_exists_anywhere() {
+    let mut vec = create_vec(1, 4, 20, 4);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 4).unwrap();
+    assert_eq!(forest.key_exists_anywhere(&7), None);
+
+    forest.insert(2, 7, 0).unwrap();
+    forest.insert(0, 1, 0).unwrap();
+    assert_eq!(forest.key_exists_anywhere(&7), Some(2));
+    assert_eq!(forest.key_exists_anywhere(&1), Some(0));
+    assert_eq!(forest.key_exists_anywhere(&2), None);
+
+    // The first tree is returned if the key is not unique
+    forest.insert(3, 7, 0).unwrap();
+    forest.insert(1, 7, 0).unwrap();
+    assert_eq!(forest.key_exists_anywhere(&7), Some(1));
+}
+
+#[test]
+fn rebase_indices() {
+    let mut vec = create_vec(1, 4, 40, 3);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 3).unwrap();
+    forest.free_tree(2).unwrap();
+    // Nodes are allocated from the end of the pool, so the occupied ones are 25..40
+    for key in 0..15 {
+        forest
+            .insert(usize::from(key % 2), key, u32::from(key) * 3)
+            .unwrap();
+    }
+    let expected: Vec<_> = (0..2)
+        .map(|tree_id| forest.pairs(tree_id).unwrap().collect::<Vec<_>>())
+        .collect();
+    assert_eq!(
+        forest
+            .pool_order()
+            .filter(|(id, live)| *live && *id < 25)
+            .count(),
+        0
+    );
+
+    assert_eq!(forest.rebase_indices(1), Err(Error::IndexOutOfRange));
+    assert_eq!(forest.rebase_indices(-26), Err(Error::IndexOutOfRange));
+
+    forest.rebase_indices(-25).unwrap();
+    assert_eq!(
+        forest
+            .pool_order()
+            .filter(|(id, live)| *live && *id >= 15)
+            .count(),
+        0
+    );
+    for (tree_id, expected) in expected.iter().enumerate() {
+        assert_eq!(
+            &forest.pairs(tree_id).unwrap().collect::<Vec<_>>(),
+            expected
+        );
+        assert!(forest.is_balanced(tree_id));
+        assert!(forest.is_child_parent_links_consistent(tree_id));
+    }
+    assert!(!forest.is_allocated(2));
+    assert_eq!(forest.free_nodes_left(), 25);
+    assert!(forest.free_list_disjoint());
+
+    forest.rebase_indices(0).unwrap();
+    forest.rebase_indices(10).unwrap();
+    assert_eq!(forest.pairs(0).unwrap().collect::<Vec<_>>(), expected[0]);
+    for key in 15..40 {
+        forest.insert(0, key, 0).unwrap();
+    }
+    assert!(forest.is_balanced(0));
+}
+
+#[test]
+fn summaries() {
+    let mut vec = create_vec(1, 4, 50, 3);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 3).unwrap();
+    for key in 0..30 {
+        if key % 3 != 2 {
+            forest.insert(usize::from(key % 3), key, 0).unwrap();
+        }
+    }
+    forest.insert(2, 100, 0).unwrap();
+    forest.delete(2, &100);
+
+    assert_eq!(
+        forest.summaries().collect::<Vec<_>>(),
+        vec![
+            TreeSummary {
+                tree_id: 0,
+                len: 10,
+                min_key: Some(0),
+                max_key: Some(27),
+            },
+            TreeSummary {
+                tree_id: 1,
+                len: 10,
+                min_key: Some(1),
+                max_key: Some(28),
+            },
+            TreeSummary {
+                tree_id: 2,
+                len: 0,
+                min_key: None,
+                max_key: None,
+            },
+        ]
+    );
+}
+
+#[test]
+fn join() {
+    use std::collections::BTreeMap;
+
+    let mut vec = create_vec(1, 4, 256, 3);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 3).unwrap();
+    let mut expected = [BTreeMap::new(), BTreeMap::new()];
+    let mut state = 11;
+    for _ in 0..150 {
+        let random = splitmix64(&mut state);
+        let tree_id = (random % 2) as usize;
+        let key = (random >> 8) as u8 % 100;
+        let value = (random >> 32) as u32;
+        forest.insert(tree_id, key, value).unwrap();
+        expected[tree_id].insert(key, value);
+    }
+
+    let reference: Vec<_> = expected[0]
+        .iter()
+        .filter_map(|(key, a)| expected[1].get(key).map(|b| (*key, *a, *b)))
+        .collect();
+    assert!(!reference.is_empty());
+    assert!(reference.len() < expected[0].len());
+    assert_eq!(forest.join(0, 1).unwrap().collect::<Vec<_>>(), reference);
+
+    let swapped: Vec<_> = reference.iter().map(|(k, a, b)| (*k, *b, *a)).collect();
+    assert_eq!(forest.join(1, 0).unwrap().collect::<Vec<_>>(), swapped);
+    assert_eq!(forest.join(0, 2).unwrap().count(), 0);
+    assert_eq!(forest.join(0, 0).unwrap().count(), expected[0].len());
+    assert!(forest.join(0, 3).is_err());
+}
+
+#[test]
+fn allocator_state() {
+    let mut vec = create_vec(1, 4, 50, 3);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 3).unwrap();
+    forest.free_tree(2).unwrap();
+    for key in 0..20 {
+        forest
+            .insert(usize::from(key % 2), key, u32::from(key))
+            .unwrap();
+    }
+
+    let bytes = forest.as_bytes().to_vec();
+    let nodes = forest.nodes().to_vec();
+    let state = forest.allocator_state();
+
+    for key in 0..10 {
+        forest.delete(0, &(key * 2));
+    }
+    forest.allocate_tree().unwrap();
+    for key in 20..40 {
+        forest.insert(2, key, 0).unwrap();
+    }
+    forest.insert(1, 1, 100).unwrap();
+    assert_ne!(forest.as_bytes(), bytes.as_slice());
+
+    forest.nodes_mut().copy_from_slice(&nodes);
+    let generation = forest.generation();
+    forest.restore_allocator_state(state.clone()).unwrap();
+    // Everything but the generation is rolled back, the generation keeps advancing
+    let header_len = mem::size_of::<Header>();
+    assert_eq!(&forest.as_bytes()[header_len..], &bytes[header_len..]);
+    assert_eq!(forest.allocator_state(), state);
+    assert_ne!(forest.generation(), generation);
+    let restored = forest.as_bytes().to_vec();
+    assert_eq!(forest.len(0), Ok(10));
+    assert_eq!(forest.get(1, &1), Some(1));
+    assert!(!forest.is_allocated(2));
+    assert!(forest.free_list_disjoint());
+
+    let mut bad_head = state.clone();
+    bad_head.head = Some(50);
+    assert_eq!(
+        forest.restore_allocator_state(bad_head),
+        Err(Error::IndexOutOfRange)
+    );
+    let mut bad_root = state.clone();
+    bad_root.roots[1] = 60u32.to_be_bytes();
+    assert_eq!(
+        forest.restore_allocator_state(bad_root),
+        Err(Error::IndexOutOfRange)
+    );
+    let mut other_vec = create_vec(1, 4, 50, 2);
+    let other = RBForest::<u8, u32, 1, 4>::init_slice(other_vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(
+        forest.restore_allocator_state(other.allocator_state()),
+        Err(Error::WrongMaxRoots)
+    );
+    assert_eq!(forest.as_bytes(), restored.as_slice());
+}
+
+#[test]
+fn leaked_node_count() {
+    let mut vec = create_vec(1, 4, 20, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(forest.leaked_node_count(), 0);
+    for key in 0..12 {
+        forest.insert(usize::from(key % 2), key, 0).unwrap();
+    }
+    forest.delete(0, &4);
+    assert_eq!(forest.leaked_node_count(), 0);
+
+    // The head of the free list is orphaned
+    let head = forest.header().head().unwrap();
+    forest.set_head(forest.nodes()[head as usize].parent());
+    assert_eq!(forest.leaked_node_count(), 1);
+
+    assert_eq!(forest.rebuild_free_list(), 9);
+    assert_eq!(forest.leaked_node_count(), 0);
+}
+
+#[test]
+fn fill_sorted_dedup() {
+    let mut vec = create_vec(1, 4, 40, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    let entries = (0..60u8).map(|key| (key / 2, u32::from(key)));
+    forest
+        .fill_sorted_dedup(1, entries, |prev, new| prev + new)
+        .unwrap();
+    assert_eq!(forest.len(1), Ok(30));
+    assert!(forest.is_balanced(1));
+    assert_eq!(forest.get(1, &7), Some(29));
+    assert_eq!(forest.free_nodes_left(), 10);
+
+    assert_eq!(
+        forest.fill_sorted_dedup(1, [(1, 1)], |_, new| new),
+        Err(Error::NonEmptyTree)
+    );
+    assert_eq!(
+        forest.fill_sorted_dedup(2, [(1, 1)], |_, new| new),
+        Err(Error::TooBigTreeId)
+    );
+
+    // Failed builds leave the tree empty and return all the nodes
+    assert_eq!(
+        forest.fill_sorted_dedup(0, [(1, 1), (2, 2), (3, 3), (2, 4)], |_, new| new),
+        Err(Error::UnsortedInput)
+    );
+    assert_eq!(forest.len(0), Ok(0));
+    assert_eq!(forest.free_nodes_left(), 10);
+    assert_eq!(
+        forest.fill_sorted_dedup(0, (0..11).map(|key| (key, 0)), |_, new| new),
+        Err(Error::NoNodesLeft)
+    );
+    assert_eq!(forest.len(0), Ok(0));
+    assert_eq!(forest.free_nodes_left(), 10);
+    assert_eq!(forest.leaked_node_count(), 0);
+}
+
+#[test]
+fn residue_keys() {
+    let mut vec = create_vec(4, 4, 30, 2);
+    let mut forest = RBForest::<u32, u32, 4, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for key in 0..20 {
+        forest.insert(0, key, key * 10).unwrap();
+    }
+
+    let keys: Vec<_> = forest
+        .residue_keys(0, 3, 1)
+        .unwrap()
+        .map(|(k, _)| k)
+        .collect();
+    assert_eq!(keys, [1, 4, 7, 10, 13, 16, 19]);
+    assert!(forest
+        .residue_keys(0, 3, 1)
+        .unwrap()
+        .all(|(k, v)| v == k * 10));
+    assert_eq!(forest.residue_keys(0, 5, 7).unwrap().count(), 0);
+    assert_eq!(forest.residue_keys(0, 0, 0).unwrap().count(), 0);
+    assert_eq!(forest.residue_keys(1, 3, 1).unwrap().count(), 0);
+    assert!(forest.residue_keys(2, 3, 1).is_err());
+}
+
+#[test]
+fn growth_since() {
+    let mut vec = create_vec(1, 4, 20, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    forest.insert(1, 100, 0).unwrap();
+    assert_eq!(forest.total_len(), 1);
+
+    let checkpoint = forest.checkpoint();
+    assert_eq!(forest.growth_since(checkpoint), 0);
+    for key in 0..5 {
+        forest.insert(usize::from(key % 2), key, 0).unwrap();
+    }
+    assert!(forest.delete(0, &0));
+    assert!(forest.delete(1, &1));
+    assert_eq!(forest.total_len(), 4);
+    assert_eq!(forest.growth_since(checkpoint), 3);
+
+    forest.clear();
+    assert_eq!(forest.growth_since(checkpoint), -1);
+}
+
+#[test]
+fn len_cached() {
+    let mut vec = create_vec(1, 4, 64, 3);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 3).unwrap();
+    assert_eq!(forest.len_cached(0), 0);
+    assert_eq!(forest.len_cached(3), 0);
+
+    for (i, &key) in INSERT_KEYS.iter().take(40).enumerate() {
+        forest.insert(i % 2, key, 0).unwrap();
+        assert_eq!(forest.len_cached(0), forest.len(0).unwrap());
+        assert_eq!(forest.len_cached(1), forest.len(1).unwrap());
+    }
+    // Overwriting does not change the length
+    forest.insert(0, INSERT_KEYS[0], 1).unwrap();
+    assert_eq!(forest.len_cached(0), 20);
+
+    for &key in INSERT_KEYS.iter().take(10) {
+        forest.delete(0, &key);
+        forest.delete(1, &key);
+        assert_eq!(forest.len_cached(0), forest.len(0).unwrap());
+        assert_eq!(forest.len_cached(1), forest.len(1).unwrap());
+    }
+
+    // Storing the length is not a modification
+    let generation = forest.generation();
+    let len = forest.len_cached(1);
+    assert_eq!(forest.generation(), generation);
+
+    // The stored length survives reopening
+    let mut forest = unsafe { RBForest::<u8, u32, 1, 4>::from_slice(vec.as_mut_slice()) }.unwrap();
+    assert_eq!(forest.len_cached(1), len);
+
+    forest.move_tree(1, 2).unwrap();
+    assert_eq!(forest.len_cached(1), 0);
+    assert_eq!(forest.len_cached(2), len);
+    forest.swap_trees(0, 2).unwrap();
+    assert_eq!(forest.len_cached(0), len);
+
+    forest.clear();
+    assert_eq!(forest.len_cached(0), 0);
+    assert_eq!(forest.len_cached(2), 0);
+}
+
+#[test]
+fn split_readonly() {
+    let mut vec = create_vec(1, 4, 40, 3);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 3).unwrap();
+    for key in 0..30 {
+        forest
+            .insert(usize::from(key % 2), key, u32::from(key) * 10)
+            .unwrap();
+    }
+
+    let views = forest.split_readonly();
+    assert_eq!(views.len(), 3);
+    let (even, odd) = std::thread::scope(|scope| {
+        let even = scope.spawn(|| views[0].pairs().collect::<Vec<_>>());
+        let odd = scope.spawn(|| views[1].keys().collect::<Vec<_>>());
+        (even.join().unwrap(), odd.join().unwrap())
+    });
+    assert_eq!(even, forest.pairs(0).unwrap().collect::<Vec<_>>());
+    assert_eq!(odd, (0..30).filter(|key| key % 2 == 1).collect::<Vec<_>>());
+
+    assert_eq!(views[1].tree_id(), 1);
+    assert_eq!(views[1].len(), 15);
+    assert_eq!(views[1].get(&7), Some(70));
+    assert_eq!(views[0].get(&7), None);
+    assert!(views[0].contains_key(&8));
+    assert_eq!(views[0].values().next(), Some(0));
+    assert!(views[2].is_empty());
+    assert_eq!(views[2].pairs().count(), 0);
+}
+
+#[test]
+fn buffer_diff() {
+    let mut a = create_vec(1, 4, 10, 2);
+    let mut b = create_vec(1, 4, 10, 2);
+    let mut node_index = 0;
+    for (vec, value) in [(&mut a, 7), (&mut b, 0x0107)] {
+        let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+        for key in 0..5 {
+            forest.insert(0, key, 0).unwrap();
+        }
+        forest.insert(0, 3, value).unwrap();
+        node_index = forest.get_key_index(0, &3).unwrap() as u32;
+    }
+
+    let offset = mem::size_of::<Header>() + node_index as usize * super::node_stride(1, 4) + 2;
+    assert_eq!(
+        super::buffer_diff(&a, &b),
+        [(
+            offset,
+            0,
+            1,
+            ByteRegion::Node {
+                index: node_index,
+                offset: 2
+            }
+        )]
+    );
+    assert!(super::buffer_diff(&a, &a).is_empty());
+
+    let mut c = a.clone();
+    let root = a.len() - 9;
+    c[0] ^= 1;
+    c[root] ^= 1;
+    *c.last_mut().unwrap() ^= 1;
+    assert_eq!(
+        super::buffer_diff(&a, &c),
+        [
+            (0, a[0], c[0], ByteRegion::Header),
+            (root, a[root], c[root], ByteRegion::Root { tree_id: 1 }),
+            (
+                a.len() - 1,
+                a[a.len() - 1],
+                c[c.len() - 1],
+                ByteRegion::CachedLen { tree_id: 1 }
+            )
+        ]
+    );
+    assert_eq!(super::buffer_diff(&c, &a)[0].3, ByteRegion::Unknown);
+}
+
+#[test]
+fn insert_many_report() {
+    let mut vec = create_vec(1, 4, 5, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    forest.insert(0, 2, 20).unwrap();
+
+    let report = forest
+        .insert_many_report(0, [(1, 1), (2, 2), (3, 3), (1, 4)])
+        .unwrap();
+    assert_eq!(report, [(1, None), (2, Some(20)), (3, None), (1, Some(1))]);
+    assert_eq!(forest.get(0, &1), Some(4));
+
+    // Only two free nodes are left
+    let (err, report) = forest
+        .insert_many_report(0, [(3, 5), (4, 0), (5, 0), (6, 0), (7, 0)])
+        .unwrap_err();
+    assert_eq!(err, Error::NoNodesLeft);
+    assert_eq!(report, [(3, Some(3)), (4, None), (5, None)]);
+    assert_eq!(forest.len(0), Ok(5));
+    assert!(!forest.contains_key(0, &6));
+
+    assert_eq!(
+        forest.insert_many_report(2, [(1, 1)]),
+        Err((Error::TooBigTreeId, vec![]))
+    );
+}
+
+#[test]
+fn pairs_with_running_extrema() {
+    let mut vec = create_vec(1, 4, 10, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for (key, value) in [3, 1, 4, 1, 5].into_iter().enumerate() {
+        forest.insert(0, key as u8, value).unwrap();
+    }
+
+    assert_eq!(
+        forest
+            .pairs_with_running_extrema(0)
+            .unwrap()
+            .collect::<Vec<_>>(),
+        [
+            (0, 3, 3, 3),
+            (1, 1, 1, 3),
+            (2, 4, 1, 4),
+            (3, 1, 1, 4),
+            (4, 5, 1, 5)
+        ]
+    );
+    assert_eq!(forest.pairs_with_running_extrema(1).unwrap().count(), 0);
+    assert!(forest.pairs_with_running_extrema(2).is_err());
+}
+
+#[test]
+fn reserve_nodes() {
+    let mut vec = create_vec(1, 4, 10, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    forest.insert(1, 100, 0).unwrap();
+
+    let reservation = forest.reserve_nodes(6).unwrap();
+    assert_eq!(reservation.len(), 6);
+    assert_eq!(forest.reserved_nodes(), 6);
+    assert_eq!(forest.spare_capacity(), 3);
+    assert_eq!(forest.free_nodes_left(), 9);
+    assert_eq!(forest.reserve_nodes(4), Err(Error::NoNodesLeft));
+    assert_eq!(forest.reserved_nodes(), 6);
+
+    // Another tree can not take the reserved nodes
+    for key in 0..3 {
+        forest.insert(1, key, 0).unwrap();
+    }
+    assert_eq!(forest.insert(1, 3, 0), Err(Error::NoNodesLeft));
+    assert!(forest.would_overflow(1));
+    assert_eq!(forest.insert_bounded(1, 4, 0), Ok(Some((0, 0))));
+
+    forest
+        .extend_reserved(0, reservation, (0..5).map(|key| (key, u32::from(key))))
+        .unwrap();
+    assert_eq!(forest.len(0), Ok(5));
+    assert_eq!(forest.reserved_nodes(), 0);
+    assert_eq!(forest.free_nodes_left(), 1);
+    assert_eq!(forest.leaked_node_count(), 0);
+    assert!(forest.is_balanced(0));
+
+    let reservation = forest.reserve_nodes(1).unwrap();
+    assert_eq!(forest.spare_capacity(), 0);
+    assert_eq!(forest.release_nodes(reservation), Ok(()));
+    assert_eq!(forest.spare_capacity(), 1);
+    assert!(forest.reserve_nodes(0).unwrap().is_empty());
+    assert_eq!(forest.leaked_node_count(), 0);
+}
+
+#[test]
+fn reserve_nodes_persistence() {
+    let mut vec = create_vec(1, 4, 10, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    let reservation = forest.reserve_nodes(4).unwrap();
+
+    // The free list is not touched, so clearing and rebuilding it keeps the forest consistent
+    forest.insert(0, 1, 1).unwrap();
+    forest.clear();
+    assert_eq!(forest.release_nodes(reservation), Ok(()));
+    assert_eq!(forest.reserved_nodes(), 0);
+    assert_eq!(forest.allocator_stats().free, 10);
+    assert_eq!(forest.leaked_node_count(), 0);
+
+    // The reservation is stored in the slice, so a lost token keeps the nodes reserved
+    let _ = forest.reserve_nodes(8).unwrap();
+    let mut forest = unsafe { RBForest::<u8, u32, 1, 4>::from_slice(vec.as_mut_slice()).unwrap() };
+    assert_eq!(forest.reserved_nodes(), 8);
+    for key in 0..2 {
+        forest.insert(1, key, 0).unwrap();
+    }
+    assert_eq!(forest.insert(1, 2, 0), Err(Error::NoNodesLeft));
+
+    let mut bigger = create_vec(1, 4, 12, 2);
+    assert_eq!(
+        forest.grow_into(&mut bigger, 2).unwrap().reserved_nodes(),
+        8
+    );
+
+    forest.cancel_reservations();
+    assert_eq!(forest.reserved_nodes(), 0);
+    forest.insert(1, 2, 0).unwrap();
+}
+
+#[test]
+fn wrong_reservation() {
+    let mut vec = create_vec(1, 4, 10, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    let mut other_vec = create_vec(1, 4, 10, 2);
+    let mut other = RBForest::<u8, u32, 1, 4>::init_slice(other_vec.as_mut_slice(), 2).unwrap();
+
+    let small = forest.reserve_nodes(2).unwrap();
+    let big = other.reserve_nodes(5).unwrap();
+
+    // A reservation of another forest is detected if it is bigger than the local one
+    assert_eq!(forest.release_nodes(big), Err(Error::WrongReservation));
+    assert_eq!(forest.reserved_nodes(), 2);
+    let big = other.reserve_nodes(3).unwrap();
+    assert_eq!(
+        forest.extend_reserved(0, big, [(1, 1)]),
+        Err(Error::WrongReservation)
+    );
+    assert!(forest.is_empty(0));
+    assert_eq!(forest.reserved_nodes(), 2);
+
+    // Cancelled reservations can not be released again
+    forest.cancel_reservations();
+    assert_eq!(forest.release_nodes(small), Err(Error::WrongReservation));
+    assert_eq!(forest.reserved_nodes(), 0);
+}
+
+#[test]
+fn free_nodes_counter() {
+    let mut vec = create_vec(1, 4, 20, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    let check = |forest: &RBForest<u8, u32, 1, 4>, free: usize| {
+        assert_eq!(forest.free_nodes_left(), free);
+        assert_eq!(forest.free_list_len(), free);
+    };
+    check(&forest, 20);
+
+    for key in 0..15 {
+        forest.insert(usize::from(key % 2), key, 0).unwrap();
+    }
+    check(&forest, 5);
+    for key in 0..5 {
+        forest.remove(0, &(key * 2));
+    }
+    check(&forest, 10);
+
+    let state = forest.allocator_state();
+    let nodes = forest.nodes().to_vec();
+    forest.insert(1, 100, 0).unwrap();
+    check(&forest, 9);
+    forest.nodes_mut().copy_from_slice(&nodes);
+    forest.restore_allocator_state(state).unwrap();
+    check(&forest, 10);
+
+    forest.reset_roots();
+    assert_eq!(forest.rebuild_free_list(), 20);
+    check(&forest, 20);
+
+    forest.insert(0, 1, 1).unwrap();
+    forest.clear();
+    check(&forest, 20);
+
+    // Reservations are kept by pack and unpack_into
+    forest.insert(0, 1, 1).unwrap();
+    forest.insert(0, 2, 2).unwrap();
+    let _ = forest.reserve_nodes(10).unwrap();
+    let mut packed = Vec::new();
+    forest.pack(&mut packed).unwrap();
+
+    let mut unpacked = create_vec(1, 4, 12, 2);
+    let unpacked =
+        RBForest::<u8, u32, 1, 4>::unpack_into(unpacked.as_mut_slice(), &packed).unwrap();
+    check(&unpacked, 10);
+    assert_eq!(unpacked.reserved_nodes(), 10);
+    assert_eq!(unpacked.spare_capacity(), 0);
+
+    let mut too_small = create_vec(1, 4, 11, 2);
+    assert_eq!(
+        RBForest::<u8, u32, 1, 4>::unpack_into(too_small.as_mut_slice(), &packed).err(),
+        Some(Error::TooSmall)
+    );
+
+    let mut bigger = create_vec(1, 4, 30, 2);
+    let grown = forest.grow_into(bigger.as_mut_slice(), 2).unwrap();
+    check(&grown, 28);
+}
+
+#[test]
+fn group_by() {
+    let mut vec = create_vec(1, 4, 40, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for key in [1, 5, 9, 12, 31, 30, 38, 39, 40] {
+        forest.insert(0, key, u32::from(key) * 2).unwrap();
+    }
+
+    let groups: Vec<_> = forest.group_by(0, |key| key / 10).unwrap().collect();
+    let decades: Vec<_> = groups
+        .iter()
+        .map(|(decade, group)| (*decade, group.iter().map(|(k, _)| *k).collect::<Vec<_>>()))
+        .collect();
+    assert_eq!(
+        decades,
+        [
+            (0, vec![1, 5, 9]),
+            (1, vec![12]),
+            (3, vec![30, 31, 38, 39]),
+            (4, vec![40])
+        ]
+    );
+    assert!(groups
+        .iter()
+        .flat_map(|(_, group)| group)
+        .all(|(k, v)| *v == u32::from(*k) * 2));
+
+    assert_eq!(forest.group_by(1, |key| key / 10).unwrap().count(), 0);
+    assert!(forest.group_by(2, |key| key / 10).is_err());
+}
+
+#[test]
+fn try_for_each() {
+    let mut vec = create_vec(1, 4, 256, 2);
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    for (i, &key) in INSERT_KEYS.iter().enumerate() {
+        forest.insert(0, key, i as u32).unwrap();
+    }
+
+    let expected = forest.pairs(0).unwrap().find(|(_, value)| *value > 200);
+    assert!(expected.is_some());
+    let mut visited = 0;
+    let result = forest.try_for_each(0, |key, value| {
+        visited += 1;
+        if *value > 200 {
+            ControlFlow::Break((*key, *value))
+        } else {
+            ControlFlow::Continue(())
+        }
+    });
+    assert_eq!(result, ControlFlow::Break(expected.unwrap()));
+    let position = forest
+        .keys(0)
+        .unwrap()
+        .position(|key| key == expected.unwrap().0);
+    assert_eq!(Some(visited - 1), position);
+
+    let mut visited = 0;
+    let result = forest.try_for_each(0, |_, _| -> ControlFlow<()> {
+        visited += 1;
+        ControlFlow::Continue(())
+    });
+    assert_eq!(result, ControlFlow::Continue(()));
+    assert_eq!(Ok(visited), forest.len(0));
+
+    assert_eq!(
+        forest.try_for_each(1, |_, _| ControlFlow::Break(())),
+        ControlFlow::Continue(())
+    );
+    assert_eq!(
+        forest.try_for_each(2, |_, _| ControlFlow::Break(())),
+        ControlFlow::Continue(())
+    );
+}
+
+#[test]
+fn payload_ratio() {
+    let mut vec = create_vec(1, 4, 20, 2);
+    let slice_len = vec.len();
+    let mut forest = RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(forest.payload_ratio(), 0.0);
+
+    let mut previous = 0.0;
+    for key in 0..20 {
+        forest.insert(usize::from(key % 2), key, 0).unwrap();
+        let ratio = forest.payload_ratio();
+        assert!(ratio > previous);
+        previous = ratio;
+    }
+    assert_eq!(previous, (20 * 5) as f64 / slice_len as f64);
+    assert!(previous < 1.0);
+
+    forest.delete(0, &0);
+    assert!(forest.payload_ratio() < previous);
+}
+
+#[test]
+fn insert_checked() {
+    let mut vec = create_vec(4, 4, 2, 2);
+    let mut forest = RBForest::<u32, u32, 4, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert_eq!(forest.generation(), 0);
+
+    assert_eq!(forest.insert_checked(0, 1, 10, 0), Ok(None));
+    let first = forest.generation();
+    assert_ne!(first, 0);
+    assert_eq!(forest.insert_checked(1, 1, 20, first), Ok(None));
+    let second = forest.generation();
+    assert_ne!(second, first);
+    assert_eq!(forest.insert_checked(0, 1, 11, second), Ok(Some(10)));
+    let current = forest.generation();
+    assert_ne!(current, second);
+
+    assert_eq!(
+        forest.insert_checked(0, 2, 30, second),
+        Err(Error::VersionConflict)
+    );
+    assert_eq!(
+        forest.insert_checked(0, 2, 30, current.wrapping_add(1)),
+        Err(Error::VersionConflict)
+    );
+    assert_eq!(forest.get(0, &2), None);
+    assert_eq!(forest.generation(), current);
+
+    // failed insertions do not change the generation
+    assert_eq!(
+        forest.insert_checked(0, 2, 30, current),
+        Err(Error::NoNodesLeft)
+    );
+    assert_eq!(
+        forest.insert_checked(2, 2, 30, current),
+        Err(Error::TooBigTreeId)
+    );
+    assert_eq!(forest.generation(), current);
+
+    // the generation is stored in the slice
+    let forest = unsafe { RBForest::<u32, u32, 4, 4>::from_slice(vec.as_mut_slice()).unwrap() };
+    assert_eq!(forest.header_snapshot().generation, current);
+}
+
+#[test]
+fn generation_unchecked_writers() {
+    let mut vec = create_vec(4, 4, 10, 2);
+    let mut forest = RBForest::<u32, u32, 4, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+
+    // every modification is visible to a checked writer
+    type Modify = fn(&mut RBForest<u32, u32, 4, 4>);
+    let checks: [(&str, Modify); 10] = [
+        ("insert", |forest| {
+            forest.insert(0, 1, 10).unwrap();
+        }),
+        ("overwrite", |forest| {
+            forest.insert(0, 1, 11).unwrap();
+        }),
+        ("replace", |forest| {
+            forest.replace(0, &1, 12).unwrap();
+        }),
+        ("map_values", |forest| {
+            forest.map_values(0, |_, v| v + 1).unwrap()
+        }),
+        ("move_tree", |forest| forest.move_tree(0, 1).unwrap()),
+        ("swap_trees", |forest| forest.swap_trees(0, 1).unwrap()),
+        ("remove", |forest| {
+            forest.remove(0, &1).unwrap();
+        }),
+        ("apply_batch", |forest| {
+            forest.apply_batch(&[BatchOp::Insert(0, 2, 20)]).unwrap()
+        }),
+        ("partition", |forest| {
+            forest.partition(0, 1, |_, _| true).unwrap()
+        }),
+        ("clear", |forest| forest.clear()),
+    ];
+    for (name, modify) in checks {
+        let generation = forest.generation();
+        modify(&mut forest);
+        assert_ne!(forest.generation(), generation, "{name}");
+        assert_eq!(
+            forest.insert_checked(0, 3, 30, generation),
+            Err(Error::VersionConflict),
+            "{name}"
+        );
+    }
+
+    // reading does not change the generation
+    let generation = forest.generation();
+    assert!(forest.is_empty(0));
+    assert_eq!(forest.get(1, &2), None);
+    assert_eq!(forest.generation(), generation);
+}
+
+#[test]
+fn generation_copied() {
+    let mut vec = create_vec(4, 4, 10, 2);
+    let mut forest = RBForest::<u32, u32, 4, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    forest.insert(0, 1, 10).unwrap();
+    forest.insert(1, 2, 20).unwrap();
+    let generation = forest.generation();
+
+    let mut bigger = create_vec(4, 4, 20, 3);
+    let grown = forest.grow_into(bigger.as_mut_slice(), 3).unwrap();
+    assert_eq!(grown.generation(), generation);
+
+    let mut packed = Vec::new();
+    forest.pack(&mut packed).unwrap();
+    let mut unpacked = create_vec(4, 4, 10, 2);
+    let unpacked =
+        RBForest::<u32, u32, 4, 4>::unpack_into(unpacked.as_mut_slice(), &packed).unwrap();
+    assert_eq!(unpacked.generation(), generation);
+}
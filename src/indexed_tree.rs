@@ -0,0 +1,196 @@
+//! A slice-based map with a secondary index by value
+//!
+//! [`IndexedTree`] is a composition of two [`RBTree`]s: the primary one maps keys to values and
+//! the secondary one maps values back to keys. Both trees are updated by every modification, so
+//! a key can be found by its value in `O(log(n))`.
+//!
+//! Each entry is stored twice, so the index doubles the required storage: the secondary tree
+//! needs a separate slice of size `tree_size(TreeParams { k_size: VSIZE, v_size: KSIZE }, n)`,
+//! which is the same as the size of the primary one.
+//!
+//! ```
+//! use slice_rbtree::indexed_tree::IndexedTree;
+//! use slice_rbtree::tree::{tree_size, TreeParams};
+//!
+//! let size = tree_size(TreeParams { k_size: 1, v_size: 4 }, 10);
+//! let mut primary = vec![0; size];
+//! let mut secondary = vec![0; size];
+//!
+//! let mut tree: IndexedTree<u8, u32, 1, 4> =
+//!     IndexedTree::init_slices(&mut primary, &mut secondary).unwrap();
+//!
+//! tree.insert(1, 100).unwrap();
+//! tree.insert(2, 200).unwrap();
+//!
+//! assert_eq!(tree.get(&1), Some(100));
+//! assert_eq!(tree.get_by_value(&200), Some(2));
+//! ```
+use borsh::{BorshDeserialize, BorshSerialize};
+use core::borrow::Borrow;
+use core::cmp::Ord;
+use core::fmt;
+
+use super::tree::{PairsIterator, RBTree};
+use super::Error;
+
+/// A map with unique values, which can be searched both by key and by value
+///
+/// See [module](super::indexed_tree) level documentation for more info.
+pub struct IndexedTree<'a, K, V, const KSIZE: usize, const VSIZE: usize>
+where
+    K: Ord + Clone + BorshDeserialize + BorshSerialize,
+    V: Ord + Clone + BorshDeserialize + BorshSerialize,
+{
+    primary: RBTree<'a, K, V, KSIZE, VSIZE>,
+    secondary: RBTree<'a, V, K, VSIZE, KSIZE>,
+}
+
+impl<'a, K, V, const KSIZE: usize, const VSIZE: usize> IndexedTree<'a, K, V, KSIZE, VSIZE>
+where
+    K: Ord + Clone + BorshDeserialize + BorshSerialize,
+    V: Ord + Clone + BorshDeserialize + BorshSerialize,
+{
+    /// Initializes [`IndexedTree`] in the given slices
+    ///
+    /// `primary` holds the key to value map and `secondary` holds the value to key map.
+    pub fn init_slices(primary: &'a mut [u8], secondary: &'a mut [u8]) -> Result<Self, Error> {
+        Ok(Self {
+            primary: RBTree::init_slice(primary)?,
+            secondary: RBTree::init_slice(secondary)?,
+        })
+    }
+
+    /// Returns [`IndexedTree`], contained in the given slices
+    ///
+    /// # Safety
+    /// This function must be called only on slices, previously initialized as [`IndexedTree`]
+    /// using [`IndexedTree::init_slices`]
+    pub unsafe fn from_slices(
+        primary: &'a mut [u8],
+        secondary: &'a mut [u8],
+    ) -> Result<Self, Error> {
+        unsafe {
+            Ok(Self {
+                primary: RBTree::from_slice(primary)?,
+                secondary: RBTree::from_slice(secondary)?,
+            })
+        }
+    }
+
+    /// Returns the number of entries
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.primary.len()
+    }
+
+    /// Returns `true` if the map contains no elements
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.primary.is_empty()
+    }
+
+    /// Returns the value corresponding to the key
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn get<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.primary.get(k)
+    }
+
+    /// Returns the key corresponding to the value
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn get_by_value(&self, v: &V) -> Option<K> {
+        self.secondary.get(v)
+    }
+
+    /// Inserts a new entry and returns the old value if the key was present
+    ///
+    /// Values must be unique: if `v` is already associated with another key,
+    /// [`Error::DuplicateValue`] is returned. If any of the trees can not be updated, the map is
+    /// left unchanged.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn insert(&mut self, k: K, v: V) -> Result<Option<V>, Error> {
+        if let Some(owner) = self.secondary.get(&v) {
+            return if owner == k {
+                Ok(Some(v))
+            } else {
+                Err(Error::DuplicateValue)
+            };
+        }
+
+        // The old index entry is deleted first, so that overwriting does not need a spare node
+        let old_value = self.primary.get(&k);
+        if let Some(old_value) = &old_value {
+            self.secondary.delete(old_value);
+        }
+
+        let result = self.secondary.insert(v.clone(), k.clone()).and_then(|_| {
+            match self.primary.insert(k.clone(), v.clone()) {
+                Ok(_) => Ok(()),
+                Err(e) => {
+                    self.secondary.delete(&v);
+                    Err(e)
+                }
+            }
+        });
+
+        if let Err(e) = result {
+            if let Some(old_value) = old_value {
+                // The node of the old entry has just been freed and the old value has been
+                // serialized before, so the restoration can not fail
+                let _ = self.secondary.insert(old_value, k);
+            }
+            return Err(e);
+        }
+
+        Ok(old_value)
+    }
+
+    /// Deletes the entry and returns its value
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let value = self.primary.remove(k)?;
+        self.secondary.delete(&value);
+        Some(value)
+    }
+
+    /// Creates an iterator over key-value pairs, in order by key
+    #[must_use]
+    pub fn pairs<'b>(&'b self) -> PairsIterator<'b, 'a, K, V, KSIZE, VSIZE> {
+        self.primary.pairs()
+    }
+
+    /// Creates an iterator over value-key pairs, in order by value
+    #[must_use]
+    pub fn pairs_by_value<'b>(&'b self) -> PairsIterator<'b, 'a, V, K, VSIZE, KSIZE> {
+        self.secondary.pairs()
+    }
+}
+
+impl<'a, K, V, const KSIZE: usize, const VSIZE: usize> fmt::Debug
+    for IndexedTree<'a, K, V, KSIZE, VSIZE>
+where
+    K: Ord + Clone + BorshDeserialize + BorshSerialize + fmt::Debug,
+    V: Ord + Clone + BorshDeserialize + BorshSerialize + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.debug_map().entries(self.pairs()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests;
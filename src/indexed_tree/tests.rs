@@ -0,0 +1,91 @@
+use super::*;
+use crate::forest::tests as forest_helpers;
+use core::fmt::Debug;
+use pretty_assertions::assert_eq;
+
+#[test]
+fn insert_and_remove() {
+    let mut primary = create_vec(1, 4, 256);
+    let mut secondary = create_vec(4, 1, 256);
+    let mut tree =
+        IndexedTree::<u8, u32, 1, 4>::init_slices(primary.as_mut_slice(), secondary.as_mut_slice())
+            .unwrap();
+    assert!(tree.is_empty());
+
+    for &key in forest_helpers::INSERT_KEYS.iter() {
+        assert_eq!(tree.insert(key, u32::from(key) * 1000), Ok(None));
+    }
+    assert_eq!(tree.len(), 256);
+    assert_consistent(&tree);
+
+    for key in 0..=255 {
+        assert_eq!(tree.get(&key), Some(u32::from(key) * 1000));
+        assert_eq!(tree.get_by_value(&(u32::from(key) * 1000)), Some(key));
+    }
+    assert_eq!(tree.get_by_value(&1), None);
+
+    // Overwriting replaces the value in the index
+    assert_eq!(tree.insert(5, 5), Ok(Some(5000)));
+    assert_eq!(tree.get_by_value(&5000), None);
+    assert_eq!(tree.get_by_value(&5), Some(5));
+    assert_eq!(tree.insert(5, 5), Ok(Some(5)));
+    assert_consistent(&tree);
+
+    for &key in forest_helpers::INSERT_KEYS.iter().step_by(2) {
+        let value = tree.get(&key).unwrap();
+        assert_eq!(tree.remove(&key), Some(value));
+        assert_eq!(tree.get_by_value(&value), None);
+        assert_eq!(tree.remove(&key), None);
+    }
+    assert_eq!(tree.len(), 128);
+    assert_consistent(&tree);
+}
+
+#[test]
+fn duplicate_value() {
+    let mut primary = create_vec(1, 4, 2);
+    let mut secondary = create_vec(4, 1, 2);
+    let mut tree =
+        IndexedTree::<u8, u32, 1, 4>::init_slices(primary.as_mut_slice(), secondary.as_mut_slice())
+            .unwrap();
+
+    tree.insert(1, 10).unwrap();
+    tree.insert(2, 20).unwrap();
+    assert_eq!(tree.insert(3, 10), Err(Error::DuplicateValue));
+    assert_eq!(tree.insert(2, 10), Err(Error::DuplicateValue));
+    assert_eq!(tree.insert(3, 30), Err(Error::NoNodesLeft));
+    assert_eq!(tree.len(), 2);
+    assert_consistent(&tree);
+}
+
+#[test]
+fn failed_insert_is_rolled_back() {
+    let mut primary = create_vec(1, 8, 4);
+    let mut secondary = create_vec(8, 1, 5);
+    let mut tree =
+        IndexedTree::<u8, u64, 1, 8>::init_slices(primary.as_mut_slice(), secondary.as_mut_slice())
+            .unwrap();
+
+    for key in 0..4 {
+        tree.insert(key, u64::from(key)).unwrap();
+    }
+    // The secondary tree has a spare node, but the primary one does not
+    assert_eq!(tree.insert(4, 4), Err(Error::NoNodesLeft));
+    assert_eq!(tree.get_by_value(&4), None);
+    assert_consistent(&tree);
+}
+
+fn assert_consistent<K, V, const KSIZE: usize, const VSIZE: usize>(
+    tree: &IndexedTree<K, V, KSIZE, VSIZE>,
+) where
+    K: Ord + Clone + BorshDeserialize + BorshSerialize + Debug,
+    V: Ord + Clone + BorshDeserialize + BorshSerialize + Debug,
+{
+    let mut inverted: Vec<(V, K)> = tree.pairs().map(|(k, v)| (v, k)).collect();
+    inverted.sort();
+    assert_eq!(inverted, tree.pairs_by_value().collect::<Vec<_>>());
+}
+
+fn create_vec(k_size: usize, v_size: usize, num_entries: usize) -> Vec<u8> {
+    forest_helpers::create_vec(k_size, v_size, num_entries, 1)
+}
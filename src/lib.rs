@@ -6,22 +6,44 @@
 
 use borsh::{BorshDeserialize, BorshSerialize};
 
+pub mod arena_forest;
+pub mod dyn_forest;
 pub mod forest;
+pub mod indexed_tree;
 pub mod tree;
 
-/// Possible errors for [`RBTree`](tree::RBTree) and [`RBForest`](forest::RBForest)
+pub use forest::is_forest;
+
+/// Copying of a slice-based structure into a new buffer
+///
+/// The structures of this crate borrow their buffers, so they can not implement [`Clone`]. This
+/// trait makes a copy in the caller-provided buffer instead.
+pub trait CloneInto {
+    /// The type of the copy, which borrows the destination buffer
+    type Output<'b>;
+
+    /// Initializes a new structure in `dst` and copies all the contents there
+    ///
+    /// `dst` must be big enough to hold at least as many nodes as the original structure.
+    fn clone_into<'b>(&self, dst: &'b mut [u8]) -> Result<Self::Output<'b>, Error>;
+}
+
+/// Possible errors for [`RBTree`](tree::RBTree), [`RBForest`](forest::RBForest) and [`DynForest`](dyn_forest::DynForest)
 #[derive(Debug, PartialEq, Eq, Copy, Clone, BorshDeserialize, BorshSerialize)]
 pub enum Error {
-    /// Failed to serialize key to key buffer, maybe it is too big?
+    /// Failed to serialize key to key buffer, maybe it is too big? Also returned by `try_`
+    /// methods if a stored key is corrupted
     KeySerializationError,
     /// no free nodes left in the slice
     NoNodesLeft,
     /// the provided slice is too big for the map: the map internally uses `u32` indices, so there
-    /// can't be more than `u32::MAX` nodes
+    /// can't be more than `u32::MAX - 1` nodes
     TooBig,
-    /// the provided slice is too small for the map
+    /// the provided slice is too small for the map, or the provided output buffer is too small,
+    /// see [`RBForest::snapshot_into`](forest::RBForest::snapshot_into)
     TooSmall,
-    /// failed to serialize value to value buffer, maybe it is too big?
+    /// failed to serialize value to value buffer, maybe it is too big? Also returned by `try_`
+    /// methods if a stored value is corrupted
     ValueSerializationError,
     /// key size of the map does not match key size of the type
     WrongKeySize,
@@ -35,4 +57,87 @@ pub enum Error {
     WrongValueSize,
     /// There are fewer trees than the supplied tree_id
     TooBigTreeId,
+    /// failed to deserialize the supplied bytes, maybe they are corrupted or truncated?
+    DeserializationError,
+    /// the destination tree must be empty
+    NonEmptyTree,
+    /// all the tree slots of the forest are already allocated
+    NoTreesLeft,
+    /// the value is already associated with another key, see
+    /// [`IndexedTree`](indexed_tree::IndexedTree)
+    DuplicateValue,
+    /// the layout of the forest does not match the expected one, see
+    /// [`RBForest::open_with_schema`](forest::RBForest::open_with_schema)
+    WrongSchema,
+    /// failed to write to the output, see
+    /// [`RBForest::stream_frames`](forest::RBForest::stream_frames)
+    WriteError,
+    /// the slice contains a forest with a wrong number of tree slots, e.g. it can not be opened as
+    /// [`RBTree`](tree::RBTree)
+    WrongMaxRoots,
+    /// the requested node is not in the free list, see `RBForest::insert_at`
+    NodeNotFree,
+    /// a node index would leave the node pool, see
+    /// [`RBForest::rebase_indices`](forest::RBForest::rebase_indices)
+    IndexOutOfRange,
+    /// the supplied entries are not sorted by key, see
+    /// [`RBForest::fill_sorted_dedup`](forest::RBForest::fill_sorted_dedup)
+    UnsortedInput,
+    /// the supplied limit must be positive, see
+    /// [`RBForest::pairs_chunked`](forest::RBForest::pairs_chunked) and
+    /// [`RBForest::sample_every`](forest::RBForest::sample_every)
+    ZeroLimit,
+    /// the generation of the forest does not match the expected one, see
+    /// [`RBForest::insert_checked`](forest::RBForest::insert_checked)
+    VersionConflict,
+    /// the supplied slices must have the same length, see
+    /// [`RBForest::insert_columns`](forest::RBForest::insert_columns)
+    LengthMismatch,
+    /// the reservation is bigger than the number of reserved nodes of the forest, maybe it was
+    /// taken from another forest? See [`RBForest::release_nodes`](forest::RBForest::release_nodes)
+    WrongReservation,
 }
+
+impl Error {
+    /// Returns a short human-readable description of the error
+    ///
+    /// This does not require `std` or allocation, so it can be used for logging in `no_std`
+    /// environments.
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Error::KeySerializationError => "failed to serialize or deserialize a key",
+            Error::NoNodesLeft => "no free nodes left in the slice",
+            Error::TooBig => "the provided slice is too big for the map",
+            Error::TooSmall => "the provided slice is too small for the map",
+            Error::ValueSerializationError => "failed to serialize or deserialize a value",
+            Error::WrongKeySize => "key size of the map does not match key size of the type",
+            Error::WrongMagic => "struct header has incorrect magic",
+            Error::WrongNodePoolSize => {
+                "node pool size from the map header does not match the actual slice size"
+            }
+            Error::WrongSliceSize => "slice size is incorrect",
+            Error::WrongValueSize => "value size of the map does not match value size of the type",
+            Error::TooBigTreeId => "there are fewer trees than the supplied tree_id",
+            Error::DeserializationError => "failed to deserialize the supplied bytes",
+            Error::NonEmptyTree => "the destination tree must be empty",
+            Error::NoTreesLeft => "all the tree slots of the forest are already allocated",
+            Error::DuplicateValue => "the value is already associated with another key",
+            Error::WrongSchema => "the layout of the forest does not match the expected one",
+            Error::WriteError => "failed to write to the output",
+            Error::WrongMaxRoots => "the forest has a wrong number of tree slots",
+            Error::NodeNotFree => "the requested node is not in the free list",
+            Error::IndexOutOfRange => "a node index would leave the node pool",
+            Error::UnsortedInput => "the supplied entries are not sorted by key",
+            Error::ZeroLimit => "the supplied limit must be positive",
+            Error::VersionConflict => "the forest was modified since the expected generation",
+            Error::LengthMismatch => "the supplied slices must have the same length",
+            Error::WrongReservation => {
+                "the reservation is bigger than the number of reserved nodes"
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
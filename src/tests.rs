@@ -0,0 +1,40 @@
+use super::*;
+use pretty_assertions::assert_eq;
+
+#[test]
+fn error_as_str() {
+    let errors = [
+        Error::KeySerializationError,
+        Error::NoNodesLeft,
+        Error::TooBig,
+        Error::TooSmall,
+        Error::ValueSerializationError,
+        Error::WrongKeySize,
+        Error::WrongMagic,
+        Error::WrongNodePoolSize,
+        Error::WrongSliceSize,
+        Error::WrongValueSize,
+        Error::TooBigTreeId,
+        Error::DeserializationError,
+        Error::NonEmptyTree,
+        Error::NoTreesLeft,
+        Error::DuplicateValue,
+        Error::WrongSchema,
+        Error::WriteError,
+        Error::WrongMaxRoots,
+        Error::NodeNotFree,
+        Error::IndexOutOfRange,
+        Error::UnsortedInput,
+        Error::ZeroLimit,
+        Error::VersionConflict,
+        Error::LengthMismatch,
+        Error::WrongReservation,
+    ];
+
+    let mut messages: Vec<_> = errors.iter().map(Error::as_str).collect();
+    assert!(messages.iter().all(|message| !message.is_empty()));
+
+    messages.sort_unstable();
+    messages.dedup();
+    assert_eq!(messages.len(), errors.len());
+}
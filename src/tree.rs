@@ -48,14 +48,23 @@
 //! # Internal structure
 //! Internally, [`RBTree`] is just a wrapper around [`RBForest`](super::forest::RBForest) with `max_roots`
 //! equal to `1`. See [`RBForest`](super::forest::RBForest) docs for description of the internals.
+use borsh::maybestd::vec::Vec;
 use borsh::{BorshDeserialize, BorshSerialize};
+use bytemuck::from_bytes;
 use core::borrow::Borrow;
 use core::cmp::Ord;
+use core::cmp::Ordering;
 use core::fmt;
+use core::iter::StepBy;
+use core::mem;
+use core::ops::{Add, ControlFlow, RangeBounds};
 
-pub use super::forest::iterators::{KeysIterator, PairsIterator, ValuesIterator};
-use super::forest::{forest_size, init_forest, ForestParams, RBForest};
-use super::Error;
+pub use super::forest::iterators::{KeysIterator, PairsIterator, TryPairsIterator, ValuesIterator};
+use super::forest::{
+    forest_size, init_forest, is_forest, Checkpoint, Descend, ForestParams, FreeListOrder, Header,
+    KeyStep, NodeRef, RBForest, SizeStats,
+};
+use super::{CloneInto, Error};
 
 /// Parameters required to calculate [`RBTree`] size
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
@@ -98,29 +107,91 @@ pub fn init_tree(params: TreeParams, slice: &mut [u8]) -> Result<(), Error> {
 ///
 /// See [module](super::tree) level documentation for more info.
 pub struct RBTree<'a, K, V, const KSIZE: usize, const VSIZE: usize>(
-    RBForest<'a, K, V, KSIZE, VSIZE>,
+    pub(crate) RBForest<'a, K, V, KSIZE, VSIZE>,
 )
 where
     K: Ord + BorshDeserialize + BorshSerialize,
     V: BorshDeserialize + BorshSerialize;
 
+/// Checks if the given slice contains an initialized [`RBTree`]
+///
+/// This is the same as [`is_forest()`](super::forest::is_forest), but additionally checks that the
+/// forest has exactly one tree slot, so it can be opened with [`RBTree::from_slice`].
+#[must_use]
+pub fn is_tree(slice: &[u8]) -> bool {
+    is_forest(slice) && from_bytes::<Header>(&slice[..mem::size_of::<Header>()]).max_roots() == 1
+}
+
 impl<'a, K, V, const KSIZE: usize, const VSIZE: usize> RBTree<'a, K, V, KSIZE, VSIZE>
 where
     K: Ord + BorshDeserialize + BorshSerialize,
     V: BorshDeserialize + BorshSerialize,
 {
+    /// Size of the key buffer of every node, equal to `KSIZE`
+    pub const KEY_SIZE: usize = KSIZE;
+    /// Size of the value buffer of every node, equal to `VSIZE`
+    pub const VALUE_SIZE: usize = VSIZE;
+
     /// Initializes [`RBTree`] in a given slice
     pub fn init_slice(slice: &'a mut [u8]) -> Result<Self, Error> {
         RBForest::<'a, K, V, KSIZE, VSIZE>::init_slice(slice, 1).map(|tree| Self(tree))
     }
 
+    /// Initializes [`RBTree`] in a given slice with the given order of the free list
+    ///
+    /// See [`RBForest::init_slice_with()`] for details.
+    pub fn init_slice_with(
+        slice: &'a mut [u8],
+        free_list_order: FreeListOrder,
+    ) -> Result<Self, Error> {
+        RBForest::<'a, K, V, KSIZE, VSIZE>::init_slice_with(slice, 1, free_list_order)
+            .map(|tree| Self(tree))
+    }
+
+    /// Initializes [`RBTree`] in a given slice and fills it with the entries of `iter`, sorted by
+    /// key
+    ///
+    /// Values of adjacent entries with equal keys are merged with `combine(prev, new)`. See
+    /// [`RBForest::fill_sorted_dedup()`] for details.
+    pub fn build_from_sorted_dedup<I, F>(
+        slice: &'a mut [u8],
+        iter: I,
+        combine: F,
+    ) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        F: FnMut(V, V) -> V,
+    {
+        let mut tree = Self::init_slice(slice)?;
+        tree.0.fill_sorted_dedup(0, iter, combine)?;
+        Ok(tree)
+    }
+
     /// Returns [`RBTree`], contained in the given slice
     ///
     /// # Safety
     /// This function must be called only on slices, previously initialized as [`RBTree`] using
     /// [`init_tree`] or [`RBTree::init_slice`]
+    ///
+    /// Returns [`Error::WrongMaxRoots`] if the slice contains a forest with more than one tree
+    /// slot.
     pub unsafe fn from_slice(slice: &'a mut [u8]) -> Result<Self, Error> {
-        unsafe { RBForest::<'a, K, V, KSIZE, VSIZE>::from_slice(slice).map(|tree| Self(tree)) }
+        let forest = unsafe { RBForest::<'a, K, V, KSIZE, VSIZE>::from_slice(slice)? };
+        if forest.max_roots() != 1 {
+            return Err(Error::WrongMaxRoots);
+        }
+        Ok(Self(forest))
+    }
+
+    /// Initializes a new [`RBTree`] in `dst` and moves all the nodes there
+    ///
+    /// `dst` must be big enough to hold at least as many nodes as the current tree.
+    /// See [`RBForest::grow_into()`] for details.
+    pub fn grow_into<'b>(
+        &self,
+        dst: &'b mut [u8],
+    ) -> Result<RBTree<'b, K, V, KSIZE, VSIZE>, Error> {
+        self.0.grow_into(dst, 1).map(RBTree)
     }
 
     /// Returns the number of occupied nodes
@@ -131,6 +202,40 @@ where
         self.0.len(0).unwrap()
     }
 
+    /// Returns the fraction of the backing slice, which holds the keys and values of the entries
+    ///
+    /// See [`RBForest::payload_ratio()`] for details.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn payload_ratio(&self) -> f64 {
+        self.0.payload_ratio()
+    }
+
+    /// Saves the current number of occupied nodes to measure the growth of the tree with
+    /// [`growth_since()`](RBTree::growth_since)
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.0.checkpoint()
+    }
+
+    /// Returns the signed change of the number of occupied nodes since `checkpoint` was taken
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn growth_since(&self, checkpoint: Checkpoint) -> isize {
+        self.0.growth_since(checkpoint)
+    }
+
+    /// Returns the number of occupied nodes, caching it in the slice until the tree is modified
+    ///
+    /// See [`RBForest::len_cached()`] for details.
+    pub fn len_cached(&mut self) -> usize {
+        self.0.len_cached(0)
+    }
+
     /// Clears the tree
     ///
     /// This function runs in `O(n)`, where `n` - is the number of nodes
@@ -138,14 +243,64 @@ where
         self.0.clear();
     }
 
-    /// Returns the number of free nodes
+    /// Clears the tree and zero-fills the key and value buffers of all the nodes
+    ///
+    /// See [`RBForest::clear_zeroed()`] for details.
     ///
     /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn clear_zeroed(&mut self) {
+        self.0.clear_zeroed();
+    }
+
+    /// Detaches the tree without touching the nodes
+    ///
+    /// See [`RBForest::reset_roots()`] for details.
+    pub fn reset_root(&mut self) {
+        self.0.reset_roots();
+    }
+
+    /// Rethreads the free list through all the nodes, which are not reachable from the root, and
+    /// returns the number of free nodes
+    ///
+    /// See [`RBForest::rebuild_free_list()`] for details.
+    pub fn rebuild_free_list(&mut self) -> usize {
+        self.0.rebuild_free_list()
+    }
+
+    /// Returns the number of free nodes
+    ///
+    /// This function runs in `O(1)`
     #[must_use]
     pub fn free_nodes_left(&self) -> usize {
         self.0.free_nodes_left()
     }
 
+    /// Returns the number of new keys, which could be inserted before the tree is full
+    ///
+    /// Inserts, which overwrite existing keys, do not consume any capacity.
+    ///
+    /// This function runs in `O(1)`
+    #[must_use]
+    pub fn spare_capacity(&self) -> usize {
+        self.0.spare_capacity()
+    }
+
+    /// Returns the whole backing slice of the tree
+    ///
+    /// See [`RBForest::as_bytes()`] for details.
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+
+    /// Checks that the fields of the header are consistent with each other and with the tree
+    ///
+    /// See [`RBForest::header_consistent()`] for details.
+    #[must_use]
+    pub fn header_consistent(&self) -> bool {
+        self.0.header_consistent()
+    }
+
     /// Returns true if the map contains a value for the specified key
     ///
     /// This function runs in `O(log(n))`, where `n` - is the number of nodes
@@ -158,6 +313,27 @@ where
         self.0.contains_key(0, k)
     }
 
+    /// Returns true if the map contains a value for the key with the given serialized
+    /// representation
+    ///
+    /// See [`RBForest::contains_key_bytes()`] for the requirements on the key type.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn contains_key_bytes(&self, key_bytes: &[u8]) -> bool {
+        self.0.contains_key_bytes(0, key_bytes)
+    }
+
+    /// Looks up raw value buffers for a batch of serialized keys
+    ///
+    /// See [`RBForest::get_bytes_many`] for details.
+    ///
+    /// This function runs in `O(m * log(n))`, where `m` - is the number of keys and `n` - is the
+    /// number of nodes
+    pub fn get_bytes_many<'b>(&'b self, keys: &[&[u8]], out: &mut [Option<&'b [u8]>]) -> usize {
+        self.0.get_bytes_many(0, keys, out)
+    }
+
     /// Returns a key-value pair corresponding to the supplied key
     ///
     /// This function runs in `O(log(n))`, where `n` - is the number of nodes
@@ -182,6 +358,59 @@ where
         self.0.get(0, k)
     }
 
+    /// Returns the value corresponding to the owned key
+    ///
+    /// See [`RBForest::get_owned()`] for details.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn get_owned(&self, k: K) -> Option<V> {
+        self.0.get_owned(0, k)
+    }
+
+    /// Returns the value associated with the given key, without panicking on corrupted data
+    ///
+    /// Unlike [`get()`](RBTree::get), returns [`Error::KeySerializationError`] or
+    /// [`Error::ValueSerializationError`] if a key or the value can not be deserialized.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn try_get<Q>(&self, k: &Q) -> Result<Option<V>, Error>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.0.try_get(0, k)
+    }
+
+    /// Compares the beginning of the raw value buffer of the given key with `expected`
+    ///
+    /// Returns `None` if there is no such key, otherwise returns `Some(true)` if the value buffer
+    /// starts with `expected`.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn value_bytes_eq<Q>(&self, k: &Q, expected: &[u8]) -> Option<bool>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.0.value_bytes_eq(0, k, expected)
+    }
+
+    /// Returns `true` if `k` is present and its value equals `v`, so inserting `v` would not
+    /// change the tree
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn is_unchanged_by<Q>(&self, k: &Q, v: &V) -> bool
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+        V: PartialEq,
+    {
+        self.0.is_unchanged_by(0, k, v)
+    }
+
     /// Inserts a new key-value pair and returns the old value if it was present
     ///
     /// This function runs in `O(log(n))`, where `n` - is the number of nodes
@@ -189,6 +418,124 @@ where
         self.0.insert(0, k, v)
     }
 
+    /// Returns the generation of the tree, which is advanced by every modification
+    ///
+    /// See [`RBForest::generation`] for details.
+    #[must_use]
+    pub fn generation(&self) -> u32 {
+        self.0.generation()
+    }
+
+    /// Inserts a new key-value pair if the generation of the tree equals `expected_gen`
+    ///
+    /// See [`RBForest::insert_checked`] for details.
+    pub fn insert_checked(&mut self, k: K, v: V, expected_gen: u32) -> Result<Option<V>, Error> {
+        self.0.insert_checked(0, k, v, expected_gen)
+    }
+
+    /// Replaces the value of an existing key and returns the old value
+    ///
+    /// See [`RBForest::replace`] for details.
+    pub fn replace<Q>(&mut self, k: &Q, value: V) -> Result<Option<V>, Error>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.0.replace(0, k, value)
+    }
+
+    /// Inserts pairs of keys and values, taken from two parallel slices, and returns the number of
+    /// inserted new keys
+    ///
+    /// See [`RBForest::insert_columns`] for details.
+    pub fn insert_columns(&mut self, keys: &[K], values: &[V]) -> Result<usize, Error>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.0.insert_columns(0, keys, values)
+    }
+
+    /// Inserts all the pairs of `iter` and returns the previous value for every inserted key
+    ///
+    /// See [`RBForest::insert_many_report()`] for details.
+    #[allow(clippy::type_complexity)]
+    pub fn insert_many_report<I>(
+        &mut self,
+        iter: I,
+    ) -> Result<Vec<(K, Option<V>)>, (Error, Vec<(K, Option<V>)>)>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Clone,
+    {
+        self.0.insert_many_report(0, iter)
+    }
+
+    /// Inserts placeholder entries with the default value for the keys, which are not present yet
+    ///
+    /// See [`RBForest::warm_up`] for details.
+    pub fn warm_up(&mut self, keys: &[K]) -> Result<(), Error>
+    where
+        K: Clone,
+        V: Default,
+    {
+        self.0.warm_up(0, keys)
+    }
+
+    /// Checks that `key` and `value` fit into the node buffers without modifying the tree
+    ///
+    /// See [`RBForest::would_fit`] for details.
+    pub fn would_fit(&self, key: &K, value: &V) -> Result<(), Error> {
+        self.0.would_fit(key, value)
+    }
+
+    /// Predicts if inserting `k` would require rebalancing of the tree
+    ///
+    /// See [`RBForest::insert_causes_rebalance()`] for details.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn insert_causes_rebalance<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.0.insert_causes_rebalance(0, k)
+    }
+
+    /// Inserts raw key and value buffers and returns the old value buffer if the key was present
+    ///
+    /// See [`RBForest::insert_arrays`] for details and the byte order requirements.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn insert_arrays(
+        &mut self,
+        key: [u8; KSIZE],
+        value: [u8; VSIZE],
+    ) -> Result<Option<[u8; VSIZE]>, Error> {
+        self.0.insert_arrays(0, key, value)
+    }
+
+    /// Inserts a new key-value pair only if the key is absent
+    ///
+    /// Unlike [`insert()`](RBTree::insert), an existing entry is never overwritten: if the key
+    /// is already present, the tree is left unchanged and the stored value is returned.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn insert_no_overwrite(&mut self, key: K, value: V) -> Result<Option<V>, Error> {
+        self.0.insert_no_overwrite(0, key, value)
+    }
+
+    /// Inserts a new key-value pair, evicting the entry with the smallest key if there are no
+    /// free nodes left
+    ///
+    /// See [`RBForest::insert_bounded`] for details.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn insert_bounded(&mut self, key: K, value: V) -> Result<Option<(K, V)>, Error> {
+        self.0.insert_bounded(0, key, value)
+    }
+
     /// Returns `true` if the tree contains no elements
     #[must_use]
     pub fn is_empty(&self) -> bool {
@@ -217,6 +564,30 @@ where
         self.0.remove_entry(0, key)
     }
 
+    /// Deletes entry and copies its raw value bytes to `out`
+    ///
+    /// See [`RBForest::remove_bytes()`] for details.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn remove_bytes<Q>(&mut self, key: &Q, out: &mut [u8]) -> Option<usize>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.0.remove_bytes(0, key, out)
+    }
+
+    /// Removes the entry and returns the stored key together with the value
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    pub fn take<Q>(&mut self, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.0.take(0, k)
+    }
+
     /// Deletes entry without deserializing the value
     ///
     /// Returns `true` if there was a value with the given key.
@@ -228,6 +599,266 @@ where
         self.0.delete(0, key)
     }
 
+    /// Calls `f` for every entry in order by key, removing the entries it asks to remove
+    ///
+    /// See [`RBForest::for_each_removable()`] for details.
+    ///
+    /// This function runs in `O(n * log(n))`, where `n` - is the number of nodes
+    pub fn for_each_removable<F>(&mut self, f: F) -> Result<usize, Error>
+    where
+        F: FnMut(&K, &V) -> ControlFlow<(), bool>,
+    {
+        self.0.for_each_removable(0, f)
+    }
+
+    /// Calls `f` for every entry in order by key, until it returns `ControlFlow::Break`
+    ///
+    /// See [`RBForest::try_for_each()`] for details.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn try_for_each<B, F>(&self, f: F) -> ControlFlow<B>
+    where
+        F: FnMut(&K, &V) -> ControlFlow<B>,
+    {
+        self.0.try_for_each(0, f)
+    }
+
+    /// Removes the entries with the biggest keys until at most `n` entries are left
+    ///
+    /// Returns the number of removed entries.
+    ///
+    /// This function runs in `O(N + m * log(N))`, where `N` - is the number of nodes and `m` - is
+    /// the number of removed entries
+    pub fn truncate_to(&mut self, n: usize) -> usize {
+        self.0.truncate_to(0, n)
+    }
+
+    /// Replaces every value of the tree with the result of `f`, in order by key
+    ///
+    /// See [`RBForest::map_values()`] for details.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn map_values<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: FnMut(&K, V) -> V,
+    {
+        self.0.map_values(0, f)
+    }
+
+    /// Applies `f` to every value with a key in `range`, in order by key, and returns the number
+    /// of updated values
+    ///
+    /// See [`RBForest::update_range()`] for details.
+    ///
+    /// This function runs in `O(log(n) + m)`, where `n` - is the number of nodes and `m` - is
+    /// the number of updated values
+    pub fn update_range<R, Q, F>(&mut self, range: R, f: F) -> Result<usize, Error>
+    where
+        K: Borrow<Q>,
+        R: RangeBounds<Q>,
+        Q: Ord + ?Sized,
+        F: FnMut(&K, &mut V),
+    {
+        self.0.update_range(0, range, f)
+    }
+
+    /// Rebuilds the tree into a tree of minimal height
+    ///
+    /// See [`RBForest::rebuild_balanced()`] for details.
+    ///
+    /// This function runs in `O(n)` and allocates `O(n)` memory, where `n` - is the number of
+    /// nodes
+    pub fn rebuild_balanced(&mut self) -> Result<(), Error> {
+        self.0.rebuild_balanced(0)
+    }
+
+    /// Returns the total serialized sizes of keys and values
+    ///
+    /// See [`RBForest::size_stats()`] for details.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn size_stats(&self) -> SizeStats {
+        self.0.size_stats(0)
+    }
+
+    /// Returns the first and the last keys of the longest run of consecutive keys
+    ///
+    /// See [`RBForest::longest_run()`] for details.
+    #[must_use]
+    pub fn longest_run(&self) -> Option<(K, K)>
+    where
+        K: KeyStep + Clone,
+    {
+        self.0.longest_run(0)
+    }
+
+    /// Sets bit `i` of `out` if the key `base + i` is present in the tree, for `i` in `0..len`
+    ///
+    /// See [`RBForest::present_bitmap()`] for details.
+    ///
+    /// This function runs in `O(log(n) + len)`, where `n` - is the number of nodes
+    pub fn present_bitmap(&self, base: K, len: usize, out: &mut [u8]) -> Result<(), Error>
+    where
+        K: KeyStep,
+    {
+        self.0.present_bitmap(0, base, len, out)
+    }
+
+    /// Returns the maximum serialized length of the keys, stored in the tree
+    ///
+    /// See [`RBForest::required_key_size()`] for details.
+    #[must_use]
+    pub fn required_key_size(&self) -> usize {
+        self.0.required_key_size(0)
+    }
+
+    /// Returns the maximum serialized length of the values, stored in the tree
+    ///
+    /// See [`RBForest::required_value_size()`] for details.
+    #[must_use]
+    pub fn required_value_size(&self) -> usize {
+        self.0.required_value_size(0)
+    }
+
+    /// Merges adjacent entries in a single pass in key order
+    ///
+    /// See [`RBForest::coalesce()`] for details.
+    ///
+    /// This function runs in `O(n * log(n))`, where `n` - is the number of nodes
+    pub fn coalesce<F>(&mut self, can_merge: F) -> Result<usize, Error>
+    where
+        F: Fn(&K, &V, &K, &V) -> Option<V>,
+    {
+        self.0.coalesce(0, can_merge)
+    }
+
+    /// Returns the entry with the maximum value
+    ///
+    /// If several entries have the maximum value, the one with the biggest key is returned.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn max_by_value(&self) -> Option<(K, V)>
+    where
+        V: Ord,
+    {
+        self.0.max_by_value(0)
+    }
+
+    /// Returns the entry with the minimum value
+    ///
+    /// If several entries have the minimum value, the one with the smallest key is returned.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn min_by_value(&self) -> Option<(K, V)>
+    where
+        V: Ord,
+    {
+        self.0.min_by_value(0)
+    }
+
+    /// Returns the median entry, i.e. the entry with index `(n - 1) / 2` in key order
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn median(&self) -> Option<(K, V)> {
+        self.0.median(0)
+    }
+
+    /// Returns the key with index `rank` in key order, starting from `0`
+    ///
+    /// See [`RBForest::key_at_rank()`] for details.
+    ///
+    /// This function runs in `O(log(n) + rank)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn key_at_rank(&self, rank: usize) -> Option<K> {
+        self.0.key_at_rank(0, rank)
+    }
+
+    /// Returns `true` if some value is stored under several keys
+    ///
+    /// This function runs in `O(n * log(n))` and allocates `O(n)` memory, where `n` - is the
+    /// number of nodes
+    #[must_use]
+    pub fn has_duplicate_values(&self) -> bool
+    where
+        V: Ord,
+    {
+        self.0.has_duplicate_values(0)
+    }
+
+    /// Returns two keys, which share the same value
+    ///
+    /// See [`RBForest::find_duplicate_value()`] for details.
+    ///
+    /// This function runs in `O(n * log(n))` and allocates `O(n)` memory, where `n` - is the
+    /// number of nodes
+    #[must_use]
+    pub fn find_duplicate_value(&self) -> Option<(K, K)>
+    where
+        V: Ord,
+    {
+        self.0.find_duplicate_value(0)
+    }
+
+    /// Walks the tree from the root, letting the visitor decide which subtrees to descend into
+    ///
+    /// See [`RBForest::visit()`] for details.
+    ///
+    /// This function runs in `O(m)`, where `m` - is the number of visited nodes
+    pub fn visit<F>(&self, f: F)
+    where
+        F: FnMut(&K, &V) -> Descend,
+    {
+        self.0.visit(0, f).unwrap();
+    }
+
+    /// Folds every entry of the tree into an accumulator, in order by key
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn fold<B, F>(&self, init: B, f: F) -> B
+    where
+        F: FnMut(B, K, V) -> B,
+    {
+        self.0.fold(0, init, f)
+    }
+
+    /// Returns the number of entries and the sum of their values, computed in a single walk
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn count_and_sum(&self) -> (usize, V)
+    where
+        V: Add<Output = V> + Copy + Default,
+    {
+        self.0.count_and_sum(0)
+    }
+
+    /// Returns the number of entries, for which `f` returns `true`
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn count_where<F>(&self, f: F) -> usize
+    where
+        F: Fn(&K, &V) -> bool,
+    {
+        self.0.count_where(0, f)
+    }
+
+    /// Returns the number of entries, for which both `key_filter` and `f` return `true`
+    ///
+    /// See [`RBForest::count_where_lazy()`] for details.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn count_where_lazy<P, F>(&self, key_filter: P, f: F) -> usize
+    where
+        P: Fn(&K) -> bool,
+        F: Fn(&K, &V) -> bool,
+    {
+        self.0.count_where_lazy(0, key_filter, f)
+    }
+
     /// Returns the first key-value pair in the map
     ///
     /// This function runs in `O(log(n))`, where `n` - is the number of nodes
@@ -244,12 +875,355 @@ where
         self.0.last_entry(0)
     }
 
+    /// Returns the smallest and the biggest keys of the tree
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn key_bounds(&self) -> Option<(K, K)> {
+        self.0.key_bounds(0)
+    }
+
+    /// Returns the greatest entry with key `<= k` and the least entry with key `>= k`
+    ///
+    /// Both entries are found in a single descent. If `k` is present in the tree, both of them are
+    /// the entry with this key.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    #[allow(clippy::type_complexity)]
+    pub fn surrounding<Q>(&self, k: &Q) -> (Option<(K, V)>, Option<(K, V)>)
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.0.surrounding(0, k)
+    }
+
+    /// Returns the entry with the key nearest to `k` according to the distance function `dist`
+    ///
+    /// Ties are broken toward the smaller key.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn nearest<Q, F>(&self, k: &Q, dist: F) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+        F: Fn(&K, &Q) -> u64,
+    {
+        self.0.nearest(0, k, dist)
+    }
+
+    /// Returns the entry with the greatest key strictly less than `k`
+    ///
+    /// `k` itself does not have to be present in the tree.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn last_before<Q>(&self, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.0.last_before(0, k)
+    }
+
+    /// Returns the entry with the least key strictly greater than `k`
+    ///
+    /// `k` itself does not have to be present in the tree.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn first_after<Q>(&self, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.0.first_after(0, k)
+    }
+
+    /// Returns the value of the entry with the least key strictly greater than `k`
+    ///
+    /// This is the same as [`first_after()`](RBTree::first_after), but only the value is
+    /// deserialized. `k` itself does not have to be present in the tree.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn next_value<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.0.next_value(0, k)
+    }
+
+    /// Returns the keys, which precede and follow the existing key `k` in key order
+    ///
+    /// Returns `None` if there is no such key in the tree.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn neighbors<Q>(&self, k: &Q) -> Option<(Option<K>, Option<K>)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.0.neighbors(0, k)
+    }
+
     /// Creates an iterator over key-value pairs, in order by key
     #[must_use]
     pub fn pairs<'b>(&'b self) -> PairsIterator<'b, 'a, K, V, KSIZE, VSIZE> {
         self.0.pairs(0).unwrap()
     }
 
+    /// Returns a reference to the node, containing the key
+    ///
+    /// See [`RBForest::node_ref()`] for details.
+    ///
+    /// This function runs in `O(log(n))`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn node_ref<Q>(&self, k: &Q) -> Option<NodeRef<KSIZE>>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.0.node_ref(0, k)
+    }
+
+    /// Creates an iterator over key-value pairs, starting from the referenced entry, in order by
+    /// key
+    ///
+    /// See [`RBForest::pairs_from_ref()`] for details.
+    #[must_use]
+    pub fn pairs_from_ref<'b>(
+        &'b self,
+        r: NodeRef<KSIZE>,
+    ) -> PairsIterator<'b, 'a, K, V, KSIZE, VSIZE> {
+        self.0.pairs_from_ref(r)
+    }
+
+    /// Creates an iterator over key-value pairs, starting from the position, saved with
+    /// [`PairsIterator::position`]
+    ///
+    /// See [`RBForest::pairs_resume()`] for details.
+    pub fn pairs_resume<'b>(
+        &'b self,
+        token: &[u8],
+    ) -> Result<PairsIterator<'b, 'a, K, V, KSIZE, VSIZE>, Error> {
+        self.0.pairs_resume(0, token)
+    }
+
+    /// Creates an iterator over key-value pairs in order by key, skipping the first `offset`
+    /// entries
+    ///
+    /// See [`RBForest::pairs_skip()`] for details.
+    ///
+    /// This function runs in `O(log(n) + offset)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn pairs_skip<'b>(&'b self, offset: usize) -> PairsIterator<'b, 'a, K, V, KSIZE, VSIZE> {
+        self.0.pairs_skip(0, offset).unwrap()
+    }
+
+    /// Creates an iterator over key-value pairs with `key % modulus == residue`, in order by key
+    ///
+    /// See [`RBForest::residue_keys()`] for details.
+    pub fn residue_keys<'b>(
+        &'b self,
+        modulus: u64,
+        residue: u64,
+    ) -> impl Iterator<Item = (K, V)> + 'b
+    where
+        K: Copy + Into<u64>,
+    {
+        self.0.residue_keys(0, modulus, residue).unwrap()
+    }
+
+    /// Creates an iterator over values at keys `start`, `start + step`, `start + 2 * step` and so on
+    ///
+    /// See [`RBForest::keys_stepped()`] for details.
+    pub fn keys_stepped<'b>(&'b self, start: K, step: K) -> impl Iterator<Item = V> + 'b
+    where
+        K: KeyStep + 'b,
+    {
+        self.0.keys_stepped(0, start, step).unwrap()
+    }
+
+    /// Creates an iterator over overlapping pairs of adjacent entries, in order by key
+    ///
+    /// See [`RBForest::windows2()`] for details.
+    pub fn windows2<'b>(&'b self) -> impl Iterator<Item = ((K, V), (K, V))> + 'b
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.0.windows2(0).unwrap()
+    }
+
+    /// Creates an iterator over every `step`-th key-value pair, in order by key
+    ///
+    /// # Panics
+    /// Panics if `step` is `0`
+    pub fn sample_every<'b>(
+        &'b self,
+        step: usize,
+    ) -> StepBy<PairsIterator<'b, 'a, K, V, KSIZE, VSIZE>> {
+        self.0.sample_every(0, step).unwrap()
+    }
+
+    /// Creates an iterator over key-value pairs, which does not panic on corrupted data
+    #[must_use]
+    pub fn try_pairs<'b>(&'b self) -> TryPairsIterator<'b, 'a, K, V, KSIZE, VSIZE> {
+        self.0.try_pairs(0).unwrap()
+    }
+
+    /// Creates an iterator over pairs of neighboring keys, which are not adjacent according to
+    /// `is_adjacent`
+    ///
+    /// See [`RBForest::gaps_by`] for details.
+    pub fn gaps_by<'b, F>(&'b self, is_adjacent: F) -> impl Iterator<Item = (K, K)> + 'b
+    where
+        F: FnMut(&K, &K) -> bool + 'b,
+    {
+        self.0.gaps_by(0, is_adjacent).unwrap()
+    }
+
+    /// Creates an iterator over pairs of neighboring integer keys, which differ by more than one
+    pub fn gaps<'b>(&'b self) -> impl Iterator<Item = (K, K)> + 'b
+    where
+        K: KeyStep,
+    {
+        self.0.gaps(0).unwrap()
+    }
+
+    /// Returns the share of the integer keys between the minimum and the maximum keys
+    /// (inclusive), which are present in the tree
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn density(&self) -> Option<f64>
+    where
+        K: Copy + Into<i128>,
+    {
+        self.0.density(0)
+    }
+
+    /// Creates an iterator over keys with running sums of values, in order by key
+    pub fn prefix_sums<'b>(&'b self) -> impl Iterator<Item = (K, V)> + 'b
+    where
+        V: Add<Output = V> + Copy + Default + 'b,
+    {
+        self.0.prefix_sums(0).unwrap()
+    }
+
+    /// Creates an iterator over groups of consecutive entries, which have the same bucket
+    ///
+    /// See [`RBForest::group_by()`] for details.
+    pub fn group_by<'b, B, F>(&'b self, bucket: F) -> impl Iterator<Item = (B, Vec<(K, V)>)> + 'b
+    where
+        B: Ord + 'b,
+        F: Fn(&K) -> B + 'b,
+    {
+        self.0.group_by(0, bucket).unwrap()
+    }
+
+    /// Creates an iterator over entries in order by key, together with the minimum and the
+    /// maximum of the values seen so far
+    ///
+    /// See [`RBForest::pairs_with_running_extrema()`] for details.
+    pub fn pairs_with_running_extrema<'b>(&'b self) -> impl Iterator<Item = (K, V, V, V)> + 'b
+    where
+        V: Ord + Copy + 'b,
+    {
+        self.0.pairs_with_running_extrema(0).unwrap()
+    }
+
+    /// Creates an iterator over key-value pairs, for which `f` returns `true`, in order by key
+    ///
+    /// See [`RBForest::pairs_filtered()`] for details.
+    pub fn pairs_filtered<'b, F>(&'b self, f: F) -> impl Iterator<Item = (K, V)> + 'b
+    where
+        F: Fn(&K, &V) -> bool + 'b,
+    {
+        self.0.pairs_filtered(0, f).unwrap()
+    }
+
+    /// Collects all the entries of the tree and sorts them with the comparator `f`
+    ///
+    /// This function runs in `O(n * log(n))` and allocates `O(n)` memory, where `n` - is the
+    /// number of nodes
+    pub fn sorted_by<F>(&self, f: F) -> Vec<(K, V)>
+    where
+        F: FnMut(&(K, V), &(K, V)) -> Ordering,
+    {
+        self.0.sorted_by(0, f).unwrap()
+    }
+
+    /// Collects all the entries of the tree in order by key
+    ///
+    /// See [`RBForest::collect_pairs()`] for details.
+    ///
+    /// This function runs in `O(n)` and allocates `O(n)` memory, where `n` - is the number of
+    /// nodes
+    #[must_use]
+    pub fn collect_pairs(&self) -> Vec<(K, V)> {
+        self.0.collect_pairs(0).unwrap()
+    }
+
+    /// Copies all the entries of the tree to the start of `out` in order by key and returns their
+    /// number
+    ///
+    /// See [`RBForest::snapshot_into()`] for details.
+    ///
+    /// This function runs in `O(n)`, where `n` - is the number of nodes
+    pub fn snapshot_into<const N: usize>(&self, out: &mut [(K, V); N]) -> Result<usize, Error> {
+        self.0.snapshot_into(0, out)
+    }
+
+    /// Collects up to `k` entries with the smallest keys, in ascending order by key
+    ///
+    /// This function runs in `O(log(n) + k)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn smallest(&self, k: usize) -> Vec<(K, V)> {
+        self.0.smallest(0, k).unwrap()
+    }
+
+    /// Collects up to `k` entries with the biggest keys, in descending order by key
+    ///
+    /// This function runs in `O(log(n) + k)`, where `n` - is the number of nodes
+    #[must_use]
+    pub fn largest(&self, k: usize) -> Vec<(K, V)> {
+        self.0.largest(0, k).unwrap()
+    }
+
+    /// Collects all the entries of the tree in a pseudo-random order, determined by `seed`
+    ///
+    /// See [`RBForest::shuffled_pairs()`] for details.
+    ///
+    /// This function runs in `O(n)` and allocates `O(n)` memory, where `n` - is the number of
+    /// nodes
+    #[must_use]
+    pub fn shuffled_pairs(&self, seed: u64) -> Vec<(K, V)> {
+        self.0.shuffled_pairs(0, seed).unwrap()
+    }
+
+    /// Collects up to `limit` entries with keys strictly greater than `last_key`, in order by key
+    ///
+    /// Returns the entries and the key to resume from, see [`RBForest::pairs_chunked()`] for
+    /// details.
+    ///
+    /// # Panics
+    /// Panics if `limit` is `0`
+    ///
+    /// This function runs in `O(log(n) + limit)`, where `n` - is the number of nodes
+    pub fn pairs_chunked<Q>(&self, last_key: Option<&Q>, limit: usize) -> (Vec<(K, V)>, Option<K>)
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.0.pairs_chunked(0, last_key, limit).unwrap()
+    }
+
     /// Creates an iterator over keys, from smallest to biggest
     #[must_use]
     pub fn keys<'b>(&'b self) -> KeysIterator<'b, 'a, K, V, KSIZE, VSIZE> {
@@ -263,6 +1237,21 @@ where
     }
 }
 
+impl<'a, K, V, const KSIZE: usize, const VSIZE: usize> CloneInto for RBTree<'a, K, V, KSIZE, VSIZE>
+where
+    K: Ord + BorshDeserialize + BorshSerialize,
+    V: BorshDeserialize + BorshSerialize,
+{
+    type Output<'b> = RBTree<'b, K, V, KSIZE, VSIZE>;
+
+    /// Copies the tree into `dst`
+    ///
+    /// See [`grow_into()`](RBTree::grow_into) for details.
+    fn clone_into<'b>(&self, dst: &'b mut [u8]) -> Result<Self::Output<'b>, Error> {
+        self.grow_into(dst)
+    }
+}
+
 impl<'a, K, V, const KSIZE: usize, const VSIZE: usize> fmt::Debug for RBTree<'a, K, V, KSIZE, VSIZE>
 where
     K: Ord + BorshDeserialize + BorshSerialize + fmt::Debug,
@@ -294,5 +1283,5 @@ where
 #[cfg(test)]
 mod tests;
 
-#[cfg(any(test, fuzzing))]
+#[cfg(any(test, fuzzing, feature = "checks"))]
 pub mod internal_checks;
@@ -1,5 +1,6 @@
 //! Additional methods for self-consistency checking on [`RBTree`]
 use super::*;
+#[cfg(any(test, fuzzing, feature = "unchecked-mutators"))]
 use crate::forest::Node;
 use borsh::maybestd::vec::Vec;
 
@@ -9,6 +10,9 @@ where
     V: Eq + BorshDeserialize + BorshSerialize,
 {
     /// Set all the fields of `id` node to a given value (for testing purposes only)
+    ///
+    /// See [`RBForest::set_node()`](crate::forest::RBForest::set_node) for details.
+    #[cfg(any(test, fuzzing, feature = "unchecked-mutators"))]
     pub fn set_node(&mut self, id: usize, node: &Node<KSIZE, VSIZE>) {
         {
             self.0.set_node(id, node);
@@ -29,6 +33,18 @@ where
         self.0.is_child_parent_links_consistent(0)
     }
 
+    /// Checks that no node of the free list is occupied by the tree
+    #[must_use]
+    pub fn free_list_disjoint(&self) -> bool {
+        self.0.free_list_disjoint()
+    }
+
+    /// Returns the number of nodes, which are neither in the tree nor in the free list
+    #[must_use]
+    pub fn leaked_node_count(&self) -> usize {
+        self.0.leaked_node_count()
+    }
+
     /// Checks if the tree is balances (for each node black depths of its subtrees are equal)
     #[must_use]
     pub fn is_balanced(&self) -> bool {
@@ -42,6 +58,101 @@ where
         self.0.no_double_red(0)
     }
 
+    /// Returns the first pair of adjacent keys in iteration order, where the earlier key is not
+    /// strictly less than the later one
+    #[must_use]
+    pub fn first_order_violation(&self) -> Option<(K, K)> {
+        self.0.first_order_violation(0)
+    }
+
+    /// Counts the nodes of the tree by walking it in key order, as the iterators do
+    #[must_use]
+    pub fn structural_node_count(&self) -> usize {
+        self.0.structural_node_count(0)
+    }
+
+    /// Inserts a new key-value pair, placing it into the free node `node_index`, and returns the
+    /// old value if it was present
+    ///
+    /// See [`RBForest::insert_at()`] for details.
+    pub fn insert_at(&mut self, key: K, value: V, node_index: u32) -> Result<Option<V>, Error> {
+        self.0.insert_at(0, key, value, node_index)
+    }
+
+    /// Returns the average distance between the nodes of adjacent entries, divided by the size of
+    /// the node pool
+    ///
+    /// See [`RBForest::fragmentation()`] for details.
+    #[must_use]
+    pub fn fragmentation(&self) -> f64 {
+        self.0.fragmentation(0)
+    }
+
+    /// Checks that the in-order traversal of the tree yields strictly increasing keys
+    #[must_use]
+    pub fn is_bst(&self) -> bool {
+        self.0.is_bst(0)
+    }
+
+    /// Collects the entries of the tree level by level, from the root down, each level from left
+    /// to right
+    #[must_use]
+    pub fn pairs_level_order(&self) -> Vec<(K, V)> {
+        self.0.pairs_level_order(0)
+    }
+
+    /// Checks that the height of the tree (the number of nodes on the longest path from the root)
+    /// is at most `max_height`
+    #[must_use]
+    pub fn fits_height(&self, max_height: usize) -> bool {
+        self.0.fits_height(0, max_height)
+    }
+
+    /// Returns the number of rotations performed since the tree was created or since the last
+    /// call to [`reset_rotation_counter()`](RBTree::reset_rotation_counter)
+    #[must_use]
+    pub fn rotations_since_reset(&self) -> usize {
+        self.0.rotations_since_reset()
+    }
+
+    /// Resets the rotation counter, see [`rotations_since_reset()`](RBTree::rotations_since_reset)
+    pub fn reset_rotation_counter(&mut self) {
+        self.0.reset_rotation_counter();
+    }
+
+    /// Returns the number of edges between the root and the node, containing `k`
+    #[must_use]
+    pub fn key_depth<Q>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.0.key_depth(0, k)
+    }
+
+    /// Returns the key of the lowest common ancestor of the nodes, containing `a` and `b`
+    #[must_use]
+    pub fn lowest_common_ancestor<Q>(&self, a: &Q, b: &Q) -> Option<K>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.0.lowest_common_ancestor(0, a, b)
+    }
+
+    /// Returns the number of nodes at each depth: index `d` holds the number of nodes, which are
+    /// `d` edges away from the root
+    #[must_use]
+    pub fn depth_histogram(&self) -> Vec<usize> {
+        self.0.depth_histogram(0)
+    }
+
+    /// Returns the numbers of red and black nodes of the tree as `(red, black)`
+    #[must_use]
+    pub fn color_counts(&self) -> (usize, usize) {
+        self.0.color_counts(0)
+    }
+
     /// Unified way to apply [`RBTree`] methods in the fuzzing harness
     pub fn apply_method(&mut self, method: RBTreeMethod<K, V>) {
         use RBTreeMethod::*;
@@ -356,6 +356,92 @@ fn keys() {
     }
 }
 
+#[test]
+fn clone_into() {
+    let mut vec = create_vec(1, 4, 256);
+    let mut tree = RBTree::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice()).unwrap();
+    for &key in forest_helpers::INSERT_KEYS.iter() {
+        tree.insert(key, key as u32 * 2).unwrap();
+    }
+
+    let mut dst = create_vec(1, 4, 256);
+    let mut copy = CloneInto::clone_into(&tree, dst.as_mut_slice()).unwrap();
+    assert!(copy.pairs().eq(tree.pairs()));
+    assert!(copy.0.struct_eq(0, &tree.0, 0));
+
+    // The copy is independent from the original
+    copy.remove(&5);
+    assert!(tree.contains_key(&5));
+
+    let mut too_small = create_vec(1, 4, 100);
+    assert_eq!(
+        CloneInto::clone_into(&tree, too_small.as_mut_slice()).err(),
+        Some(Error::TooSmall)
+    );
+}
+
+#[test]
+fn from_forest_slice() {
+    let mut vec = forest_helpers::create_vec(1, 4, 10, 2);
+    crate::forest::RBForest::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice(), 2).unwrap();
+    assert!(crate::is_forest(&vec));
+    assert!(!is_tree(&vec));
+    assert_eq!(
+        unsafe { RBTree::<u8, u32, 1, 4>::from_slice(vec.as_mut_slice()) }.err(),
+        Some(Error::WrongMaxRoots)
+    );
+
+    let mut vec = create_vec(1, 4, 10);
+    assert!(!is_tree(&vec));
+    RBTree::<u8, u32, 1, 4>::init_slice(vec.as_mut_slice()).unwrap();
+    assert!(is_tree(&vec));
+    assert!(unsafe { RBTree::<u8, u32, 1, 4>::from_slice(vec.as_mut_slice()) }.is_ok());
+}
+
+#[test]
+fn build_from_sorted_dedup() {
+    let mut vec = create_vec(1, 1, 10);
+    let entries = [(1, b'a'), (1, b'b'), (2, b'c')];
+    let tree =
+        RBTree::<u8, u8, 1, 1>::build_from_sorted_dedup(vec.as_mut_slice(), entries, |_, new| new)
+            .unwrap();
+    assert_eq!(tree.pairs().collect::<Vec<_>>(), [(1, b'b'), (2, b'c')]);
+    assert!(tree.is_balanced());
+
+    let mut vec = create_vec(1, 1, 10);
+    let tree = RBTree::<u8, u8, 1, 1>::build_from_sorted_dedup(
+        vec.as_mut_slice(),
+        [(1, 2), (1, 3), (1, 4), (5, 1), (7, 1), (7, 1)],
+        |prev, new| prev + new,
+    )
+    .unwrap();
+    assert_eq!(tree.pairs().collect::<Vec<_>>(), [(1, 9), (5, 1), (7, 2)]);
+}
+
+#[test]
+fn buffer_sizes() {
+    assert_eq!(RBTree::<u8, u8, 1, 1>::KEY_SIZE, 1);
+    assert_eq!(RBTree::<u8, u8, 1, 1>::VALUE_SIZE, 1);
+    assert_eq!(RBTree::<String, u64, 16, 8>::KEY_SIZE, 16);
+    assert_eq!(RBTree::<String, u64, 16, 8>::VALUE_SIZE, 8);
+
+    fn params<K, V, const KSIZE: usize, const VSIZE: usize>(
+        _: &RBTree<K, V, KSIZE, VSIZE>,
+    ) -> TreeParams
+    where
+        K: Ord + BorshDeserialize + BorshSerialize,
+        V: BorshDeserialize + BorshSerialize,
+    {
+        TreeParams {
+            k_size: RBTree::<K, V, KSIZE, VSIZE>::KEY_SIZE,
+            v_size: RBTree::<K, V, KSIZE, VSIZE>::VALUE_SIZE,
+        }
+    }
+    let mut vec = create_vec(4, 2, 5);
+    let tree = RBTree::<u32, u16, 4, 2>::init_slice(vec.as_mut_slice()).unwrap();
+    assert_eq!(tree_size(params(&tree), 5), vec.len());
+}
+
 fn create_vec(k_size: usize, v_size: usize, num_entries: usize) -> Vec<u8> {
     forest_helpers::create_vec(k_size, v_size, num_entries, 1)
 }